@@ -0,0 +1,4 @@
+ALTER TABLE group_v2s
+    DROP COLUMN storage_proto;
+ALTER TABLE group_v2s
+    DROP COLUMN storage_service_id;
@@ -0,0 +1,4 @@
+ALTER TABLE group_v2s
+    ADD COLUMN storage_service_id BLOB;
+ALTER TABLE group_v2s
+    ADD COLUMN storage_proto BLOB;
@@ -0,0 +1,2 @@
+DROP INDEX link_previews_message_id;
+DROP TABLE link_previews;
@@ -0,0 +1,12 @@
+CREATE TABLE link_previews (
+    id INTEGER PRIMARY KEY NOT NULL,
+    message_id INTEGER NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    title TEXT,
+    description TEXT,
+    date TIMESTAMP,
+    -- The thumbnail is stored and downloaded like any other attachment of the message
+    attachment_id INTEGER REFERENCES attachments(id) ON DELETE SET NULL
+);
+
+CREATE INDEX link_previews_message_id ON link_previews(message_id);
@@ -0,0 +1,67 @@
+-- Received stickers do not necessarily belong to an installed sticker pack,
+-- so the attachment cannot reference the stickers table.
+-- Uninstalling a pack should not remove the stickers from the conversations either.
+CREATE TABLE new_attachments (
+    id INTEGER PRIMARY KEY NOT NULL,
+    json TEXT,
+    message_id INTEGER NOT NULL,
+    content_type TEXT DEFAULT "" NOT NULL,
+    name TEXT,
+    content_disposition TEXT,
+    content_location TEXT,
+    attachment_path TEXT,
+    is_pending_upload BOOLEAN DEFAULT FALSE NOT NULL,
+    transfer_file_path TEXT,
+    size INTEGER,
+    file_name TEXT,
+    unique_id TEXT,
+    digest TEXT,
+    is_voice_note BOOLEAN NOT NULL,
+    is_borderless BOOLEAN NOT NULL,
+    is_quote BOOLEAN NOT NULL,
+
+    width INTEGER,
+    height INTEGER,
+
+    sticker_pack_id TEXT DEFAULT NULL,
+    sticker_pack_key BLOB DEFAULT NULL,
+    sticker_id INTEGER DEFAULT NULL,
+    sticker_emoji TEXT DEFAULT NULL,
+
+    data_hash BLOB,
+    visual_hash TEXT,
+    transform_properties TEXT,
+
+    -- This is the encrypted file, used for resumable uploads (#107)
+    transfer_file TEXT,
+    display_order INTEGER DEFAULT 0 NOT NULL,
+    -- default is timestamp of the signal-2020 migration.
+    upload_timestamp TIMESTAMP DEFAULT "2021-02-14T18:05:49Z" NOT NULL,
+    cdn_number INTEGER DEFAULT 0,
+
+    caption TEXT DEFAULT NULL,
+    pointer BLOB DEFAULT NULL,
+    transcription TEXT DEFAULT NULL,
+    download_length INTEGER DEFAULT NULL,
+    original_path TEXT,
+
+    FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+INSERT INTO new_attachments
+    SELECT id, json, message_id, content_type, name, content_disposition,
+        content_location, attachment_path, is_pending_upload, transfer_file_path,
+        size, file_name, unique_id, digest, is_voice_note, is_borderless, is_quote,
+        width, height, sticker_pack_id, sticker_pack_key, sticker_id, sticker_emoji,
+        data_hash, visual_hash, transform_properties, transfer_file, display_order,
+        upload_timestamp, cdn_number, caption, pointer, transcription,
+        download_length, original_path
+    FROM attachments;
+
+DROP TABLE attachments;
+
+ALTER TABLE new_attachments RENAME TO attachments;
+
+CREATE INDEX attachment_message_id ON attachments(message_id);
+CREATE INDEX attachment_paths ON attachments(attachment_path ASC);
+CREATE INDEX attachment_sticker ON attachments(sticker_pack_id, sticker_id);
@@ -0,0 +1,27 @@
+-- Replies to a story refer to it; the reply stays when the story expires.
+ALTER TABLE messages
+    ADD COLUMN parent_story_id INTEGER DEFAULT NULL REFERENCES messages (id) ON DELETE SET NULL;
+
+CREATE INDEX messages_parent_story_id ON messages(parent_story_id);
+CREATE INDEX messages_story_type ON messages(story_type);
+
+-- Stories expire, so their sends should go along with them.
+CREATE TABLE new_story_sends (
+    message_id INTEGER NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    sent_timestamp TIMESTAMP NOT NULL,
+    allows_replies BOOLEAN NOT NULL,
+    distribution_id VARCHAR(36) NOT NULL REFERENCES distribution_lists(distribution_id) ON DELETE CASCADE,
+
+    PRIMARY KEY (message_id, session_id)
+);
+
+INSERT INTO new_story_sends
+    SELECT message_id, session_id, sent_timestamp, allows_replies, distribution_id
+    FROM story_sends;
+
+DROP TABLE story_sends;
+
+ALTER TABLE new_story_sends RENAME TO story_sends;
+
+CREATE INDEX story_sends_message_id_distribution_id_index ON story_sends(message_id, distribution_id);
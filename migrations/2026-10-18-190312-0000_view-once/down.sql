@@ -0,0 +1,2 @@
+ALTER TABLE messages DROP COLUMN viewed_timestamp;
+ALTER TABLE messages DROP COLUMN is_view_once;
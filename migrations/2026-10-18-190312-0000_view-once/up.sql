@@ -0,0 +1,4 @@
+-- View-once media is shown once; the attachments are gone after viewing.
+ALTER TABLE messages ADD COLUMN is_view_once BOOLEAN NOT NULL DEFAULT FALSE;
+-- When we viewed a voice note or view-once media ourselves.
+ALTER TABLE messages ADD COLUMN viewed_timestamp TIMESTAMP DEFAULT NULL;
@@ -0,0 +1,7 @@
+DROP TRIGGER messages_fts_attachment_delete;
+DROP TRIGGER messages_fts_attachment_update;
+DROP TRIGGER messages_fts_attachment_insert;
+DROP TRIGGER messages_fts_delete;
+DROP TRIGGER messages_fts_update;
+DROP TRIGGER messages_fts_insert;
+DROP TABLE messages_fts;
@@ -0,0 +1,64 @@
+-- Full-text index over message bodies and their attachments' captions and transcriptions.
+-- The rowid of the index is the message id.
+CREATE VIRTUAL TABLE messages_fts USING fts5(
+    body,
+    attachments,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO messages_fts (rowid, body, attachments)
+SELECT
+    messages.id,
+    COALESCE(messages.text, ''),
+    COALESCE((
+        SELECT group_concat(COALESCE(caption, '') || ' ' || COALESCE(transcription, ''), ' ')
+        FROM attachments
+        WHERE attachments.message_id = messages.id
+    ), '')
+FROM messages;
+
+CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages
+BEGIN
+    INSERT INTO messages_fts (rowid, body, attachments)
+    VALUES (NEW.id, COALESCE(NEW.text, ''), '');
+END;
+
+CREATE TRIGGER messages_fts_update AFTER UPDATE OF text ON messages
+BEGIN
+    UPDATE messages_fts SET body = COALESCE(NEW.text, '') WHERE rowid = NEW.id;
+END;
+
+CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages
+BEGIN
+    DELETE FROM messages_fts WHERE rowid = OLD.id;
+END;
+
+CREATE TRIGGER messages_fts_attachment_insert AFTER INSERT ON attachments
+BEGIN
+    UPDATE messages_fts SET attachments = COALESCE((
+        SELECT group_concat(COALESCE(caption, '') || ' ' || COALESCE(transcription, ''), ' ')
+        FROM attachments
+        WHERE attachments.message_id = NEW.message_id
+    ), '')
+    WHERE rowid = NEW.message_id;
+END;
+
+CREATE TRIGGER messages_fts_attachment_update AFTER UPDATE OF caption, transcription ON attachments
+BEGIN
+    UPDATE messages_fts SET attachments = COALESCE((
+        SELECT group_concat(COALESCE(caption, '') || ' ' || COALESCE(transcription, ''), ' ')
+        FROM attachments
+        WHERE attachments.message_id = NEW.message_id
+    ), '')
+    WHERE rowid = NEW.message_id;
+END;
+
+CREATE TRIGGER messages_fts_attachment_delete AFTER DELETE ON attachments
+BEGIN
+    UPDATE messages_fts SET attachments = COALESCE((
+        SELECT group_concat(COALESCE(caption, '') || ' ' || COALESCE(transcription, ''), ' ')
+        FROM attachments
+        WHERE attachments.message_id = OLD.message_id
+    ), '')
+    WHERE rowid = OLD.message_id;
+END;
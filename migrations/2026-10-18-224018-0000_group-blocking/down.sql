@@ -0,0 +1,2 @@
+ALTER TABLE group_v2s
+    DROP COLUMN is_blocked;
@@ -0,0 +1,2 @@
+ALTER TABLE group_v2s
+    ADD COLUMN is_blocked BOOLEAN DEFAULT FALSE NOT NULL;
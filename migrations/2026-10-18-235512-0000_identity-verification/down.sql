@@ -0,0 +1,2 @@
+ALTER TABLE identity_records
+    DROP COLUMN verified_status;
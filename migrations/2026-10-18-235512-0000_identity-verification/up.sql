@@ -0,0 +1,2 @@
+ALTER TABLE identity_records
+    ADD COLUMN verified_status INTEGER DEFAULT 0 NOT NULL;
@@ -0,0 +1,2 @@
+DROP TABLE message_send_log_recipients;
+DROP TABLE message_send_log;
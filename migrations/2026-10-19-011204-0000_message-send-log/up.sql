@@ -0,0 +1,17 @@
+-- Recently sent messages, kept around to answer retry receipts.
+CREATE TABLE message_send_log (
+    id INTEGER PRIMARY KEY NOT NULL,
+    sent_timestamp TIMESTAMP NOT NULL,
+    content BLOB NOT NULL
+);
+
+CREATE INDEX message_send_log_sent_timestamp ON message_send_log(sent_timestamp);
+
+CREATE TABLE message_send_log_recipients (
+    payload_id INTEGER NOT NULL REFERENCES message_send_log(id) ON DELETE CASCADE,
+    recipient_id INTEGER NOT NULL REFERENCES recipients(id) ON DELETE CASCADE,
+
+    PRIMARY KEY (payload_id, recipient_id)
+);
+
+CREATE INDEX message_send_log_recipients_recipient_id ON message_send_log_recipients(recipient_id);
@@ -0,0 +1,2 @@
+-- The language that was spoken in a transcribed voice note, as detected or selected.
+ALTER TABLE attachments ADD COLUMN transcription_language TEXT;
@@ -58,6 +58,7 @@ Item {
                                         || recorder.isRecording)
 
     signal sendMessage(var text, var attachments, var replyTo /* message id */, var isVoiceNote)
+    signal sendMessageLater(var text, var attachments, var replyTo /* message id */, var sendTime /* Date */)
     signal sendTypingNotification()
     signal sendTypingNotificationEnd()
     signal quotedMessageClicked(var messageId)
@@ -110,6 +111,20 @@ Item {
         if (clearAfterSend) reset()
     }
 
+    function _scheduleSend() {
+        Qt.inputMethod.commit()
+        if (text.length === 0 && attachments.length === 0) return
+        sendTimePicker.pick(null, function(sendTime) {
+            if(SettingsBridge.enable_enter_send) {
+                text = text.replace(/(\r\n\t|\n|\r\t)/gm, '')
+            }
+            sendMessageLater(text, attachments, quoteItem.messageId, sendTime)
+            if (clearAfterSend) reset()
+        })
+    }
+
+    SendTimePicker { id: sendTimePicker }
+
     function startRecording() {
         isVoiceNote = true;
         var path = "%1/Note_%2.%3"
@@ -484,9 +499,9 @@ Item {
                     }
                 }
                 onPressAndHold: {
-                    // TODO implement in backend
-                    if (canSend /*&& SettingsBridge.send_on_click === false*/) {
-                        _send()
+                    // Voice notes are always sent right away.
+                    if (canSend && !isVoiceNote) {
+                        _scheduleSend()
                         isTypingTimer.stop()
                         isNotTypingTimer.stop()
                     }
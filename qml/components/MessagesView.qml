@@ -258,6 +258,20 @@ SilicaListView {
     }
 
     verticalLayoutDirection: ListView.BottomToTop
+
+    // The model holds a window of the conversation: older messages are
+    // loaded at the top, newer ones at the bottom after jumping back in time.
+    onAtYBeginningChanged: {
+        if (atYBeginning && model && model.canFetchMore()) {
+            model.fetchMore()
+        }
+    }
+    onAtYEndChanged: {
+        if (atYEnd && model && model.canFetchNewer()) {
+            model.fetchNewer()
+        }
+    }
+
     quickScroll: true  // TODO how to only allow downwards?
     currentIndex: -1
     highlightFollowsCurrentItem: false
@@ -403,7 +417,8 @@ SilicaListView {
         if (messageId === undefined) {
             return
         }
-        var index = model.findMessageIndex(messageId)
+        // Loads the surrounding messages if the message is not loaded yet
+        var index = model.loadAround(messageId)
         if (index == -1) {
             return
         }
@@ -483,9 +498,27 @@ SilicaListView {
                 //: Transcribe message menu item
                 //% "Transcribe"
                 text: qsTrId("whisperfish-transcribe-message-menu")
-                visible: !!(menu.parent && !menu.parent.modelData.queued && menu.parent.modelData.isVoiceNote && dbusSpeechInterface.available)
+                visible: !!(menu.parent && !menu.parent.modelData.queued && menu.parent.modelData.isVoiceNote && transcriptionAvailable)
                 onClicked: transcribeInline(menu.parent)
             }
+            MenuItem {
+                //: Cancel the transcription of a voice note menu item
+                //% "Cancel transcription"
+                text: qsTrId("whisperfish-cancel-transcription-message-menu")
+                visible: !!(menu.parent && menu.parent.modelData.isVoiceNote && transcriptionAvailable)
+                onClicked: ClientWorker.cancelVoiceNoteTranscription(menu.parent.modelData.id)
+            }
+            MenuItem {
+                text: !!(menu.parent && menu.parent.modelData.starred)
+                      ? //: Unstar message menu item
+                        //% "Unstar"
+                        qsTrId("whisperfish-unstar-message-menu")
+                      : //: Star message menu item, to find it again among the starred messages
+                        //% "Star"
+                        qsTrId("whisperfish-star-message-menu")
+                visible: !!(menu.parent && !menu.parent.modelData.queued)
+                onClicked: MessageModel.star(menu.parent.modelData.id, !menu.parent.modelData.starred)
+            }
             MenuItem {
                 //: "Select and show more options" message menu item
                 //% "Select • more"
@@ -0,0 +1,61 @@
+import QtQuick 2.6
+
+// The plaintext file of an attachment, for consumers that cannot go through the
+// decrypting image provider, like media players and other applications.
+// Encrypted attachments are decrypted in the background; `path` is empty until then.
+QtObject {
+    id: root
+
+    property string source
+    // Decrypt as soon as the source is known, instead of on the first request()
+    property bool eager: false
+    readonly property string path: _path
+
+    property string _path: ""
+    property var _callbacks: []
+
+    // Calls back with the plaintext path, once it is there.
+    function request(callback) {
+        if (_path.length > 0) {
+            callback(_path)
+            return
+        }
+        _callbacks.push(callback)
+        _update()
+    }
+
+    function _update() {
+        _path = source.length > 0 ? AppState.plaintextAttachment(source) : ""
+        if (_path.length > 0) {
+            _finish()
+        }
+    }
+
+    function _finish() {
+        var callbacks = _callbacks
+        _callbacks = []
+        if (_path.length > 0) {
+            for (var i = 0; i < callbacks.length; i++) {
+                callbacks[i](_path)
+            }
+        }
+    }
+
+    onSourceChanged: {
+        _path = ""
+        _callbacks = []
+        if (eager) _update()
+    }
+    onEagerChanged: if (eager) _update()
+    Component.onCompleted: if (eager) _update()
+
+    property Connections _connections: Connections {
+        target: AppState
+        onPlaintextAttachmentReady: {
+            if (path === root.source) {
+                root._path = plaintext
+                root._finish()
+            }
+        }
+    }
+}
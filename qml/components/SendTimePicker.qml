@@ -0,0 +1,25 @@
+import QtQuick 2.6
+import Sailfish.Silica 1.0
+
+// Asks for the date and then the time at which a scheduled message is sent.
+QtObject {
+    id: root
+
+    function pick(initialDate, callback) {
+        var initial = initialDate ? initialDate : new Date(Date.now() + 60 * 60 * 1000)
+        var dateDialog = pageStack.push("Sailfish.Silica.DatePickerDialog", { date: initial })
+        dateDialog.accepted.connect(function() {
+            var day = dateDialog.date
+            pageStack.completeAnimation()
+            var timeDialog = pageStack.push("Sailfish.Silica.TimePickerDialog", {
+                hour: initial.getHours(),
+                minute: initial.getMinutes()
+            })
+            timeDialog.accepted.connect(function() {
+                var sendTime = new Date(day.getFullYear(), day.getMonth(), day.getDate(),
+                                        timeDialog.hour, timeDialog.minute)
+                callback(sendTime)
+            })
+        })
+    }
+}
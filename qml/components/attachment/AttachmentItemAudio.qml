@@ -9,6 +9,9 @@ import be.rubdos.whisperfish 1.0
 AttachmentItemBase {
     id: item
     property int recipientId: -1
+    property int messageId: -1
+    // The player needs the plaintext file
+    decryptEagerly: true
 
     Recipient {
         id: recipient
@@ -64,26 +67,28 @@ AttachmentItemBase {
 
     onClicked: {
         if (_effectiveEnableClick) {
-            pageStack.push(Qt.resolvedUrl('../../pages/ViewAudioPage.qml'), {
-                title: recipientId > -1 ? recipient.name : "",
-                subtitle: attach.is_voice_note
-                    //: Page header subtitle for a voice note
-                    //% "Voice Message"
-                    ? qsTrId('whisperfish-quoted-message-preview-voice-note')
-                    // Translated in QuotedMessagePreview.qml
-                    : qsTrId('whisperfish-quoted-message-preview-attachment'),
-                'titleOverlay.subtitleItem.wrapMode': SettingsBridge.debug_mode ? Text.Wrap : Text.NoWrap,
-                path: attach.data,
-                attachmentId: attach.id,
-                isViewOnce: false, // TODO: Implement attachment can only be viewed once
-                attachment: attach,
+            plaintext.request(function(path) {
+                pageStack.push(Qt.resolvedUrl('../../pages/ViewAudioPage.qml'), {
+                    title: recipientId > -1 ? recipient.name : "",
+                    subtitle: attach.is_voice_note
+                        //: Page header subtitle for a voice note
+                        //% "Voice Message"
+                        ? qsTrId('whisperfish-quoted-message-preview-voice-note')
+                        // Translated in QuotedMessagePreview.qml
+                        : qsTrId('whisperfish-quoted-message-preview-attachment'),
+                    'titleOverlay.subtitleItem.wrapMode': SettingsBridge.debug_mode ? Text.Wrap : Text.NoWrap,
+                    path: path,
+                    attachmentId: attach.id,
+                    isViewOnce: false, // TODO: Implement attachment can only be viewed once
+                    attachment: attach,
+                })
             })
         }
     }
 
     MediaPlayer {
         id: audioMessage
-        source: attach.is_downloaded ? attach.data : ""
+        source: plaintext.path
         // Qt 5.9+
         // notifyInterval: 20 // ms
     }
@@ -119,6 +124,9 @@ AttachmentItemBase {
                         audioMessage.pause();
                     } else {
                         audioMessage.play();
+                        if (attach.is_voice_note && messageId > -1) {
+                            MessageModel.markViewed(messageId)
+                        }
                     }
                 }
 
@@ -3,6 +3,7 @@
 import QtQuick 2.6
 import Sailfish.Silica 1.0
 import Nemo.Thumbnailer 1.0
+import ".."
 import "../../js/attachment.js" as Attachment
 
 MouseArea {
@@ -19,6 +20,9 @@ MouseArea {
     // check _effectiveEnableClick in derived types, not enableDefaultClickAction
     property bool _effectiveEnableClick: _hasAttach && attach.is_downloaded && enableDefaultClickAction
     property bool _hasAttach: attach != null
+    // Set to decrypt the attachment as soon as it is shown, instead of when it is opened
+    property bool decryptEagerly: false
+    property alias plaintext: plaintextAttachment
 
     function mimeToIcon(mimeType) {
         if (root.icon !== '') return root.icon
@@ -34,6 +38,12 @@ MouseArea {
         return path.substring(i+1);
     }
 
+    PlaintextAttachment {
+        id: plaintextAttachment
+        source: _hasAttach && attach.is_downloaded ? valueOrEmptyString(attach.data) : ''
+        eager: decryptEagerly
+    }
+
     Connections {
         target: attachments
         onDataChanged: {
@@ -67,7 +77,8 @@ MouseArea {
             Thumbnail {
                 id: thumb
                 anchors.fill: parent
-                source: (icon === '' && _hasAttach) ? valueOrEmptyString(attach.data) : ''
+                // The thumbnailer cannot read encrypted attachments
+                source: (icon === '' && _hasAttach && !AppState.isEncrypted()) ? valueOrEmptyString(attach.data) : ''
                 sourceSize { width: width; height: height }
             }
             HighlightImage {
@@ -7,7 +7,13 @@ import Sailfish.Silica 1.0
 AttachmentItemBase {
     id: item
     icon: 'image://theme/icon-m-contact'
-    onClicked: if (_effectiveEnableClick) pageStack.push('../../pages/ContactCardPage.qml', {vcfUrl: attach.data})
+    onClicked: {
+        if (_effectiveEnableClick) {
+            plaintext.request(function(path) {
+                pageStack.push('../../pages/ContactCardPage.qml', {vcfUrl: path})
+            })
+        }
+    }
 
     Column {
         anchors {
@@ -16,15 +16,17 @@ AttachmentItemBase {
 
     onClicked: {
         if (_effectiveEnableClick) {
-            pageStack.push(Qt.resolvedUrl('../../pages/ViewFilePage.qml'), {
-                title: recipientId > -1 ? recipient.name : "",
-                // Translated in QuotedMessagePreview.qml
-                subtitle: qsTrId('whisperfish-quoted-message-preview-attachment'),
-                'titleOverlay.subtitleItem.wrapMode': SettingsBridge.debug_mode ? Text.Wrap : Text.NoWrap,
-                path: attach.data,
-                attachmentId: attach.id,
-                isViewOnce: false, // TODO: Implement attachment can only be viewed once
-                attachment: attach,
+            plaintext.request(function(path) {
+                pageStack.push(Qt.resolvedUrl('../../pages/ViewFilePage.qml'), {
+                    title: recipientId > -1 ? recipient.name : "",
+                    // Translated in QuotedMessagePreview.qml
+                    subtitle: qsTrId('whisperfish-quoted-message-preview-attachment'),
+                    'titleOverlay.subtitleItem.wrapMode': SettingsBridge.debug_mode ? Text.Wrap : Text.NoWrap,
+                    path: path,
+                    attachmentId: attach.id,
+                    isViewOnce: false, // TODO: Implement attachment can only be viewed once
+                    attachment: attach,
+                })
             })
         }
     }
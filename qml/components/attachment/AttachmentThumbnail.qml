@@ -4,6 +4,7 @@ import QtQuick 2.6
 import Sailfish.Silica 1.0
 import Nemo.Thumbnailer 1.0
 import be.rubdos.whisperfish 1.0
+import ".."
 
 MouseArea {
     id: root
@@ -16,6 +17,19 @@ MouseArea {
     property bool _isAnimated: _hasAttach ? /\.(gif)$/i.test(attach.data) : false
     property bool _isVideo: _hasAttach ? /^video\//.test(attach.type) : false
     property bool _isAnimatedPaused: false
+    property bool _isViewOnce: message != null && message.isViewOnce === true
+    // The thumbnailer cannot read encrypted attachments, they are decoded by Whisperfish instead
+    property bool _isEncrypted: AppState.isEncrypted()
+    property bool _thumbnailReady: _isEncrypted
+        ? encryptedThumbnail.status == Image.Ready
+        : nemoThumbnail.status == Thumbnail.Ready
+
+    PlaintextAttachment {
+        id: plaintext
+        source: _hasAttach && attach.data != null ? attach.data : ''
+        // Animations are played from the plaintext file
+        eager: _isAnimated
+    }
 
     Recipient {
         id: recipient
@@ -44,21 +58,24 @@ MouseArea {
             if (_isVideo) {
                 var _debugMode = SettingsBridge.debug_mode
 
-                pageStack.push(Qt.resolvedUrl('../../pages/ViewVideoPage.qml'), {
-                    'sessionId': session.sessionId,
-                    'title': recipient.name,
-                    // TODO don't show the file path once attachments work reliably (#many)
-                    //      and attachments are saved in a WF-controlled directory (#253)
-                    'subtitle': attach.original_name != null && attach.original_name.length > 0
-                        ? attach.original_name
-                        : attach.data,
-                    // when not in debug mode, it is ok to fade the file path if it is too long
-                    'titleOverlay.subtitleItem.wrapMode': _debugMode ? Text.Wrap : Text.NoWrap,
-                    'path': attach.data,
-                    'originalPath': attach.original_path,
-                    'isAnimated': _isAnimated,
-                    'attachment': attach,
-                    'isViewOnce': false, // TODO: Implement attachment can only be viewed once
+                plaintext.request(function(path) {
+                    pageStack.push(Qt.resolvedUrl('../../pages/ViewVideoPage.qml'), {
+                        'sessionId': session.sessionId,
+                        'title': recipient.name,
+                        // TODO don't show the file path once attachments work reliably (#many)
+                        //      and attachments are saved in a WF-controlled directory (#253)
+                        'subtitle': attach.original_name != null && attach.original_name.length > 0
+                            ? attach.original_name
+                            : attach.data,
+                        // when not in debug mode, it is ok to fade the file path if it is too long
+                        'titleOverlay.subtitleItem.wrapMode': _debugMode ? Text.Wrap : Text.NoWrap,
+                        'path': path,
+                        'originalPath': attach.original_path,
+                        'isAnimated': _isAnimated,
+                        'attachment': attach,
+                        'isViewOnce': _isViewOnce,
+                        'viewOnceMessageId': _isViewOnce ? message.id : -1,
+                    })
                 })
             } else {
                 pageStack.push(Qt.resolvedUrl('../../pages/ViewImageGalleryPage.qml'), {
@@ -70,7 +87,8 @@ MouseArea {
                     'attachments': attachments,
                     'initialIndex': index,
                     'message': message,
-                    'isViewOnce': false, // TODO: Implement attachment can only be viewed once
+                    'isViewOnce': _isViewOnce,
+                    'viewOnceMessageId': _isViewOnce ? message.id : -1,
                 })
             }
         }
@@ -82,10 +100,10 @@ MouseArea {
     Thumbnail {
         id: nemoThumbnail
         visible: opacity > 0.0
-        opacity: (!_isAnimated && attach.data != null && (attach.visual_hash == null || status == Thumbnail.Ready)) ? 1.0 : 0.0
+        opacity: (!_isEncrypted && !_isAnimated && !_isViewOnce && attach.data != null && (attach.visual_hash == null || status == Thumbnail.Ready)) ? 1.0 : 0.0
         Behavior on opacity { NumberAnimation { duration: 250 } }
         width: parent.width; height: parent.height
-        source: (!_isAnimated && _hasAttach && attach.data != null) ? attach.data : ''
+        source: (!_isEncrypted && !_isAnimated && _hasAttach && attach.data != null) ? attach.data : ''
         sourceSize { width: width; height: height }
 
         onStatusChanged: {
@@ -99,10 +117,22 @@ MouseArea {
         }
     }
 
+    Image {
+        id: encryptedThumbnail
+        visible: opacity > 0.0
+        opacity: (_isEncrypted && !_isAnimated && !_isViewOnce && attach.data != null && (attach.visual_hash == null || status == Image.Ready)) ? 1.0 : 0.0
+        Behavior on opacity { NumberAnimation { duration: 250 } }
+        width: parent.width; height: parent.height
+        fillMode: Image.PreserveAspectCrop
+        source: (_isEncrypted && !_isAnimated && !_isVideo && _hasAttach && attach.data != null) ? AppState.attachmentImage(attach.data) : ''
+        sourceSize { width: width; height: height }
+        asynchronous: true
+    }
+
     Image {
         id: blurhashThumb
         visible: opacity > 0.0
-        opacity: (!_isAnimated && (nemoThumbnail.status != Thumbnail.Ready) && attach.visual_hash != null) ? 1.0 : 0.0
+        opacity: (!_isAnimated && (_isViewOnce || !_thumbnailReady) && attach.visual_hash != null) ? 1.0 : 0.0
         Behavior on opacity { NumberAnimation { duration: 250 } }
         width: parent.width; height: parent.height
         source: attach.visual_hash != null ? "image://blurhash/" + attach.visual_hash : "image://theme/icon-m-image"
@@ -142,7 +172,20 @@ MouseArea {
         id: animationLoader
         anchors.fill: parent
         asynchronous: true
-        sourceComponent: _isAnimated ? animatedComponent : null
+        sourceComponent: (_isAnimated && !_isViewOnce) ? animatedComponent : null
+    }
+
+    Label {
+        visible: _isViewOnce && attach.is_downloaded
+        anchors.centerIn: parent
+        width: parent.width - 2*Theme.paddingMedium
+        horizontalAlignment: Text.AlignHCenter
+        wrapMode: Text.Wrap
+        //: Shown instead of the thumbnail of view-once media, which is only shown once opened
+        //% "View once"
+        text: qsTrId("whisperfish-attachment-view-once")
+        font.pixelSize: Theme.fontSizeSmall
+        color: highlighted ? Theme.highlightColor : Theme.primaryColor
     }
 
     HighlightImage {
@@ -167,7 +210,7 @@ MouseArea {
             property int rounds: 0
             property int maxRounds: 2
             fillMode: Image.PreserveAspectCrop
-            source: _hasAttach ? attach.data : ''
+            source: plaintext.path
             onCurrentFrameChanged: if (currentFrame === 0) rounds++
             onRoundsChanged: {
                 if (rounds <= maxRounds) return
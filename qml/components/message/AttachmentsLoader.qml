@@ -358,6 +358,7 @@ Loader {
             attachments: detailAttachments
             index: currentAttachmentIndex
             recipientId: message.senderRecipientId
+            messageId: message.messageId
             onPressAndHold: root.pressAndHold(mouse)
         }
     }
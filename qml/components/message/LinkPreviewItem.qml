@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+import QtQuick 2.6
+import Sailfish.Silica 1.0
+
+BackgroundItem {
+    id: root
+    property string url
+    property string title
+    property string description
+    property string thumbnail
+
+    height: visible ? Math.max(textColumn.height, thumb.height) + 2*Theme.paddingSmall : 0
+
+    onClicked: Qt.openUrlExternally(url)
+
+    Image {
+        id: thumb
+        visible: thumbnail !== ""
+        source: thumbnail === "" ? ""
+            : AppState.isEncrypted() ? AppState.attachmentImage(thumbnail)
+            : "file://" + thumbnail
+        width: visible ? Theme.itemSizeLarge : 0
+        height: width
+        fillMode: Image.PreserveAspectCrop
+        asynchronous: true
+        anchors {
+            left: parent.left
+            verticalCenter: parent.verticalCenter
+        }
+    }
+
+    Column {
+        id: textColumn
+        spacing: Theme.paddingSmall / 2
+        anchors {
+            left: thumb.right; leftMargin: thumb.visible ? Theme.paddingMedium : 0
+            right: parent.right
+            verticalCenter: parent.verticalCenter
+        }
+
+        Label {
+            width: parent.width
+            visible: text !== ""
+            text: root.title
+            font.bold: true
+            font.pixelSize: Theme.fontSizeSmall
+            wrapMode: Text.Wrap
+            maximumLineCount: 2
+            truncationMode: TruncationMode.Elide
+            color: highlighted ? Theme.highlightColor : Theme.primaryColor
+        }
+        Label {
+            width: parent.width
+            visible: text !== ""
+            text: root.description
+            font.pixelSize: Theme.fontSizeExtraSmall
+            wrapMode: Text.Wrap
+            maximumLineCount: 3
+            truncationMode: TruncationMode.Elide
+            color: highlighted ? Theme.secondaryHighlightColor : Theme.secondaryColor
+        }
+        Label {
+            width: parent.width
+            text: root.url
+            font.pixelSize: Theme.fontSizeExtraSmall
+            truncationMode: TruncationMode.Fade
+            color: highlighted ? Theme.secondaryHighlightColor : Theme.secondaryColor
+        }
+    }
+}
@@ -88,11 +88,13 @@ ListItem {
     readonly property bool hasData: modelData != null
     readonly property bool hasReactions: hasData && modelData.reactions > 0
     readonly property bool hasQuotedMessage: modelData.quotedMessageId != -1 && !isRemoteDeleted
-    readonly property bool hasAttachments: hasData && modelData.attachments > 0 && !isRemoteDeleted
+    readonly property bool hasAttachments: hasData && modelData.attachments > 0 && !isRemoteDeleted && !isViewOnceClosed
+    // View-once media is gone after opening it, and we can't open what we sent ourselves.
+    readonly property bool isViewOnceClosed: hasData && modelData.isViewOnce && (modelData.outgoing || modelData.viewed)
     readonly property bool hasText: hasData && _message !== ''
     readonly property bool unidentifiedSender: modelData.unidentifiedSender !== undefined ? modelData.unidentifiedSender : true
     readonly property bool isOutbound: hasData && modelData.outgoing
-    readonly property bool isEmpty: !hasText && !hasAttachments
+    readonly property bool isEmpty: !hasText && !hasAttachments && !isViewOnceClosed
     readonly property bool isRemoteDeleted: hasData && ((isSelected && listView.appearDeleted) || modelData.remoteDeleted)
     property bool isExpanded: false
     property bool isSelected: listView !== null && listView.selectedMessages[modelData.id] !== undefined
@@ -256,6 +258,27 @@ ListItem {
 
         Item { width: 1; height: hasAttachments ? Theme.paddingSmall : 0 }
 
+        Label {
+            visible: isViewOnceClosed && !isRemoteDeleted
+            width: delegateContentWidth
+            horizontalAlignment: isOutbound ? Text.AlignRight : Text.AlignLeft
+            //: Placeholder for view-once media that was opened, or that we sent
+            //% "View-once media"
+            text: qsTrId("whisperfish-message-view-once-closed")
+            font.italic: true
+            color: isOutbound ? Theme.secondaryHighlightColor : Theme.secondaryColor
+        }
+
+        LinkPreviewItem {
+            visible: hasData && modelData.hasLinkPreview && !isRemoteDeleted
+            width: delegateContentWidth
+            url: visible ? modelData.linkPreviewUrl : ""
+            title: visible && modelData.linkPreviewTitle ? modelData.linkPreviewTitle : ""
+            description: visible && modelData.linkPreviewDescription ? modelData.linkPreviewDescription : ""
+            thumbnail: visible && modelData.linkPreviewThumbnail ? modelData.linkPreviewThumbnail : ""
+            enabled: listView !== null && !listView.isSelecting
+        }
+
         Column {
             id: contentColumn
             width: delegateContentWidth
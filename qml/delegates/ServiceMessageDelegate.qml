@@ -80,6 +80,10 @@ ListItem {
             return "image://theme/icon-s-blocked"
         case "contact":
             return "image://theme/icon-m-file-vcard"
+        case "number_change":
+        case "session_switchover":
+        case "thread_merge":
+            return "image://theme/icon-s-sync"
         // case "sticker":
         default:
             return ""
@@ -329,6 +333,26 @@ ListItem {
             //: Message with a contact card
             //% "Contact cards can't yet be supported"
             return qsTrId("whisperfish-service-message-contact")
+        case "number_change":
+            //: Service message, %1 is a name
+            //% "%1 changed their phone number."
+            return qsTrId("whisperfish-service-message-number-change").arg(recipientName)
+        case "session_switchover":
+            //: Service message, %1 is a phone number, %2 is a name
+            //% "%1 belongs to %2."
+            return modelData.message
+                ? qsTrId("whisperfish-service-message-session-switchover").arg(modelData.message).arg(recipientName)
+                //: Service message, %1 is a name
+                //% "Your messages with %1 are now sent to their account."
+                : qsTrId("whisperfish-service-message-session-switchover-no-number").arg(recipientName)
+        case "thread_merge":
+            //: Service message, %1 is a name, %2 is a phone number
+            //% "Your message history with %1 and their number %2 has been merged."
+            return modelData.message
+                ? qsTrId("whisperfish-service-message-thread-merge").arg(recipientName).arg(modelData.message)
+                //: Service message, %1 is a name
+                //% "Your message history with %1 and another chat that belonged to them has been merged."
+                : qsTrId("whisperfish-service-message-thread-merge-no-number").arg(recipientName)
         default:
             console.warn("Unsupported service message: id", modelData.id, "flags", modelData.flags, "type", _type, "text", modelData.message)
             //: Service message, %1 is an integer, %2 is a word, %3 is the message text (if any)
@@ -27,6 +27,11 @@ ApplicationWindow
 
     property alias contactsReady: resolvePeopleModel.populated
 
+    // Whether the configured transcription backend can transcribe voice notes
+    readonly property bool transcriptionAvailable: SettingsBridge.transcription_backend === "command"
+        ? SettingsBridge.transcription_command.trim() !== ""
+        : dbusSpeechInterface.available
+
     property string shareClientId: ""
     property string proofCaptchaToken: ''
 
@@ -143,7 +148,7 @@ ApplicationWindow
         }
 
         if (SettingsBridge.avatarExists(groupId)) {
-            return "file://" + SettingsBridge.avatar_dir + "/" + groupId
+            return AppState.attachmentImage(SettingsBridge.avatar_dir + "/" + groupId)
         } else {
             return ""
         }
@@ -167,7 +172,7 @@ ApplicationWindow
         var contact_avatar = (contact && contact.avatarPath) ? contact.avatarPath.toString() : ''
         var contact_avatar_ok = contact_avatar !== '' && contact_avatar.indexOf('image://theme/') !== 0
 
-        var signal_avatar = uuid !== undefined ? "file://" + SettingsBridge.avatar_dir + "/" + uuid : ''
+        var signal_avatar = uuid !== undefined ? AppState.attachmentImage(SettingsBridge.avatar_dir + "/" + uuid) : ''
         var signal_avatar_ok = uuid !== undefined ? SettingsBridge.avatarExists(uuid) : false
 
         if(signal_avatar_ok && contact_avatar_ok) {
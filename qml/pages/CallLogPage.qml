@@ -0,0 +1,109 @@
+import QtQuick 2.6
+import Sailfish.Silica 1.0
+import be.rubdos.whisperfish 1.0
+
+Page {
+    id: root
+    objectName: "callLogPage"
+
+    CallLog {
+        id: callLog
+        app: AppState
+    }
+
+    onStatusChanged: if (status === PageStatus.Active && callLog.unreadCount > 0) {
+        callLog.markAllRead()
+    }
+
+    SilicaListView {
+        id: listView
+        anchors.fill: parent
+        model: callLog.calls
+
+        header: PageHeader {
+            //: Title of the call log page
+            //% "Calls"
+            title: qsTrId("whisperfish-call-log-title")
+        }
+
+        PullDownMenu {
+            enabled: callLog.count > 0
+            MenuItem {
+                //: Call log menu item: remove all calls from the log
+                //% "Clear call log"
+                text: qsTrId("whisperfish-call-log-clear")
+                //: Remorse message while clearing the call log
+                //% "Clearing call log"
+                onClicked: Remorse.popupAction(root, qsTrId("whisperfish-call-log-clearing"), function() { callLog.clear() })
+            }
+        }
+
+        ViewPlaceholder {
+            enabled: callLog.count === 0
+            //: Placeholder of the empty call log
+            //% "No calls"
+            text: qsTrId("whisperfish-call-log-empty")
+        }
+
+        delegate: ListItem {
+            id: delegate
+            contentHeight: Theme.itemSizeMedium
+
+            property QtObject peer: Recipient {
+                app: AppState
+                recipientId: model.peerId
+            }
+
+            onClicked: pageStack.push(Qt.resolvedUrl("ConversationPage.qml"), { sessionId: model.sessionId })
+
+            Icon {
+                id: icon
+                anchors {
+                    left: parent.left
+                    leftMargin: Theme.horizontalPageMargin
+                    verticalCenter: parent.verticalCenter
+                }
+                source: model.isMissed
+                        ? "image://theme/icon-s-activity-missed-call"
+                        : model.outgoing
+                          ? "image://theme/icon-s-activity-outgoing-call"
+                          : "image://theme/icon-s-activity-incoming-call"
+            }
+
+            Column {
+                anchors {
+                    left: icon.right
+                    leftMargin: Theme.paddingMedium
+                    right: parent.right
+                    rightMargin: Theme.horizontalPageMargin
+                    verticalCenter: parent.verticalCenter
+                }
+
+                Label {
+                    width: parent.width
+                    truncationMode: TruncationMode.Fade
+                    font.bold: !model.isRead
+                    color: model.isMissed ? Theme.errorColor : Theme.primaryColor
+                    text: peer.valid ? getRecipientName(peer.e164, peer.externalId, peer.name, false) : ""
+                }
+
+                Label {
+                    width: parent.width
+                    truncationMode: TruncationMode.Fade
+                    font.pixelSize: Theme.fontSizeExtraSmall
+                    color: Theme.secondaryColor
+                    text: (model.isVideo
+                           //: Call log entry: a video call
+                           //% "Video call"
+                           ? qsTrId("whisperfish-call-log-video-call")
+                           //: Call log entry: a voice call
+                           //% "Voice call"
+                           : qsTrId("whisperfish-call-log-voice-call"))
+                          + " · " + Format.formatDate(model.timestamp, Formatter.TimepointRelative)
+                }
+            }
+        }
+
+        VerticalScrollDecorator {}
+    }
+}
@@ -26,7 +26,7 @@ Page {
     property bool _showDeleteAll: false
     // XXX handle group.group_change like a real client
     property bool _accepted: root.isGroup || !recipient.valid ? true : recipient.accepted
-    property bool _blocked: root.isGroup || !recipient.valid ? false : recipient.blocked
+    property bool _blocked: root.isGroup ? group.blocked : (!recipient.valid ? false : recipient.blocked)
     property bool _showInputPanel: true
     on_ShowInputPanelChanged: maybeShowPanel()
 
@@ -51,6 +51,12 @@ Page {
         }
     }
 
+    ScheduledMessages {
+        id: scheduledMessages
+        app: AppState
+        sessionId: root.sessionId
+    }
+
     Group {
         id: group
         app: AppState
@@ -295,13 +301,38 @@ Page {
         opacity: (actionsPanel.visibleSize > 0 || messages.menuOpen ||
                   messages.quickScrollAnimating) ? 0.0 : 1.0
         width: parent.width
-        height: textInput.height
+        height: textInput.height + scheduledHint.height
         open: true
         dock: Dock.Bottom
         onHeightChanged: if (open) show()
 
         Behavior on opacity { FadeAnimator { duration: 80 } }
 
+        BackgroundItem {
+            id: scheduledHint
+            width: parent.width
+            height: visible ? Theme.itemSizeExtraSmall : 0
+            anchors.bottom: textInput.top
+            visible: scheduledMessages.count > 0
+            onClicked: pageStack.push(Qt.resolvedUrl("ScheduledMessagesPage.qml"), { scheduledMessages: scheduledMessages })
+
+            Label {
+                anchors {
+                    left: parent.left
+                    right: parent.right
+                    leftMargin: Theme.horizontalPageMargin
+                    rightMargin: Theme.horizontalPageMargin
+                    verticalCenter: parent.verticalCenter
+                }
+                truncationMode: TruncationMode.Fade
+                font.pixelSize: Theme.fontSizeSmall
+                color: scheduledHint.highlighted ? Theme.highlightColor : Theme.secondaryHighlightColor
+                //: Shown above the message input when there are messages that will be sent later
+                //% "%n scheduled message(s)"
+                text: qsTrId("whisperfish-scheduled-messages-hint", scheduledMessages.count)
+            }
+        }
+
         ChatTextInput {
             id: textInput
             width: parent.width
@@ -329,6 +360,9 @@ Page {
                 console.log(JSON.stringify(attachments))
                 MessageModel.createMessage(sessionId, text, attachments, replyTo, true, isVoiceNote)
             }
+            onSendMessageLater: {
+                MessageModel.createScheduledMessage(sessionId, text, attachments, replyTo, false, sendTime.getTime())
+            }
             onSendTypingNotification: {
                 ClientWorker.send_typing_notification(sessionId, true)
             }
@@ -504,7 +538,7 @@ Page {
                                         //% "Transcribe %n message(s)"
                                         qsTrId("whisperfish-message-action-transcribe", _selectedCount)
                                     )
-                    visible: root.isLandscape && dbusSpeechInterface.available
+                    visible: root.isLandscape && transcriptionAvailable
                     onClicked: messages.messageAction(messages.transcribeSelected)
                 }
             }
@@ -565,7 +599,7 @@ Page {
                                         // Translation defined above
                                         qsTrId("whisperfish-message-action-transcribe", _selectedCount)
                                     )
-                    visible: dbusSpeechInterface.available
+                    visible: transcriptionAvailable
                     onClicked: messages.messageAction(messages.transcribeSelected)
                 }
             }
@@ -15,6 +15,8 @@ Page {
     property var detailAttachments: modelData.detailAttachments
     property int detailAttachmentCount: detailAttachments !== undefined ? detailAttachments.count : 0
 
+    PlaintextAttachment { id: textAttachment }
+
     Component.onCompleted: {
         var textFound = false
         var attachment = null
@@ -26,14 +28,17 @@ Page {
             }
         }
         if(textFound) {
-            var xhr = new XMLHttpRequest
-            xhr.open("GET", attachment.data)
-            xhr.onreadystatechange = function() {
-                if (xhr.readyState == XMLHttpRequest.DONE) {
-                    root.messageText = xhr.responseText
+            textAttachment.source = attachment.data
+            textAttachment.request(function(path) {
+                var xhr = new XMLHttpRequest
+                xhr.open("GET", path)
+                xhr.onreadystatechange = function() {
+                    if (xhr.readyState == XMLHttpRequest.DONE) {
+                        root.messageText = xhr.responseText
+                    }
                 }
-            }
-            xhr.send()
+                xhr.send()
+            })
         } else {
             root.messageText = modelData.message.trim()
         }
@@ -53,19 +53,32 @@ Page {
                 //: Leave group menu item
                 //% "Leave this group"
                 text: qsTrId("whisperfish-group-leave-menu")
+                visible: group.isGroupV2 && group.hasSelfAsMember
+                //: Leave group remorse message (past tense)
+                //% "Left group"
+                onClicked: remorse.execute(qsTrId("whisperfish-group-left-remorse"), function () {
+                    group.leave()
+                })
+            }
+            MenuItem {
+                text: group.blocked
+                    //: Unblock group menu item
+                    //% "Unblock this group"
+                    ? qsTrId("whisperfish-group-unblock-menu")
+                    //: Block group menu item
+                    //% "Block this group"
+                    : qsTrId("whisperfish-group-block-menu")
+                visible: group.isGroupV2
                 onClicked: {
-                    // TODO Leaving a group should *never* delete its messages.
-                    //      Two different destructive actions should require two different
-                    //      inputs and two confirmations.
-                    //      Is it enough to remove the 'remove' line?
-                    //: Leave group remorse message (past tense)
-                    //% "Left group and deleted all messages"
-                    remorse.execute(qsTrId("whisperfish-group-leave-remorse"), function () {
-                        console.log("Leaving group");
-                        MessageModel.leaveGroup();
-                        SessionModel.remove(sessionId);
-                        mainWindow.showMainPage();
-                    });
+                    if (group.blocked) {
+                        ClientWorker.handleGroupInvite(group.groupId, "accept")
+                    } else {
+                        //: Block group remorse message (past tense)
+                        //% "Blocked and left group"
+                        remorse.execute(qsTrId("whisperfish-group-blocked-remorse"), function () {
+                            ClientWorker.handleGroupInvite(group.groupId, "block")
+                        })
+                    }
                 }
             }
             MenuItem {
@@ -74,6 +87,24 @@ Page {
                 visible: youAreAdmin && session != null && groupProfile.newDuration !== session.expiringMessageTimeout
                 onClicked: MessageModel.createExpiryUpdate(sessionId, groupProfile.newDuration)
             }
+            MenuItem {
+                //: Reset group invite link menu item
+                //% "Reset invite link"
+                text: qsTrId("whisperfish-group-invite-link-reset-menu")
+                visible: youAreAdmin && group.inviteLink !== ""
+                //: Reset group invite link remorse message (past tense)
+                //% "Invite link reset"
+                onClicked: remorse.execute(qsTrId("whisperfish-group-invite-link-reset-remorse"), function () {
+                    group.resetInviteLink()
+                })
+            }
+            MenuItem {
+                //: Copy group invite link menu item
+                //% "Copy invite link"
+                text: qsTrId("whisperfish-group-invite-link-copy-menu")
+                visible: group.inviteLink !== ""
+                onClicked: Clipboard.text = group.inviteLink
+            }
             MenuItem {
                 // Translated in MainPage.qml
                 text: qsTrId("whisperfish-search-menu")
@@ -106,7 +137,7 @@ Page {
                 width: height
                 highlighted: false
                 labelsHighlighted: false
-                imageSource: !!session.groupId ? SettingsBridge.avatar_dir + "/" + session.groupId : ''
+                imageSource: !!session.groupId ? AppState.attachmentImage(SettingsBridge.avatar_dir + "/" + session.groupId) : ''
                 isGroup: true
                 showInfoMark: infoMarkSource !== ''
                 infoMarkSource: session.isGroupV2 ? '' : 'image://theme/icon-s-filled-warning'
@@ -157,8 +188,7 @@ Page {
             }
 
             ComboBox {
-                // XXX Sending group updates is not implemented
-                enabled: false
+                enabled: group.isGroupV2 && group.hasSelfAsMember && !group.isTerminated
                 // XXX Consider separate settings sub-page
                 //: Announcements only setting label
                 //% "Message sending allowed"
@@ -182,8 +212,7 @@ Page {
                 }
                 onCurrentIndexChanged: {
                     if ((currentIndex == 1) != group.isAnnouncementsOnly) {
-                        // XXX: sending group updates is not implemented
-                        console.log("announcements only mode", currentIndex == 1 ? "enabled" : "disabled")
+                        group.setAnnouncementsOnly(currentIndex == 1)
                     }
                 }
                 onAnnouncementsOnlyChanged: {
@@ -194,6 +223,115 @@ Page {
                     }
                 }
             }
+
+            ComboBox {
+                visible: group.isGroupV2 && group.hasSelfAsMember
+                enabled: youAreAdmin && !group.isTerminated
+                //: Group invite link setting label
+                //% "Invite link"
+                label: qsTrId("whisperfish-group-invite-link-label")
+                // Access required to join: 1 = any, 3 = administrator approval, 4 = disabled
+                property int access: group.inviteLinkAccess
+                readonly property var accessByIndex: [4, 1, 3]
+                currentIndex: Math.max(0, accessByIndex.indexOf(access))
+                menu: ContextMenu {
+                    MenuItem {
+                        //: Group invite link is disabled
+                        //% "Off"
+                        text: qsTrId("whisperfish-group-invite-link-off")
+                    }
+                    MenuItem {
+                        //: Anyone with the group invite link can join
+                        //% "Anyone can join"
+                        text: qsTrId("whisperfish-group-invite-link-any")
+                    }
+                    MenuItem {
+                        //: Joining through the group invite link requires approval by an administrator
+                        //% "Administrators approve new members"
+                        text: qsTrId("whisperfish-group-invite-link-approval")
+                    }
+                }
+                onCurrentIndexChanged: {
+                    if (accessByIndex[currentIndex] != access) {
+                        group.setInviteLinkAccess(accessByIndex[currentIndex])
+                    }
+                }
+                onAccessChanged: currentIndex = Math.max(0, accessByIndex.indexOf(access))
+            }
+
+            Label {
+                visible: group.hasSelfAsPendingMember
+                x: Theme.horizontalPageMargin
+                width: parent.width - 2 * Theme.horizontalPageMargin
+                wrapMode: Text.Wrap
+                color: Theme.highlightColor
+                //: Shown on the profile of a group we are invited to
+                //% "You have been invited to this group."
+                text: qsTrId("whisperfish-group-invitation-label")
+            }
+
+            ButtonLayout {
+                visible: group.hasSelfAsPendingMember
+                Button {
+                    //: Accept an invitation to a group
+                    //% "Accept"
+                    text: qsTrId("whisperfish-group-invitation-accept")
+                    onClicked: group.acceptInvitation()
+                }
+                Button {
+                    //: Decline an invitation to a group
+                    //% "Decline"
+                    text: qsTrId("whisperfish-group-invitation-decline")
+                    onClicked: group.declineInvitation()
+                }
+            }
+
+            SectionHeader {
+                visible: youAreAdmin && requestingMembers.count > 0
+                //: Section header for users that asked to join the group through its invite link
+                //% "Requests to join"
+                text: qsTrId("whisperfish-group-requesting-members-header")
+            }
+
+            Repeater {
+                id: requestingMembers
+                model: youAreAdmin ? group.requestingMembers : null
+                delegate: ListItem {
+                    id: requestingItem
+                    contentHeight: Theme.itemSizeSmall
+                    property string name: getRecipientName(model.e164, model.externalId, model.name, false)
+
+                    Label {
+                        anchors {
+                            verticalCenter: parent.verticalCenter
+                            left: parent.left
+                            right: parent.right
+                            leftMargin: Theme.horizontalPageMargin
+                            rightMargin: Theme.horizontalPageMargin
+                        }
+                        truncationMode: TruncationMode.Fade
+                        // Translated in SessionDelegate.qml
+                        text: requestingItem.name.length > 0 ? requestingItem.name : qsTrId("whisperfish-recipient-no-name")
+                    }
+
+                    menu: Component {
+                        ContextMenu {
+                            MenuItem {
+                                //: Let a user that asked to join into the group
+                                //% "Approve"
+                                text: qsTrId("whisperfish-group-requesting-member-approve")
+                                onClicked: group.approveRequest(model.id)
+                            }
+                            MenuItem {
+                                //: Refuse a user that asked to join the group
+                                //% "Deny"
+                                text: qsTrId("whisperfish-group-requesting-member-deny")
+                                onClicked: group.denyRequest(model.id)
+                            }
+                        }
+                    }
+                }
+            }
         }
 
         GroupMemberListView {
@@ -0,0 +1,189 @@
+import QtQuick 2.2
+import Sailfish.Silica 1.0
+import "../components"
+
+Page {
+    id: joinGroupPage
+    objectName: "joinGroupPage"
+
+    property alias link: linkField.text
+
+    property bool previewing: false
+    property bool previewed: false
+    property bool joining: false
+    property string title: ""
+    property string description: ""
+    property int memberCount: 0
+    property bool requiresApproval: false
+    property bool pendingApproval: false
+    property string error: ""
+
+    function isInviteLink(s) {
+        return /^(https:\/\/|sgnl:\/\/)signal\.group\/#[A-Za-z0-9_=-]+$/.test(s.trim())
+    }
+
+    function preview() {
+        previewed = false
+        error = ""
+        if (isInviteLink(link)) {
+            previewing = true
+            ClientWorker.previewGroupInviteLink(link)
+        }
+    }
+
+    onLinkChanged: preview()
+    Component.onCompleted: preview()
+
+    Connections {
+        target: ClientWorker
+        onGroupInvitePreviewed: {
+            if (!previewing) {
+                return
+            }
+            previewing = false
+            previewed = true
+            joinGroupPage.title = title
+            joinGroupPage.description = description
+            memberCount = member_count
+            requiresApproval = requires_approval
+            pendingApproval = pending_approval
+        }
+        onGroupInvitePreviewFailed: {
+            if (!previewing) {
+                return
+            }
+            previewing = false
+            //: Group invite link could not be previewed
+            //% "This group link is not valid or no longer active."
+            error = qsTrId("whisperfish-join-group-preview-failed")
+        }
+        onGroupV2Joined: {
+            if (!joining) {
+                return
+            }
+            joining = false
+            if (pending_approval) {
+                pendingApproval = true
+            } else {
+                pageStack.replace(Qt.resolvedUrl("ConversationPage.qml"), { sessionId: session_id })
+            }
+        }
+        onGroupV2JoinFailed: {
+            if (!joining) {
+                return
+            }
+            joining = false
+            //: Joining a group through its invite link failed
+            //% "Failed to join the group"
+            error = qsTrId("whisperfish-join-group-failed")
+        }
+    }
+
+    SilicaFlickable {
+        anchors.fill: parent
+        contentHeight: column.height
+
+        Column {
+            id: column
+            width: parent.width
+            spacing: Theme.paddingMedium
+
+            PageHeader {
+                //: Join group page title
+                //% "Join group"
+                title: qsTrId("whisperfish-join-group-title")
+            }
+
+            TextField {
+                id: linkField
+                width: parent.width
+                enabled: !joining
+                inputMethodHints: Qt.ImhUrlCharactersOnly | Qt.ImhNoPredictiveText
+                //: Group invite link text field label
+                //% "Group link"
+                label: qsTrId("whisperfish-join-group-link-label")
+                placeholderText: "https://signal.group/#..."
+            }
+
+            BusyIndicator {
+                anchors.horizontalCenter: parent.horizontalCenter
+                size: BusyIndicatorSize.Medium
+                running: previewing || joining
+                visible: running
+            }
+
+            Label {
+                visible: previewed
+                x: Theme.horizontalPageMargin
+                width: parent.width - 2 * Theme.horizontalPageMargin
+                wrapMode: Text.Wrap
+                font.pixelSize: Theme.fontSizeLarge
+                color: Theme.highlightColor
+                text: joinGroupPage.title
+            }
+
+            Label {
+                visible: previewed
+                x: Theme.horizontalPageMargin
+                width: parent.width - 2 * Theme.horizontalPageMargin
+                color: Theme.secondaryHighlightColor
+                //: Number of members of a group that is previewed through its invite link
+                //% "%n member(s)"
+                text: qsTrId("whisperfish-join-group-member-count", memberCount)
+            }
+
+            Label {
+                visible: previewed && joinGroupPage.description !== ""
+                x: Theme.horizontalPageMargin
+                width: parent.width - 2 * Theme.horizontalPageMargin
+                wrapMode: Text.Wrap
+                font.pixelSize: Theme.fontSizeSmall
+                text: joinGroupPage.description
+            }
+
+            Label {
+                visible: previewed && (requiresApproval || pendingApproval)
+                x: Theme.horizontalPageMargin
+                width: parent.width - 2 * Theme.horizontalPageMargin
+                wrapMode: Text.Wrap
+                font.pixelSize: Theme.fontSizeSmall
+                color: Theme.secondaryHighlightColor
+                text: pendingApproval
+                    //: Shown after asking to join a group, while an administrator has to approve
+                    //% "Your request to join is waiting for approval by an administrator."
+                    ? qsTrId("whisperfish-join-group-pending-approval")
+                    //: Shown when joining a group through its invite link requires approval
+                    //% "An administrator has to approve your request to join."
+                    : qsTrId("whisperfish-join-group-requires-approval")
+            }
+
+            Label {
+                visible: error !== ""
+                x: Theme.horizontalPageMargin
+                width: parent.width - 2 * Theme.horizontalPageMargin
+                wrapMode: Text.Wrap
+                color: Theme.errorColor
+                text: error
+            }
+
+            Button {
+                anchors.horizontalCenter: parent.horizontalCenter
+                visible: previewed && !pendingApproval
+                enabled: !joining
+                text: requiresApproval
+                    //: Button to ask the administrators of a group to join it
+                    //% "Request to join"
+                    ? qsTrId("whisperfish-join-group-request-button")
+                    //: Button to join a group through its invite link
+                    //% "Join group"
+                    : qsTrId("whisperfish-join-group-button")
+                onClicked: {
+                    error = ""
+                    joining = true
+                    ClientWorker.joinGroupV2(link)
+                }
+            }
+        }
+        VerticalScrollDecorator {}
+    }
+}
@@ -97,6 +97,27 @@ Page {
                 visible: !SetupWorker.locked
                 onClicked: pageStack.push(Qt.resolvedUrl("CreateConversationPage.qml"), { query: "" })
             }
+            MenuItem {
+                //: Whisperfish main menu item: join a group through its invite link
+                //% "Join group"
+                text: qsTrId("whisperfish-join-group-menu")
+                visible: !SetupWorker.locked
+                onClicked: pageStack.push(Qt.resolvedUrl("JoinGroupPage.qml"), { link: Clipboard.text.indexOf("signal.group/#") >= 0 ? Clipboard.text : "" })
+            }
+            MenuItem {
+                //: Whisperfish main menu item: show the call log
+                //% "Calls"
+                text: qsTrId("whisperfish-call-log-menu")
+                visible: !SetupWorker.locked
+                onClicked: pageStack.push(Qt.resolvedUrl("CallLogPage.qml"))
+            }
+            MenuItem {
+                //: Whisperfish main menu item: show the starred messages of all conversations
+                //% "Starred messages"
+                text: qsTrId("whisperfish-starred-messages-menu")
+                visible: !SetupWorker.locked
+                onClicked: pageStack.push(Qt.resolvedUrl("StarredMessagesPage.qml"))
+            }
             MenuItem {
                 text: "Call test"
                 visible: SetupWorker.callingSupported && SettingsBridge.debug_mode
@@ -14,9 +14,34 @@ Page {
     property Label errorLabel
 
     property var selectedContacts: recipientField.selectedContacts
+    property string firstMessage: ""
+    property bool creating: false
 
     _clickablePageIndicators: !(isLandscape && recipientField.activeFocus)
 
+    Connections {
+        target: ClientWorker
+        onGroupV2Created: {
+            if (!creating) {
+                return
+            }
+            creating = false
+            if (firstMessage !== "") {
+                MessageModel.createMessage(session_id, firstMessage, [], -1, true, false)
+            }
+            pageStack.pop()
+        }
+        onGroupV2CreationFailed: {
+            if (!creating) {
+                return
+            }
+            creating = false
+            //: Group creation failed error
+            //% "Failed to create the group"
+            errorLabel.text = qsTrId("whisperfish-error-group-creation-failed")
+        }
+    }
+
     SilicaFlickable {
         id: newGroup
         focus: true
@@ -95,12 +120,11 @@ Page {
                 width: parent.width
                 enablePersonalizedPlaceholder: false
                 showSeparator: false
-                enableSending: selectedContacts.count > 0 && groupName.text != ""
-                clearAfterSend: selectedContacts.count > 0 && groupName.text != ""
+                enableSending: !creating && selectedContacts.count > 0 && groupName.text != ""
+                clearAfterSend: !creating && selectedContacts.count > 0 && groupName.text != ""
                 enableAttachments: false // TODO support attachments
 
                 onSendMessage: {
-                    // TODO rewrite
                     // TODO support attachments
                     if (selectedContacts.count === 0) {
                         //: Invalid recipient error
@@ -123,8 +147,10 @@ Page {
                         }
                         var source = numbers.join(",")
                         console.log("Creating group for " + source);
-                        MessageModel.createMessage(source, text, groupName.text, "", false, isVoiceNote)
-                        pageStack.pop()
+                        firstMessage = text
+                        creating = true
+                        errorLabel.text = ""
+                        ClientWorker.createGroupV2(groupName.text, "", "", 0, source)
                     }
                 }
             }
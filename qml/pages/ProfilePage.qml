@@ -168,7 +168,7 @@ Page {
                 width: height
                 highlighted: false
                 labelsHighlighted: false
-                imageSource: "file://" + SettingsBridge.avatar_dir + "/" + recipient.uuid
+                imageSource: AppState.attachmentImage(SettingsBridge.avatar_dir + "/" + recipient.uuid)
                 isGroup: false
                 showInfoMark: true
                 infoMarkSource: 'image://theme/icon-s-chat'
@@ -139,7 +139,7 @@ Page {
                 width: height
                 highlighted: false
                 labelsHighlighted: false
-                imageSource: "file://" + SettingsBridge.avatar_dir + "/" + recipient.uuid
+                imageSource: AppState.attachmentImage(SettingsBridge.avatar_dir + "/" + recipient.uuid)
                 isGroup: false
                 showInfoMark: true
                 infoMarkSource: 'image://theme/icon-s-chat'
@@ -366,6 +366,46 @@ Page {
                 text: qsTrId("whisperfish-numeric-fingerprint-directions").arg(recipient.name)
             }
 
+            IconTextSwitch {
+                id: isVerified
+                automaticCheck: false
+                visible: recipient.verifiedStatus !== 2 && numericFingerprint.text.length > 0
+                anchors.horizontalCenter: parent.horizontalCenter
+                //: Profile page: whether the safety number of a contact was verified
+                //% "Verified"
+                text: qsTrId("whisperfish-profile-verified")
+                //: Profile page: description for marking a contact as verified
+                //% "Mark as verified after comparing the numbers above with the numbers on their device."
+                description: qsTrId("whisperfish-profile-verified-description")
+                checked: recipient.verifiedStatus === 1
+                icon.source: "image://theme/icon-m-acknowledge"
+                onClicked: recipient.markVerified(!checked)
+            }
+
+            Label {
+                visible: recipient.verifiedStatus === 2
+                anchors {
+                    left: parent.left
+                    right: parent.right
+                    leftMargin: Theme.paddingLarge
+                    rightMargin: Theme.paddingLarge
+                }
+                wrapMode: Text.Wrap
+                color: Theme.errorColor
+                //: Profile page: the safety number of a verified contact has changed
+                //% "The safety number with %1 has changed since you verified it. Messages will not be sent until you accept the new safety number."
+                text: qsTrId("whisperfish-profile-verified-identity-changed").arg(recipient.name)
+            }
+
+            Button {
+                visible: recipient.verifiedStatus === 2
+                anchors.horizontalCenter: parent.horizontalCenter
+                //: Profile page: button to accept the changed safety number of a verified contact
+                //% "Accept new safety number"
+                text: qsTrId("whisperfish-profile-accept-identity-change")
+                onClicked: recipient.markVerified(false)
+            }
+
             IconTextSwitch {
                 automaticCheck: false
                 visible: false
@@ -0,0 +1,130 @@
+import QtQuick 2.6
+import Sailfish.Silica 1.0
+import be.rubdos.whisperfish 1.0
+import "../components"
+
+Page {
+    id: root
+    objectName: "scheduledMessagesPage"
+
+    property QtObject scheduledMessages
+
+    SendTimePicker { id: sendTimePicker }
+
+    Component {
+        id: editDialog
+        Dialog {
+            property alias text: editor.text
+            Column {
+                width: parent.width
+                DialogHeader {
+                    //: Dialog title: change the text of a message that is not sent yet
+                    //% "Edit message"
+                    title: qsTrId("whisperfish-scheduled-messages-edit-title")
+                }
+                TextArea {
+                    id: editor
+                    width: parent.width
+                    focus: true
+                }
+            }
+        }
+    }
+
+    SilicaListView {
+        id: listView
+        anchors.fill: parent
+        model: scheduledMessages ? scheduledMessages.messages : null
+
+        header: PageHeader {
+            //: Title of the page listing the messages that will be sent later
+            //% "Scheduled messages"
+            title: qsTrId("whisperfish-scheduled-messages-title")
+        }
+
+        ViewPlaceholder {
+            enabled: listView.count === 0
+            //: Placeholder when no messages are scheduled
+            //% "No scheduled messages"
+            text: qsTrId("whisperfish-scheduled-messages-empty")
+        }
+
+        delegate: ListItem {
+            id: delegate
+            contentHeight: column.height + 2 * Theme.paddingMedium
+
+            menu: ContextMenu {
+                MenuItem {
+                    //: Scheduled message menu item: send the message right away
+                    //% "Send now"
+                    text: qsTrId("whisperfish-scheduled-messages-send-now")
+                    onClicked: scheduledMessages.reschedule(model.id, Date.now())
+                }
+                MenuItem {
+                    //: Scheduled message menu item: pick another send time
+                    //% "Reschedule"
+                    text: qsTrId("whisperfish-scheduled-messages-reschedule")
+                    onClicked: {
+                        var messageId = model.id
+                        sendTimePicker.pick(model.scheduleSendTime, function(sendTime) {
+                            scheduledMessages.reschedule(messageId, sendTime.getTime())
+                        })
+                    }
+                }
+                MenuItem {
+                    //: Scheduled message menu item: change the text
+                    //% "Edit"
+                    text: qsTrId("whisperfish-scheduled-messages-edit")
+                    onClicked: {
+                        var messageId = model.id
+                        var dialog = pageStack.push(editDialog, { text: model.message })
+                        dialog.accepted.connect(function() {
+                            scheduledMessages.edit(messageId, dialog.text)
+                        })
+                    }
+                }
+                MenuItem {
+                    //: Scheduled message menu item: do not send the message
+                    //% "Cancel message"
+                    text: qsTrId("whisperfish-scheduled-messages-cancel")
+                    //: Remorse message while cancelling a scheduled message
+                    //% "Cancelling message"
+                    onClicked: {
+                        var messageId = model.id
+                        delegate.remorseAction(qsTrId("whisperfish-scheduled-messages-cancelling"),
+                                               function() { scheduledMessages.cancel(messageId) })
+                    }
+                }
+            }
+
+            Column {
+                id: column
+                anchors {
+                    left: parent.left
+                    right: parent.right
+                    leftMargin: Theme.horizontalPageMargin
+                    rightMargin: Theme.horizontalPageMargin
+                    verticalCenter: parent.verticalCenter
+                }
+
+                Label {
+                    width: parent.width
+                    wrapMode: Text.Wrap
+                    maximumLineCount: 3
+                    elide: Text.ElideRight
+                    text: model.message
+                }
+
+                Label {
+                    width: parent.width
+                    truncationMode: TruncationMode.Fade
+                    font.pixelSize: Theme.fontSizeExtraSmall
+                    color: Theme.secondaryColor
+                    text: Format.formatDate(model.scheduleSendTime, Formatter.TimepointRelative)
+                }
+            }
+        }
+
+        VerticalScrollDecorator {}
+    }
+}
@@ -224,7 +224,7 @@ Page {
                     id: messageLabel
 
                     visible: true
-                    plainText: cssStyle + modelData.text
+                    plainText: cssStyle + modelData.snippet
                     bypassLinking: true
                     needsRichText: true
                     wrapMode: Text.Wrap
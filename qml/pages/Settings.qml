@@ -150,7 +150,7 @@ Page {
                 //% "Link previews"
                 text: qsTrId("whisperfish-settings-enable-link-previews")
                 //: Settings page enable link previews description
-                //% "Create and send previews of the links you send in messages. Note: Feature not yet implemented in Whisperfish."
+                //% "Create and send previews of the links you send in messages. The linked websites are visited through the Signal content proxy."
                 description: qsTrId("whisperfish-settings-enable-link-previews-description")
                 checked: SettingsBridge.enable_link_previews
                 icon.source: "image://theme/icon-m-website"
@@ -341,7 +341,10 @@ Page {
                 //: Settings page auto transcribe voice notes
                 //% "Transcribe voice notes"
                 text: qsTrId("whisperfish-transcribe-voice-notes-label")
-                description: dbusSpeechInterface.available ?
+                description: SettingsBridge.transcription_backend === "command" ?
+                    //: Auto transcribe voice notes description, using a local transcription command
+                    //% "Automatically transcribe voice notes to text upon reception using the transcription command."
+                    qsTrId("whisperfish-transcribe-voice-notes-description-command") : dbusSpeechInterface.available ?
                     //: Auto transcribe voice notes description, Speech Note installed
                     //% "Automatically transcribe voice notes to text upon reception using Speech Note."
                     qsTrId("whisperfish-transcribe-voice-notes-description-available") : (dbusSpeechInterface.installed ?
@@ -353,7 +356,7 @@ Page {
                     qsTrId("whisperfish-transcribe-voice-notes-description-uninstalled")
                 )
                 checked: SettingsBridge.transcribe_voice_notes
-                enabled: dbusSpeechInterface.available
+                enabled: transcriptionAvailable
                 icon.source: "image://theme/icon-m-file-note-dark"
                 onCheckedChanged: {
                     if(checked != SettingsBridge.transcribe_voice_notes) {
@@ -361,6 +364,74 @@ Page {
                     }
                 }
             }
+            ComboBox {
+                id: transcriptionBackendCombo
+                visible: !AppState.isHarbour()
+                property string transcription_backend: SettingsBridge.transcription_backend.toString()
+                width: parent.width
+                //: Settings page, which program transcribes voice notes
+                //% "Transcription backend"
+                label: qsTrId("whisperfish-settings-transcription-backend-text")
+                menu: ContextMenu {
+                    MenuItem {
+                        property string value: "speech-note"
+                        //: Settings page, transcribe voice notes with Speech Note
+                        //% "Speech Note"
+                        text: qsTrId("whisperfish-settings-transcription-backend-speech-note")
+                    }
+                    MenuItem {
+                        property string value: "command"
+                        //: Settings page, transcribe voice notes with a local command, e.g. whisper.cpp
+                        //% "Command"
+                        text: qsTrId("whisperfish-settings-transcription-backend-command")
+                    }
+                }
+                onCurrentItemChanged: {
+                    if (currentItem != null && transcription_backend != currentItem.value) {
+                        SettingsBridge.transcription_backend = currentItem.value
+                    }
+                }
+                Component.onCompleted: {
+                    var i = ["speech-note", "command"].indexOf(transcription_backend)
+                    transcriptionBackendCombo.currentIndex = Math.max(i, 0)
+                }
+            }
+            TextField {
+                visible: !AppState.isHarbour() && SettingsBridge.transcription_backend === "command"
+                width: parent.width
+                inputMethodHints: Qt.ImhNoPredictiveText | Qt.ImhNoAutoUppercase
+                //: Settings page, command line that transcribes a voice note
+                //% "Transcription command"
+                label: qsTrId("whisperfish-settings-transcription-command")
+                //: Settings page, placeholder of the transcription command; {file} and {language} are replaced
+                //% "e.g. whisper-cli -m model.bin -nt -l {language} -f {file}"
+                placeholderText: qsTrId("whisperfish-settings-transcription-command-placeholder")
+                text: SettingsBridge.transcription_command
+                EnterKey.iconSource: "image://theme/icon-m-enter-close"
+                EnterKey.onClicked: focus = false
+                onActiveFocusChanged: {
+                    if (!activeFocus && text != SettingsBridge.transcription_command) {
+                        SettingsBridge.transcription_command = text
+                    }
+                }
+            }
+            TextField {
+                visible: !AppState.isHarbour()
+                width: parent.width
+                inputMethodHints: Qt.ImhNoPredictiveText | Qt.ImhNoAutoUppercase
+                validator: RegExpValidator { regExp: /auto|[a-zA-Z]{2,3}/ }
+                //: Settings page, spoken language of voice notes, as a language code or "auto"
+                //% "Transcription language (e.g. en, or auto)"
+                label: qsTrId("whisperfish-settings-transcription-language")
+                text: SettingsBridge.transcription_language
+                EnterKey.iconSource: "image://theme/icon-m-enter-close"
+                EnterKey.onClicked: focus = false
+                onActiveFocusChanged: {
+                    if (!activeFocus && acceptableInput && text != SettingsBridge.transcription_language) {
+                        SettingsBridge.transcription_language = text
+                    }
+                }
+            }
             // ------ END GENERAL SETTINGS ------
 
             // ------ BEGIN PRIVACY SETTINGS ------
@@ -0,0 +1,125 @@
+import QtQuick 2.6
+import Sailfish.Silica 1.0
+import be.rubdos.whisperfish 1.0
+import "../components"
+
+Page {
+    id: root
+    objectName: "starredMessagesPage"
+
+    StarredMessages {
+        id: starredMessages
+        app: AppState
+    }
+
+    function goToMessage(targetSessionId, targetMessageId) {
+        var mainPage = pageStack.find(function (page) {
+            return page.objectName == "mainPage";
+        })
+        pageStack.replaceAbove(mainPage, Qt.resolvedUrl("ConversationPage.qml"), {
+            sessionId: targetSessionId,
+            targetMessageId: targetMessageId
+        })
+    }
+
+    SilicaListView {
+        id: listView
+        anchors.fill: parent
+        model: starredMessages.messages
+
+        header: PageHeader {
+            //: Title of the page listing the starred messages of all conversations
+            //% "Starred messages"
+            title: qsTrId("whisperfish-starred-messages-title")
+        }
+
+        ViewPlaceholder {
+            enabled: starredMessages.count === 0
+            //: Placeholder when no messages are starred
+            //% "No starred messages"
+            text: qsTrId("whisperfish-starred-messages-empty")
+            //: Hint on how to star a message
+            //% "Star messages from their menu in a conversation to find them here"
+            hintText: qsTrId("whisperfish-starred-messages-empty-hint")
+        }
+
+        delegate: ListItem {
+            id: delegate
+            contentHeight: column.height + 2 * Theme.paddingMedium
+
+            property QtObject session: Session {
+                app: AppState
+                sessionId: model.sessionId
+            }
+            property QtObject peer: Recipient {
+                app: AppState
+                recipientId: session.valid && !session.isGroup ? session.recipientId : -1
+            }
+            property QtObject sender: Recipient {
+                app: AppState
+                recipientId: model.outgoing ? -1 : model.senderRecipientId
+            }
+            property string chatName: session.isGroup
+                                      ? session.groupName
+                                      : (peer.valid ? getRecipientName(peer.e164, peer.externalId, peer.name, false) : "")
+            property string senderName: model.outgoing
+                                        ? // Translated in SearchPage.qml
+                                          qsTrId("whisperfish-sender-name-label-outgoing")
+                                        : (sender.valid ? getRecipientName(sender.e164, sender.externalId, sender.name, false) : "")
+
+            onClicked: goToMessage(model.sessionId, model.id)
+
+            menu: ContextMenu {
+                MenuItem {
+                    // Translated in MessagesView.qml
+                    text: qsTrId("whisperfish-unstar-message-menu")
+                    onClicked: starredMessages.unstar(model.id)
+                }
+            }
+
+            Column {
+                id: column
+                anchors {
+                    left: parent.left
+                    right: parent.right
+                    leftMargin: Theme.horizontalPageMargin
+                    rightMargin: Theme.horizontalPageMargin
+                    verticalCenter: parent.verticalCenter
+                }
+
+                Label {
+                    width: parent.width
+                    truncationMode: TruncationMode.Fade
+                    font.pixelSize: Theme.fontSizeExtraSmall
+                    font.bold: true
+                    text: session.isGroup ? senderName + " (" + chatName + ")" : senderName
+                }
+
+                Label {
+                    width: parent.width
+                    visible: text.length > 0
+                    wrapMode: Text.Wrap
+                    maximumLineCount: 4
+                    elide: Text.ElideRight
+                    font.pixelSize: Theme.fontSizeSmall
+                    text: model.message ? model.message : ""
+                }
+
+                Label {
+                    width: parent.width
+                    truncationMode: TruncationMode.Fade
+                    font.pixelSize: Theme.fontSizeExtraSmall
+                    color: Theme.secondaryColor
+                    text: (model.attachments > 0
+                           ? //: Number of attachments of a starred message
+                             //% "%n attachment(s)"
+                             qsTrId("whisperfish-starred-messages-attachments", model.attachments) + " · "
+                           : "")
+                          + Format.formatDate(model.timestamp, Formatter.TimepointRelative)
+                }
+            }
+        }
+
+        VerticalScrollDecorator {}
+    }
+}
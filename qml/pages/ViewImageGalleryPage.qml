@@ -30,6 +30,7 @@ Page {
     property int initialIndex: 0
     property var message
     property bool isViewOnce: false
+    property int viewOnceMessageId: -1
     property var currentAttach: null
 
     allowedOrientations: Orientation.All
@@ -83,6 +84,10 @@ Page {
     }
 
     onStatusChanged: {
+        if (page.status === PageStatus.Deactivating && isViewOnce && viewOnceMessageId > -1) {
+            // View-once media can't be opened again.
+            MessageModel.markViewed(viewOnceMessageId)
+        }
         if (page.status === PageStatus.Inactive) {
             var item = swipeView.currentItem;
             if (item && item._pzi && item._pzi.imageReady)
@@ -147,10 +152,18 @@ Page {
                 property bool isCurrentImage: swipeView.currentIndex === index
                 property alias _pzi: _pinchZoomImage
 
+                PlaintextAttachment {
+                    id: plaintextImage
+                    source: attachments.get(imageIndexes[index]).data || ''
+                    eager: _pinchZoomImage.isAnimated
+                }
+
                 PinchZoomImage {
                     id: _pinchZoomImage
                     anchors.fill: parent
-                    source: attachments.get(imageIndexes[index]).data
+                    source: isAnimated
+                        ? plaintextImage.path
+                        : AppState.attachmentImage(attachments.get(imageIndexes[index]).data)
                     isAnimated: attachments.get(imageIndexes[index]).type === "image/gif"
                     paused: page.status !== PageStatus.Active
                             || !Qt.application.active
@@ -29,10 +29,18 @@ Page {
     property bool enableDarkBackground: true
     property var attachment
     property bool isViewOnce
+    property int viewOnceMessageId: -1
 
     property bool _isPlaying: video.playbackState === MediaPlayer.PlayingState
     property string _errorString
 
+    onStatusChanged: {
+        if (status === PageStatus.Deactivating && isViewOnce && viewOnceMessageId > -1) {
+            // View-once media can't be opened again.
+            MessageModel.markViewed(viewOnceMessageId)
+        }
+    }
+
     Loader {
         sourceComponent: enableDarkBackground ? backgroundComponent : null
         anchors.fill: parent
@@ -63,6 +63,9 @@ fn fetch_augmented_messages(c: &mut Criterion) {
                     story_type: orm::StoryType::None,
                     body_ranges: None,
                     message_type: None,
+                    parent_story_id: None,
+                    is_view_once: false,
+                    schedule_send_time: None,
                     edit: None,
                     expire_timer_version: 1,
                     expiry_started: None,
@@ -40,6 +40,7 @@ diesel::table! {
         transcription -> Nullable<Text>,
         download_length -> Nullable<Integer>,
         original_path -> Nullable<Text>,
+        transcription_language -> Nullable<Text>,
     }
 }
 
@@ -155,6 +156,36 @@ diesel::table! {
         announcement_only -> Bool,
         access_required_for_member_labels -> Integer,
         terminated -> Bool,
+        storage_service_id -> Nullable<Binary>,
+        storage_proto -> Nullable<Binary>,
+        is_blocked -> Bool,
+    }
+}
+
+diesel::table! {
+    link_previews (id) {
+        id -> Integer,
+        message_id -> Integer,
+        url -> Text,
+        title -> Nullable<Text>,
+        description -> Nullable<Text>,
+        date -> Nullable<Timestamp>,
+        attachment_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    message_send_log (id) {
+        id -> Integer,
+        sent_timestamp -> Timestamp,
+        content -> Binary,
+    }
+}
+
+diesel::table! {
+    message_send_log_recipients (payload_id, recipient_id) {
+        payload_id -> Integer,
+        recipient_id -> Integer,
     }
 }
 
@@ -189,6 +220,9 @@ diesel::table! {
         revision_number -> Integer,
         message_type -> Nullable<MessageTypeMapping>,
         expire_timer_version -> Integer,
+        parent_story_id -> Nullable<Integer>,
+        is_view_once -> Bool,
+        viewed_timestamp -> Nullable<Timestamp>,
     }
 }
 
@@ -311,6 +345,10 @@ diesel::joinable!(group_v2_members -> group_v2s (group_v2_id));
 diesel::joinable!(group_v2_members -> recipients (recipient_id));
 diesel::joinable!(group_v2_pending_members -> group_v2s (group_v2_id));
 diesel::joinable!(group_v2_requesting_members -> group_v2s (group_v2_id));
+diesel::joinable!(link_previews -> attachments (attachment_id));
+diesel::joinable!(link_previews -> messages (message_id));
+diesel::joinable!(message_send_log_recipients -> message_send_log (payload_id));
+diesel::joinable!(message_send_log_recipients -> recipients (recipient_id));
 diesel::joinable!(messages -> recipients (sender_recipient_id));
 diesel::joinable!(messages -> sessions (session_id));
 diesel::joinable!(reactions -> messages (message_id));
@@ -336,6 +374,9 @@ diesel::allow_tables_to_appear_in_same_query!(
     group_v2_pending_members,
     group_v2_requesting_members,
     group_v2s,
+    link_previews,
+    message_send_log,
+    message_send_log_recipients,
     messages,
     reactions,
     receipts,
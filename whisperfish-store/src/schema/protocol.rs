@@ -8,6 +8,7 @@ diesel::table! {
         address -> Text,
         record -> Binary,
         identity -> IdentityMapping,
+        verified_status -> Integer,
     }
 }
 
@@ -1,15 +1,32 @@
 pub mod orm;
 
+pub mod attachment_download;
+pub mod backup;
 pub mod body_ranges;
 mod calls;
+pub mod contacts_sync;
+pub mod delete_for_me;
+pub mod edits;
+mod encrypted_files;
 mod encryption;
+pub mod groups;
 #[cfg(feature = "diesel-instrumentation")]
 mod instrumentation;
+mod link_previews;
 pub mod migrations;
 pub mod observer;
+pub mod pagination;
 mod protocol_store;
 mod protos;
 mod recipient_merge;
+mod scheduled_messages;
+pub mod search;
+pub mod send_log;
+mod starred_messages;
+mod stickers;
+pub mod storage_service;
+pub mod stories;
+mod stream_cipher;
 mod utils;
 
 use self::orm::{AugmentedMessage, MessageType, StoryType, UnidentifiedAccessMode};
@@ -37,10 +54,13 @@ use libsignal_service::{
     prelude::*,
     protocol::{Aci, Pni, ServiceIdKind},
 };
+use link_previews::link_preview_thumbnails;
 use phonenumber::PhoneNumber;
 pub use protocol_store::AciOrPniStorage;
 use protocol_store::ProtocolStore;
 use recipient_merge::*;
+pub use starred_messages::StarredChange;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fs::File;
 use std::panic::AssertUnwindSafe;
@@ -70,6 +90,7 @@ impl Settings {
     pub const MASTER_KEY: &'static str = "master_key";
     pub const STORAGE_SERVICE_KEY: &'static str = "storage_service_key";
     pub const ACCOUNT_ENTROPY_POOL: &'static str = "account_entropy_pool";
+    pub const STORAGE_MANIFEST: &'static str = "storage_manifest";
 
     pub const VERBOSE: &'static str = "verbose";
 }
@@ -152,6 +173,10 @@ pub struct NewMessage<'a> {
     pub story_type: StoryType,
     pub body_ranges: Option<Vec<u8>>,
     pub message_type: Option<MessageType>,
+    pub parent_story_id: Option<i32>,
+    pub is_view_once: bool,
+    /// When set, the message is held back until this time, instead of being sent right away.
+    pub schedule_send_time: Option<NaiveDateTime>,
 
     pub edit: Option<&'a orm::Message>,
 }
@@ -177,6 +202,9 @@ impl NewMessage<'_> {
             story_type: StoryType::None,
             body_ranges: None,
             message_type: None,
+            parent_story_id: None,
+            is_view_once: false,
+            schedule_send_time: None,
             edit: None,
         }
     }
@@ -201,6 +229,9 @@ impl NewMessage<'_> {
             story_type: StoryType::None,
             body_ranges: None,
             message_type: None,
+            parent_story_id: None,
+            is_view_once: false,
+            schedule_send_time: None,
             edit: None,
         }
     }
@@ -485,18 +516,7 @@ impl<O: Observable + Default> Storage<O> {
     ) -> Result<Self, anyhow::Error> {
         let path: &Path = std::ops::Deref::deref(db_path);
 
-        let store_enc = if let Some(password) = password {
-            // Get storage and db salt
-            let storage_salt = utils::read_salt_file(path.join("storage").join("salt")).await?;
-            let db_salt = utils::read_salt_file(path.join("db").join("salt")).await?;
-
-            Some(
-                encryption::StorageEncryption::new(password.to_string(), storage_salt, db_salt)
-                    .await?,
-            )
-        } else {
-            None
-        };
+        let store_enc = Self::read_storage_encryption(path, password).await?;
 
         let db = Self::open_db(db_path, store_enc.as_ref().map(|x| x.get_database_key()))
             .await
@@ -566,8 +586,27 @@ impl<O: Observable> Storage<O> {
         Ok(())
     }
 
+    /// Derives the storage encryption of an existing storage from its salts.
+    async fn read_storage_encryption(
+        path: &Path,
+        password: Option<String>,
+    ) -> Result<Option<encryption::StorageEncryption>, anyhow::Error> {
+        let Some(password) = password else {
+            return Ok(None);
+        };
+
+        // Get storage and db salt
+        let storage_salt = utils::read_salt_file(path.join("storage").join("salt")).await?;
+        let db_salt = utils::read_salt_file(path.join("db").join("salt")).await?;
+
+        Ok(Some(
+            encryption::StorageEncryption::new(password, storage_salt, db_salt).await?,
+        ))
+    }
+
+    /// Opens the database and unlocks it, without running migrations.
     #[tracing::instrument]
-    async fn open_db<T: AsRef<Path> + Debug>(
+    fn connect_db<T: AsRef<Path> + Debug>(
         db_path: &StorageLocation<T>,
         database_key: Option<&[u8]>,
     ) -> anyhow::Result<SqliteConnection, anyhow::Error> {
@@ -611,6 +650,16 @@ impl<O: Observable> Storage<O> {
         // XXX: Do we have to signal somehow that the password was wrong?
         //      Offer retries?
 
+        Ok(db)
+    }
+
+    #[tracing::instrument]
+    async fn open_db<T: AsRef<Path> + Debug>(
+        db_path: &StorageLocation<T>,
+        database_key: Option<&[u8]>,
+    ) -> anyhow::Result<SqliteConnection, anyhow::Error> {
+        let mut db = Self::connect_db(db_path, database_key)?;
+
         // Run migrations.
         // We execute the transactions without foreign key checking enabled.
         // This is because foreign_keys=OFF implies that foreign key references are
@@ -958,6 +1007,38 @@ impl<O: Observable> Storage<O> {
             .collect()
     }
 
+    /// The group identifiers of the blocked groups, as used in the `Blocked` sync message.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_blocked_group_v2_ids(&self) -> Vec<Vec<u8>> {
+        use crate::schema::group_v2s::dsl::*;
+        let ids: Vec<String> = schema::group_v2s::table
+            .select(id)
+            .filter(is_blocked.eq(true))
+            .load(&mut *self.db())
+            .expect("db");
+        ids.into_iter()
+            .filter_map(|group_id| hex::decode(group_id).ok())
+            .collect()
+    }
+
+    /// Block or unblock a group; messages in a blocked group are dropped.
+    #[tracing::instrument(skip(self))]
+    pub fn mark_group_v2_blocked(&self, group_v2_id: &str, blocked: bool) {
+        use crate::schema::group_v2s::dsl::*;
+
+        let affected_rows =
+            diesel::update(group_v2s.filter(id.eq(group_v2_id).and(is_blocked.ne(blocked))))
+                .set(is_blocked.eq(blocked))
+                .execute(&mut *self.db())
+                .expect("db");
+        if affected_rows > 0 {
+            self.observe_update(schema::group_v2s::table, group_v2_id.to_string());
+            if let Some(session) = self.fetch_session_by_group_v2_id(group_v2_id) {
+                self.observe_update(schema::sessions::table, session.id);
+            }
+        }
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn mark_recipient_needs_pni_signature(&self, recipient: &orm::Recipient, val: bool) {
         use crate::schema::recipients::dsl::*;
@@ -1446,7 +1527,7 @@ impl<O: Observable> Storage<O> {
         aci: Option<Aci>,
         pni: Option<Pni>,
     ) -> orm::Recipient {
-        let (id, changed) = self
+        let (id, changed, _events) = self
             .db()
             .transaction::<_, diesel::result::Error, _>(|db| {
                 merge_and_fetch_recipient_inner(
@@ -1483,7 +1564,7 @@ impl<O: Observable> Storage<O> {
         pni: Option<Pni>,
         trust_level: TrustLevel,
     ) -> orm::Recipient {
-        let (id, changed) = self
+        let (id, changed, events) = self
             .db()
             .transaction::<_, Error, _>(|db| {
                 merge_and_fetch_recipient_inner(
@@ -1502,12 +1583,48 @@ impl<O: Observable> Storage<O> {
         if changed {
             self.observe_update(crate::schema::recipients::table, id);
         }
+        if !events.is_empty() {
+            self.insert_recipient_events(events);
+        }
 
         tracing::trace!("Fetched recipient: {}", recipient);
 
         recipient
     }
 
+    /// Shows number changes and merged conversations in the direct conversations they concern.
+    fn insert_recipient_events(&self, events: Vec<RecipientEvent>) {
+        let self_id = self.fetch_self_recipient().map(|r| r.id);
+        for event in events {
+            tracing::debug!(?event, "inserting recipient event");
+            let recipient_id = event.recipient_id();
+            if Some(recipient_id) == self_id {
+                continue;
+            }
+            let Some(recipient) = self.fetch_recipient_by_id(recipient_id) else {
+                continue;
+            };
+            let Some(session) = self.fetch_session_by_recipient_id(recipient_id) else {
+                continue;
+            };
+            let (message_type, e164) = match event {
+                RecipientEvent::NumberChange { .. } => (MessageType::NumberChange, None),
+                RecipientEvent::SessionSwitchover { e164, .. } => {
+                    (MessageType::SessionSwitchover, e164)
+                }
+                RecipientEvent::ThreadMerge { e164, .. } => (MessageType::ThreadMerge, e164),
+            };
+            self.create_message(&NewMessage {
+                session_id: session.id,
+                source_addr: recipient.to_service_address(),
+                text: e164.map(|e164| e164.to_string()).unwrap_or_default(),
+                is_read: true,
+                message_type: Some(message_type),
+                ..NewMessage::new_incoming()
+            });
+        }
+    }
+
     #[tracing::instrument(skip(self, addr), fields(addr = addr.service_id_string()))]
     pub fn fetch_or_insert_recipient_by_address(&self, addr: &ServiceId) -> orm::Recipient {
         use crate::schema::recipients::dsl::*;
@@ -1581,6 +1698,8 @@ impl<O: Observable> Storage<O> {
         use schema::messages;
         messages::table
             .filter(messages::session_id.eq(session_id))
+            .filter(messages::story_type.eq(StoryType::None))
+            .filter(messages::schedule_send_time.is_null())
             .order_by(messages::server_timestamp.desc())
             .first(&mut *self.db())
             .ok()
@@ -1801,6 +1920,118 @@ impl<O: Observable> Storage<O> {
         pointers
     }
 
+    /// Marks our messages with the given timestamps as viewed by a certain person.
+    ///
+    /// Viewed receipts are sent for voice notes and view-once media.
+    #[tracing::instrument(skip(self, sender), fields(sender = sender.service_id_string()))]
+    pub fn mark_messages_viewed(
+        &self,
+        sender: ServiceId,
+        timestamps: Vec<NaiveDateTime>,
+        viewed_at: NaiveDateTime,
+    ) -> Vec<MessagePointer> {
+        let rcpt = self.merge_and_fetch_recipient_by_address(None, sender, TrustLevel::Certain);
+
+        let pointers: Vec<MessagePointer> = schema::messages::table
+            .select((
+                schema::messages::id,
+                schema::messages::session_id,
+                schema::messages::server_timestamp,
+            ))
+            .filter(schema::messages::server_timestamp.eq_any(timestamps))
+            .filter(schema::messages::is_outbound.eq(true))
+            .load::<(i32, i32, NaiveDateTime)>(&mut *self.db())
+            .expect("db")
+            .into_iter()
+            .map(|(m_id, s_id, ts)| MessagePointer {
+                message_id: m_id,
+                session_id: s_id,
+                timestamp: ts,
+            })
+            .collect();
+
+        for pointer in pointers.iter() {
+            // A viewed message was delivered and read before, so the row is likely present
+            let mut affected = diesel::update(schema::receipts::table)
+                .filter(
+                    schema::receipts::message_id
+                        .eq(pointer.message_id)
+                        .and(schema::receipts::recipient_id.eq(rcpt.id))
+                        .and(schema::receipts::viewed.is_null()),
+                )
+                .set(schema::receipts::viewed.eq(viewed_at))
+                .execute(&mut *self.db())
+                .map_err(|e| {
+                    tracing::error!("Could not update viewed receipt: {}", e);
+                    e
+                })
+                .unwrap_or(0);
+
+            // SQLite doesn't support SupportsOnConflictClauseWhere so we have to resort to two queries
+            if affected == 0 {
+                affected += diesel::insert_into(schema::receipts::table)
+                    .values((
+                        schema::receipts::message_id.eq(pointer.message_id),
+                        schema::receipts::recipient_id.eq(rcpt.id),
+                        schema::receipts::viewed.eq(viewed_at),
+                    ))
+                    .on_conflict((schema::receipts::message_id, schema::receipts::recipient_id))
+                    .do_nothing()
+                    .execute(&mut *self.db())
+                    .map_err(|e| {
+                        tracing::error!("Could not save viewed receipt: {}", e);
+                        e
+                    })
+                    .unwrap_or(0);
+            }
+
+            if affected > 1 {
+                tracing::warn!("Viewed receipt update affected {} rows", affected);
+            }
+            if affected > 0 {
+                self.observe_upsert(schema::receipts::table, PrimaryKey::Unknown)
+                    .with_relation(schema::messages::table, pointer.message_id)
+                    .with_transitive_relation(
+                        schema::messages::table,
+                        schema::sessions::table,
+                        pointer.session_id,
+                    )
+                    .with_relation(schema::recipients::table, rcpt.id);
+            }
+        }
+
+        pointers
+    }
+
+    /// Mark a voice note or view-once message as viewed by ourselves.
+    ///
+    /// View-once media is shown only once, so its attachments are deleted.
+    /// Returns the message if it was not viewed before.
+    #[tracing::instrument(skip(self))]
+    pub fn mark_message_viewed(
+        &mut self,
+        message_id: i32,
+        viewed_at: NaiveDateTime,
+    ) -> Option<orm::Message> {
+        let message: orm::Message = diesel::update(schema::messages::table)
+            .filter(schema::messages::id.eq(message_id))
+            .filter(schema::messages::viewed_timestamp.is_null())
+            .set(schema::messages::viewed_timestamp.eq(viewed_at))
+            .get_result(&mut *self.db())
+            .optional()
+            .expect("db")?;
+
+        if message.is_view_once {
+            let n_attachments = self.delete_attachments_for_message(message.id);
+            tracing::trace!("Deleted {n_attachments} view-once attachment file(s)");
+        }
+
+        self.observe_update(schema::messages::table, message.id)
+            .with_relation(schema::sessions::table, message.session_id);
+
+        Some(message)
+    }
+
     /// Handle marking multiple messages as read and potentially starting their expiry timer.
     #[tracing::instrument(skip(self))]
     pub fn mark_messages_read_in_ui(&self, msg_ids: Vec<i32>) {
@@ -2023,11 +2254,13 @@ impl<O: Observable> Storage<O> {
             .unwrap()
     }
 
+    /// Fetch the attachments of a message, excluding link preview thumbnails.
     #[tracing::instrument(skip(self))]
     pub fn fetch_attachments_for_message(&self, mid: i32) -> Vec<orm::Attachment> {
         use schema::attachments::dsl::*;
         attachments
             .filter(message_id.eq(mid))
+            .filter(id.ne_all(link_preview_thumbnails()))
             .order_by(display_order.asc())
             .load(&mut *self.db())
             .unwrap()
@@ -2228,6 +2461,56 @@ impl<O: Observable> Storage<O> {
             .expect("db")
     }
 
+    /// Fetch the members that asked to join a GroupV2 through its invite link.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_group_v2_requesting_members(
+        &self,
+        id: &str,
+    ) -> Vec<(orm::GroupV2RequestingMember, orm::Recipient)> {
+        let requesting: Vec<orm::GroupV2RequestingMember> =
+            schema::group_v2_requesting_members::table
+                .filter(schema::group_v2_requesting_members::group_v2_id.eq(id))
+                .order(schema::group_v2_requesting_members::timestamp.asc())
+                .load(&mut *self.db())
+                .expect("db");
+        requesting
+            .into_iter()
+            .filter_map(|member| {
+                let service_id = ServiceId::parse_from_service_id_string(&member.aci)?;
+                let recipient = self.fetch_or_insert_recipient_by_address(&service_id);
+                Some((member, recipient))
+            })
+            .collect()
+    }
+
+    /// Fetch our own invitation to a GroupV2, addressed to either our ACI or our PNI.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_group_v2_self_pending_member(
+        &self,
+        gv2_id: &str,
+    ) -> Option<orm::GroupV2PendingMember> {
+        let self_service_ids: Vec<String> = [
+            self.config
+                .get_aci()
+                .map(|aci| Aci::from(aci).service_id_string()),
+            self.config
+                .get_pni()
+                .map(|pni| Pni::from(pni).service_id_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        schema::group_v2_pending_members::table
+            .filter(
+                schema::group_v2_pending_members::group_v2_id
+                    .eq(gv2_id)
+                    .and(schema::group_v2_pending_members::service_id.eq_any(self_service_ids)),
+            )
+            .first(&mut *self.db())
+            .optional()
+            .expect("db")
+    }
+
     #[tracing::instrument(skip(self, group_v2))]
     pub fn fetch_group_v2_banned_member(
         &self,
@@ -2470,6 +2753,11 @@ impl<O: Observable> Storage<O> {
             announcement_only: false,
 
             terminated: false,
+
+            storage_service_id: None,
+            storage_proto: None,
+
+            is_blocked: false,
         };
 
         // Group does not exist, insert first.
@@ -2719,7 +3007,8 @@ impl<O: Observable> Storage<O> {
             schema::messages::table.filter(
                 schema::messages::session_id
                     .eq(session_id)
-                    .and(schema::messages::is_read.eq(false)),
+                    .and(schema::messages::is_read.eq(false))
+                    .and(schema::messages::story_type.eq(StoryType::None)),
             ),
         )
         .set(schema::messages::is_read.eq(true))
@@ -3020,7 +3309,7 @@ impl<O: Observable> Storage<O> {
             0
         };
 
-        let latest_message: orm::Message = {
+        let mut latest_message: orm::Message = {
             use schema::messages::dsl::*;
             diesel::insert_into(messages)
                 .values((
@@ -3049,6 +3338,9 @@ impl<O: Observable> Storage<O> {
                     expire_timer_version.eq(new_message.expire_timer_version),
                     expiry_started.eq(new_message.expiry_started),
                     story_type.eq(new_message.story_type as i32),
+                    parent_story_id.eq(new_message.parent_story_id),
+                    is_view_once.eq(new_message.is_view_once),
+                    schedule_send_time.eq(new_message.schedule_send_time),
                     message_ranges.eq(&new_message.body_ranges),
                     original_message_id.eq(edit_id),
                     revision_number.eq(computed_revision),
@@ -3068,7 +3360,7 @@ impl<O: Observable> Storage<O> {
         // Then we process the edit
         if let Some(edit) = &new_message.edit {
             tracing::trace!("Message was an edit, updating old messages");
-            let ids: Vec<i32> = {
+            let revisions: Vec<(i32, bool)> = {
                 use schema::messages::dsl::*;
                 diesel::update(messages)
                     .filter(
@@ -3081,16 +3373,26 @@ impl<O: Observable> Storage<O> {
                         // Set the latest revision id to the new inserted message
                         latest_revision_id.eq(latest_message.id),
                     ))
-                    .returning(id)
+                    .returning((id, is_bookmarked))
                     .load(&mut *self.db())
                     .expect("update edited messages")
             };
-            let affected_rows = ids.len();
+            let affected_rows = revisions.len();
             assert!(
                 affected_rows >= 1,
                 "Did not update any message. Dazed and confused."
             );
-            for id in ids {
+            // A starred message stays starred when it is edited.
+            if revisions.iter().any(|(_, starred)| *starred) {
+                diesel::update(schema::messages::table)
+                    .filter(schema::messages::id.eq(latest_message.id))
+                    .set(schema::messages::is_bookmarked.eq(true))
+                    .execute(&mut *self.db())
+                    .expect("star edited message");
+                latest_message.is_bookmarked = true;
+                self.observe_event(latest_message.id, vec![], StarredChange { starred: true });
+            }
+            for (id, _) in revisions {
                 self.observe_update(schema::messages::table, id)
                     .with_relation(schema::sessions::table, session);
             }
@@ -3109,12 +3411,37 @@ impl<O: Observable> Storage<O> {
         latest_message
     }
 
+    /// Stores the (partial) transcription of a voice note, and the spoken language once it is
+    /// known.
     #[tracing::instrument(skip(self))]
-    pub fn update_transcription(&mut self, attachment_id: i32, new_transcription: &str) {
+    pub fn update_transcription(
+        &self,
+        attachment_id: i32,
+        new_transcription: &str,
+        language: Option<&str>,
+    ) {
+        self.set_transcription(attachment_id, Some(new_transcription), language);
+    }
+
+    /// Removes the transcription of a voice note, e.g. the partial one of a cancelled transcription.
+    #[tracing::instrument(skip(self))]
+    pub fn clear_transcription(&self, attachment_id: i32) {
+        self.set_transcription(attachment_id, None, None);
+    }
+
+    fn set_transcription(
+        &self,
+        attachment_id: i32,
+        new_transcription: Option<&str>,
+        language: Option<&str>,
+    ) {
         use schema::attachments::dsl::*;
 
         let updated_message_id = diesel::update(attachments.filter(id.eq(attachment_id)))
-            .set(transcription.eq(new_transcription))
+            .set((
+                transcription.eq(new_transcription),
+                transcription_language.eq(language),
+            ))
             .returning(message_id)
             .get_result::<i32>(&mut *self.db())
             .optional()
@@ -3257,6 +3584,8 @@ impl<O: Observable> Storage<O> {
     }
 
     /// Returns a vector of messages for a specific session, ordered by server timestamp.
+    ///
+    /// Stories are not part of the conversation, and are not returned.
     #[tracing::instrument(skip(self))]
     pub fn fetch_all_messages(&self, session_id: i32, only_most_recent: bool) -> Vec<orm::Message> {
         if only_most_recent {
@@ -3266,12 +3595,16 @@ impl<O: Observable> Storage<O> {
                         schema::messages::latest_revision_id.eq(schema::messages::id.nullable()),
                     ),
                 ))
+                .filter(schema::messages::story_type.eq(StoryType::None))
+                .filter(schema::messages::schedule_send_time.is_null())
                 .order_by(schema::messages::columns::server_timestamp.desc())
                 .load(&mut *self.db())
                 .expect("database")
         } else {
             schema::messages::table
                 .filter(schema::messages::session_id.eq(session_id))
+                .filter(schema::messages::story_type.eq(StoryType::None))
+                .filter(schema::messages::schedule_send_time.is_null())
                 .order_by(schema::messages::columns::server_timestamp.desc())
                 .load(&mut *self.db())
                 .expect("database")
@@ -3327,6 +3660,7 @@ impl<O: Observable> Storage<O> {
         let receipt_counts = self.count_message_receipts(message.id);
         let attachments: i64 = schema::attachments::table
             .filter(schema::attachments::message_id.eq(message_id))
+            .filter(schema::attachments::id.ne_all(link_preview_thumbnails()))
             .count()
             .get_result(&mut *self.db())
             .expect("db");
@@ -3339,6 +3673,7 @@ impl<O: Observable> Storage<O> {
         let is_voice_note = if attachments == 1 {
             schema::attachments::table
                 .filter(schema::attachments::message_id.eq(message_id))
+                .filter(schema::attachments::id.ne_all(link_preview_thumbnails()))
                 .select(schema::attachments::is_voice_note)
                 .get_result(&mut *self.db())
                 .expect("db")
@@ -3356,6 +3691,11 @@ impl<O: Observable> Storage<O> {
 
         let sender_membership = self.fetch_message_sender_membership(&message);
 
+        let link_preview = self
+            .fetch_link_previews_for_message(message_id)
+            .into_iter()
+            .next();
+
         Some(AugmentedMessage {
             inner: message,
             is_voice_note,
@@ -3365,6 +3705,7 @@ impl<O: Observable> Storage<O> {
             mentions,
             body_ranges,
             sender_membership,
+            link_preview,
         })
     }
 
@@ -3426,15 +3767,39 @@ impl<O: Observable> Storage<O> {
         &self,
         sid: i32,
         only_most_recent: bool,
+    ) -> Vec<orm::AugmentedMessage> {
+        let messages = self.fetch_all_messages(sid, only_most_recent);
+        self.augment_messages(sid, messages)
+    }
+
+    /// Augment messages of a single session, ordered newest first.
+    ///
+    /// The messages should be a contiguous range of the session; the additional data is fetched
+    /// for the time span they cover.
+    fn augment_messages(
+        &self,
+        sid: i32,
+        messages: Vec<orm::Message>,
     ) -> Vec<orm::AugmentedMessage> {
         // XXX double/aliased-join would be very useful.
         // Our strategy is to fetch as much as possible, and to augment with as few additional
         // queries as possible. We chose to not join `sender`, and instead use a loop for that
         // part.
-        let messages = self.fetch_all_messages(sid, only_most_recent);
+        let (Some(oldest), Some(newest)) = (
+            messages.iter().map(|m| m.server_timestamp).min(),
+            messages.iter().map(|m| m.server_timestamp).max(),
+        ) else {
+            return Vec::new();
+        };
+        let in_range = || {
+            schema::messages::session_id
+                .eq(sid)
+                .and(schema::messages::story_type.eq(StoryType::None))
+                .and(schema::messages::server_timestamp.between(oldest, newest))
+        };
 
-        // message_id, is_voice_note, attachment count
-        let attachments: Vec<(i32, Option<i16>, i64)> =
+        // message_id -> (is_voice_note, attachment count)
+        let mut attachments: HashMap<i32, (Option<i16>, i64)> =
             tracing::trace_span!("fetching attachments").in_scope(|| {
                 schema::attachments::table
                     .inner_join(schema::messages::table)
@@ -3446,14 +3811,17 @@ impl<O: Observable> Storage<O> {
                         )),
                         diesel::dsl::count(schema::attachments::id),
                     ))
-                    .filter(schema::messages::session_id.eq(sid))
-                    .order_by(schema::messages::columns::id.desc())
-                    .load(&mut *self.db())
+                    .filter(in_range())
+                    .filter(schema::attachments::id.ne_all(link_preview_thumbnails()))
+                    .load::<(i32, Option<i16>, i64)>(&mut *self.db())
                     .expect("db")
+                    .into_iter()
+                    .map(|(id, voice_note, count)| (id, (voice_note, count)))
+                    .collect()
             });
 
-        // message_id, reaction count
-        let reactions: Vec<(i32, i64)> =
+        // message_id -> reaction count
+        let mut reactions: HashMap<i32, i64> =
             tracing::trace_span!("fetching reactions").in_scope(|| {
                 schema::reactions::table
                     .inner_join(schema::messages::table)
@@ -3462,55 +3830,57 @@ impl<O: Observable> Storage<O> {
                         schema::messages::id,
                         diesel::dsl::count(schema::reactions::reaction_id),
                     ))
-                    .filter(schema::messages::session_id.eq(sid))
-                    .order_by(schema::messages::columns::id.desc())
-                    .load(&mut *self.db())
+                    .filter(in_range())
+                    .load::<(i32, i64)>(&mut *self.db())
                     .expect("db")
+                    .into_iter()
+                    .collect()
             });
 
         // Fetch receipt counts grouped by message_id for better performance
-        // Fetch receipt counts using the same pattern as reactions
-        let (read_counts, delivered_counts, viewed_counts) =
+        let (mut read_counts, mut delivered_counts, mut viewed_counts) =
             tracing::trace_span!("fetching receipt counts").in_scope(|| {
                 use schema::{messages, receipts};
 
-                // Fetch counts for each receipt type, using the same ordering as messages
-                let read_counts: Vec<(i32, i64)> = receipts::table
+                let read_counts: HashMap<i32, i64> = receipts::table
                     .inner_join(messages::table)
                     .filter(receipts::read.is_not_null())
-                    .filter(messages::session_id.eq(sid))
+                    .filter(in_range())
                     .group_by(receipts::message_id)
                     .select((receipts::message_id, diesel::dsl::count_star()))
-                    .order_by(schema::receipts::columns::message_id.desc())
-                    .load(&mut *self.db())
-                    .expect("db");
+                    .load::<(i32, i64)>(&mut *self.db())
+                    .expect("db")
+                    .into_iter()
+                    .collect();
 
-                let delivered_counts: Vec<(i32, i64)> = receipts::table
+                let delivered_counts: HashMap<i32, i64> = receipts::table
                     .inner_join(messages::table)
                     .filter(receipts::delivered.is_not_null())
-                    .filter(messages::session_id.eq(sid))
+                    .filter(in_range())
                     .group_by(receipts::message_id)
                     .select((receipts::message_id, diesel::dsl::count_star()))
-                    .order_by(schema::receipts::columns::message_id.desc())
-                    .load(&mut *self.db())
-                    .expect("db");
+                    .load::<(i32, i64)>(&mut *self.db())
+                    .expect("db")
+                    .into_iter()
+                    .collect();
 
-                let viewed_counts: Vec<(i32, i64)> = receipts::table
+                let viewed_counts: HashMap<i32, i64> = receipts::table
                     .inner_join(messages::table)
                     .filter(receipts::viewed.is_not_null())
-                    .filter(messages::session_id.eq(sid))
+                    .filter(in_range())
                     .group_by(receipts::message_id)
                     .select((receipts::message_id, diesel::dsl::count_star()))
-                    .order_by(schema::receipts::columns::message_id.desc())
-                    .load(&mut *self.db())
-                    .expect("db");
+                    .load::<(i32, i64)>(&mut *self.db())
+                    .expect("db")
+                    .into_iter()
+                    .collect();
 
                 (read_counts, delivered_counts, viewed_counts)
             });
 
         // Fetch the sender's group membership for each member of the group
         // session, so per-message sender roles and labels can be rendered.
-        let sender_memberships: std::collections::HashMap<i32, orm::GroupV2Member> =
+        let sender_memberships: HashMap<i32, orm::GroupV2Member> =
             tracing::trace_span!("fetching sender memberships").in_scope(|| {
                 let session = self.fetch_session_by_id(sid);
                 if !session.as_ref().is_some_and(|s| s.is_group_v2()) {
@@ -3526,103 +3896,55 @@ impl<O: Observable> Storage<O> {
                     .collect()
             });
 
-        let mut aug_messages = Vec::with_capacity(messages.len());
+        let mut link_previews = tracing::trace_span!("fetching link previews")
+            .in_scope(|| self.fetch_link_previews_for_session(sid, oldest, newest));
+
         tracing::trace_span!("joining messages, attachments, receipts into AugmentedMessage")
             .in_scope(|| {
-                let mut attachments = attachments.into_iter().peekable();
-                let mut reactions = reactions.into_iter().peekable();
-                let mut read_counts_iter = read_counts.into_iter().peekable();
-                let mut delivered_counts_iter = delivered_counts.into_iter().peekable();
-                let mut viewed_counts_iter = viewed_counts.into_iter().peekable();
-
-                for message in messages {
-                    let (attachments, is_voice_note) = if attachments
-                        .peek()
-                        .map(|(id, _, _)| *id == message.id)
-                        .unwrap_or(false)
-                    {
-                        let (_, voice_note, attachments) = attachments.next().unwrap();
-                        (
-                            attachments as usize,
-                            voice_note.map(|x| x > 0).unwrap_or(false),
-                        )
-                    } else {
-                        (0, false)
-                    };
-
-                    let reactions = if reactions
-                        .peek()
-                        .map(|(id, _)| *id == message.id)
-                        .unwrap_or(false)
-                    {
-                        let (_, reactions) = reactions.next().unwrap();
-                        reactions as usize
-                    } else {
-                        0
-                    };
-
-                    // Look up receipt counts for this message
-                    let read_count = if read_counts_iter
-                        .peek()
-                        .map(|(id, _)| *id == message.id)
-                        .unwrap_or(false)
-                    {
-                        let (_, count) = read_counts_iter.next().unwrap();
-                        count
-                    } else {
-                        0
-                    };
-                    let delivered_count = if delivered_counts_iter
-                        .peek()
-                        .map(|(id, _)| *id == message.id)
-                        .unwrap_or(false)
-                    {
-                        let (_, count) = delivered_counts_iter.next().unwrap();
-                        count
-                    } else {
-                        0
-                    };
-                    let viewed_count = if viewed_counts_iter
-                        .peek()
-                        .map(|(id, _)| *id == message.id)
-                        .unwrap_or(false)
-                    {
-                        let (_, count) = viewed_counts_iter.next().unwrap();
-                        count
-                    } else {
-                        0
-                    };
-                    let receipt_counts = orm::ReceiptCounts {
-                        read: read_count as usize,
-                        delivered: delivered_count as usize,
-                        viewed: viewed_count as usize,
-                    };
-
-                    let body_ranges = if let Some(r) = &message.message_ranges {
-                        crate::store::body_ranges::deserialize(r)
-                    } else {
-                        vec![]
-                    };
-
-                    let mentions = self.fetch_mentions(&body_ranges);
-
-                    let sender_membership = message
-                        .sender_recipient_id
-                        .and_then(|rid| sender_memberships.get(&rid).cloned());
-
-                    aug_messages.push(orm::AugmentedMessage {
-                        inner: message,
-                        is_voice_note,
-                        attachments,
-                        reactions,
-                        receipt_counts,
-                        body_ranges,
-                        mentions,
-                        sender_membership,
-                    });
-                }
-            });
-        aug_messages
+                messages
+                    .into_iter()
+                    .map(|message| {
+                        let (is_voice_note, attachments) = attachments
+                            .remove(&message.id)
+                            .map(|(voice_note, count)| {
+                                (voice_note.is_some_and(|x| x > 0), count as usize)
+                            })
+                            .unwrap_or((false, 0));
+                        let reactions = reactions.remove(&message.id).unwrap_or(0) as usize;
+                        let receipt_counts = orm::ReceiptCounts {
+                            read: read_counts.remove(&message.id).unwrap_or(0) as usize,
+                            delivered: delivered_counts.remove(&message.id).unwrap_or(0) as usize,
+                            viewed: viewed_counts.remove(&message.id).unwrap_or(0) as usize,
+                        };
+
+                        let body_ranges = if let Some(r) = &message.message_ranges {
+                            crate::store::body_ranges::deserialize(r)
+                        } else {
+                            vec![]
+                        };
+
+                        let mentions = self.fetch_mentions(&body_ranges);
+
+                        let sender_membership = message
+                            .sender_recipient_id
+                            .and_then(|rid| sender_memberships.get(&rid).cloned());
+
+                        let link_preview = link_previews.remove(&message.id);
+
+                        orm::AugmentedMessage {
+                            inner: message,
+                            is_voice_note,
+                            attachments,
+                            reactions,
+                            receipt_counts,
+                            body_ranges,
+                            mentions,
+                            sender_membership,
+                            link_preview,
+                        }
+                    })
+                    .collect()
+            })
     }
 
     fn fetch_mentions(
@@ -3679,6 +4001,13 @@ impl<O: Observable> Storage<O> {
             .returning(schema::reactions::reaction_id)
             .load(&mut *self.db())
             .unwrap();
+        drop(_span);
+
+        let link_previews: Vec<i32> = diesel::delete(schema::link_previews::table)
+            .filter(schema::link_previews::message_id.eq(message.id))
+            .returning(schema::link_previews::id)
+            .load(&mut *self.db())
+            .expect("db");
 
         self.observe_update(schema::messages::table, message.id)
             .with_relation(schema::sessions::table, message.session_id);
@@ -3687,6 +4016,10 @@ impl<O: Observable> Storage<O> {
             self.observe_delete(schema::reactions::table, *reaction)
                 .with_relation(schema::messages::table, message.id);
         }
+        for link_preview in link_previews {
+            self.observe_delete(schema::link_previews::table, link_preview)
+                .with_relation(schema::messages::table, message.id);
+        }
 
         tracing::trace!("Marked Message {{ id: {} }} deleted", message.id);
         tracing::trace!(
@@ -3701,48 +4034,59 @@ impl<O: Observable> Storage<O> {
     /// Delete all attachments of the message, is no other message references them.
     #[tracing::instrument(skip(self))]
     fn delete_attachments_for_message(&mut self, message_id: i32) -> usize {
-        let mut n_attachments = 0;
-        let allowed = self.config.attachments_regex();
         // TODO: refactor this with delete-returning-all-columns
-        self.fetch_attachments_for_message(message_id)
-            .into_iter()
-            .for_each(|attachment| {
-                diesel::delete(schema::attachments::table)
-                    .filter(schema::attachments::id.eq(attachment.id))
-                    .execute(&mut *self.db())
-                    .unwrap();
-                self.observe_delete(schema::attachments::table, attachment.id)
-                    .with_relation(schema::messages::table, message_id);
-
-                if let Some(path) = attachment.absolute_attachment_path() {
-                    let _span = tracing::debug_span!("considering attachment file deletion", id = attachment.id, path = %path).entered();
-                    let remaining = schema::attachments::table
-                        .filter(schema::attachments::attachment_path.eq(&path))
-                        .count()
-                        .get_result::<i64>(&mut *self.db())
-                        .unwrap();
-                    if remaining > 0 {
-                        tracing::warn!(attachment.id, %path, "references to attachment exist, not deleting");
-                    } else if allowed.is_match(attachment.attachment_path.as_ref().unwrap()) {
-                        match std::fs::remove_file(path.as_ref()) {
-                            Ok(()) => {
-                                tracing::trace!("deleted file");
-                                n_attachments += 1;
-                            }
-                            Err(e) => {
-                                tracing::trace!("could not delete file: {:?}", e);
-                            }
-                        };
-                    } else {
-                        tracing::warn!(
-                            attachment.id,
-                            ?path,
-                            "not deleting attachment because it does not match the allowed regex"
-                        );
-                    }
+        // This includes the link preview thumbnails.
+        schema::attachments::table
+            .filter(schema::attachments::message_id.eq(message_id))
+            .load::<orm::Attachment>(&mut *self.db())
+            .expect("db")
+            .iter()
+            .filter(|attachment| self.delete_attachment(attachment))
+            .count()
+    }
+
+    /// Delete an attachment, and its file if no other attachment references it.
+    ///
+    /// Returns whether the file was deleted.
+    fn delete_attachment(&self, attachment: &orm::Attachment) -> bool {
+        let allowed = self.config.attachments_regex();
+
+        diesel::delete(schema::attachments::table)
+            .filter(schema::attachments::id.eq(attachment.id))
+            .execute(&mut *self.db())
+            .unwrap();
+        self.observe_delete(schema::attachments::table, attachment.id)
+            .with_relation(schema::messages::table, attachment.message_id);
+
+        let Some(path) = attachment.absolute_attachment_path() else {
+            return false;
+        };
+        let _span = tracing::debug_span!("considering attachment file deletion", id = attachment.id, path = %path).entered();
+        let remaining = schema::attachments::table
+            .filter(schema::attachments::attachment_path.eq(&path))
+            .count()
+            .get_result::<i64>(&mut *self.db())
+            .unwrap();
+        if remaining > 0 {
+            tracing::warn!(attachment.id, %path, "references to attachment exist, not deleting");
+        } else if allowed.is_match(attachment.attachment_path.as_ref().unwrap()) {
+            match std::fs::remove_file(path.as_ref()) {
+                Ok(()) => {
+                    tracing::trace!("deleted file");
+                    return true;
                 }
-            });
-        n_attachments
+                Err(e) => {
+                    tracing::trace!("could not delete file: {:?}", e);
+                }
+            };
+        } else {
+            tracing::warn!(
+                attachment.id,
+                ?path,
+                "not deleting attachment because it does not match the allowed regex"
+            );
+        }
+        false
     }
 
     /// Marks all messages that are outbound and unsent as failed.
@@ -3819,6 +4163,8 @@ impl<O: Observable> Storage<O> {
 
     /// Saves the given contents into a randomly-named file in the attachment folder
     /// and updates the attachment with the resulting path, which is then returned.
+    ///
+    /// The file is encrypted when the storage is; read it back with [Self::open_attachment].
     #[tracing::instrument(skip(self, attachment), fields(attachment_size = attachment.len()))]
     pub async fn save_attachment(
         &self,
@@ -3827,15 +4173,10 @@ impl<O: Observable> Storage<O> {
         ext: &str,
         attachment: &[u8],
     ) -> Result<PathBuf, anyhow::Error> {
-        let fname = Uuid::new_v4();
-        let fname = fname.as_simple();
-        let fname_formatted = format!("{}", fname);
-        let fname_path = Path::new(&fname_formatted);
-
-        let mut path = destination.join(fname_path);
-        path.set_extension(ext);
+        let path = Self::new_attachment_path(destination, ext);
 
-        utils::write_file_async(&path, attachment)
+        let contents = self.attachment_file_contents(attachment)?;
+        utils::write_file_async(&path, &contents)
             .await
             .with_context(|| {
                 format!(
@@ -3844,6 +4185,24 @@ impl<O: Observable> Storage<O> {
                 )
             })?;
 
+        self.set_attachment_file(attachment_id, &path);
+        Ok(path)
+    }
+
+    /// A random file name for a new attachment in `destination`.
+    fn new_attachment_path(destination: &Path, ext: &str) -> PathBuf {
+        let fname = Uuid::new_v4();
+        let fname = fname.as_simple();
+        let fname_formatted = format!("{}", fname);
+        let fname_path = Path::new(&fname_formatted);
+
+        let mut path = destination.join(fname_path);
+        path.set_extension(ext);
+        path
+    }
+
+    /// Points the attachment to its freshly written file, which also ends its download.
+    fn set_attachment_file(&self, attachment_id: i32, path: &Path) {
         let relative_dir =
             crate::replace_home_with_tilde(path.to_str().expect("UTF8-compliant path"));
 
@@ -3868,10 +4227,11 @@ impl<O: Observable> Storage<O> {
                 "Could not save attachment path",
             );
         };
-
-        Ok(path)
     }
 
+    /// Reports how far the download of an attachment is, for showing its progress.
+    ///
+    /// This is not where a download resumes from; that is decided by its partial file.
     pub fn update_attachment_progress(
         &self,
         attachment_id: i32,
@@ -4058,65 +4418,6 @@ impl<O: Observable> Storage<O> {
             .expect("db");
     }
 
-    pub fn search_messages(
-        &self,
-        search_text: &str,
-        search_session_id: Option<i32>,
-    ) -> Vec<orm::Message> {
-        if search_text.len() < 3 {
-            return vec![];
-        }
-
-        use crate::schema::messages::dsl::*;
-
-        // SQLite uses ' as escape character for strings,
-        // but se have to tell it to SQLite when using .like()
-        let search_text = search_text.replace('\'', "''").replace('%', "'%");
-
-        // - null message type = regular message
-        // - any text matches = text is implicitly non-null
-        // - only consider the latest edits so we can scroll to it
-        if let Some(s_id) = search_session_id {
-            messages
-                .filter(
-                    session_id
-                        .eq(s_id)
-                        .and(message_type.is_null())
-                        .and(
-                            text.assume_not_null()
-                                .like(format!("%{search_text}%"))
-                                .escape('\''),
-                        )
-                        .and(
-                            latest_revision_id
-                                .is_null()
-                                .or(latest_revision_id.eq(id.nullable())),
-                        ),
-                )
-                .order_by((
-                    schema::messages::columns::server_timestamp.desc(),
-                    schema::messages::columns::id.desc(),
-                ))
-                .load(&mut *self.db())
-                .unwrap()
-        } else {
-            messages
-                .filter(
-                    message_type.is_null().and(
-                        text.assume_not_null()
-                            .like(format!("%{search_text}%"))
-                            .escape('\''),
-                    ),
-                )
-                .order_by((
-                    schema::messages::columns::server_timestamp.desc(),
-                    schema::messages::columns::id.desc(),
-                ))
-                .load(&mut *self.db())
-                .unwrap()
-        }
-    }
-
     // GroupV2 update functions
 
     /// Update group revision number, if it is higher than the current one.
@@ -4387,22 +4688,19 @@ impl<O: Observable> Storage<O> {
         }
     }
 
-    /// Update the group's access control.
+    /// Update the group's invite link password.
     /// Empty password signals unset password.
     ///
     /// Does not trigger observer update.
     pub fn update_group_v2_invite_link_password(
         &self,
         group_v2: &orm::GroupV2,
-        next_password: &String,
+        next_password: &[u8],
     ) {
         use crate::schema::group_v2s::dsl::*;
 
-        let bytes = next_password.as_bytes();
-
-        // TODO: Stub -- needs to consider access level and modifiers access level
         diesel::update(group_v2s.filter(id.eq(&group_v2.id)))
-            .set(invite_link_password.eq(Some(bytes)))
+            .set(invite_link_password.eq(Some(next_password).filter(|p| !p.is_empty())))
             .execute(&mut *self.db())
             .expect("db");
     }
@@ -0,0 +1,199 @@
+//! Incremental verification and decryption of Signal attachments.
+//!
+//! Attachments are downloaded into a partial file, such that an interrupted download can be
+//! resumed, and are decrypted from there in chunks. The ciphertext is the IV, the AES-256-CBC
+//! encrypted and padded plaintext, and a HMAC-SHA256 over both.
+
+use aes::cipher::{BlockModeDecrypt, KeyIvInit};
+use hmac::{KeyInit, Mac};
+use sha2::Digest;
+use std::io::{self, Read, Take};
+use std::path::{Path, PathBuf};
+
+const IV_LEN: usize = 16;
+const BLOCK_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+/// The number of ciphertext bytes that are decrypted at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Name of the directory in the attachment directory that holds partial downloads.
+const PARTIAL_DIR: &str = ".partial";
+
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+/// Where the ciphertext of an attachment is downloaded to, until it is complete.
+///
+/// The partial file survives restarts of Whisperfish, such that the download can be resumed.
+pub fn partial_attachment_path(attachment_dir: &Path, attachment_id: i32) -> PathBuf {
+    attachment_dir
+        .join(PARTIAL_DIR)
+        .join(format!("{attachment_id}.part"))
+}
+
+/// Where the ciphertext of the contacts of a sync message is downloaded to.
+pub fn partial_contacts_path(attachment_dir: &Path) -> PathBuf {
+    attachment_dir.join(PARTIAL_DIR).join("contacts.part")
+}
+
+/// Computes the MAC and the digest of attachment ciphertext while it is being downloaded.
+pub struct CiphertextVerifier {
+    mac: HmacSha256,
+    digest: sha2::Sha256,
+    /// The last bytes seen, which may turn out to be the MAC itself.
+    tail: Vec<u8>,
+    len: u64,
+}
+
+impl CiphertextVerifier {
+    pub fn new(key: &[u8; 64]) -> Self {
+        Self {
+            mac: HmacSha256::new_from_slice(&key[32..]).expect("MAC keylength error"),
+            digest: sha2::Sha256::new(),
+            tail: Vec::with_capacity(MAC_LEN),
+            len: 0,
+        }
+    }
+
+    /// The number of ciphertext bytes seen so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        self.digest.update(bytes);
+
+        // Everything but the last MAC_LEN bytes is authenticated.
+        let total = self.tail.len() + bytes.len();
+        if total <= MAC_LEN {
+            self.tail.extend_from_slice(bytes);
+            return;
+        }
+        let authenticated = total - MAC_LEN;
+        let from_tail = authenticated.min(self.tail.len());
+        self.mac.update(&self.tail[..from_tail]);
+        let from_bytes = authenticated - from_tail;
+        self.mac.update(&bytes[..from_bytes]);
+        self.tail.drain(..from_tail);
+        self.tail.extend_from_slice(&bytes[from_bytes..]);
+    }
+
+    /// Checks the MAC at the end of the ciphertext, and the digest of the attachment pointer if
+    /// it has one.
+    pub fn verify(self, digest: Option<&[u8]>) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.len >= (IV_LEN + BLOCK_LEN + MAC_LEN) as u64,
+            "Attachment of {} bytes is too short",
+            self.len
+        );
+        self.mac
+            .verify_slice(&self.tail)
+            .map_err(|_| anyhow::anyhow!("Attachment MAC verification failed"))?;
+        if let Some(digest) = digest {
+            anyhow::ensure!(
+                self.digest.finalize()[..] == *digest,
+                "Attachment digest mismatch"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Decrypts verified attachment ciphertext in chunks.
+///
+/// The padding is removed, and the plaintext is cut off at the size of the attachment pointer,
+/// because Signal pads attachments to hide their size.
+pub struct AttachmentReader<R: Read> {
+    /// The encrypted blocks, without the IV and the MAC.
+    inner: Take<R>,
+    cipher: Aes256CbcDec,
+    buffer: Vec<u8>,
+    position: usize,
+    /// The number of plaintext bytes that are still to be returned, if known.
+    remaining: Option<u64>,
+}
+
+impl<R: Read> AttachmentReader<R> {
+    pub fn new(
+        mut inner: R,
+        key: &[u8; 64],
+        ciphertext_len: u64,
+        plaintext_len: Option<u64>,
+    ) -> io::Result<Self> {
+        let blocks_len = ciphertext_len
+            .checked_sub((IV_LEN + MAC_LEN) as u64)
+            .filter(|len| *len >= BLOCK_LEN as u64 && *len % BLOCK_LEN as u64 == 0)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid attachment length")
+            })?;
+
+        let mut iv = [0u8; IV_LEN];
+        inner.read_exact(&mut iv)?;
+        let cipher =
+            Aes256CbcDec::new_from_slices(&key[..32], &iv).expect("CBC initialization error");
+
+        Ok(Self {
+            inner: inner.take(blocks_len),
+            cipher,
+            buffer: Vec::new(),
+            position: 0,
+            remaining: plaintext_len,
+        })
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let len = self.inner.limit().min(CHUNK_SIZE as u64) as usize;
+        self.buffer.resize(len, 0);
+        self.position = 0;
+        self.inner.read_exact(&mut self.buffer)?;
+
+        for block in self.buffer.chunks_exact_mut(BLOCK_LEN) {
+            let mut decrypted = aes::Block::try_from(&block[..]).expect("16 byte block");
+            self.cipher.decrypt_block(&mut decrypted);
+            block.copy_from_slice(&decrypted);
+        }
+
+        if self.inner.limit() == 0 {
+            let padding = *self.buffer.last().expect("at least one block") as usize;
+            if padding == 0
+                || padding > BLOCK_LEN
+                || !self.buffer[len - padding..]
+                    .iter()
+                    .all(|b| *b as usize == padding)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid attachment padding",
+                ));
+            }
+            self.buffer.truncate(len - padding);
+        }
+
+        if let Some(remaining) = self.remaining.as_mut() {
+            let len = (self.buffer.len() as u64).min(*remaining);
+            self.buffer.truncate(len as usize);
+            *remaining -= len;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for AttachmentReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            if self.inner.limit() == 0 || self.remaining == Some(0) {
+                return Ok(0);
+            }
+            self.read_chunk()?;
+        }
+        let len = buf.len().min(self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
@@ -0,0 +1,366 @@
+use super::observer::Observable;
+use super::stream_cipher::{Format, Header, StreamReader, StreamWriter};
+use super::{MIGRATIONS, StorageLocation};
+use crate::config::SignalConfig;
+use crate::schema;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::KeyInit;
+use anyhow::Context;
+use diesel::migration::MigrationSource;
+use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
+use diesel_migrations::MigrationHarness;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Backups, encrypted with a key derived from a passphrase with scrypt.
+static FORMAT: Format = Format {
+    magic: b"WFBACKUP",
+    version: 1,
+    // The scrypt cost parameter, followed by the salt.
+    params_len: 1 + SALT_LEN,
+    name: "backup",
+    key_name: "passphrase",
+};
+const SCRYPT_LOG_N: u8 = 15;
+const SALT_LEN: usize = 16;
+
+const DATABASE: &str = "db/harbour-whisperfish.db";
+/// Files of the storage that go into a backup besides the database, relative to its root.
+const BACKUP_FILES: [&str; 2] = ["db/salt", "storage/salt"];
+/// Directories of the storage whose files go into a backup, relative to its root.
+const BACKUP_DIRECTORIES: [&str; 2] = ["storage/identity", "storage/avatars"];
+
+/// The entries of the plaintext backup stream, each followed by `len` bytes of file contents.
+#[derive(Serialize, Deserialize, Debug)]
+enum Entry {
+    /// A file of the storage, relative to its root.
+    File {
+        path: String,
+        len: u64,
+    },
+    /// The file of an attachment, which may be stored outside of the storage.
+    Attachment {
+        id: i32,
+        file_name: String,
+        len: u64,
+    },
+    End,
+}
+
+/// The backup cipher of `header`, which holds the scrypt parameters.
+fn backup_cipher(header: &Header, passphrase: &str) -> anyhow::Result<Aes256Gcm> {
+    let (log_n, salt) = header.params().split_first().expect("scrypt parameters");
+    anyhow::ensure!(
+        (10..=20).contains(log_n),
+        "Unsupported backup key derivation"
+    );
+    let params = scrypt::Params::new(*log_n, 8, 1).context("scrypt parameters")?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key)
+        .context("Cannot compute backup key")?;
+    Ok(Aes256Gcm::new_from_slice(&key).expect("32 byte AES key"))
+}
+
+/// Encrypts a backup with a new salt.
+fn backup_writer<W: Write>(inner: W, passphrase: &str) -> anyhow::Result<StreamWriter<W>> {
+    let mut params = [0u8; 1 + SALT_LEN];
+    params[0] = SCRYPT_LOG_N;
+    rand::rng().fill_bytes(&mut params[1..]);
+    let header = FORMAT.new_header(&params);
+    let cipher = backup_cipher(&header, passphrase)?;
+    Ok(StreamWriter::new(inner, header, cipher)?)
+}
+
+/// Decrypts and authenticates a backup written by [backup_writer].
+fn backup_reader<R: Read>(mut inner: R, passphrase: &str) -> anyhow::Result<StreamReader<R>> {
+    let header = FORMAT.read_header(&mut inner)?;
+    let cipher = backup_cipher(&header, passphrase)?;
+    Ok(StreamReader::new(inner, header, cipher))
+}
+
+fn write_entry(writer: &mut impl Write, entry: &Entry, source: &Path) -> anyhow::Result<()> {
+    let len = match entry {
+        Entry::File { len, .. } | Entry::Attachment { len, .. } => *len,
+        Entry::End => 0,
+    };
+    bincode::serialize_into(&mut *writer, entry)?;
+    let copied = io::copy(&mut File::open(source)?.take(len), writer)?;
+    anyhow::ensure!(
+        copied == len,
+        "{} changed while writing the backup",
+        source.display()
+    );
+    Ok(())
+}
+
+fn write_file(writer: &mut impl Write, root: &Path, path: &str) -> anyhow::Result<()> {
+    let source = root.join(path);
+    let entry = Entry::File {
+        path: path.to_string(),
+        len: std::fs::metadata(&source)?.len(),
+    };
+    write_entry(writer, &entry, &source).with_context(|| format!("Backing up {path}"))
+}
+
+fn read_entry(reader: &mut impl Read, destination: &Path, len: u64) -> anyhow::Result<()> {
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(destination)?;
+    let copied = io::copy(&mut reader.take(len), &mut file)?;
+    anyhow::ensure!(copied == len, "Backup is truncated");
+    file.sync_all()?;
+    Ok(())
+}
+
+/// A path from the backup, which must stay within the directory it is restored into.
+fn relative_path(path: &str) -> anyhow::Result<PathBuf> {
+    let path = PathBuf::from(path);
+    anyhow::ensure!(
+        path.components().next().is_some()
+            && path.components().all(|c| matches!(c, Component::Normal(_))),
+        "Invalid path in backup: {}",
+        path.display()
+    );
+    Ok(path)
+}
+
+/// Restores the files of a backup into `root`, and returns where the attachments went.
+fn extract_backup(
+    backup: &Path,
+    passphrase: &str,
+    root: &Path,
+) -> anyhow::Result<Vec<(i32, PathBuf)>> {
+    let file = File::open(backup).context("Opening backup")?;
+    let mut reader = backup_reader(BufReader::new(file), passphrase)?;
+    let attachment_dir = root.join("storage").join("attachments");
+    let mut attachments = Vec::new();
+
+    loop {
+        match bincode::deserialize_from::<_, Entry>(&mut reader).context("Reading backup")? {
+            Entry::File { path, len } => {
+                read_entry(&mut reader, &root.join(relative_path(&path)?), len)?;
+            }
+            Entry::Attachment { id, file_name, len } => {
+                let file_name = relative_path(&file_name)?;
+                anyhow::ensure!(
+                    file_name.components().count() == 1,
+                    "Invalid attachment file name in backup"
+                );
+                let mut destination = attachment_dir.join(&file_name);
+                if destination.exists() {
+                    destination = attachment_dir.join(format!("{id}-{}", file_name.display()));
+                }
+                read_entry(&mut reader, &destination, len)?;
+                attachments.push((id, destination));
+            }
+            Entry::End => break,
+        }
+    }
+    reader.finish()?;
+
+    anyhow::ensure!(
+        root.join(DATABASE).exists(),
+        "The backup does not contain a database"
+    );
+    Ok(attachments)
+}
+
+/// Refuses databases with migrations that this version of Whisperfish does not know about.
+fn check_known_migrations(db: &mut SqliteConnection) -> anyhow::Result<()> {
+    let known: HashSet<String> = MigrationSource::<Sqlite>::migrations(&MIGRATIONS)
+        .map_err(|e| anyhow::anyhow!("Listing migrations: {}", e))?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect();
+    let unknown: Vec<String> = db
+        .applied_migrations()
+        .map_err(|e| anyhow::anyhow!("Reading applied migrations: {}", e))?
+        .iter()
+        .map(ToString::to_string)
+        .filter(|version| !known.contains(version))
+        .collect();
+    anyhow::ensure!(
+        unknown.is_empty(),
+        "The backup was made with a newer version of Whisperfish (unknown migrations {:?})",
+        unknown
+    );
+    Ok(())
+}
+
+impl<O: Observable> super::Storage<O> {
+    /// Writes an encrypted backup of the whole storage to `path`.
+    ///
+    /// The backup contains the database, the identity files, the avatars, and the files of all
+    /// attachments, encrypted with a key derived from `passphrase`. The database keeps its own
+    /// encryption, so restoring it needs the storage password too.
+    #[tracing::instrument(skip(self, passphrase))]
+    pub fn export_backup(
+        &self,
+        path: impl AsRef<Path> + Debug,
+        passphrase: &str,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let result = self.write_backup(path, passphrase);
+        if result.is_err() {
+            let _ = std::fs::remove_file(path);
+        }
+        result
+    }
+
+    fn write_backup(&self, path: &Path, passphrase: &str) -> anyhow::Result<()> {
+        // A consistent copy of the database, which stays encrypted with the storage key.
+        // It is made in the database directory, and removed once it is in the backup.
+        let snapshot_dir = tempfile::Builder::new()
+            .prefix("backup")
+            .tempdir_in(self.path.join("db"))
+            .context("Creating database snapshot directory")?;
+        let snapshot = snapshot_dir.path().join("snapshot.db");
+        let attachments: Vec<(i32, Option<String>)> = {
+            let mut db = self.db();
+            diesel::sql_query("VACUUM INTO ?")
+                .bind::<diesel::sql_types::Text, _>(
+                    snapshot.to_str().context("non-UTF8 storage path")?,
+                )
+                .execute(&mut *db)
+                .context("Snapshotting the database")?;
+            // Listed along with the snapshot, such that both agree on the attachments.
+            schema::attachments::table
+                .select((
+                    schema::attachments::id,
+                    schema::attachments::attachment_path,
+                ))
+                .filter(schema::attachments::attachment_path.is_not_null())
+                .load(&mut *db)?
+        };
+
+        let file = File::create(path).context("Creating backup")?;
+        let mut writer = backup_writer(BufWriter::new(file), passphrase)?;
+
+        let entry = Entry::File {
+            path: DATABASE.to_string(),
+            len: std::fs::metadata(&snapshot)?.len(),
+        };
+        write_entry(&mut writer, &entry, &snapshot).context("Backing up the database")?;
+        drop(snapshot_dir);
+
+        for path in BACKUP_FILES {
+            if self.path.join(path).exists() {
+                write_file(&mut writer, &self.path, path)?;
+            }
+        }
+
+        for directory in BACKUP_DIRECTORIES {
+            let Ok(entries) = std::fs::read_dir(self.path.join(directory)) else {
+                continue;
+            };
+            for entry in entries {
+                let entry = entry?;
+                if !entry.file_type()?.is_file() {
+                    continue;
+                }
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    tracing::warn!("Skipping non-UTF8 file name {:?}", entry.file_name());
+                    continue;
+                };
+                write_file(&mut writer, &self.path, &format!("{directory}/{name}"))?;
+            }
+        }
+
+        for (id, attachment_path) in attachments {
+            let attachment_path = attachment_path.expect("non-null attachment path");
+            let source = PathBuf::from(crate::replace_tilde_with_home(&attachment_path).as_ref());
+            let (Ok(metadata), Some(file_name)) = (
+                std::fs::metadata(&source),
+                source.file_name().and_then(|name| name.to_str()),
+            ) else {
+                tracing::warn!("Attachment {} has no file, not backing it up", id);
+                continue;
+            };
+            let entry = Entry::Attachment {
+                id,
+                file_name: file_name.to_string(),
+                len: metadata.len(),
+            };
+            write_entry(&mut writer, &entry, &source)
+                .with_context(|| format!("Backing up attachment {id}"))?;
+        }
+
+        bincode::serialize_into(&mut writer, &Entry::End)?;
+        writer
+            .finish()?
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        Ok(())
+    }
+}
+
+impl<O: Observable + Default> super::Storage<O> {
+    /// Restores a backup written by [Self::export_backup] into a new storage, and opens it.
+    ///
+    /// The database of the backup is checked against the embedded migrations before it is
+    /// migrated, such that backups of newer Whisperfish versions are refused. Attachments are
+    /// restored into the attachment directory of the new storage.
+    #[tracing::instrument(skip(config, passphrase, password))]
+    pub async fn import_backup<T: AsRef<Path> + Debug>(
+        config: Arc<SignalConfig>,
+        db_path: &StorageLocation<T>,
+        backup: impl AsRef<Path> + Debug,
+        passphrase: &str,
+        password: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let root: &Path = std::ops::Deref::deref(db_path);
+        let existed = root.exists();
+        anyhow::ensure!(
+            !existed || std::fs::read_dir(root)?.next().is_none(),
+            "Refusing to restore a backup into non-empty {}",
+            root.display()
+        );
+
+        let result = async {
+            Self::scaffold_directories(root)?;
+
+            let attachments = {
+                let backup = backup.as_ref().to_path_buf();
+                let passphrase = passphrase.to_string();
+                let root = root.to_path_buf();
+                tokio::task::spawn_blocking(move || extract_backup(&backup, &passphrase, &root))
+                    .await??
+            };
+
+            let store_enc = Self::read_storage_encryption(root, password.clone()).await?;
+            let mut db =
+                Self::connect_db(db_path, store_enc.as_ref().map(|x| x.get_database_key()))
+                    .context("Opening restored database; probably wrong storage password")?;
+            check_known_migrations(&mut db)?;
+            drop(db);
+
+            let storage = Self::open(config, db_path, password).await?;
+            for (id, path) in attachments {
+                let path = path
+                    .to_str()
+                    .context("path to attachment contains a non-UTF8 character")?;
+                storage.update_attachment_path(id, &crate::replace_home_with_tilde(path));
+            }
+            Ok(storage)
+        }
+        .await;
+
+        if result.is_err() {
+            // Leave the location as we found it, such that restoring can be retried.
+            let _ = std::fs::remove_dir_all(root);
+            if existed {
+                let _ = std::fs::create_dir(root);
+            }
+        }
+        result
+    }
+}
@@ -80,6 +80,15 @@ pub fn serialize(value: &[WireBodyRange]) -> Option<Vec<u8>> {
     Some(message_ranges.encode_to_vec())
 }
 
+/// Serialize body ranges that are in the database format already, like the ones from the UI.
+pub fn serialize_local(ranges: Vec<BodyRange>) -> Option<Vec<u8>> {
+    if ranges.is_empty() {
+        return None;
+    }
+
+    Some(database_protos::BodyRangeList { ranges }.encode_to_vec())
+}
+
 #[tracing::instrument(level = "debug", skip(message_ranges), fields(message_ranges_len = message_ranges.map(Vec::len)), name="body_ranges::to_vec")]
 pub fn to_vec(message_ranges: Option<&Vec<u8>>) -> Vec<WireBodyRange> {
     let Some(message_ranges) = message_ranges else {
@@ -1,5 +1,6 @@
 use super::observer::Observable;
 use crate::orm;
+use crate::schema;
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 
@@ -18,9 +19,7 @@ impl<O: Observable + Default> super::Storage<O> {
         event: orm::EventType,
         unidentified: bool,
     ) -> (orm::Session, i32) {
-        let session = self
-            .fetch_session_by_recipient_id(recipient_id)
-            .expect("fetching session by recipient id");
+        let session = self.fetch_or_insert_session_by_recipient_id(recipient_id);
 
         let _message = self.insert_call_log(
             Some(recipient_id),
@@ -49,17 +48,308 @@ impl<O: Observable + Default> super::Storage<O> {
                 calls::event.eq(event),
                 calls::timestamp.eq(timestamp),
                 calls::ringer.eq(ringer),
-                calls::is_read.eq(true),
+                calls::is_read.eq(is_outgoing || !event.is_missed_call()),
                 calls::local_joined.eq(false),
                 calls::group_call_active.eq(false),
             ))
             .returning(calls::id)
             .get_result(&mut *self.db())
             .expect("inserting a call");
+        self.observe_insert(calls::table, new_call_id)
+            .with_relation(schema::sessions::table, session.id);
 
         (session, new_call_id)
     }
 
+    /// Insert a one-to-one call, or update the event of the call we already know.
+    ///
+    /// Return (session, call ID)
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip(self))]
+    pub fn merge_one_to_one_call(
+        &self,
+        call_id: u64,
+        timestamp: NaiveDateTime,
+        recipient_id: i32,
+        r#type: orm::CallType,
+        is_outgoing: bool,
+        event: orm::EventType,
+        unidentified: bool,
+    ) -> (orm::Session, i32) {
+        let session = self.fetch_or_insert_session_by_recipient_id(recipient_id);
+        match self.fetch_call(call_id, session.id) {
+            Some(call) if call.deletion_timestamp.is_some() => {
+                tracing::debug!("call was deleted, not reviving it");
+                (session, call.id)
+            }
+            Some(call) => {
+                self.update_call_event(&call, event);
+                (session, call.id)
+            }
+            None => self.insert_one_to_one_call(
+                call_id,
+                timestamp,
+                recipient_id,
+                r#type,
+                is_outgoing,
+                event,
+                unidentified,
+            ),
+        }
+    }
+
+    /// Record which call, if any, is going on in a group, as (call ID, ringer).
+    ///
+    /// Other group calls in the session are marked inactive.
+    /// A newly seen call is inserted in the call log and in the conversation.
+    #[tracing::instrument(skip(self))]
+    pub fn set_active_group_call(&self, session_id: i32, active_call: Option<(u64, i32)>) {
+        use schema::calls;
+
+        let active_call_id = active_call.map(|(call_id, _)| call_id as i32);
+        let deactivated: Vec<i32> = calls::table
+            .select((calls::id, calls::call_id))
+            .filter(calls::session_id.eq(session_id))
+            .filter(calls::group_call_active.eq(true))
+            .load::<(i32, i32)>(&mut *self.db())
+            .expect("fetching active group calls")
+            .into_iter()
+            .filter(|(_, call_id)| Some(*call_id) != active_call_id)
+            .map(|(id, _)| id)
+            .collect();
+        diesel::update(calls::table)
+            .filter(calls::id.eq_any(&deactivated))
+            .set(calls::group_call_active.eq(false))
+            .execute(&mut *self.db())
+            .expect("deactivating group calls");
+        for id in deactivated {
+            self.observe_update(calls::table, id)
+                .with_relation(schema::sessions::table, session_id);
+        }
+
+        let Some((call_id, ringer)) = active_call else {
+            return;
+        };
+        if let Some(call) = self.fetch_call(call_id, session_id) {
+            if !call.group_call_active {
+                diesel::update(calls::table)
+                    .filter(calls::id.eq(call.id))
+                    .set(calls::group_call_active.eq(true))
+                    .execute(&mut *self.db())
+                    .expect("activating group call");
+                self.observe_update(calls::table, call.id)
+                    .with_relation(schema::sessions::table, session_id);
+            }
+            return;
+        }
+
+        let timestamp = chrono::Utc::now().naive_utc();
+        let is_outgoing = ringer == self.fetch_self_recipient_id();
+        let message_id = self.insert_call_log(
+            Some(ringer).filter(|_| !is_outgoing),
+            session_id,
+            orm::MessageType::GroupCall,
+            timestamp,
+            is_outgoing,
+            false,
+        );
+        let id: i32 = diesel::insert_into(calls::table)
+            .values((
+                calls::call_id.eq(call_id as i32),
+                calls::message_id.eq(Some(message_id)),
+                calls::session_id.eq(session_id),
+                calls::type_.eq(orm::CallType::Group),
+                calls::is_outbound.eq(is_outgoing),
+                calls::event.eq(orm::EventType::GenericGroupCall),
+                calls::timestamp.eq(timestamp),
+                calls::ringer.eq(ringer),
+                calls::is_read.eq(true),
+                calls::local_joined.eq(false),
+                calls::group_call_active.eq(true),
+            ))
+            .returning(calls::id)
+            .get_result(&mut *self.db())
+            .expect("inserting a group call");
+        self.observe_insert(calls::table, id)
+            .with_relation(schema::sessions::table, session_id);
+    }
+
+    /// We joined the group call that is going on in the session.
+    #[tracing::instrument(skip(self))]
+    pub fn set_group_call_joined(&self, session_id: i32) {
+        use schema::calls;
+
+        let joined: Vec<i32> = diesel::update(calls::table)
+            .filter(calls::session_id.eq(session_id))
+            .filter(calls::group_call_active.eq(true))
+            .filter(calls::local_joined.eq(false))
+            .set((
+                calls::local_joined.eq(true),
+                calls::event.eq(orm::EventType::Joined),
+            ))
+            .returning(calls::id)
+            .load(&mut *self.db())
+            .expect("joining group call");
+        for id in joined {
+            self.observe_update(calls::table, id)
+                .with_relation(schema::sessions::table, session_id);
+        }
+    }
+
+    // The call id is a random u64, of which the calls table only holds the lower half.
+    pub fn fetch_call(&self, call_id: u64, session_id: i32) -> Option<orm::Call> {
+        schema::calls::table
+            .filter(schema::calls::call_id.eq(call_id as i32))
+            .filter(schema::calls::session_id.eq(session_id))
+            .first(&mut *self.db())
+            .optional()
+            .expect("db")
+    }
+
+    pub fn fetch_call_by_call_id(&self, call_id: u64) -> Option<orm::Call> {
+        schema::calls::table
+            .filter(schema::calls::call_id.eq(call_id as i32))
+            .order_by(schema::calls::timestamp.desc())
+            .first(&mut *self.db())
+            .optional()
+            .expect("db")
+    }
+
+    /// The calls that were not cleared from the call log, most recent first.
+    pub fn fetch_call_log(&self) -> Vec<orm::AugmentedCall> {
+        use schema::{calls, sessions};
+
+        let calls: Vec<(orm::Call, Option<i32>)> = calls::table
+            .inner_join(sessions::table)
+            .select((calls::all_columns, sessions::direct_message_recipient_id))
+            .filter(calls::deletion_timestamp.is_null())
+            .order_by(calls::timestamp.desc())
+            .load(&mut *self.db())
+            .expect("db");
+        calls
+            .into_iter()
+            .map(|(inner, peer_recipient_id)| orm::AugmentedCall {
+                inner,
+                peer_recipient_id,
+            })
+            .collect()
+    }
+
+    /// Update the event of a call and the type of its message in the conversation.
+    #[tracing::instrument(skip(self, call), fields(call_id = call.id))]
+    pub fn update_call_event(&self, call: &orm::Call, event: orm::EventType) {
+        if call.event == event {
+            return;
+        }
+
+        diesel::update(schema::calls::table)
+            .filter(schema::calls::id.eq(call.id))
+            .set(schema::calls::event.eq(event))
+            .execute(&mut *self.db())
+            .expect("updating call event");
+        self.observe_update(schema::calls::table, call.id)
+            .with_relation(schema::sessions::table, call.session_id);
+
+        if let Some(message_id) = call.message_id {
+            let message_type =
+                orm::MessageType::from_call_type(call.r#type, call.is_outbound, event);
+            diesel::update(schema::messages::table)
+                .filter(schema::messages::id.eq(message_id))
+                .set(schema::messages::message_type.eq(message_type))
+                .execute(&mut *self.db())
+                .expect("updating call message");
+            self.observe_update(schema::messages::table, message_id)
+                .with_relation(schema::sessions::table, call.session_id);
+        }
+    }
+
+    /// Remove calls up to and including `timestamp` from the call log and their conversations,
+    /// optionally only those in one session.
+    ///
+    /// The rows stay around with a deletion timestamp, so that late events don't bring them back.
+    #[tracing::instrument(skip(self))]
+    pub fn clear_call_log(&self, timestamp: NaiveDateTime, session_id: Option<i32>) -> usize {
+        use schema::calls;
+
+        let mut query = calls::table
+            .select((calls::id, calls::session_id, calls::message_id))
+            .filter(calls::deletion_timestamp.is_null())
+            .filter(calls::timestamp.le(timestamp))
+            .into_boxed();
+        if let Some(session_id) = session_id {
+            query = query.filter(calls::session_id.eq(session_id));
+        }
+        let cleared: Vec<(i32, i32, Option<i32>)> =
+            query.load(&mut *self.db()).expect("fetching call log");
+
+        self.delete_calls(&cleared);
+
+        cleared.len()
+    }
+
+    /// Remove a single call from the call log and its conversation.
+    #[tracing::instrument(skip(self, call), fields(call_id = call.id))]
+    pub fn delete_call(&self, call: &orm::Call) {
+        if call.deletion_timestamp.is_none() {
+            self.delete_calls(&[(call.id, call.session_id, call.message_id)]);
+        }
+    }
+
+    /// Mark (id, session_id, message_id) calls as deleted, and delete their messages.
+    fn delete_calls(&self, calls: &[(i32, i32, Option<i32>)]) {
+        use schema::calls;
+
+        let now = chrono::Utc::now().naive_utc();
+        for (id, session_id, message_id) in calls {
+            if let Some(message_id) = message_id {
+                diesel::delete(schema::messages::table)
+                    .filter(schema::messages::id.eq(message_id))
+                    .execute(&mut *self.db())
+                    .expect("deleting call message");
+                self.observe_delete(schema::messages::table, *message_id)
+                    .with_relation(schema::sessions::table, *session_id);
+            }
+            diesel::update(calls::table)
+                .filter(calls::id.eq(id))
+                .set((
+                    calls::deletion_timestamp.eq(now),
+                    calls::message_id.eq(None::<i32>),
+                ))
+                .execute(&mut *self.db())
+                .expect("clearing call");
+            self.observe_update(calls::table, *id)
+                .with_relation(schema::sessions::table, *session_id);
+        }
+    }
+
+    /// Mark calls up to and including `timestamp` as read, optionally only those in one session.
+    #[tracing::instrument(skip(self))]
+    pub fn mark_call_log_read(&self, timestamp: NaiveDateTime, session_id: Option<i32>) -> usize {
+        use schema::calls;
+
+        let mut query = calls::table
+            .select((calls::id, calls::session_id))
+            .filter(calls::is_read.eq(false))
+            .filter(calls::timestamp.le(timestamp))
+            .into_boxed();
+        if let Some(session_id) = session_id {
+            query = query.filter(calls::session_id.eq(session_id));
+        }
+        let marked: Vec<(i32, i32)> = query.load(&mut *self.db()).expect("fetching call log");
+
+        diesel::update(calls::table)
+            .filter(calls::id.eq_any(marked.iter().map(|(id, _)| id)))
+            .set(calls::is_read.eq(true))
+            .execute(&mut *self.db())
+            .expect("marking call log read");
+        for (id, session_id) in &marked {
+            self.observe_update(calls::table, *id)
+                .with_relation(schema::sessions::table, *session_id);
+        }
+
+        marked.len()
+    }
+
     #[tracing::instrument(skip(self))]
     fn insert_call_log(
         &self,
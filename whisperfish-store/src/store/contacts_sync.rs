@@ -0,0 +1,123 @@
+//! Contacts, as synchronized from our primary device.
+//!
+//! The primary device sends its contacts as an attachment: a stream of length-delimited
+//! [`ContactDetails`](proto::ContactDetails), each one directly followed by its avatar image
+//! when it has one.
+
+use super::TrustLevel;
+use super::observer::Observable;
+use crate::{orm, schema};
+use anyhow::Context;
+use diesel::prelude::*;
+use libsignal_service::proto;
+use libsignal_service::protocol::Aci;
+use phonenumber::PhoneNumber;
+use prost::Message;
+use std::str::FromStr;
+
+/// One contact of a contacts sync stream.
+#[derive(Clone, Debug)]
+pub struct SyncedContact {
+    pub details: proto::ContactDetails,
+    /// The avatar image that the primary device shows for the contact.
+    pub avatar: Option<Vec<u8>>,
+}
+
+/// Parse a decrypted contacts sync attachment.
+pub fn parse_contacts_stream(mut blob: &[u8]) -> anyhow::Result<Vec<SyncedContact>> {
+    let mut contacts = Vec::new();
+    while !blob.is_empty() {
+        let details = proto::ContactDetails::decode_length_delimited(&mut blob)
+            .context("malformed contact details")?;
+        let avatar_len = details
+            .avatar
+            .as_ref()
+            .and_then(|avatar| avatar.length)
+            .unwrap_or(0) as usize;
+        let avatar = if avatar_len > 0 {
+            anyhow::ensure!(blob.len() >= avatar_len, "truncated contact avatar");
+            let (avatar, rest) = blob.split_at(avatar_len);
+            blob = rest;
+            Some(avatar.to_vec())
+        } else {
+            None
+        };
+        contacts.push(SyncedContact { details, avatar });
+    }
+    Ok(contacts)
+}
+
+fn parse_aci(details: &proto::ContactDetails) -> Option<Aci> {
+    details
+        .aci_binary
+        .as_deref()
+        .and_then(|aci| uuid::Uuid::from_slice(aci).ok())
+        .or_else(|| {
+            details
+                .aci
+                .as_deref()
+                .and_then(|aci| uuid::Uuid::parse_str(aci).ok())
+        })
+        .map(Aci::from)
+}
+
+impl<O: Observable> super::Storage<O> {
+    /// Merge a contact of our primary device into our recipients.
+    ///
+    /// Names only fill in for recipients whose profile we don't know yet, and the disappearing
+    /// messages timer only applies when it is newer than ours.  Contacts with an inbox position
+    /// have a conversation on the primary device, so they get a session here too.
+    ///
+    /// Returns the updated recipient, or `None` if the contact could not be identified, or is
+    /// ourselves.
+    #[tracing::instrument(skip(self, details))]
+    pub fn merge_synced_contact(&self, details: &proto::ContactDetails) -> Option<orm::Recipient> {
+        let aci = parse_aci(details);
+        let e164 = details
+            .number
+            .as_deref()
+            .filter(|number| !number.is_empty())
+            .and_then(|number| match PhoneNumber::from_str(number) {
+                Ok(e164) => Some(e164),
+                Err(e) => {
+                    tracing::warn!("Ignoring unparsable phone number in synced contact: {e}");
+                    None
+                }
+            });
+        if aci.is_none() && e164.is_none() {
+            tracing::warn!("Synced contact without any identifier, ignoring");
+            return None;
+        }
+
+        let recipient = self.merge_and_fetch_recipient(e164, aci, None, TrustLevel::Uncertain);
+        if self.fetch_self_recipient().map(|r| r.id) == Some(recipient.id) {
+            tracing::debug!("Ignoring synced contact of self");
+            return None;
+        }
+
+        if recipient.profile_joined_name.is_none()
+            && let Some(name) = details.name.as_deref().filter(|name| !name.is_empty())
+        {
+            use schema::recipients::dsl::*;
+            diesel::update(recipients.filter(id.eq(recipient.id)))
+                .set(profile_joined_name.eq(name))
+                .execute(&mut *self.db())
+                .expect("db");
+        }
+        self.observe_update(schema::recipients::table, recipient.id);
+
+        let session = if details.inbox_position.is_some() {
+            Some(self.fetch_or_insert_session_by_recipient_id(recipient.id))
+        } else {
+            self.fetch_session_by_recipient_id(recipient.id)
+        };
+        if let Some(session) = session {
+            let version = details.expire_timer_version.unwrap_or(0);
+            if version as i32 > session.expire_timer_version {
+                self.update_expiration_timer(&session, details.expire_timer, Some(version));
+            }
+        }
+
+        self.fetch_recipient_by_id(recipient.id)
+    }
+}
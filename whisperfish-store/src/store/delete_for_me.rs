@@ -0,0 +1,184 @@
+use super::observer::Observable;
+use crate::orm::{self, StoryType};
+use crate::schema;
+use chrono::prelude::*;
+use diesel::prelude::*;
+use libsignal_service::proto::AttachmentPointer;
+use prost::Message;
+
+/// How many of the most recent messages identify a conversation that gets deleted.
+pub const CONVERSATION_DELETE_MESSAGE_COUNT: i64 = 5;
+
+/// A message as our other devices know it: by its author and sent timestamp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageAddress {
+    pub author_id: i32,
+    pub sent_timestamp: NaiveDateTime,
+}
+
+impl<O: Observable> super::Storage<O> {
+    #[tracing::instrument(skip(self))]
+    pub fn message_address(&self, message: &orm::Message) -> Option<MessageAddress> {
+        let author_id = if message.is_outbound {
+            self.fetch_self_recipient_id()
+        } else {
+            message.sender_recipient_id?
+        };
+        Some(MessageAddress {
+            author_id,
+            sent_timestamp: message.server_timestamp,
+        })
+    }
+
+    /// The message at `address`, within session `sid` when the conversation is known.
+    ///
+    /// Our own messages are only told apart by their timestamp, which can be shared by messages
+    /// in different conversations.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_message_by_address(
+        &self,
+        sid: Option<i32>,
+        address: MessageAddress,
+    ) -> Option<orm::Message> {
+        use schema::messages::dsl::*;
+
+        let query = messages
+            .filter(server_timestamp.eq(address.sent_timestamp))
+            .into_boxed();
+        let query = match sid {
+            Some(sid) => query.filter(session_id.eq(sid)),
+            None => query,
+        };
+        let query = if address.author_id == self.fetch_self_recipient_id() {
+            query.filter(is_outbound.eq(true))
+        } else {
+            query
+                .filter(is_outbound.eq(false))
+                .filter(sender_recipient_id.eq(address.author_id))
+        };
+        query.first(&mut *self.db()).optional().expect("db")
+    }
+
+    /// The most recent messages of a session, newest first.
+    ///
+    /// These identify the conversation to our other devices when we delete it.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_most_recent_messages(
+        &self,
+        sid: i32,
+        only_non_expiring: bool,
+    ) -> Vec<orm::Message> {
+        use schema::messages::dsl::*;
+
+        let query = messages
+            .filter(session_id.eq(sid))
+            .filter(story_type.eq(StoryType::None))
+            .filter(
+                latest_revision_id
+                    .is_null()
+                    .or(latest_revision_id.eq(id.nullable())),
+            )
+            .order_by(server_timestamp.desc())
+            .limit(CONVERSATION_DELETE_MESSAGE_COUNT)
+            .into_boxed();
+        let query = if only_non_expiring {
+            query.filter(expires_in.is_null())
+        } else {
+            query
+        };
+        query.load(&mut *self.db()).expect("db")
+    }
+
+    /// Delete a conversation that was deleted on another device.
+    ///
+    /// The other device sends its most recent messages of the conversation. Everything up to
+    /// the newest one of those that we know of is deleted, such that messages that only arrived
+    /// here are kept. The non-expiring messages are a fallback for when the most recent ones
+    /// expired already. If we know none of them, nothing is deleted.
+    ///
+    /// With `full_delete`, the session itself is deleted too when no messages remain.
+    /// Returns whether any message was deleted.
+    #[tracing::instrument(skip(self))]
+    pub fn delete_conversation_for_me(
+        &mut self,
+        session_id: i32,
+        most_recent: &[MessageAddress],
+        most_recent_non_expiring: &[MessageAddress],
+        full_delete: bool,
+    ) -> bool {
+        let newest_known = |addresses: &[MessageAddress]| {
+            addresses
+                .iter()
+                .filter_map(|address| self.fetch_message_by_address(Some(session_id), *address))
+                .map(|message| message.server_timestamp)
+                .max()
+        };
+        let Some(until) =
+            newest_known(most_recent).or_else(|| newest_known(most_recent_non_expiring))
+        else {
+            tracing::warn!("None of the addressed messages are known, not deleting conversation");
+            return false;
+        };
+
+        let ids: Vec<i32> = schema::messages::table
+            .select(schema::messages::id)
+            .filter(schema::messages::session_id.eq(session_id))
+            .filter(schema::messages::server_timestamp.le(until))
+            .load(&mut *self.db())
+            .expect("db");
+        for id in &ids {
+            self.delete_attachments_for_message(*id);
+        }
+        diesel::delete(schema::messages::table)
+            .filter(schema::messages::id.eq_any(&ids))
+            .execute(&mut *self.db())
+            .expect("delete conversation messages");
+        for id in &ids {
+            self.observe_delete(schema::messages::table, *id)
+                .with_relation(schema::sessions::table, session_id);
+        }
+        tracing::trace!("Deleted {} messages", ids.len());
+
+        if full_delete && self.fetch_last_message_by_session_id(session_id).is_none() {
+            self.delete_session(session_id);
+        }
+
+        !ids.is_empty()
+    }
+
+    /// Delete an attachment that was deleted on another device.
+    ///
+    /// The attachment is recognized by the client uuid of its pointer, or else its digest.
+    #[tracing::instrument(skip(self, client_uuid, digest))]
+    pub fn delete_attachment_for_me(
+        &mut self,
+        message_id: i32,
+        client_uuid: Option<&[u8]>,
+        digest: Option<&[u8]>,
+    ) -> bool {
+        let attachment = self
+            .fetch_attachments_for_message(message_id)
+            .into_iter()
+            .find(|attachment| {
+                let pointer = attachment
+                    .pointer
+                    .as_deref()
+                    .and_then(|pointer| AttachmentPointer::decode(pointer).ok());
+                let uuid_matches = client_uuid.is_some()
+                    && pointer.and_then(|pointer| pointer.client_uuid).as_deref() == client_uuid;
+                let digest_matches = digest.is_some() && attachment.data_hash.as_deref() == digest;
+                uuid_matches || digest_matches
+            });
+
+        match attachment {
+            Some(attachment) => {
+                self.delete_attachment(&attachment);
+                true
+            }
+            None => {
+                tracing::warn!("Attachment to delete not found");
+                false
+            }
+        }
+    }
+}
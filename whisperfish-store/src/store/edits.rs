@@ -0,0 +1,59 @@
+use super::observer::Observable;
+use crate::orm::{self, StoryType};
+use anyhow::Context;
+use chrono::prelude::*;
+use std::time::Duration;
+
+/// Like Signal, messages can be edited up to a day after sending them.
+pub const MESSAGE_EDIT_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The number of times a message can be edited.
+pub const MAX_MESSAGE_EDITS: i32 = 10;
+
+impl<O: Observable> super::Storage<O> {
+    /// Fetch the original message and its latest revision, if we may send an edit of it.
+    ///
+    /// `message_id` can refer to any revision of the message.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_editable_message(
+        &self,
+        message_id: i32,
+        now: NaiveDateTime,
+    ) -> anyhow::Result<(orm::Message, orm::Message)> {
+        let message = self
+            .fetch_message_by_id(message_id)
+            .context("message does not exist")?;
+        let original = self
+            .fetch_message_by_id(message.original_message_id())
+            .context("original message does not exist")?;
+        let latest = self
+            .fetch_message_by_id(message.latest_revision_id())
+            .context("latest revision does not exist")?;
+
+        if !original.is_outbound {
+            anyhow::bail!("only our own messages can be edited");
+        }
+        if original.is_remote_deleted {
+            anyhow::bail!("message was deleted");
+        }
+        if original.story_type != StoryType::None
+            || original.message_type.is_some()
+            || original.flags != 0
+        {
+            anyhow::bail!("only ordinary messages can be edited");
+        }
+        if original.sent_timestamp.is_none() || latest.sent_timestamp.is_none() {
+            anyhow::bail!("message was not sent yet");
+        }
+        if now - original.server_timestamp
+            > chrono::Duration::from_std(MESSAGE_EDIT_WINDOW).expect("edit window")
+        {
+            anyhow::bail!("message is too old to be edited");
+        }
+        if latest.revision >= MAX_MESSAGE_EDITS {
+            anyhow::bail!("message was edited too often");
+        }
+
+        Ok((original, latest))
+    }
+}
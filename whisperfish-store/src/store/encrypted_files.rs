@@ -0,0 +1,314 @@
+use super::Storage;
+use super::encryption::StorageEncryption;
+use super::observer::Observable;
+use super::stream_cipher::{FRAME_SIZE, Format, StreamReader, StreamWriter};
+use anyhow::Context;
+use rand::RngCore;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// Attachment and avatar files, encrypted with a key derived from the storage key and a random salt.
+static FORMAT: Format = Format {
+    magic: b"WFATTACH",
+    version: 1,
+    params_len: SALT_LEN,
+    name: "encrypted file",
+    key_name: "storage key",
+};
+const SALT_LEN: usize = 16;
+
+/// Name of the directory that holds decrypted copies of attachments, for other applications.
+const PLAINTEXT_DIR: &str = "whisperfish-plaintext";
+
+/// Encrypts a file with its own key, derived from the storage key and a random salt.
+fn encrypted_file_writer<W: Write>(
+    inner: W,
+    store_enc: &StorageEncryption,
+) -> io::Result<StreamWriter<W>> {
+    let mut salt = [0u8; SALT_LEN];
+    rand::rng().fill_bytes(&mut salt);
+    let cipher = store_enc.file_cipher(&salt);
+    StreamWriter::new(inner, FORMAT.new_header(&salt), cipher)
+}
+
+/// Decrypts and authenticates a file written by [encrypted_file_writer].
+fn encrypted_file_reader<R: Read>(
+    mut inner: R,
+    store_enc: &StorageEncryption,
+) -> io::Result<StreamReader<R>> {
+    let header = FORMAT.read_header(&mut inner)?;
+    let cipher = store_enc.file_cipher(header.params());
+    Ok(StreamReader::new(inner, header, cipher))
+}
+
+/// Whether a file starts like an encrypted Whisperfish file. The file is rewound afterwards.
+fn is_encrypted_file(file: &mut File) -> io::Result<bool> {
+    let encrypted = FORMAT.is_format(file)?;
+    file.rewind()?;
+    Ok(encrypted)
+}
+
+/// Encrypts `plaintext` in the encrypted file format.
+fn encrypt(store_enc: &StorageEncryption, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+    let mut writer = encrypted_file_writer(
+        Vec::with_capacity(
+            FORMAT.header_len() + plaintext.len() + plaintext.len() / FRAME_SIZE * 20 + 20,
+        ),
+        store_enc,
+    )?;
+    writer.write_all(plaintext)?;
+    writer.finish()
+}
+
+fn open(path: &Path, store_enc: Option<&StorageEncryption>) -> io::Result<Box<dyn Read + Send>> {
+    let mut file = File::open(path)?;
+    if !is_encrypted_file(&mut file)? {
+        return Ok(Box::new(BufReader::new(file)));
+    }
+    let store_enc = store_enc.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "encrypted file in an unencrypted storage",
+        )
+    })?;
+    Ok(Box::new(encrypted_file_reader(
+        BufReader::new(file),
+        store_enc,
+    )?))
+}
+
+fn read(path: &Path, store_enc: Option<&StorageEncryption>) -> anyhow::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    open(path, store_enc)
+        .and_then(|mut reader| reader.read_to_end(&mut contents))
+        .with_context(|| format!("Could not read attachment file {}", path.display()))?;
+    Ok(contents)
+}
+
+/// The directory of decrypted copies of attachments.
+///
+/// The runtime directory lives in memory and is only accessible by the user, which makes it the
+/// least bad place for plaintext.
+fn plaintext_dir() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(PLAINTEXT_DIR)
+}
+
+impl<O: Observable> Storage<O> {
+    /// The contents of an attachment file as they should be written to disk.
+    ///
+    /// In an encrypted storage, attachments are encrypted with a key derived from the storage key.
+    pub(super) fn attachment_file_contents<'a>(
+        &self,
+        attachment: &'a [u8],
+    ) -> io::Result<std::borrow::Cow<'a, [u8]>> {
+        match self.store_enc.as_ref() {
+            Some(store_enc) => encrypt(store_enc, attachment).map(Into::into),
+            None => Ok(attachment.into()),
+        }
+    }
+
+    /// Writes a file of the storage that is no attachment, like an avatar or a sticker, to `path`.
+    /// It is encrypted like an attachment when the storage is encrypted.
+    ///
+    /// Read it back with [Self::read_attachment], or through the decrypting image provider.
+    #[tracing::instrument(skip(self, file), fields(path = %path.as_ref().display()))]
+    pub async fn save_file(&self, path: impl AsRef<Path>, file: &[u8]) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let contents = self.attachment_file_contents(file)?;
+        super::utils::write_file_async(path, &contents)
+            .await
+            .with_context(|| format!("Could not write {}", path.display()))
+    }
+
+    /// Streams the contents of an attachment into a randomly-named file in `destination`, like
+    /// [Self::save_attachment] does for contents in memory. This blocks on file I/O.
+    #[tracing::instrument(skip(self, attachment))]
+    pub fn save_attachment_from_reader(
+        &self,
+        attachment_id: i32,
+        destination: &Path,
+        ext: &str,
+        attachment: &mut dyn Read,
+    ) -> anyhow::Result<PathBuf> {
+        let path = Self::new_attachment_path(destination, ext);
+
+        let mut write = || -> io::Result<()> {
+            let file = BufWriter::new(File::create(&path)?);
+            let file = match self.store_enc.as_ref() {
+                Some(store_enc) => {
+                    let mut writer = encrypted_file_writer(file, store_enc)?;
+                    io::copy(attachment, &mut writer)?;
+                    writer.finish()?
+                }
+                None => {
+                    let mut file = file;
+                    io::copy(attachment, &mut file)?;
+                    file
+                }
+            };
+            file.into_inner().map_err(|e| e.into_error())?.sync_all()
+        };
+        if let Err(e) = write() {
+            std::fs::remove_file(&path).ok();
+            return Err(e).with_context(|| {
+                format!(
+                    "Could not create and write to attachment file: {}",
+                    path.display()
+                )
+            });
+        }
+
+        self.set_attachment_file(attachment_id, &path);
+        Ok(path)
+    }
+
+    /// Opens an attachment file for reading, decrypting it on the fly when it is encrypted.
+    ///
+    /// Plaintext files are read as they are, such that files that were not written by Whisperfish
+    /// (e.g. a picture that is being sent) and files from before the storage encrypted its
+    /// attachments keep working.
+    pub fn open_attachment(&self, path: impl AsRef<Path>) -> io::Result<Box<dyn Read + Send>> {
+        open(path.as_ref(), self.store_enc.as_ref())
+    }
+
+    /// Reads the decrypted contents of an attachment file.
+    pub fn read_attachment_sync(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
+        read(path.as_ref(), self.store_enc.as_ref())
+    }
+
+    /// Reads the decrypted contents of an attachment file, without blocking the executor.
+    pub async fn read_attachment(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
+        let store_enc = self.store_enc.clone();
+        let path = path.as_ref().to_owned();
+        tokio::task::spawn_blocking(move || read(&path, store_enc.as_ref())).await?
+    }
+
+    /// Writes the decrypted contents of an attachment file to `destination`, which is meant to
+    /// leave the storage, and returns the number of bytes written.
+    pub fn export_attachment(
+        &self,
+        path: impl AsRef<Path>,
+        destination: impl AsRef<Path>,
+    ) -> anyhow::Result<u64> {
+        let (path, destination) = (path.as_ref(), destination.as_ref());
+        let mut reader = self.open_attachment(path)?;
+        let mut file = BufWriter::new(File::create(destination)?);
+        let written = io::copy(&mut reader, &mut file)
+            .with_context(|| format!("Could not export attachment file {}", path.display()))?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(written)
+    }
+
+    /// A plaintext version of an attachment file, for applications that cannot decrypt it.
+    ///
+    /// Plaintext files are returned as they are. Encrypted files are decrypted into a temporary
+    /// copy, which is removed by [Self::clear_plaintext_attachments].
+    pub fn plaintext_attachment(&self, path: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
+        let path = path.as_ref();
+        if !is_encrypted_file(&mut File::open(path)?)? {
+            return Ok(path.to_owned());
+        }
+
+        let dir = plaintext_dir();
+        std::fs::create_dir_all(&dir)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+        }
+        let destination = dir.join(path.file_name().context("attachment file name")?);
+        if !destination.exists() {
+            // Decrypt next to the destination, such that a failed or interrupted decryption
+            // never leaves a partial copy behind under the final name.
+            let temp = tempfile::NamedTempFile::new_in(&dir)?;
+            self.export_attachment(path, temp.path())?;
+            temp.persist(&destination)
+                .with_context(|| format!("Could not move {}", destination.display()))?;
+        }
+        Ok(destination)
+    }
+
+    /// The plaintext version of an attachment file, when it is available without decrypting.
+    ///
+    /// In an unencrypted storage, that is the file itself. In an encrypted storage, it is the
+    /// copy left by an earlier [Self::plaintext_attachment]. This does not read the file.
+    pub fn cached_plaintext_attachment(&self, path: impl AsRef<Path>) -> Option<PathBuf> {
+        let path = path.as_ref();
+        if !self.is_encrypted() {
+            return Some(path.to_owned());
+        }
+        let destination = plaintext_dir().join(path.file_name()?);
+        destination.exists().then_some(destination)
+    }
+
+    /// Removes the temporary copies made by [Self::plaintext_attachment].
+    pub fn clear_plaintext_attachments() {
+        let dir = plaintext_dir();
+        if dir.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                tracing::warn!("Could not remove plaintext attachments: {e}");
+            }
+        }
+    }
+
+    /// Encrypts a plaintext file in place, if the storage is encrypted. Returns whether the file
+    /// was encrypted.
+    ///
+    /// The encrypted file is written next to the original and then renamed over it, such that
+    /// an interruption never leaves a half-encrypted file behind.
+    pub fn encrypt_file_in_place(&self, path: impl AsRef<Path>) -> anyhow::Result<bool> {
+        let path = path.as_ref();
+        let Some(store_enc) = self.store_enc.as_ref() else {
+            return Ok(false);
+        };
+        let mut file = File::open(path)?;
+        if is_encrypted_file(&mut file)? {
+            return Ok(false);
+        }
+
+        let dir = path.parent().context("file in a directory")?;
+        let temp = tempfile::NamedTempFile::new_in(dir)?;
+        let mut writer = encrypted_file_writer(BufWriter::new(temp), store_enc)?;
+        io::copy(&mut BufReader::new(file), &mut writer)?;
+        let temp = writer.finish()?.into_inner().map_err(|e| e.into_error())?;
+        temp.as_file().sync_all()?;
+        temp.persist(path)
+            .with_context(|| format!("Could not replace {}", path.display()))?;
+        Ok(true)
+    }
+
+    /// Encrypts the plaintext files in `dir` and its subdirectories, e.g. the attachments and
+    /// stickers that were saved before the storage encrypted its attachments. Returns the number
+    /// of files that were encrypted.
+    pub fn encrypt_files_in_dir(&self, dir: impl AsRef<Path>) -> anyhow::Result<usize> {
+        let dir = dir.as_ref();
+        if !self.is_encrypted() || !dir.exists() {
+            return Ok(0);
+        }
+
+        let mut count = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                count += self.encrypt_files_in_dir(entry.path())?;
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+            match self.encrypt_file_in_place(entry.path()) {
+                Ok(true) => count += 1,
+                Ok(false) => {}
+                Err(e) => tracing::warn!("Could not encrypt {}: {e:#}", entry.path().display()),
+            }
+        }
+        Ok(count)
+    }
+}
@@ -164,6 +164,17 @@ impl StorageEncryption {
     pub fn get_database_key(&self) -> &[u8] {
         self.key_database.expose_secret()
     }
+
+    /// Derives the AES-256-GCM cipher of a single encrypted file from the storage key.
+    pub fn file_cipher(&self, salt: &[u8]) -> aes_gcm::Aes256Gcm {
+        use aes_gcm::aead::KeyInit;
+
+        let mut key = [0u8; 32];
+        hkdf::Hkdf::<sha2::Sha256>::new(Some(salt), self.key_storage.expose_secret())
+            .expand(b"Whisperfish file encryption", &mut key)
+            .expect("32 bytes is a valid HKDF output length");
+        aes_gcm::Aes256Gcm::new_from_slice(&key).expect("32 byte AES key")
+    }
 }
 
 #[cfg(test)]
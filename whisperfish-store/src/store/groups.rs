@@ -0,0 +1,1194 @@
+//! Creating and changing Groups V2 on the group server.
+//!
+//! The group server only ever stores encrypted group state.  Every attribute is encrypted with
+//! the group's secret params, and members are added by presenting a zero-knowledge proof of
+//! their profile key credential, which the server verifies without learning who they are.
+//!
+//! Members for whom no profile key credential can be obtained are invited instead, as members
+//! pending their profile key.
+//!
+//! Changes to an existing group are submitted as a `GroupChange.Actions` for the next revision.
+//! The server signs the accepted change, which is then applied locally and sent to the members
+//! in the group context of a data message, such that they can apply it without refetching the
+//! whole group.
+//!
+//! Groups can also be joined through an invite link, which carries the master key of the group
+//! and the password of the link.  Depending on the access control of the group, joining either
+//! adds us as a member directly, or asks the administrators to approve our request.
+use super::GroupV2;
+use super::observer::Observable;
+use crate::orm::{self, AccessRequired};
+use anyhow::Context;
+use base64::prelude::*;
+use chrono::prelude::*;
+use libsignal_service::groups_v2::Role;
+use libsignal_service::proto::{self, GroupAttributeBlob, group_attribute_blob, group_invite_link};
+use libsignal_service::protocol::{Aci, ServiceId};
+use libsignal_service::zkgroup::api::groups::GroupSecretParams;
+use libsignal_service::zkgroup::profiles::{
+    ExpiringProfileKeyCredential, ExpiringProfileKeyCredentialResponse, ProfileKey,
+    ProfileKeyCredentialRequest, ProfileKeyVersion,
+};
+use libsignal_service::zkgroup::{
+    GROUP_MASTER_KEY_LEN, GroupMasterKey, PROFILE_KEY_LEN, RANDOMNESS_LEN, RandomnessBytes,
+    ServerPublicParams, Timestamp,
+};
+use prost::Message;
+use rand::RngCore;
+
+/// The parts of the group server API that are needed to create and change groups.
+#[async_trait::async_trait]
+pub trait GroupsServiceApi {
+    /// Fetch an expiring profile key credential for the profile of `aci`.
+    ///
+    /// Returns `None` when the profile has no version for the profile key we know of, in which
+    /// case the member can only be invited.
+    async fn fetch_profile_key_credential(
+        &mut self,
+        aci: Aci,
+        version: ProfileKeyVersion,
+        request: ProfileKeyCredentialRequest,
+    ) -> anyhow::Result<Option<ExpiringProfileKeyCredentialResponse>>;
+
+    /// Upload an encrypted group avatar, returning its key on the CDN.
+    async fn upload_avatar(
+        &mut self,
+        secret: &GroupSecretParams,
+        encrypted_avatar: Vec<u8>,
+    ) -> anyhow::Result<String>;
+
+    /// Create a group at revision 0.
+    async fn create_group(
+        &mut self,
+        secret: &GroupSecretParams,
+        group: proto::Group,
+    ) -> anyhow::Result<()>;
+
+    /// Submit `actions` as the next revision of a group, returning the change signed by the
+    /// server.
+    ///
+    /// Fails with [RevisionConflict] when the group is at a later revision on the server.
+    async fn modify_group(
+        &mut self,
+        secret: &GroupSecretParams,
+        actions: proto::group_change::Actions,
+    ) -> anyhow::Result<proto::GroupChange>;
+
+    /// Fetch what the invite link with `password` reveals about a group.
+    async fn fetch_group_join_info(
+        &mut self,
+        secret: &GroupSecretParams,
+        password: &[u8],
+    ) -> anyhow::Result<proto::GroupJoinInfo>;
+
+    /// Submit `actions` to join a group through the invite link with `password`, returning the
+    /// change signed by the server.
+    async fn join_group(
+        &mut self,
+        secret: &GroupSecretParams,
+        password: &[u8],
+        actions: proto::group_change::Actions,
+    ) -> anyhow::Result<proto::GroupChange>;
+}
+
+/// The group server refused a change, because it was not made against the current revision of
+/// the group.  The group has to be refetched before the change can be submitted again.
+#[derive(Clone, Copy, Debug)]
+pub struct RevisionConflict;
+
+impl std::fmt::Display for RevisionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the group changed on the server")
+    }
+}
+
+impl std::error::Error for RevisionConflict {}
+
+#[derive(Clone, Debug, Default)]
+pub struct NewGroupV2 {
+    pub title: String,
+    pub description: Option<String>,
+    /// The avatar image, unencrypted.
+    pub avatar: Option<Vec<u8>>,
+    /// Disappearing messages timer, in seconds.
+    pub expire_timer: Option<u32>,
+    /// The members to add, besides ourselves.
+    pub members: Vec<orm::Recipient>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CreatedGroupV2 {
+    pub session: orm::Session,
+    pub master_key: [u8; GROUP_MASTER_KEY_LEN],
+}
+
+/// The length of a newly generated invite link password.
+const INVITE_LINK_PASSWORD_LEN: usize = 16;
+
+/// The contents of a `https://signal.group/#...` invite link.
+#[derive(Clone, PartialEq, Eq)]
+pub struct GroupInviteLink {
+    pub master_key: [u8; GROUP_MASTER_KEY_LEN],
+    pub password: Vec<u8>,
+}
+
+impl std::fmt::Debug for GroupInviteLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Anyone with the link can join the group, so keep it out of the logs.
+        f.debug_struct("GroupInviteLink").finish_non_exhaustive()
+    }
+}
+
+impl GroupInviteLink {
+    /// The invite link of a group, if it has one.
+    pub fn of_group(group_v2: &orm::GroupV2) -> Option<Self> {
+        let password = group_v2.invite_link_password.clone()?;
+        if AccessRequired::from(group_v2.access_required_for_add_from_invite_link)
+            == AccessRequired::Unsatisfiable
+        {
+            return None;
+        }
+        let master_key = hex::decode(&group_v2.master_key).ok()?.try_into().ok()?;
+        Some(Self {
+            master_key,
+            password,
+        })
+    }
+
+    /// Parse a `https://signal.group/#...` or `sgnl://signal.group/#...` link.
+    pub fn parse(url: &str) -> anyhow::Result<Self> {
+        let url = url.trim();
+        let fragment = ["https://signal.group/#", "sgnl://signal.group/#"]
+            .iter()
+            .find_map(|prefix| url.strip_prefix(prefix))
+            .context("not a signal.group link")?;
+        let bytes = BASE64_URL_SAFE_NO_PAD
+            .decode(fragment.trim_end_matches('='))
+            .context("malformed invite link")?;
+        let link = proto::GroupInviteLink::decode(&bytes[..]).context("malformed invite link")?;
+        let Some(group_invite_link::Contents::V1Contents(contents)) = link.contents else {
+            anyhow::bail!("unsupported invite link version");
+        };
+        Ok(Self {
+            master_key: contents
+                .group_master_key
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("invalid master key length"))?,
+            password: contents.invite_link_password,
+        })
+    }
+
+    pub fn to_url(&self) -> String {
+        let link = proto::GroupInviteLink {
+            contents: Some(group_invite_link::Contents::V1Contents(
+                group_invite_link::GroupInviteLinkContentsV1 {
+                    group_master_key: self.master_key.to_vec(),
+                    invite_link_password: self.password.clone(),
+                },
+            )),
+        };
+        format!(
+            "https://signal.group/#{}",
+            BASE64_URL_SAFE_NO_PAD.encode(link.encode_to_vec())
+        )
+    }
+
+    fn secret(&self) -> GroupSecretParams {
+        GroupSecretParams::derive_from_master_key(GroupMasterKey::new(self.master_key))
+    }
+}
+
+/// What an invite link reveals about its group, before joining it.
+#[derive(Clone, Debug)]
+pub struct GroupInvitePreview {
+    pub title: String,
+    pub description: Option<String>,
+    pub member_count: u32,
+    /// Whether an administrator has to approve joining.
+    pub requires_approval: bool,
+    /// Whether we already asked to join, and wait for approval.
+    pub pending_approval: bool,
+}
+
+/// A group that was joined through an invite link.
+#[derive(Clone, Debug)]
+pub struct JoinedGroupV2 {
+    pub session: orm::Session,
+    /// The group context that announces our joining to the members, or `None` if we asked to
+    /// join and wait for an administrator to approve.
+    pub context: Option<proto::GroupContextV2>,
+}
+
+/// A change to an existing group, as made by one of its members.
+#[derive(Clone, Debug)]
+pub enum GroupV2Action {
+    AddMember(orm::Recipient),
+    RemoveMember(orm::Recipient),
+    SetMemberRole(orm::Recipient, Role),
+    BanMember(orm::Recipient),
+    UnbanMember(orm::Recipient),
+    Title(String),
+    Description(Option<String>),
+    /// The new avatar image, unencrypted, or `None` to remove the avatar.
+    Avatar(Option<Vec<u8>>),
+    /// Disappearing messages timer in seconds, or `None` to disable it.
+    ExpireTimer(Option<u32>),
+    AnnouncementsOnly(bool),
+    AttributesAccess(AccessRequired),
+    MembersAccess(AccessRequired),
+    MemberLabelAccess(AccessRequired),
+    /// Our own label in the group, and its emoji.
+    MemberLabel {
+        label: Option<String>,
+        emoji: Option<String>,
+    },
+    /// Accept the invitation to the group, as a pending member.
+    AcceptInvitation,
+    /// Decline the invitation to the group, as a pending member.
+    DeclineInvitation,
+    /// Let a member that asked to join through the invite link into the group.
+    ApproveRequest(orm::Recipient),
+    DenyRequest(orm::Recipient),
+    /// Who may join through the invite link; `Unsatisfiable` disables the link.
+    InviteLinkAccess(AccessRequired),
+    /// Replace the invite link, such that the current link stops working.
+    ResetInviteLink,
+    /// Leave the group.  When we are its last administrator, the longest-standing other member
+    /// is made administrator in the same change.
+    Leave,
+}
+
+impl GroupV2Action {
+    /// The access level this action requires, given the access control of the group.
+    fn access_required(&self, group_v2: &orm::GroupV2) -> AccessRequired {
+        match self {
+            Self::AddMember(_) => group_v2.access_required_for_members.into(),
+            Self::Title(_) | Self::Description(_) | Self::Avatar(_) | Self::ExpireTimer(_) => {
+                group_v2.access_required_for_attributes.into()
+            }
+            Self::MemberLabel { .. } => group_v2.access_required_for_member_labels.into(),
+            Self::Leave => AccessRequired::Member,
+            Self::RemoveMember(_)
+            | Self::SetMemberRole(..)
+            | Self::BanMember(_)
+            | Self::UnbanMember(_)
+            | Self::AnnouncementsOnly(_)
+            | Self::AttributesAccess(_)
+            | Self::MembersAccess(_)
+            | Self::MemberLabelAccess(_)
+            | Self::ApproveRequest(_)
+            | Self::DenyRequest(_)
+            | Self::InviteLinkAccess(_)
+            | Self::ResetInviteLink => AccessRequired::Administrator,
+            // Pending members are not members yet; they may only answer their own invitation.
+            Self::AcceptInvitation | Self::DeclineInvitation => AccessRequired::Unsatisfiable,
+        }
+    }
+}
+
+/// Whether a member with `role` may do what requires `access`.
+///
+/// An unknown access level is treated as requiring an administrator.
+fn has_access(role: i32, access: AccessRequired) -> bool {
+    let is_admin = role == Role::Administrator as i32;
+    match access {
+        AccessRequired::Any | AccessRequired::Member => true,
+        AccessRequired::Administrator | AccessRequired::Unknown => is_admin,
+        AccessRequired::Unsatisfiable => false,
+    }
+}
+
+fn randomness() -> RandomnessBytes {
+    let mut randomness = [0u8; RANDOMNESS_LEN];
+    rand::rng().fill_bytes(&mut randomness);
+    randomness
+}
+
+fn encrypt_attribute(
+    secret: &GroupSecretParams,
+    content: group_attribute_blob::Content,
+) -> Vec<u8> {
+    let blob = GroupAttributeBlob {
+        content: Some(content),
+    };
+    secret.encrypt_blob_with_padding(randomness(), &blob.encode_to_vec(), 0)
+}
+
+fn decrypt_attribute(
+    secret: &GroupSecretParams,
+    blob: &[u8],
+) -> Option<group_attribute_blob::Content> {
+    let blob = secret.decrypt_blob_with_padding(blob).ok()?;
+    GroupAttributeBlob::decode(&blob[..]).ok()?.content
+}
+
+fn invite_link_password() -> Vec<u8> {
+    let mut password = vec![0u8; INVITE_LINK_PASSWORD_LEN];
+    rand::rng().fill_bytes(&mut password);
+    password
+}
+
+fn profile_key(recipient: &orm::Recipient) -> Option<ProfileKey> {
+    let bytes: [u8; PROFILE_KEY_LEN] = recipient.profile_key.as_deref()?.try_into().ok()?;
+    Some(ProfileKey::create(bytes))
+}
+
+/// Obtain a profile key credential for `aci`, through the group server.
+async fn fetch_credential<A: GroupsServiceApi + Send>(
+    api: &mut A,
+    server_params: &ServerPublicParams,
+    aci: Aci,
+    profile_key: ProfileKey,
+) -> anyhow::Result<Option<ExpiringProfileKeyCredential>> {
+    let context =
+        server_params.create_profile_key_credential_request_context(randomness(), aci, profile_key);
+    let Some(response) = api
+        .fetch_profile_key_credential(
+            aci,
+            profile_key.get_profile_key_version(aci),
+            context.get_request(),
+        )
+        .await?
+    else {
+        return Ok(None);
+    };
+    let now = Timestamp::from_epoch_seconds(Utc::now().timestamp() as u64);
+    let credential = server_params
+        .receive_expiring_profile_key_credential(&context, &response, now)
+        .map_err(|_| anyhow::anyhow!("invalid profile key credential for {:?}", aci))?;
+    Ok(Some(credential))
+}
+
+fn encrypt_service_id(secret: &GroupSecretParams, service_id: ServiceId) -> Vec<u8> {
+    libsignal_service::zkgroup::serialize(&secret.encrypt_service_id(service_id))
+}
+
+/// A recipient that is about to join a group.
+enum Joining {
+    /// Added with a presentation of their profile key credential.
+    Member {
+        aci: Aci,
+        profile_key: ProfileKey,
+        member: proto::Member,
+    },
+    /// Invited, and pending their profile key.
+    Invited {
+        service_id: ServiceId,
+        member: proto::Member,
+    },
+}
+
+async fn joining<A: GroupsServiceApi + Send>(
+    api: &mut A,
+    server_params: &ServerPublicParams,
+    secret: &GroupSecretParams,
+    recipient: &orm::Recipient,
+    role: Role,
+) -> anyhow::Result<Joining> {
+    if let (Some(aci), Some(profile_key)) = (recipient.uuid.map(Aci::from), profile_key(recipient))
+        && let Some(credential) = fetch_credential(api, server_params, aci, profile_key).await?
+    {
+        let presentation = server_params.create_expiring_profile_key_credential_presentation(
+            randomness(),
+            *secret,
+            credential,
+        );
+        return Ok(Joining::Member {
+            aci,
+            profile_key,
+            member: proto::Member {
+                role: role.into(),
+                presentation: libsignal_service::zkgroup::serialize(&presentation),
+                ..Default::default()
+            },
+        });
+    }
+
+    let service_id = recipient
+        .to_service_address()
+        .with_context(|| format!("recipient {} has no service id", recipient.id))?;
+    tracing::debug!("Inviting {} without a profile key credential", recipient);
+    Ok(Joining::Invited {
+        service_id,
+        member: proto::Member {
+            user_id: encrypt_service_id(secret, service_id),
+            role: role.into(),
+            ..Default::default()
+        },
+    })
+}
+
+impl<O: Observable> super::Storage<O> {
+    /// Create a new group with ourselves as its administrator, and store it locally.
+    ///
+    /// Members with a known profile key are added directly; the others are invited.  The caller
+    /// is responsible for announcing the group to its members, using the returned master key.
+    #[tracing::instrument(skip(self, api, server_params, new_group))]
+    pub async fn create_group_v2<A: GroupsServiceApi + Send>(
+        &self,
+        api: &mut A,
+        server_params: &ServerPublicParams,
+        new_group: NewGroupV2,
+    ) -> anyhow::Result<CreatedGroupV2> {
+        anyhow::ensure!(!new_group.title.trim().is_empty(), "a group needs a title");
+        let self_aci = self
+            .config
+            .get_aci()
+            .map(Aci::from)
+            .context("no ACI to create a group with")?;
+        let self_recipient = self.fetch_self_recipient().context("no self recipient")?;
+        let self_profile_key = profile_key(&self_recipient).context("no own profile key")?;
+
+        let mut master_key = [0u8; GROUP_MASTER_KEY_LEN];
+        rand::rng().fill_bytes(&mut master_key);
+        let secret = GroupSecretParams::derive_from_master_key(GroupMasterKey::new(master_key));
+        let encrypted_self = encrypt_service_id(&secret, self_aci.into());
+        let now = Utc::now();
+
+        let self_credential = fetch_credential(api, server_params, self_aci, self_profile_key)
+            .await?
+            .context("no profile key credential for ourselves")?;
+        let self_presentation = server_params.create_expiring_profile_key_credential_presentation(
+            randomness(),
+            secret,
+            self_credential,
+        );
+
+        let mut members = vec![proto::Member {
+            role: Role::Administrator.into(),
+            presentation: libsignal_service::zkgroup::serialize(&self_presentation),
+            ..Default::default()
+        }];
+        let mut members_pending_profile_key = Vec::new();
+        let mut full_members = Vec::new();
+        let mut pending_members = Vec::new();
+        for recipient in &new_group.members {
+            match joining(api, server_params, &secret, recipient, Role::Default).await? {
+                Joining::Member {
+                    aci,
+                    profile_key,
+                    member,
+                } => {
+                    members.push(member);
+                    full_members.push((aci, profile_key));
+                }
+                Joining::Invited { service_id, member } => {
+                    members_pending_profile_key.push(proto::MemberPendingProfileKey {
+                        member: Some(member),
+                        added_by_user_id: encrypted_self.clone(),
+                        timestamp: now.timestamp_millis() as u64,
+                    });
+                    pending_members.push(service_id);
+                }
+            }
+        }
+
+        let avatar = match &new_group.avatar {
+            Some(avatar) => {
+                let encrypted = encrypt_attribute(
+                    &secret,
+                    group_attribute_blob::Content::Avatar(avatar.clone()),
+                );
+                api.upload_avatar(&secret, encrypted)
+                    .await
+                    .context("uploading group avatar")?
+            }
+            None => String::new(),
+        };
+        let description = new_group.description.filter(|d| !d.is_empty());
+        let expire_timer = new_group.expire_timer.filter(|&t| t > 0);
+
+        let group = proto::Group {
+            public_key: libsignal_service::zkgroup::serialize(&secret.get_public_params()),
+            title: encrypt_attribute(
+                &secret,
+                group_attribute_blob::Content::Title(new_group.title.clone()),
+            ),
+            description: description
+                .clone()
+                .map(|d| {
+                    encrypt_attribute(&secret, group_attribute_blob::Content::DescriptionText(d))
+                })
+                .unwrap_or_default(),
+            avatar: avatar.clone(),
+            disappearing_messages_timer: expire_timer
+                .map(|t| {
+                    encrypt_attribute(
+                        &secret,
+                        group_attribute_blob::Content::DisappearingMessagesDuration(t),
+                    )
+                })
+                .unwrap_or_default(),
+            access_control: Some(proto::AccessControl {
+                attributes: i32::from(AccessRequired::Member),
+                members: i32::from(AccessRequired::Member),
+                add_from_invite_link: i32::from(AccessRequired::Unsatisfiable),
+                ..Default::default()
+            }),
+            version: 0,
+            members,
+            members_pending_profile_key,
+            ..Default::default()
+        };
+        api.create_group(&secret, group)
+            .await
+            .context("creating group")?;
+
+        // The group exists on the server now; mirror it locally.
+        let session = self.fetch_or_insert_session_by_group_v2(&GroupV2 {
+            secret,
+            revision: 0,
+        });
+        let group_v2 = session.unwrap_group_v2();
+        self.update_group_v2_title(group_v2, &new_group.title);
+        self.update_group_v2_description(group_v2, description.as_ref());
+        self.update_group_v2_avatar(group_v2, Some(&avatar).filter(|a| !a.is_empty()));
+        self.update_group_v2_attribute_access(group_v2, AccessRequired::Member);
+        self.update_group_v2_member_access(group_v2, AccessRequired::Member);
+        self.update_group_v2_invite_link_access(group_v2, AccessRequired::Unsatisfiable);
+        self.observe_update(crate::schema::group_v2s::table, group_v2.id.clone());
+
+        self.add_group_v2_member(
+            group_v2,
+            self_aci,
+            Role::Administrator,
+            &self_profile_key,
+            0,
+            Some(now.naive_utc()),
+        );
+        for (aci, profile_key) in full_members {
+            self.add_group_v2_member(
+                group_v2,
+                aci,
+                Role::Default,
+                &profile_key,
+                0,
+                Some(now.naive_utc()),
+            );
+        }
+        for service_id in pending_members {
+            self.add_group_v2_pending_member(
+                group_v2,
+                service_id,
+                self_aci,
+                Role::Default,
+                now.naive_utc(),
+            );
+        }
+        self.update_expiration_timer(&session, expire_timer, None);
+
+        let session = self
+            .fetch_session_by_id(session.id)
+            .expect("the new group's session");
+        Ok(CreatedGroupV2 {
+            session,
+            master_key,
+        })
+    }
+
+    /// Change a group on the server, and apply the accepted change locally.
+    ///
+    /// Returns the group context, carrying the signed change, that announces the new revision
+    /// to the members.  The caller is responsible for sending it, also to members that were
+    /// removed by the change.
+    ///
+    /// The change is made against the locally known revision.  When the group moved on in the
+    /// meantime, this fails with [RevisionConflict], and the caller should refetch the group and
+    /// try again.
+    #[tracing::instrument(skip(self, api, server_params, action), fields(%group_v2))]
+    pub async fn modify_group_v2<A: GroupsServiceApi + Send>(
+        &self,
+        api: &mut A,
+        server_params: &ServerPublicParams,
+        group_v2: &orm::GroupV2,
+        action: GroupV2Action,
+    ) -> anyhow::Result<proto::GroupContextV2> {
+        let self_aci = self
+            .config
+            .get_aci()
+            .map(Aci::from)
+            .context("no ACI to change a group with")?;
+        anyhow::ensure!(!group_v2.terminated, "the group is terminated");
+        let self_pending = match action {
+            GroupV2Action::AcceptInvitation | GroupV2Action::DeclineInvitation => Some(
+                self.fetch_group_v2_self_pending_member(&group_v2.id)
+                    .context("not invited to the group")?,
+            ),
+            _ => {
+                let self_member = self
+                    .fetch_group_v2_self_member(&group_v2.id)
+                    .context("not a member of the group")?;
+                anyhow::ensure!(
+                    has_access(self_member.role, action.access_required(group_v2)),
+                    "not allowed to change the group"
+                );
+                None
+            }
+        };
+        // Invitations may be addressed to our PNI.
+        let self_pending_service_id = self_pending
+            .as_ref()
+            .map(|pending| {
+                ServiceId::parse_from_service_id_string(&pending.service_id)
+                    .context("invalid service id of pending member")
+            })
+            .transpose()?;
+
+        let master_key: [u8; GROUP_MASTER_KEY_LEN] = hex::decode(&group_v2.master_key)
+            .expect("hex in db")
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid master key length"))?;
+        let secret = GroupSecretParams::derive_from_master_key(GroupMasterKey::new(master_key));
+        let revision = group_v2.revision as u32 + 1;
+        let now = Utc::now();
+
+        let service_id = |recipient: &orm::Recipient| {
+            recipient
+                .to_service_address()
+                .with_context(|| format!("recipient {} has no service id", recipient.id))
+        };
+        let aci = |recipient: &orm::Recipient| {
+            recipient
+                .uuid
+                .map(Aci::from)
+                .with_context(|| format!("recipient {} has no ACI", recipient.id))
+        };
+        let is_member = |recipient: &orm::Recipient| {
+            self.fetch_group_members_by_group_v2_id(&group_v2.id)
+                .iter()
+                .any(|(_, r)| r.id == recipient.id)
+        };
+        let encrypt_string = |value: &Option<String>| {
+            value
+                .as_ref()
+                .map(|value| secret.encrypt_blob_with_padding(randomness(), value.as_bytes(), 0))
+                .unwrap_or_default()
+        };
+
+        let mut actions = proto::group_change::Actions {
+            group_id: secret.get_group_identifier().to_vec(),
+            version: revision,
+            ..Default::default()
+        };
+        let mut joining_member = None;
+        let mut new_invite_link_password = None;
+        let mut new_admin = None;
+        use proto::group_change::actions::*;
+        match &action {
+            GroupV2Action::AddMember(recipient) => {
+                anyhow::ensure!(!is_member(recipient), "{} is already a member", recipient);
+                match joining(api, server_params, &secret, recipient, Role::Default).await? {
+                    Joining::Member {
+                        aci,
+                        profile_key,
+                        member,
+                    } => {
+                        actions.add_members.push(AddMemberAction {
+                            added: Some(member),
+                            join_from_invite_link: false,
+                        });
+                        joining_member = Some((aci, profile_key));
+                    }
+                    Joining::Invited {
+                        service_id: _,
+                        member,
+                    } => {
+                        actions.add_pending_members.push(AddPendingMemberAction {
+                            added: Some(proto::MemberPendingProfileKey {
+                                member: Some(member),
+                                added_by_user_id: encrypt_service_id(&secret, self_aci.into()),
+                                timestamp: now.timestamp_millis() as u64,
+                            }),
+                        });
+                    }
+                }
+            }
+            GroupV2Action::RemoveMember(recipient) => {
+                actions.delete_members.push(DeleteMemberAction {
+                    deleted_user_id: encrypt_service_id(&secret, aci(recipient)?.into()),
+                });
+            }
+            GroupV2Action::SetMemberRole(recipient, role) => {
+                actions.modify_member_roles.push(ModifyMemberRoleAction {
+                    user_id: encrypt_service_id(&secret, aci(recipient)?.into()),
+                    role: (*role).into(),
+                });
+            }
+            GroupV2Action::BanMember(recipient) => {
+                let user_id = encrypt_service_id(&secret, service_id(recipient)?);
+                // A banned member is removed from the group at the same time.
+                if is_member(recipient) {
+                    actions.delete_members.push(DeleteMemberAction {
+                        deleted_user_id: user_id.clone(),
+                    });
+                }
+                actions.add_banned_members.push(AddBannedMemberAction {
+                    added: Some(proto::BannedMember {
+                        user_id,
+                        timestamp: now.timestamp_millis() as u64,
+                    }),
+                });
+            }
+            GroupV2Action::UnbanMember(recipient) => {
+                actions
+                    .delete_banned_members
+                    .push(DeleteBannedMemberAction {
+                        deleted_user_id: encrypt_service_id(&secret, service_id(recipient)?),
+                    });
+            }
+            GroupV2Action::Title(title) => {
+                anyhow::ensure!(!title.trim().is_empty(), "a group needs a title");
+                actions.modify_title = Some(ModifyTitleAction {
+                    title: encrypt_attribute(
+                        &secret,
+                        group_attribute_blob::Content::Title(title.clone()),
+                    ),
+                });
+            }
+            GroupV2Action::Description(description) => {
+                actions.modify_description = Some(ModifyDescriptionAction {
+                    description: description
+                        .clone()
+                        .filter(|d| !d.is_empty())
+                        .map(|d| {
+                            encrypt_attribute(
+                                &secret,
+                                group_attribute_blob::Content::DescriptionText(d),
+                            )
+                        })
+                        .unwrap_or_default(),
+                });
+            }
+            GroupV2Action::Avatar(avatar) => {
+                let avatar = match avatar {
+                    Some(avatar) => {
+                        let encrypted = encrypt_attribute(
+                            &secret,
+                            group_attribute_blob::Content::Avatar(avatar.clone()),
+                        );
+                        api.upload_avatar(&secret, encrypted)
+                            .await
+                            .context("uploading group avatar")?
+                    }
+                    None => String::new(),
+                };
+                actions.modify_avatar = Some(ModifyAvatarAction { avatar });
+            }
+            GroupV2Action::ExpireTimer(timer) => {
+                actions.modify_disappearing_messages_timer =
+                    Some(ModifyDisappearingMessagesTimerAction {
+                        timer: encrypt_attribute(
+                            &secret,
+                            group_attribute_blob::Content::DisappearingMessagesDuration(
+                                timer.unwrap_or(0),
+                            ),
+                        ),
+                    });
+            }
+            GroupV2Action::AnnouncementsOnly(announcements_only) => {
+                actions.modify_announcements_only = Some(ModifyAnnouncementsOnlyAction {
+                    announcements_only: *announcements_only,
+                });
+            }
+            GroupV2Action::AttributesAccess(access) => {
+                actions.modify_attributes_access = Some(ModifyAttributesAccessControlAction {
+                    attributes_access: (*access).into(),
+                });
+            }
+            GroupV2Action::MembersAccess(access) => {
+                actions.modify_member_access = Some(ModifyMembersAccessControlAction {
+                    members_access: (*access).into(),
+                });
+            }
+            GroupV2Action::MemberLabelAccess(access) => {
+                actions.modify_member_label_access = Some(ModifyMemberLabelAccessControlAction {
+                    member_label_access: (*access).into(),
+                });
+            }
+            GroupV2Action::MemberLabel { label, emoji } => {
+                actions.modify_member_labels.push(ModifyMemberLabelAction {
+                    user_id: encrypt_service_id(&secret, self_aci.into()),
+                    label_emoji: encrypt_string(emoji),
+                    label_string: encrypt_string(label),
+                });
+            }
+            GroupV2Action::AcceptInvitation => {
+                let self_recipient = self.fetch_self_recipient().context("no self recipient")?;
+                let Joining::Member {
+                    aci,
+                    profile_key,
+                    member,
+                } = joining(api, server_params, &secret, &self_recipient, Role::Default).await?
+                else {
+                    anyhow::bail!("no profile key credential for ourselves");
+                };
+                match self_pending_service_id {
+                    Some(ServiceId::Pni(_)) => actions.promote_pending_pni_aci_members.push(
+                        PromotePendingPniAciMemberProfileKeyAction {
+                            presentation: member.presentation,
+                            ..Default::default()
+                        },
+                    ),
+                    _ => actions
+                        .promote_pending_members
+                        .push(PromotePendingMemberAction {
+                            presentation: member.presentation,
+                            ..Default::default()
+                        }),
+                }
+                joining_member = Some((aci, profile_key));
+            }
+            GroupV2Action::DeclineInvitation => {
+                let pending = self_pending_service_id.expect("checked above");
+                actions
+                    .delete_pending_members
+                    .push(DeletePendingMemberAction {
+                        deleted_user_id: encrypt_service_id(&secret, pending),
+                    });
+            }
+            GroupV2Action::ApproveRequest(recipient) => {
+                let aci = aci(recipient)?;
+                anyhow::ensure!(
+                    self.fetch_group_v2_requesting_member(group_v2, aci)
+                        .is_some(),
+                    "{} did not ask to join",
+                    recipient
+                );
+                actions
+                    .promote_requesting_members
+                    .push(PromoteRequestingMemberAction {
+                        user_id: encrypt_service_id(&secret, aci.into()),
+                        role: Role::Default.into(),
+                    });
+            }
+            GroupV2Action::DenyRequest(recipient) => {
+                actions
+                    .delete_requesting_members
+                    .push(DeleteRequestingMemberAction {
+                        deleted_user_id: encrypt_service_id(&secret, aci(recipient)?.into()),
+                    });
+            }
+            GroupV2Action::InviteLinkAccess(access) => {
+                actions.modify_add_from_invite_link_access =
+                    Some(ModifyAddFromInviteLinkAccessControlAction {
+                        add_from_invite_link_access: (*access).into(),
+                    });
+                // A link can only be shared once it has a password.
+                if *access != AccessRequired::Unsatisfiable
+                    && group_v2.invite_link_password.is_none()
+                {
+                    new_invite_link_password = Some(invite_link_password());
+                }
+            }
+            GroupV2Action::ResetInviteLink => {
+                new_invite_link_password = Some(invite_link_password());
+            }
+            GroupV2Action::Leave => {
+                let members = self.fetch_group_members_by_group_v2_id(&group_v2.id);
+                let (self_member, others): (Vec<_>, Vec<_>) = members
+                    .iter()
+                    .partition(|(_, recipient)| recipient.uuid == Some(self_aci.into()));
+                let is_admin = |role: i32| role == Role::Administrator as i32;
+                let is_last_admin = self_member.iter().any(|(member, _)| is_admin(member.role))
+                    && !others.iter().any(|(member, _)| is_admin(member.role));
+                let successor = others
+                    .iter()
+                    .min_by_key(|(member, _)| (member.joined_at_revision, member.member_since));
+                if let (true, Some((_, successor))) = (is_last_admin, successor) {
+                    let successor = aci(successor)?;
+                    actions.modify_member_roles.push(ModifyMemberRoleAction {
+                        user_id: encrypt_service_id(&secret, successor.into()),
+                        role: Role::Administrator.into(),
+                    });
+                    new_admin = Some(successor);
+                }
+                actions.delete_members.push(DeleteMemberAction {
+                    deleted_user_id: encrypt_service_id(&secret, self_aci.into()),
+                });
+            }
+        }
+        if let Some(password) = &new_invite_link_password {
+            actions.modify_invite_link_password = Some(ModifyInviteLinkPasswordAction {
+                invite_link_password: password.clone(),
+            });
+        }
+
+        let group_change = api
+            .modify_group(&secret, actions)
+            .await
+            .context("changing group")?;
+
+        // The server accepted the change; apply it locally.
+        match action {
+            GroupV2Action::AddMember(recipient) => match joining_member {
+                Some((aci, profile_key)) => {
+                    self.add_group_v2_member(
+                        group_v2,
+                        aci,
+                        Role::Default,
+                        &profile_key,
+                        revision as i32,
+                        Some(now.naive_utc()),
+                    );
+                }
+                None => {
+                    self.add_group_v2_pending_member(
+                        group_v2,
+                        service_id(&recipient)?,
+                        self_aci,
+                        Role::Default,
+                        now.naive_utc(),
+                    );
+                }
+            },
+            GroupV2Action::RemoveMember(recipient) => {
+                self.delete_group_v2_member(group_v2, aci(&recipient)?);
+            }
+            GroupV2Action::SetMemberRole(recipient, role) => {
+                self.update_group_v2_member_role(group_v2, aci(&recipient)?, role);
+            }
+            GroupV2Action::BanMember(recipient) => {
+                let service_id = service_id(&recipient)?;
+                if let Some(aci) = recipient.uuid.map(Aci::from) {
+                    self.delete_group_v2_member(group_v2, aci);
+                }
+                self.add_group_v2_banned_member(
+                    group_v2,
+                    &service_id,
+                    now.timestamp_millis() as u64,
+                );
+            }
+            GroupV2Action::UnbanMember(recipient) => {
+                self.delete_group_v2_banned_member(group_v2, service_id(&recipient)?);
+            }
+            GroupV2Action::Title(title) => {
+                self.update_group_v2_title(group_v2, &title);
+            }
+            GroupV2Action::Description(description) => {
+                let description = description.filter(|d| !d.is_empty());
+                self.update_group_v2_description(group_v2, description.as_ref());
+            }
+            GroupV2Action::Avatar(_) => {
+                let avatar = proto::group_change::Actions::decode(&group_change.actions[..])
+                    .ok()
+                    .and_then(|actions| actions.modify_avatar)
+                    .map(|modify| modify.avatar)
+                    .filter(|avatar| !avatar.is_empty());
+                self.update_group_v2_avatar(group_v2, avatar.as_ref());
+            }
+            GroupV2Action::ExpireTimer(timer) => {
+                if let Some(session) = self.fetch_session_by_group_v2_id(&group_v2.id) {
+                    self.update_expiration_timer(&session, timer, None);
+                }
+            }
+            GroupV2Action::AnnouncementsOnly(announcements_only) => {
+                self.update_group_v2_announcement_only(group_v2, announcements_only);
+            }
+            GroupV2Action::AttributesAccess(access) => {
+                self.update_group_v2_attribute_access(group_v2, access);
+            }
+            GroupV2Action::MembersAccess(access) => {
+                self.update_group_v2_member_access(group_v2, access);
+            }
+            GroupV2Action::MemberLabelAccess(access) => {
+                self.update_group_v2_member_label_access(group_v2, access);
+            }
+            GroupV2Action::MemberLabel { label, emoji } => {
+                self.update_group_v2_member_label(group_v2, self_aci, label, emoji);
+            }
+            GroupV2Action::AcceptInvitation => {
+                let (aci, profile_key) = joining_member.expect("a credential for ourselves");
+                match self_pending_service_id.expect("checked above") {
+                    ServiceId::Pni(pni) => {
+                        // Ties our ACI to the PNI the invitation was addressed to.
+                        self.promote_pending_pni_aci_member_profile_key(
+                            group_v2,
+                            aci,
+                            pni,
+                            profile_key,
+                        );
+                        self.promote_group_v2_pending_member(group_v2, pni.into(), &profile_key);
+                    }
+                    pending => {
+                        self.promote_group_v2_pending_member(group_v2, pending, &profile_key);
+                    }
+                }
+            }
+            GroupV2Action::DeclineInvitation => {
+                self.delete_group_v2_pending_member(
+                    group_v2,
+                    self_pending_service_id.expect("checked above"),
+                );
+            }
+            GroupV2Action::ApproveRequest(recipient) => {
+                self.promote_group_v2_requesting_member(group_v2, aci(&recipient)?, Role::Default);
+            }
+            GroupV2Action::DenyRequest(recipient) => {
+                self.delete_group_v2_requesting_member(group_v2, aci(&recipient)?);
+            }
+            GroupV2Action::InviteLinkAccess(access) => {
+                self.update_group_v2_invite_link_access(group_v2, access);
+            }
+            GroupV2Action::ResetInviteLink => {}
+            GroupV2Action::Leave => {
+                if let Some(successor) = new_admin {
+                    self.update_group_v2_member_role(group_v2, successor, Role::Administrator);
+                }
+                self.delete_group_v2_member(group_v2, self_aci);
+            }
+        }
+        if let Some(password) = new_invite_link_password {
+            self.update_group_v2_invite_link_password(group_v2, &password);
+        }
+        // Also notifies the observers of the group
+        self.update_group_v2_revision(group_v2, revision as i32);
+
+        Ok(proto::GroupContextV2 {
+            master_key: Some(master_key.to_vec()),
+            revision: Some(revision),
+            group_change: Some(group_change.encode_to_vec()),
+        })
+    }
+
+    /// Join a group through its invite link, and store it locally.
+    ///
+    /// Depending on the access control of the group, we either become a member right away, or
+    /// ask the administrators to let us in.  In the former case, the caller is responsible for
+    /// sending the returned group context to the members, once they are known.
+    #[tracing::instrument(skip(self, api, server_params))]
+    pub async fn join_group_v2<A: GroupsServiceApi + Send>(
+        &self,
+        api: &mut A,
+        server_params: &ServerPublicParams,
+        link: &GroupInviteLink,
+    ) -> anyhow::Result<JoinedGroupV2> {
+        let self_recipient = self.fetch_self_recipient().context("no self recipient")?;
+        let secret = link.secret();
+        let now = Utc::now();
+
+        let join_info = api
+            .fetch_group_join_info(&secret, &link.password)
+            .await
+            .context("fetching group join info")?;
+        anyhow::ensure!(
+            !join_info.pending_admin_approval,
+            "already asked to join the group"
+        );
+        let requires_approval = match AccessRequired::from(join_info.add_from_invite_link) {
+            AccessRequired::Any => false,
+            AccessRequired::Administrator => true,
+            _ => anyhow::bail!("the invite link of the group is disabled"),
+        };
+
+        let Joining::Member {
+            aci,
+            profile_key,
+            member,
+        } = joining(api, server_params, &secret, &self_recipient, Role::Default).await?
+        else {
+            anyhow::bail!("no profile key credential for ourselves");
+        };
+        let revision = join_info.version + 1;
+        let mut actions = proto::group_change::Actions {
+            group_id: secret.get_group_identifier().to_vec(),
+            version: revision,
+            ..Default::default()
+        };
+        use proto::group_change::actions::*;
+        if requires_approval {
+            actions
+                .add_requesting_members
+                .push(AddRequestingMemberAction {
+                    added: Some(proto::RequestingMember {
+                        presentation: member.presentation,
+                        ..Default::default()
+                    }),
+                });
+        } else {
+            actions.add_members.push(AddMemberAction {
+                added: Some(member),
+                join_from_invite_link: true,
+            });
+        }
+
+        let group_change = api
+            .join_group(&secret, &link.password, actions)
+            .await
+            .context("joining group")?;
+
+        // Mirror what we know of the group, until it is fetched as a member.
+        let session = self.fetch_or_insert_session_by_group_v2(&GroupV2 {
+            secret,
+            revision: join_info.version,
+        });
+        let group_v2 = session.unwrap_group_v2();
+        if let Some(group_attribute_blob::Content::Title(title)) =
+            decrypt_attribute(&secret, &join_info.title)
+        {
+            self.update_group_v2_title(group_v2, &title);
+        }
+        let description = match decrypt_attribute(&secret, &join_info.description) {
+            Some(group_attribute_blob::Content::DescriptionText(d)) if !d.is_empty() => Some(d),
+            _ => None,
+        };
+        self.update_group_v2_description(group_v2, description.as_ref());
+        self.update_group_v2_avatar(group_v2, Some(&join_info.avatar).filter(|a| !a.is_empty()));
+        self.update_group_v2_invite_link_access(group_v2, join_info.add_from_invite_link.into());
+        self.update_group_v2_invite_link_password(group_v2, &link.password);
+
+        let context = if requires_approval {
+            self.add_group_v2_requesting_member(group_v2, aci, profile_key, now.naive_utc());
+            self.observe_update(crate::schema::group_v2s::table, group_v2.id.clone());
+            None
+        } else {
+            self.add_group_v2_member(
+                group_v2,
+                aci,
+                Role::Default,
+                &profile_key,
+                revision as i32,
+                Some(now.naive_utc()),
+            );
+            // Also notifies the observers of the group
+            self.update_group_v2_revision(group_v2, revision as i32);
+            Some(proto::GroupContextV2 {
+                master_key: Some(link.master_key.to_vec()),
+                revision: Some(revision),
+                group_change: Some(group_change.encode_to_vec()),
+            })
+        };
+
+        let session = self
+            .fetch_session_by_id(session.id)
+            .expect("the joined group's session");
+        Ok(JoinedGroupV2 { session, context })
+    }
+}
+
+/// Fetch what an invite link reveals about its group.
+pub async fn fetch_group_invite_preview<A: GroupsServiceApi + Send>(
+    api: &mut A,
+    link: &GroupInviteLink,
+) -> anyhow::Result<GroupInvitePreview> {
+    let secret = link.secret();
+    let join_info = api
+        .fetch_group_join_info(&secret, &link.password)
+        .await
+        .context("fetching group join info")?;
+    let title = match decrypt_attribute(&secret, &join_info.title) {
+        Some(group_attribute_blob::Content::Title(title)) => title,
+        _ => String::new(),
+    };
+    let description = match decrypt_attribute(&secret, &join_info.description) {
+        Some(group_attribute_blob::Content::DescriptionText(d)) if !d.is_empty() => Some(d),
+        _ => None,
+    };
+    Ok(GroupInvitePreview {
+        title,
+        description,
+        member_count: join_info.member_count,
+        requires_approval: AccessRequired::from(join_info.add_from_invite_link)
+            == AccessRequired::Administrator,
+        pending_approval: join_info.pending_admin_approval,
+    })
+}
@@ -0,0 +1,96 @@
+use super::observer::{Observable, PrimaryKey};
+use crate::{millis_to_naive_chrono, orm, schema};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use libsignal_service::proto::Preview;
+use std::collections::HashMap;
+
+/// The ids of the attachments that are link preview thumbnails.
+#[diesel::dsl::auto_type]
+pub(super) fn link_preview_thumbnails() -> _ {
+    schema::link_previews::table
+        .select(schema::link_previews::attachment_id.assume_not_null())
+        .filter(schema::link_previews::attachment_id.is_not_null())
+}
+
+impl<O: Observable> super::Storage<O> {
+    /// Save a link preview of a message.
+    ///
+    /// The thumbnail, if present, is registered as an attachment of the message;
+    /// its id is returned so it can be fetched.
+    #[tracing::instrument(skip(self, preview), fields(url = preview.url.as_deref()))]
+    pub fn register_link_preview(&mut self, msg_id: i32, preview: &Preview) -> Option<i32> {
+        use schema::link_previews::dsl::*;
+
+        let Some(preview_url) = preview.url.as_deref() else {
+            tracing::warn!("Link preview without url, dropping it.");
+            return None;
+        };
+
+        let thumbnail_id = preview
+            .image
+            .clone()
+            .map(|image| self.register_attachment(msg_id, image));
+
+        let preview_id = diesel::insert_into(link_previews)
+            .values((
+                message_id.eq(msg_id),
+                url.eq(preview_url),
+                title.eq(preview.title.as_deref().filter(|t| !t.is_empty())),
+                description.eq(preview.description.as_deref().filter(|d| !d.is_empty())),
+                date.eq(preview.date.filter(|d| *d > 0).map(millis_to_naive_chrono)),
+                attachment_id.eq(thumbnail_id),
+            ))
+            .returning(id)
+            .get_result::<i32>(&mut *self.db())
+            .expect("insert link preview");
+
+        self.observe_insert(schema::link_previews::table, PrimaryKey::RowId(preview_id))
+            .with_relation(schema::messages::table, msg_id);
+
+        thumbnail_id
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_link_previews_for_message(
+        &self,
+        mid: i32,
+    ) -> Vec<(orm::LinkPreview, Option<orm::Attachment>)> {
+        schema::link_previews::table
+            .left_join(schema::attachments::table)
+            .filter(schema::link_previews::message_id.eq(mid))
+            .order_by(schema::link_previews::id.asc())
+            .load(&mut *self.db())
+            .expect("db")
+    }
+
+    /// The first link preview of every message in the session sent in the given time span, by message id.
+    #[tracing::instrument(skip(self))]
+    pub(super) fn fetch_link_previews_for_session(
+        &self,
+        sid: i32,
+        oldest: NaiveDateTime,
+        newest: NaiveDateTime,
+    ) -> HashMap<i32, (orm::LinkPreview, Option<orm::Attachment>)> {
+        let previews: Vec<(orm::LinkPreview, Option<orm::Attachment>)> =
+            schema::link_previews::table
+                .left_join(schema::attachments::table)
+                .filter(
+                    schema::link_previews::message_id.eq_any(
+                        schema::messages::table
+                            .select(schema::messages::id)
+                            .filter(schema::messages::session_id.eq(sid))
+                            .filter(schema::messages::server_timestamp.between(oldest, newest)),
+                    ),
+                )
+                // Reversed, such that the first preview of a message wins.
+                .order_by(schema::link_previews::id.desc())
+                .load(&mut *self.db())
+                .expect("db");
+
+        previews
+            .into_iter()
+            .map(|(preview, thumbnail)| (preview.message_id, (preview, thumbnail)))
+            .collect()
+    }
+}
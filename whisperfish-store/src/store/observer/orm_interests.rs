@@ -67,6 +67,11 @@ impl orm::AugmentedMessage {
                 schema::messages::table,
                 self.id,
             )))
+            .chain(std::iter::once(Interest::whole_table_with_relation(
+                schema::link_previews::table,
+                schema::messages::table,
+                self.id,
+            )))
     }
 }
 
@@ -3,8 +3,8 @@ use chrono::prelude::*;
 use diesel::sql_types::Integer;
 use libsignal_service::groups_v2::AccessRequired as AccessRequiredProto;
 use libsignal_service::prelude::*;
-use libsignal_service::proto::GroupContextV2;
-use libsignal_service::protocol::{Aci, Pni, ServiceId, ServiceIdKind};
+use libsignal_service::proto::{self, GroupContextV2};
+use libsignal_service::protocol::{Aci, IdentityKey, Pni, ServiceId, ServiceIdKind};
 use phonenumber::PhoneNumber;
 use std::borrow::Cow;
 use std::fmt::{Display, Error, Formatter};
@@ -114,6 +114,11 @@ pub struct GroupV2 {
     pub access_required_for_member_labels: i32,
 
     pub terminated: bool,
+
+    pub storage_service_id: Option<Vec<u8>>,
+    pub storage_proto: Option<Vec<u8>>,
+
+    pub is_blocked: bool,
 }
 
 impl Display for GroupV2 {
@@ -247,6 +252,11 @@ pub enum MessageType {
 
     Contact,
     Sticker,
+
+    // Recipient merging
+    NumberChange,
+    SessionSwitchover,
+    ThreadMerge,
 }
 
 impl MessageType {
@@ -288,6 +298,9 @@ impl AsRef<str> for MessageType {
             MessageType::GroupCall => "group_call",
             MessageType::Contact => "contact",
             MessageType::Sticker => "sticker",
+            MessageType::NumberChange => "number_change",
+            MessageType::SessionSwitchover => "session_switchover",
+            MessageType::ThreadMerge => "thread_merge",
         }
     }
 }
@@ -358,6 +371,12 @@ pub struct Message {
     pub message_type: Option<MessageType>,
 
     pub expire_timer_version: i32,
+
+    pub parent_story_id: Option<i32>,
+
+    pub is_view_once: bool,
+    /// When we viewed this voice note or view-once message.
+    pub viewed_timestamp: Option<NaiveDateTime>,
 }
 
 impl Message {
@@ -380,6 +399,18 @@ impl Message {
     pub fn is_edited(&self) -> bool {
         !self.is_latest_revision() && !self.is_original_message()
     }
+
+    pub fn story_allows_replies(&self) -> bool {
+        self.story_type.allows_replies()
+    }
+
+    pub fn is_text_story(&self) -> bool {
+        self.story_type.is_text_story()
+    }
+
+    pub fn is_viewed(&self) -> bool {
+        self.viewed_timestamp.is_some()
+    }
 }
 
 #[derive(Queryable, Identifiable, Debug, Clone, PartialEq, Eq)]
@@ -387,17 +418,52 @@ pub struct Call {
     pub id: i32,
     pub call_id: i32,
     pub message_id: Option<i32>,
-    pub session_id: Option<i32>,
+    pub session_id: i32,
     pub r#type: CallType,
     pub is_outbound: bool,
     pub event: EventType,
     pub timestamp: NaiveDateTime,
+    pub ringer: i32,
     pub deletion_timestamp: Option<NaiveDateTime>,
     pub is_read: bool,
     pub local_joined: bool,
     pub group_call_active: bool,
 }
 
+impl Call {
+    pub fn is_video(&self) -> bool {
+        self.r#type == CallType::Video
+    }
+
+    pub fn is_group(&self) -> bool {
+        matches!(self.r#type, CallType::Group | CallType::AdHoc)
+    }
+
+    pub fn is_missed(&self) -> bool {
+        !self.is_outbound && self.event.is_missed_call()
+    }
+
+    /// Whether the call never got answered.
+    pub fn is_ringing(&self) -> bool {
+        matches!(self.event, EventType::Ringing | EventType::OutgoingRing)
+    }
+}
+
+/// A call, with the recipient of its one-to-one conversation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AugmentedCall {
+    pub inner: Call,
+    pub peer_recipient_id: Option<i32>,
+}
+
+impl std::ops::Deref for AugmentedCall {
+    type Target = Call;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
 impl Display for Message {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         match (&self.text, &self.quote_id) {
@@ -460,6 +526,9 @@ impl Default for Message {
             revision: 0,
             message_type: None,
             expire_timer_version: 1,
+            parent_story_id: None,
+            is_view_once: false,
+            viewed_timestamp: None,
         }
     }
 }
@@ -628,6 +697,70 @@ pub struct IdentityRecord {
     pub address: String,
     pub record: Vec<u8>,
     pub identity: Identity,
+    pub verified_status: i32,
+}
+
+impl IdentityRecord {
+    pub fn identity_key(&self) -> IdentityKey {
+        IdentityKey::decode(&self.record).expect("only valid identity keys in db")
+    }
+
+    pub fn verified_status(&self) -> VerifiedStatus {
+        self.verified_status.into()
+    }
+}
+
+/// Whether the user has compared safety numbers with a contact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerifiedStatus {
+    #[default]
+    Default,
+    Verified,
+    /// The contact was verified, but their identity key has changed since.
+    Unverified,
+}
+
+impl From<VerifiedStatus> for proto::verified::State {
+    fn from(value: VerifiedStatus) -> Self {
+        match value {
+            VerifiedStatus::Default => proto::verified::State::Default,
+            VerifiedStatus::Verified => proto::verified::State::Verified,
+            VerifiedStatus::Unverified => proto::verified::State::Unverified,
+        }
+    }
+}
+
+impl From<proto::verified::State> for VerifiedStatus {
+    fn from(value: proto::verified::State) -> Self {
+        match value {
+            proto::verified::State::Default => VerifiedStatus::Default,
+            proto::verified::State::Verified => VerifiedStatus::Verified,
+            proto::verified::State::Unverified => VerifiedStatus::Unverified,
+        }
+    }
+}
+
+impl std::convert::From<i32> for VerifiedStatus {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => VerifiedStatus::Default,
+            1 => VerifiedStatus::Verified,
+            2 => VerifiedStatus::Unverified,
+            _ => {
+                tracing::error!(
+                    "Invalid VerifiedStatus value {}, fallback to Default",
+                    value
+                );
+                VerifiedStatus::Default
+            }
+        }
+    }
+}
+
+impl From<VerifiedStatus> for i32 {
+    fn from(value: VerifiedStatus) -> Self {
+        value as i32
+    }
 }
 
 impl Display for IdentityRecord {
@@ -914,6 +1047,7 @@ pub struct Attachment {
 
     pub download_length: Option<i32>,
     pub original_path: Option<String>,
+    pub transcription_language: Option<String>,
 }
 
 impl Display for Attachment {
@@ -1003,6 +1137,76 @@ impl Attachment {
     }
 }
 
+/// A link preview attached to a message.
+///
+/// The thumbnail, if any, is stored as an attachment of the same message,
+/// but it is not listed among the message's ordinary attachments.
+#[derive(Queryable, Debug, Clone)]
+pub struct LinkPreview {
+    pub id: i32,
+    pub message_id: i32,
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub date: Option<NaiveDateTime>,
+    pub attachment_id: Option<i32>,
+}
+
+impl Display for LinkPreview {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(
+            f,
+            "LinkPreview {{ id: {}, message_id: {}, url: \"{}\", has_thumbnail: {} }}",
+            &self.id,
+            &self.message_id,
+            &self.url,
+            self.attachment_id.is_some()
+        )
+    }
+}
+
+/// A sticker of an installed sticker pack.
+///
+/// Every sticker row carries the pack metadata; the cover of a pack is the row
+/// for which `sticker_id == cover_sticker_id`.
+#[derive(Queryable, Debug, Clone)]
+pub struct Sticker {
+    pub pack_id: Option<String>,
+    pub sticker_id: i32,
+    pub cover_sticker_id: i32,
+    pub key: Vec<u8>,
+    pub title: String,
+    pub author: String,
+    pub pack_order: i32,
+    pub emoji: String,
+    pub content_type: Option<String>,
+    pub last_used: NaiveDateTime,
+    pub installed: NaiveDateTime,
+    pub file_path: String,
+    pub file_length: i32,
+    pub file_random: Vec<u8>,
+}
+
+impl Sticker {
+    pub fn absolute_file_path(&self) -> Cow<'_, str> {
+        crate::replace_tilde_with_home(&self.file_path)
+    }
+
+    pub fn is_cover(&self) -> bool {
+        self.sticker_id == self.cover_sticker_id
+    }
+}
+
+impl Display for Sticker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(
+            f,
+            "Sticker {{ pack_id: {:?}, sticker_id: {}, title: \"{}\", emoji: \"{}\" }}",
+            &self.pack_id, &self.sticker_id, &self.title, &self.emoji,
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Session {
     pub id: i32,
@@ -1304,6 +1508,8 @@ pub struct AugmentedMessage {
     pub body_ranges: Vec<crate::store::protos::body_range_list::BodyRange>,
     pub mentions: std::collections::HashMap<uuid::Uuid, Recipient>,
     pub sender_membership: Option<GroupV2Member>,
+    /// The first link preview of the message, with its thumbnail.
+    pub link_preview: Option<(LinkPreview, Option<Attachment>)>,
 }
 
 impl Display for AugmentedMessage {
@@ -1365,6 +1571,34 @@ impl AugmentedMessage {
         self.attachments as _
     }
 
+    pub fn has_link_preview(&self) -> bool {
+        self.link_preview.is_some()
+    }
+
+    pub fn link_preview_url(&self) -> Option<&str> {
+        self.link_preview.as_ref().map(|(p, _)| p.url.as_str())
+    }
+
+    pub fn link_preview_title(&self) -> Option<&str> {
+        self.link_preview
+            .as_ref()
+            .and_then(|(p, _)| p.title.as_deref())
+    }
+
+    pub fn link_preview_description(&self) -> Option<&str> {
+        self.link_preview
+            .as_ref()
+            .and_then(|(p, _)| p.description.as_deref())
+    }
+
+    /// The absolute path to the downloaded preview thumbnail, if any.
+    pub fn link_preview_thumbnail(&self) -> Option<Cow<'_, str>> {
+        self.link_preview
+            .as_ref()
+            .and_then(|(_, a)| a.as_ref())
+            .and_then(Attachment::absolute_attachment_path)
+    }
+
     pub fn reactions(&self) -> u32 {
         self.reactions as _
     }
@@ -1548,7 +1782,7 @@ impl AugmentedSession {
     pub fn is_blocked(&self) -> bool {
         match &self.inner.r#type {
             SessionType::GroupV1(_group) => false,
-            SessionType::GroupV2(_group) => false,
+            SessionType::GroupV2(group) => group.is_blocked,
             SessionType::DirectMessage(recipient) => recipient.is_blocked,
         }
     }
@@ -1703,6 +1937,21 @@ impl StoryType {
             (true, true) => Self::TextStoryWithReplies,
         }
     }
+
+    pub fn is_story(self) -> bool {
+        self != Self::None
+    }
+
+    pub fn allows_replies(self) -> bool {
+        matches!(self, Self::StoryWithReplies | Self::TextStoryWithReplies)
+    }
+
+    pub fn is_text_story(self) -> bool {
+        matches!(
+            self,
+            Self::TextStoryWithReplies | Self::TextStoryWithoutReplies
+        )
+    }
 }
 
 #[derive(Clone, Copy, Debug, FromSqlRow, PartialEq, Eq, AsExpression)]
@@ -1755,6 +2004,17 @@ pub struct DistributionListMember {
     pub privacy_mode: DistributionListPrivacyMode,
 }
 
+/// A story we sent, to one session.
+#[derive(Queryable, Debug, Clone)]
+pub struct StorySend {
+    pub message_id: i32,
+    pub session_id: i32,
+    pub sent_timestamp: NaiveDateTime,
+    pub allows_replies: bool,
+    #[diesel(deserialize_as = UuidString)]
+    pub distribution_id: Uuid,
+}
+
 pub fn shorten(text: &str, limit: usize) -> Cow<'_, str> {
     let limit = text
         .char_indices()
@@ -1797,6 +2057,9 @@ mod tests {
             announcement_only: false,
             access_required_for_member_labels: 0,
             terminated: false,
+            storage_service_id: None,
+            storage_proto: None,
+            is_blocked: false,
         }
     }
 
@@ -1887,6 +2150,7 @@ mod tests {
             transcription: None,
             download_length: None,
             original_path: None,
+            transcription_language: None,
         }
     }
 
@@ -1932,6 +2196,7 @@ mod tests {
             body_ranges: vec![],
             mentions: Default::default(),
             sender_membership: None,
+            link_preview: None,
         }
     }
 
@@ -1959,7 +2224,7 @@ mod tests {
         let mut g2 = get_group_v2();
         assert_eq!(
             format!("{:?}", g2),
-            "GroupV2 { id: \"abc\", name: \"G2\", master_key: \"123\", revision: 42, invite_link_password: None, access_required_for_attributes: 0, access_required_for_members: 0, access_required_for_add_from_invite_link: 0, avatar: None, description: Some(\"desc\"), announcement_only: false, access_required_for_member_labels: 0, terminated: false }"
+            "GroupV2 { id: \"abc\", name: \"G2\", master_key: \"123\", revision: 42, invite_link_password: None, access_required_for_attributes: 0, access_required_for_members: 0, access_required_for_add_from_invite_link: 0, avatar: None, description: Some(\"desc\"), announcement_only: false, access_required_for_member_labels: 0, terminated: false, storage_service_id: None, storage_proto: None, is_blocked: false }"
         );
         g2.description = None;
         assert_eq!(format!("{}", g2), "GroupV2 { id: \"abc\", name: \"G2\" }");
@@ -2044,6 +2309,7 @@ mod tests {
             device_id: 2,
             record: vec![65],
             identity: Identity::Aci,
+            verified_status: 0,
         };
         assert_eq!(
             format!("{}", s),
@@ -0,0 +1,183 @@
+use super::observer::Observable;
+use crate::orm::{self, StoryType};
+use crate::schema;
+use chrono::prelude::*;
+use diesel::prelude::*;
+
+/// The position of a message in the conversation view, which is ordered newest first.
+///
+/// Messages are ordered by their server timestamp, and by id when those are equal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MessageCursor {
+    pub server_timestamp: NaiveDateTime,
+    pub id: i32,
+}
+
+impl From<&orm::Message> for MessageCursor {
+    fn from(message: &orm::Message) -> Self {
+        Self {
+            server_timestamp: message.server_timestamp,
+            id: message.id,
+        }
+    }
+}
+
+/// The latest revisions of the ordinary messages of a session, without those scheduled for later.
+#[diesel::dsl::auto_type]
+fn conversation_messages(sid: i32) -> _ {
+    schema::messages::table
+        .filter(schema::messages::session_id.eq(sid))
+        .filter(schema::messages::story_type.eq(StoryType::None))
+        .filter(schema::messages::schedule_send_time.is_null())
+        .filter(
+            schema::messages::latest_revision_id
+                .is_null()
+                .or(schema::messages::latest_revision_id.eq(schema::messages::id.nullable())),
+        )
+}
+
+impl<O: Observable> super::Storage<O> {
+    /// At most `limit` messages of a session that are older than `before`, newest first.
+    ///
+    /// Without a cursor, this starts at the newest message of the session.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_messages_before(
+        &self,
+        sid: i32,
+        before: Option<MessageCursor>,
+        limit: i64,
+    ) -> Vec<orm::Message> {
+        use schema::messages::dsl::*;
+
+        let query = conversation_messages(sid).into_boxed();
+        let query = match before {
+            Some(cursor) => query.filter(
+                server_timestamp
+                    .lt(cursor.server_timestamp)
+                    .or(server_timestamp
+                        .eq(cursor.server_timestamp)
+                        .and(id.lt(cursor.id))),
+            ),
+            None => query,
+        };
+        query
+            .order_by((server_timestamp.desc(), id.desc()))
+            .limit(limit)
+            .load(&mut *self.db())
+            .expect("db")
+    }
+
+    /// At most `limit` messages of a session that are newer than `after`, newest first.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_messages_after(
+        &self,
+        sid: i32,
+        after: MessageCursor,
+        limit: i64,
+    ) -> Vec<orm::Message> {
+        use schema::messages::dsl::*;
+
+        let mut messages: Vec<orm::Message> = conversation_messages(sid)
+            .filter(
+                server_timestamp
+                    .gt(after.server_timestamp)
+                    .or(server_timestamp
+                        .eq(after.server_timestamp)
+                        .and(id.gt(after.id))),
+            )
+            .order_by((server_timestamp.asc(), id.asc()))
+            .limit(limit)
+            .load(&mut *self.db())
+            .expect("db");
+        messages.reverse();
+        messages
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_messages_augmented_before(
+        &self,
+        sid: i32,
+        before: Option<MessageCursor>,
+        limit: i64,
+    ) -> Vec<orm::AugmentedMessage> {
+        let messages = self.fetch_messages_before(sid, before, limit);
+        self.augment_messages(sid, messages)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_messages_augmented_after(
+        &self,
+        sid: i32,
+        after: MessageCursor,
+        limit: i64,
+    ) -> Vec<orm::AugmentedMessage> {
+        let messages = self.fetch_messages_after(sid, after, limit);
+        self.augment_messages(sid, messages)
+    }
+
+    /// The messages of a session from `newest` down to and including `oldest`, newest first.
+    ///
+    /// Without a `newest` cursor, this starts at the newest message of the session.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_messages_augmented_between(
+        &self,
+        sid: i32,
+        newest: Option<MessageCursor>,
+        oldest: MessageCursor,
+    ) -> Vec<orm::AugmentedMessage> {
+        use schema::messages::dsl::*;
+
+        let query = conversation_messages(sid)
+            .filter(
+                server_timestamp
+                    .gt(oldest.server_timestamp)
+                    .or(server_timestamp
+                        .eq(oldest.server_timestamp)
+                        .and(id.ge(oldest.id))),
+            )
+            .into_boxed();
+        let query = match newest {
+            Some(cursor) => query.filter(
+                server_timestamp
+                    .lt(cursor.server_timestamp)
+                    .or(server_timestamp
+                        .eq(cursor.server_timestamp)
+                        .and(id.le(cursor.id))),
+            ),
+            None => query,
+        };
+        let messages = query
+            .order_by((server_timestamp.desc(), id.desc()))
+            .load(&mut *self.db())
+            .expect("db");
+        self.augment_messages(sid, messages)
+    }
+
+    /// The messages surrounding `message_id`, newest first.
+    ///
+    /// This contains the latest revision of the message, and at most `limit` messages on either
+    /// side of it. Returns nothing when the message is not part of the conversation.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_messages_augmented_around(
+        &self,
+        sid: i32,
+        message_id: i32,
+        limit: i64,
+    ) -> Vec<orm::AugmentedMessage> {
+        let Some(message) = self
+            .fetch_message_by_id(message_id)
+            .and_then(|message| self.fetch_message_by_id(message.latest_revision_id()))
+        else {
+            return Vec::new();
+        };
+        if message.session_id != sid || message.story_type != StoryType::None {
+            return Vec::new();
+        }
+
+        let cursor = MessageCursor::from(&message);
+        let mut messages = self.fetch_messages_after(sid, cursor, limit);
+        messages.push(message);
+        messages.extend(self.fetch_messages_before(sid, Some(cursor), limit));
+        self.augment_messages(sid, messages)
+    }
+}
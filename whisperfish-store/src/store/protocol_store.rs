@@ -293,6 +293,94 @@ impl<T: Identity<O>, O: Observable> IdentityStorage<T, O> {
         Ok(())
     }
 
+    /// Replaces this identity with a new one, like when our PNI changes with our phone number.
+    ///
+    /// The identity key pair, the registration id, and the signed and last resort Kyber prekeys
+    /// are stored together: when one of them cannot be written, the old identity is kept.
+    /// The prekeys of the old identity are only removed once the new identity is stored.
+    #[tracing::instrument(level = "warn", skip_all)]
+    // Mutability of self is artificial
+    pub async fn replace_identity(
+        &mut self,
+        key_pair: IdentityKeyPair,
+        regid: u32,
+        signed_pre_key: &protocol::SignedPreKeyRecord,
+        last_resort_kyber_pre_key: &protocol::KyberPreKeyRecord,
+    ) -> anyhow::Result<()> {
+        use crate::schema::{kyber_prekeys, prekeys, signed_prekeys};
+
+        let own_identity = self.1.identity();
+        let signed_pre_key = orm::SignedPrekey {
+            id: u32::from(signed_pre_key.id()?) as _,
+            record: signed_pre_key.serialize()?,
+            identity: own_identity.clone(),
+        };
+        let kyber_pre_key = orm::KyberPrekey {
+            id: u32::from(last_resort_kyber_pre_key.id()?) as _,
+            record: last_resort_kyber_pre_key.serialize()?,
+            identity: own_identity.clone(),
+            is_last_resort: true,
+        };
+
+        let _lock = self.0.protocol_store.write().await;
+
+        let identity_dir = self.0.path.join("storage").join("identity");
+        let key_path = identity_dir.join(self.1.identity_key_filename());
+        let regid_path = identity_dir.join(self.1.regid_filename());
+
+        // The files cannot take part in the database transaction;
+        // they are written first, and put back when the transaction fails.
+        let old_key = self.0.read_file(&key_path).await.ok();
+        let old_regid = self.0.read_file(&regid_path).await.ok();
+        let stored = async {
+            self.0
+                .write_file(&key_path, ProtocolStore::serialize_identity_key(key_pair))
+                .await?;
+            self.0
+                .write_file(&regid_path, format!("{}", regid).into_bytes())
+                .await?;
+            self.0.db().transaction(|db| {
+                diesel::insert_into(signed_prekeys::table)
+                    .values(&signed_pre_key)
+                    .execute(db)?;
+                diesel::insert_into(kyber_prekeys::table)
+                    .values(&kyber_pre_key)
+                    .execute(db)?;
+                anyhow::Ok(())
+            })
+        }
+        .await;
+        if let Err(e) = stored {
+            for (path, old) in [(&key_path, old_key), (&regid_path, old_regid)] {
+                let restored = match old {
+                    Some(old) => self.0.write_file(path, old).await,
+                    None => tokio::fs::remove_file(path).await.map_err(Into::into),
+                };
+                if let Err(e) = restored {
+                    tracing::error!("Cannot restore {}: {e:#}", path.display());
+                }
+            }
+            return Err(e.context("storing the new identity"));
+        }
+        *self.1.identity_key_pair_cached_mut(&self.0).await = Some(key_pair);
+
+        let mut db = self.0.db();
+        let removed = diesel::delete(prekeys::table)
+            .filter(prekeys::identity.eq(own_identity.clone()))
+            .execute(&mut *db)?
+            + diesel::delete(signed_prekeys::table)
+                .filter(signed_prekeys::identity.eq(own_identity.clone()))
+                .filter(signed_prekeys::id.ne(signed_pre_key.id))
+                .execute(&mut *db)?
+            + diesel::delete(kyber_prekeys::table)
+                .filter(kyber_prekeys::identity.eq(own_identity))
+                .filter(kyber_prekeys::id.ne(kyber_pre_key.id))
+                .execute(&mut *db)?;
+        tracing::info!("Removed {} prekeys of the old identity", removed);
+
+        Ok(())
+    }
+
     #[tracing::instrument(level = "trace", skip(self, key_pair))]
     // Mutability of self is artificial
     pub async fn write_identity_key_pair(
@@ -401,24 +489,40 @@ impl<T: Identity<O>, O: Observable> protocol::IdentityKeyStore for IdentityStora
         Ok(regid)
     }
 
+    /// Whether `key` may be used to talk to `addr`.
+    ///
+    /// Incoming messages are always accepted, like Signal does: the message was sent
+    /// already, and refusing to decrypt it would lose it without telling the user.
+    /// A changed key is recorded by [Self::save_identity] instead, which warns in the
+    /// conversation and makes a verified contact unverified.
+    ///
+    /// Outgoing messages are only sent to the stored key, and not to a contact that became
+    /// unverified, until the user accepted the new key. A refused key is handled by
+    /// [super::Storage::mark_identity_key_changed].
     #[tracing::instrument(level = "trace", skip(self, addr), fields(addr = %addr))]
     async fn is_trusted_identity(
         &self,
         addr: &ProtocolAddress,
         key: &IdentityKey,
-        // XXX
-        _direction: Direction,
+        direction: Direction,
     ) -> Result<bool, SignalProtocolError> {
-        if let Some(trusted_key) = self.get_identity(addr).await? {
-            Ok(trusted_key == *key)
-        } else {
-            // Trust on first use
-            Ok(true)
+        if matches!(direction, Direction::Receiving) {
+            return Ok(true);
         }
+
+        let Some(trusted) = self.get_identity_record(addr) else {
+            // Trust on first use
+            return Ok(true);
+        };
+        Ok(trusted.identity_key() == *key
+            && trusted.verified_status() != orm::VerifiedStatus::Unverified)
     }
 
     /// Should return true when the older key, if present, is different from the new one.
     /// False otherwise.
+    ///
+    /// A changed key inserts a warning in the conversation, and makes a verified contact
+    /// unverified.
     #[tracing::instrument(level = "trace", skip(self, addr), fields(addr = %addr))]
     async fn save_identity(
         &mut self,
@@ -426,20 +530,32 @@ impl<T: Identity<O>, O: Observable> protocol::IdentityKeyStore for IdentityStora
         key: &IdentityKey,
     ) -> Result<IdentityChange, SignalProtocolError> {
         use crate::schema::identity_records::dsl::*;
-        let previous = self.get_identity(addr).await?;
+        let previous = self.get_identity_record(addr);
+
+        if let Some(previous) = previous {
+            if previous.identity_key() == *key {
+                return Ok(IdentityChange::NewOrUnchanged);
+            }
 
-        if previous.is_some() {
+            let status = match previous.verified_status() {
+                orm::VerifiedStatus::Default => orm::VerifiedStatus::Default,
+                orm::VerifiedStatus::Verified | orm::VerifiedStatus::Unverified => {
+                    orm::VerifiedStatus::Unverified
+                }
+            };
             diesel::update(identity_records)
                 .filter(address.eq(addr.name()).and(identity.eq(self.1.identity())))
-                .set(record.eq(key.serialize().to_vec()))
+                .set((
+                    record.eq(key.serialize().to_vec()),
+                    verified_status.eq(i32::from(status)),
+                ))
                 .execute(&mut *self.0.db())
                 .expect("db");
 
-            if previous.as_ref() == Some(key) {
-                Ok(IdentityChange::NewOrUnchanged)
-            } else {
-                Ok(IdentityChange::ReplacedExisting)
+            if let Ok(service_id) = ServiceId::parse_from_service_id_string(addr.name()) {
+                self.0.insert_identity_key_change(&service_id);
             }
+            Ok(IdentityChange::ReplacedExisting)
         } else {
             diesel::insert_into(identity_records)
                 .values((
@@ -458,15 +574,9 @@ impl<T: Identity<O>, O: Observable> protocol::IdentityKeyStore for IdentityStora
         &self,
         addr: &ProtocolAddress,
     ) -> Result<Option<IdentityKey>, SignalProtocolError> {
-        use crate::schema::identity_records::dsl::*;
-        Ok(identity_records
-            .filter(address.eq(addr.name()).and(identity.eq(self.1.identity())))
-            .first(&mut *self.0.db())
-            .optional()
-            .expect("db")
-            .map(|found: orm::IdentityRecord| {
-                IdentityKey::decode(&found.record).expect("only valid identity keys in db")
-            }))
+        Ok(self
+            .get_identity_record(addr)
+            .map(|found| found.identity_key()))
     }
 }
 
@@ -901,6 +1011,15 @@ impl<T: Identity<O>, O: Observable> protocol::KyberPreKeyStore for IdentityStora
 }
 
 impl<T: Identity<O>, O: Observable> IdentityStorage<T, O> {
+    fn get_identity_record(&self, addr: &ProtocolAddress) -> Option<orm::IdentityRecord> {
+        use crate::schema::identity_records::dsl::*;
+        identity_records
+            .filter(address.eq(addr.name()).and(identity.eq(self.1.identity())))
+            .first(&mut *self.0.db())
+            .optional()
+            .expect("db")
+    }
+
     /// Check whether session exists.
     ///
     /// This does *not* lock the protocol store.  If a transactional check is required, use the
@@ -951,6 +1070,148 @@ impl<O: Observable> Storage<O> {
 
         removed
     }
+
+    /// Removes the sender keys held for a protocol address, in all distributions.
+    ///
+    /// For our own address, this makes the next group message start a fresh sender key.
+    #[tracing::instrument(level = "warn", skip(self))]
+    pub fn delete_sender_keys(&self, addr: &ProtocolAddress) -> usize {
+        use crate::schema::sender_key_records::dsl::*;
+        let removed = diesel::delete(sender_key_records)
+            .filter(
+                address
+                    .eq(addr.name())
+                    .and(device.eq(u32::from(addr.device_id()) as i32)),
+            )
+            .execute(&mut *self.db())
+            .expect("db");
+        tracing::info!("Removed {} sender keys", removed);
+        removed
+    }
+
+    /// Fetches the identity record matching ServiceId (ACI or PNI).
+    pub fn fetch_identity_record(&self, addr: &ServiceId) -> Option<orm::IdentityRecord> {
+        use crate::schema::identity_records::dsl::*;
+        identity_records
+            .filter(
+                address
+                    .eq(addr.service_id_string())
+                    .and(identity.eq(orm::Identity::from(addr.kind()))),
+            )
+            .first(&mut *self.db())
+            .optional()
+            .expect("db")
+    }
+
+    /// Sets the verification status of an identity, if its key is still `key`.
+    ///
+    /// Returns false if the identity is unknown, or its key has changed in the meantime.
+    #[tracing::instrument(level = "debug", skip(self, addr, key), fields(addr = addr.service_id_string()))]
+    pub fn set_identity_verified_status(
+        &self,
+        addr: &ServiceId,
+        key: &IdentityKey,
+        status: orm::VerifiedStatus,
+    ) -> bool {
+        use crate::schema::identity_records::dsl::*;
+        let updated = diesel::update(identity_records)
+            .filter(
+                address
+                    .eq(addr.service_id_string())
+                    .and(identity.eq(orm::Identity::from(addr.kind())))
+                    .and(record.eq(key.serialize().to_vec())),
+            )
+            .set(verified_status.eq(i32::from(status)))
+            .execute(&mut *self.db())
+            .expect("db")
+            >= 1;
+
+        if updated {
+            self.observe_identity_change(addr);
+        } else {
+            tracing::warn!("Identity key not found, not changing its verification status");
+        }
+        updated
+    }
+
+    /// Records that an identity presented a new key while sending to it, and returns whether
+    /// the message can be sent again.
+    ///
+    /// The change is announced in the conversation. The key of a contact that was never
+    /// verified is forgotten, such that its new key is trusted when sending again. A verified
+    /// contact becomes unverified instead, and sending stays blocked until the user accepts the
+    /// change.
+    #[tracing::instrument(level = "warn", skip(self, addr), fields(addr = addr.service_id_string()))]
+    pub fn mark_identity_key_changed(&self, addr: &ServiceId) -> bool {
+        use crate::schema::identity_records::dsl::*;
+        let Some(record) = self.fetch_identity_record(addr) else {
+            // Trusted on first use when sending again.
+            return true;
+        };
+
+        match record.verified_status() {
+            orm::VerifiedStatus::Default => {
+                self.delete_identity_key(addr);
+                self.insert_identity_key_change(addr);
+                true
+            }
+            orm::VerifiedStatus::Verified => {
+                diesel::update(identity_records)
+                    .filter(
+                        address
+                            .eq(addr.service_id_string())
+                            .and(identity.eq(orm::Identity::from(addr.kind()))),
+                    )
+                    .set(verified_status.eq(i32::from(orm::VerifiedStatus::Unverified)))
+                    .execute(&mut *self.db())
+                    .expect("db");
+                self.insert_identity_key_change(addr);
+                false
+            }
+            // The user was warned already.
+            orm::VerifiedStatus::Unverified => false,
+        }
+    }
+
+    /// Accepts the new identity key of a formerly verified contact.
+    ///
+    /// The identity is forgotten, such that the next key it presents is trusted again.
+    #[tracing::instrument(level = "info", skip(self, addr), fields(addr = addr.service_id_string()))]
+    pub fn accept_identity_key_change(&self, addr: &ServiceId) -> bool {
+        let unverified = self
+            .fetch_identity_record(addr)
+            .is_some_and(|r| r.verified_status() == orm::VerifiedStatus::Unverified);
+        if !unverified {
+            tracing::warn!("Identity is not waiting for confirmation");
+            return false;
+        }
+        let removed = self.delete_identity_key(addr);
+        self.observe_identity_change(addr);
+        removed
+    }
+
+    /// Warns about a changed identity key in the direct conversation with `addr`, if there is one.
+    fn insert_identity_key_change(&self, addr: &ServiceId) {
+        let Some(recipient) = self.fetch_recipient(addr) else {
+            return;
+        };
+        self.observe_update(crate::schema::recipients::table, recipient.id);
+        let Some(session) = self.fetch_session_by_recipient_id(recipient.id) else {
+            return;
+        };
+        self.create_message(&NewMessage {
+            session_id: session.id,
+            source_addr: Some(*addr),
+            message_type: Some(orm::MessageType::IdentityKeyChange),
+            ..NewMessage::new_incoming()
+        });
+    }
+
+    fn observe_identity_change(&self, addr: &ServiceId) {
+        if let Some(recipient) = self.fetch_recipient(addr) {
+            self.observe_update(crate::schema::recipients::table, recipient.id);
+        }
+    }
 }
 // END identity key
 
@@ -4,6 +4,7 @@ use crate::orm;
 use crate::orm::Recipient;
 use crate::schema;
 use diesel::prelude::*;
+use libsignal_service::protocol::{Aci, Pni, ServiceId};
 use phonenumber::PhoneNumber;
 use std::fmt::Debug;
 use uuid::Uuid;
@@ -18,6 +19,33 @@ enum RecipientOperation {
     Create(Option<Uuid>, Option<Uuid>, Option<PhoneNumber>),
 }
 
+/// Something that happened to a recipient during a merge, worth telling the user about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecipientEvent {
+    /// The recipient has a new phone number.
+    NumberChange { recipient_id: i32 },
+    /// We had a session with the recipient's PNI, and now talk to another service id.
+    SessionSwitchover {
+        recipient_id: i32,
+        e164: Option<PhoneNumber>,
+    },
+    /// The conversation with another recipient, known by `e164`, was merged into this one.
+    ThreadMerge {
+        recipient_id: i32,
+        e164: Option<PhoneNumber>,
+    },
+}
+
+impl RecipientEvent {
+    pub fn recipient_id(&self) -> i32 {
+        match self {
+            RecipientEvent::NumberChange { recipient_id }
+            | RecipientEvent::SessionSwitchover { recipient_id, .. }
+            | RecipientEvent::ThreadMerge { recipient_id, .. } => *recipient_id,
+        }
+    }
+}
+
 struct MergeRecipients {
     pub by_aci: Option<Recipient>,
     pub by_e164: Option<Recipient>,
@@ -67,6 +95,32 @@ fn fetch_separate_recipients(
     })
 }
 
+/// Whether we have an encrypted session with `addr`, on any of its devices.
+fn has_protocol_session(
+    db: &mut SqliteConnection,
+    addr: &ServiceId,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::session_records;
+    let count: i64 = session_records::table
+        .filter(session_records::address.eq(addr.service_id_string()))
+        .count()
+        .get_result(db)?;
+    Ok(count > 0)
+}
+
+/// Whether the recipient has a direct conversation.
+fn has_direct_session(
+    db: &mut SqliteConnection,
+    rcpt_id: i32,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::sessions;
+    let count: i64 = sessions::table
+        .filter(sessions::direct_message_recipient_id.eq(rcpt_id))
+        .count()
+        .get_result(db)?;
+    Ok(count > 0)
+}
+
 #[tracing::instrument(
     skip(db, e164),
     fields(
@@ -81,7 +135,7 @@ pub fn merge_and_fetch_recipient_inner(
     pni: Option<Uuid>,
     trust_level: TrustLevel,
     change_self: bool,
-) -> Result<(i32, bool), diesel::result::Error> {
+) -> Result<(i32, bool, Vec<RecipientEvent>), diesel::result::Error> {
     if e164.is_none() && aci.is_none() && pni.is_none() {
         panic!("merge_and_fetch_recipient requires at least one of e164 or uuid");
     }
@@ -109,6 +163,13 @@ pub fn merge_and_fetch_recipient_inner(
 
     // Things can get quite cumbersome later on, so let's just use the operations queue for everything.
     let mut ops: Vec<RecipientOperation> = Vec::new();
+    let mut events: Vec<RecipientEvent> = Vec::new();
+    // Number changes of ourselves or of blocked recipients are not shown.
+    let number_change = |rcpt: &orm::Recipient| {
+        (!change_self && !rcpt.is_blocked).then_some(RecipientEvent::NumberChange {
+            recipient_id: rcpt.id,
+        })
+    };
 
     // If nothing matches, create a new recipient
     if by_all.is_empty() {
@@ -123,7 +184,7 @@ pub fn merge_and_fetch_recipient_inner(
     if let Some(common) = common {
         // If there's a common recipient, and every criteria given matches, we're done!
         if match_count == criteria_count {
-            return Ok((common.id, false));
+            return Ok((common.id, false, events));
         }
         tracing::debug!(
             "Found incomplete ({}/{}) common recipient {}",
@@ -158,12 +219,8 @@ pub fn merge_and_fetch_recipient_inner(
             if e164.is_some() && e164 != common.e164 && trust_level == TrustLevel::Certain {
                 tracing::debug!("Updating E164 in existing recipient");
                 ops.push(RecipientOperation::SetE164(common.id, e164.clone()));
-                if let Some(common_e164) = common.e164.as_ref() {
-                    tracing::warn!(
-                        "TODO: Phone number change from {:?} to {:?}",
-                        common_e164.to_string(),
-                        e164
-                    );
+                if common.e164.is_some() {
+                    events.extend(number_change(common));
                 }
             }
 
@@ -177,19 +234,28 @@ pub fn merge_and_fetch_recipient_inner(
                 ops.push(RecipientOperation::SetAci(common.id, aci));
             }
 
-            let old_service_id = common.uuid.or(common.pni);
-            let new_service_id = aci.or(pni.or(old_service_id));
-
-            if old_service_id.is_some()
-                && old_service_id != new_service_id
-                && trust_level == TrustLevel::Certain
-            /* && has_session(old_service_id) */
+            let old_service_id = common
+                .uuid
+                .map(|u| ServiceId::from(Aci::from(u)))
+                .or(common.pni.map(|u| ServiceId::from(Pni::from(u))));
+            let new_service_id = aci
+                .map(|u| ServiceId::from(Aci::from(u)))
+                .or(pni.map(|u| ServiceId::from(Pni::from(u))))
+                .or(old_service_id);
+
+            if let Some(old_service_id) = old_service_id
+                && Some(old_service_id) != new_service_id
+                && has_protocol_session(db, &old_service_id)?
             {
-                tracing::warn!(
-                    "TODO: Session switchover event change from {:?} to {:?}",
-                    old_service_id,
-                    new_service_id
+                tracing::debug!(
+                    "Session switchover from {} to {:?}",
+                    old_service_id.service_id_string(),
+                    new_service_id.map(|s| s.service_id_string())
                 );
+                events.push(RecipientEvent::SessionSwitchover {
+                    recipient_id: common.id,
+                    e164: common.e164.clone(),
+                });
             }
         }
     }
@@ -251,8 +317,8 @@ pub fn merge_and_fetch_recipient_inner(
                 if by_pni.e164 == e164 {
                     ops.push(RecipientOperation::SetE164(by_pni.id, None));
                 }
-                if e164.is_some() && by_aci.e164 != e164 {
-                    // XXX Phone number change event
+                if by_aci.e164.is_some() {
+                    events.extend(number_change(by_aci));
                 }
                 ops.push(RecipientOperation::SetE164(by_aci.id, e164.clone()));
             }
@@ -280,8 +346,8 @@ pub fn merge_and_fetch_recipient_inner(
             ops.push(RecipientOperation::Merge(by_e164.id, by_aci.id));
             if let Some(by_aci_e164) = &by_aci.e164
                 && by_aci_e164 != e164
-            { // XXX && (change_self || not_self) && !by_aci.blocked
-                // XXX Phone number change event
+            {
+                events.extend(number_change(by_aci));
             }
         } else if let Some(pni) = pni
             && by_e164.pni != Some(pni)
@@ -294,14 +360,14 @@ pub fn merge_and_fetch_recipient_inner(
             }
             ops.push(RecipientOperation::Merge(by_e164.id, by_aci.id));
             // - if byAci.e164 changed, not self, not blocked
-            if by_aci.e164.is_some() && by_aci.e164.as_ref().unwrap() != e164 { // XXX && (change_self || not_self) && !by_aci.blocked
-                // XXX Phone number change event
+            if by_aci.e164.is_some() && by_aci.e164.as_ref().unwrap() != e164 {
+                events.extend(number_change(by_aci));
             }
         } else if pni.is_some() && by_e164.pni != pni || trust_level == TrustLevel::Certain {
             ops.push(RecipientOperation::SetE164(by_e164.id, None));
             ops.push(RecipientOperation::SetE164(by_aci.id, Some(e164.clone()))); // XXX This should be handled in merge func
-            if by_aci.e164.is_some() && by_aci.e164.as_ref().unwrap() != e164 { // XXX && (change_self || not_self) && !by_aci.blocked
-                // XXX Phone number change event
+            if by_aci.e164.is_some() && by_aci.e164.as_ref().unwrap() != e164 {
+                events.extend(number_change(by_aci));
             }
         }
     }
@@ -310,7 +376,24 @@ pub fn merge_and_fetch_recipient_inner(
         tracing::trace!("Queue: {:?}", ops);
     }
 
+    let originals: Vec<&orm::Recipient> = [&by_aci, &by_pni, &by_e164]
+        .iter()
+        .filter_map(|r| r.as_ref())
+        .collect();
     for op in ops.into_iter() {
+        if let RecipientOperation::Merge(id, into_id) = op
+            && has_direct_session(db, id)?
+            && has_direct_session(db, into_id)?
+        {
+            events.push(RecipientEvent::ThreadMerge {
+                recipient_id: into_id,
+                e164: originals
+                    .iter()
+                    .find(|r| r.id == id)
+                    .and_then(|r| r.e164.clone()),
+            });
+        }
+
         #[rustfmt::skip]
         match op {
             RecipientOperation::Merge(id, into_id) => merge_recipients_inner(db, id, into_id),
@@ -365,7 +448,10 @@ pub fn merge_and_fetch_recipient_inner(
         // XXX session switchover event
     }
 
-    Ok((rcpt.id, true))
+    // Some paths above detect the same number change twice.
+    events.dedup();
+
+    Ok((rcpt.id, true, events))
 }
 
 // Inner method because the coverage report is then sensible.
@@ -0,0 +1,145 @@
+use super::observer::Observable;
+use crate::orm;
+use crate::schema;
+use chrono::prelude::*;
+use diesel::prelude::*;
+
+/// Outgoing messages that are held back until their `schedule_send_time`.
+#[diesel::dsl::auto_type]
+fn scheduled_messages() -> _ {
+    schema::messages::table
+        .filter(schema::messages::schedule_send_time.is_not_null())
+        .filter(schema::messages::is_outbound.eq(true))
+        .filter(schema::messages::sent_timestamp.is_null())
+}
+
+impl<O: Observable> super::Storage<O> {
+    /// The messages of a session that are scheduled to be sent later, the first one first.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_scheduled_messages(&self, session_id: i32) -> Vec<orm::Message> {
+        scheduled_messages()
+            .filter(schema::messages::session_id.eq(session_id))
+            .order_by((
+                schema::messages::schedule_send_time.asc(),
+                schema::messages::id.asc(),
+            ))
+            .load(&mut *self.db())
+            .expect("scheduled messages")
+    }
+
+    /// The scheduled message that is to be sent first, and when.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_next_scheduled_message_id(&self) -> Option<(i32, DateTime<Utc>)> {
+        scheduled_messages()
+            .select((
+                schema::messages::id,
+                schema::messages::schedule_send_time.assume_not_null(),
+            ))
+            .order_by(schema::messages::schedule_send_time.asc())
+            .first::<(i32, NaiveDateTime)>(&mut *self.db())
+            .optional()
+            .expect("messages by schedule send time")
+            .map(|(id, ndt)| (id, DateTime::<Utc>::from_naive_utc_and_offset(ndt, Utc)))
+    }
+
+    /// The scheduled messages that should have been sent by now.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_due_scheduled_message_ids(&self) -> Vec<i32> {
+        scheduled_messages()
+            .select(schema::messages::id)
+            .filter(schema::messages::schedule_send_time.le(Utc::now().naive_utc()))
+            .order_by(schema::messages::schedule_send_time.asc())
+            .load(&mut *self.db())
+            .expect("due scheduled messages")
+    }
+
+    /// Releases a scheduled message for sending.
+    ///
+    /// The message moves into the conversation as if it was written just now.
+    /// Returns false when the message is not scheduled (anymore).
+    #[tracing::instrument(skip(self))]
+    pub fn dispatch_scheduled_message(&self, message_id: i32) -> bool {
+        let session_id: Option<i32> = diesel::update(scheduled_messages())
+            .filter(schema::messages::id.eq(message_id))
+            .set((
+                schema::messages::schedule_send_time.eq(None::<NaiveDateTime>),
+                schema::messages::server_timestamp.eq(Utc::now().naive_utc()),
+            ))
+            .returning(schema::messages::session_id)
+            .get_result(&mut *self.db())
+            .optional()
+            .expect("dispatch scheduled message");
+
+        let Some(session_id) = session_id else {
+            return false;
+        };
+        self.observe_update(schema::messages::table, message_id)
+            .with_relation(schema::sessions::table, session_id);
+        true
+    }
+
+    /// Moves a scheduled message to another send time.
+    ///
+    /// Returns false when the message is not scheduled (anymore).
+    #[tracing::instrument(skip(self))]
+    pub fn reschedule_message(&self, message_id: i32, send_time: NaiveDateTime) -> bool {
+        let session_id: Option<i32> = diesel::update(scheduled_messages())
+            .filter(schema::messages::id.eq(message_id))
+            .set(schema::messages::schedule_send_time.eq(send_time))
+            .returning(schema::messages::session_id)
+            .get_result(&mut *self.db())
+            .optional()
+            .expect("reschedule message");
+        self.observe_scheduled_message_update(message_id, session_id)
+    }
+
+    /// Replaces the text of a scheduled message.
+    ///
+    /// Returns false when the message is not scheduled (anymore).
+    #[tracing::instrument(skip(self, new_text))]
+    pub fn update_scheduled_message_text(&self, message_id: i32, new_text: &str) -> bool {
+        let session_id: Option<i32> = diesel::update(scheduled_messages())
+            .filter(schema::messages::id.eq(message_id))
+            .set(schema::messages::text.eq(new_text))
+            .returning(schema::messages::session_id)
+            .get_result(&mut *self.db())
+            .optional()
+            .expect("update scheduled message text");
+        self.observe_scheduled_message_update(message_id, session_id)
+    }
+
+    fn observe_scheduled_message_update(&self, message_id: i32, session_id: Option<i32>) -> bool {
+        let Some(session_id) = session_id else {
+            tracing::warn!("Message {} is not scheduled (anymore)", message_id);
+            return false;
+        };
+        self.observe_update(schema::messages::table, message_id)
+            .with_relation(schema::sessions::table, session_id);
+        true
+    }
+
+    /// Removes a scheduled message before it is sent, together with its attachments.
+    ///
+    /// Returns false when the message is not scheduled (anymore).
+    #[tracing::instrument(skip(self))]
+    pub fn cancel_scheduled_message(&mut self, message_id: i32) -> bool {
+        let Some(message): Option<orm::Message> = scheduled_messages()
+            .filter(schema::messages::id.eq(message_id))
+            .first(&mut *self.db())
+            .optional()
+            .expect("db")
+        else {
+            tracing::warn!("Message {} is not scheduled (anymore)", message_id);
+            return false;
+        };
+
+        self.delete_attachments_for_message(message.id);
+        diesel::delete(schema::messages::table)
+            .filter(schema::messages::id.eq(message.id))
+            .execute(&mut *self.db())
+            .expect("delete scheduled message");
+        self.observe_delete(schema::messages::table, message.id)
+            .with_relation(schema::sessions::table, message.session_id);
+        true
+    }
+}
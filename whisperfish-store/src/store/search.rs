@@ -0,0 +1,161 @@
+use super::observer::Observable;
+use crate::orm::{self, StoryType};
+use crate::schema::messages;
+use chrono::prelude::*;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Double, Text};
+
+diesel::table! {
+    /// The full-text index of messages, maintained by triggers on `messages` and `attachments`.
+    messages_fts (rowid) {
+        rowid -> Integer,
+        body -> Text,
+        attachments -> Text,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(messages_fts, messages);
+
+/// Marks the start of a matched term in [SearchHit::snippet].
+pub const MATCH_START: char = '\u{2}';
+/// Marks the end of a matched term in [SearchHit::snippet].
+pub const MATCH_END: char = '\u{3}';
+
+/// The best matching part of a message, with the matched terms between the markers.
+const SNIPPET: &str = "snippet(messages_fts, -1, char(2), char(3), '…', 16)";
+/// Lower is better; matches in the message body weigh more than in its attachments.
+const RANK: &str = "bm25(messages_fts, 1.0, 0.5)";
+const HAS_ATTACHMENT: &str =
+    "EXISTS (SELECT 1 FROM attachments WHERE attachments.message_id = messages.id)";
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchQuery {
+    /// The words to search for.
+    ///
+    /// Words ending with `*` match as a prefix, and text between double quotes matches as a
+    /// phrase.
+    pub text: String,
+    pub session_id: Option<i32>,
+    pub sender_recipient_id: Option<i32>,
+    pub after: Option<NaiveDateTime>,
+    pub before: Option<NaiveDateTime>,
+    pub has_attachment: Option<bool>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchHit {
+    pub message: orm::Message,
+    /// Part of the message text, or of its captions and transcriptions, with the matched terms
+    /// between [MATCH_START] and [MATCH_END].
+    pub snippet: String,
+    pub rank: f64,
+}
+
+/// Quote a search term, such that FTS5 syntax has no special meaning in it.
+fn quote_term(term: &str) -> String {
+    format!("\"{}\"", term.replace('"', "\"\""))
+}
+
+/// Turn search text into an FTS5 query expression that matches all terms.
+///
+/// Returns `None` when there is nothing to search for.
+pub fn fts_query(text: &str) -> Option<String> {
+    let mut terms = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let term = if let Some(phrase) = rest.strip_prefix('"') {
+            let end = phrase.find('"').unwrap_or(phrase.len());
+            rest = phrase.get(end + 1..).unwrap_or_default();
+            quote_term(&phrase[..end])
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '"')
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+            match word.strip_suffix('*') {
+                Some(prefix) if !prefix.is_empty() => format!("{}*", quote_term(prefix)),
+                _ => quote_term(word),
+            }
+        };
+        if term.chars().any(char::is_alphanumeric) {
+            terms.push(term);
+        }
+        rest = rest.trim_start();
+    }
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+impl<O: Observable> super::Storage<O> {
+    /// Search the latest revisions of ordinary messages, best matches first.
+    #[tracing::instrument(skip(self))]
+    pub fn search(&self, search: &SearchQuery) -> Vec<SearchHit> {
+        let Some(expression) = fts_query(&search.text) else {
+            return Vec::new();
+        };
+
+        let mut query = messages_fts::table
+            .inner_join(messages::table.on(messages::id.eq(messages_fts::rowid)))
+            .select((
+                messages::all_columns,
+                sql::<Text>(SNIPPET),
+                sql::<Double>(RANK),
+            ))
+            .filter(sql::<Bool>("messages_fts MATCH ").bind::<Text, _>(expression))
+            .filter(messages::message_type.is_null())
+            .filter(messages::story_type.eq(StoryType::None))
+            .filter(
+                messages::latest_revision_id
+                    .is_null()
+                    .or(messages::latest_revision_id.eq(messages::id.nullable())),
+            )
+            .into_boxed();
+
+        if let Some(sid) = search.session_id {
+            query = query.filter(messages::session_id.eq(sid));
+        }
+        if let Some(sender) = search.sender_recipient_id {
+            query = query.filter(messages::sender_recipient_id.eq(sender));
+        }
+        if let Some(after) = search.after {
+            query = query.filter(messages::server_timestamp.ge(after));
+        }
+        if let Some(before) = search.before {
+            query = query.filter(messages::server_timestamp.lt(before));
+        }
+        if let Some(has_attachment) = search.has_attachment {
+            let with_attachments = sql::<Bool>(HAS_ATTACHMENT);
+            query = if has_attachment {
+                query.filter(with_attachments)
+            } else {
+                query.filter(diesel::dsl::not(with_attachments))
+            };
+        }
+        if let Some(limit) = search.limit {
+            query = query.limit(limit);
+        }
+
+        query
+            .order_by((
+                sql::<Double>(RANK).asc(),
+                messages::server_timestamp.desc(),
+                messages::id.desc(),
+            ))
+            .load::<(orm::Message, String, f64)>(&mut *self.db())
+            .expect("db")
+            .into_iter()
+            .map(|(message, snippet, rank)| SearchHit {
+                message,
+                snippet,
+                rank,
+            })
+            .collect()
+    }
+}
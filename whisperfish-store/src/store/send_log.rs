@@ -0,0 +1,101 @@
+use super::observer::Observable;
+use crate::schema;
+use chrono::prelude::*;
+use diesel::prelude::*;
+
+/// How long a sent message can be resent after a retry receipt.
+pub const SEND_LOG_MAX_AGE: chrono::Duration = chrono::Duration::days(1);
+
+/// The send log never grows beyond this many messages.
+pub const SEND_LOG_MAX_ENTRIES: i64 = 1000;
+
+impl<O: Observable> super::Storage<O> {
+    /// Remember the serialized `Content` of a sent message, in case one of its recipients
+    /// asks for it again.
+    ///
+    /// Inserting also prunes the log, which keeps it bounded in both age and size.  Entries are
+    /// not dropped on delivery receipts: a receipt only tells that one of the devices of a
+    /// recipient decrypted the message, and another one may still ask for it.
+    #[tracing::instrument(skip(self, content))]
+    pub fn insert_send_log(
+        &self,
+        sent_timestamp: NaiveDateTime,
+        content: &[u8],
+        recipient_ids: &[i32],
+    ) {
+        use schema::{message_send_log as msl, message_send_log_recipients as mslr};
+
+        if recipient_ids.is_empty() {
+            return;
+        }
+
+        self.db()
+            .transaction::<_, diesel::result::Error, _>(|db| {
+                let payload_id: i32 = diesel::insert_into(msl::table)
+                    .values((
+                        msl::sent_timestamp.eq(sent_timestamp),
+                        msl::content.eq(content),
+                    ))
+                    .returning(msl::id)
+                    .get_result(db)?;
+                for recipient_id in recipient_ids {
+                    diesel::insert_or_ignore_into(mslr::table)
+                        .values((
+                            mslr::payload_id.eq(payload_id),
+                            mslr::recipient_id.eq(recipient_id),
+                        ))
+                        .execute(db)?;
+                }
+                Ok(())
+            })
+            .expect("insert send log");
+
+        self.prune_send_log();
+    }
+
+    /// The `Content` we sent to a recipient at a certain timestamp, if it is still in the send log.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_send_log(
+        &self,
+        sent_timestamp: NaiveDateTime,
+        recipient_id: i32,
+    ) -> Option<Vec<u8>> {
+        use schema::{message_send_log as msl, message_send_log_recipients as mslr};
+
+        msl::table
+            .inner_join(mslr::table)
+            .filter(msl::sent_timestamp.eq(sent_timestamp))
+            .filter(mslr::recipient_id.eq(recipient_id))
+            .filter(msl::sent_timestamp.gt(Utc::now().naive_utc() - SEND_LOG_MAX_AGE))
+            .select(msl::content)
+            .first(&mut *self.db())
+            .optional()
+            .expect("fetch send log")
+    }
+
+    /// Drop messages that are too old to be resent, and the oldest ones beyond [`SEND_LOG_MAX_ENTRIES`].
+    #[tracing::instrument(skip(self))]
+    pub fn prune_send_log(&self) -> usize {
+        use schema::message_send_log as msl;
+
+        let expired = diesel::delete(msl::table)
+            .filter(msl::sent_timestamp.le(Utc::now().naive_utc() - SEND_LOG_MAX_AGE))
+            .execute(&mut *self.db())
+            .expect("prune expired send log");
+
+        let newest = msl::table
+            .select(msl::id)
+            .order_by(msl::sent_timestamp.desc())
+            .then_order_by(msl::id.desc())
+            .limit(SEND_LOG_MAX_ENTRIES);
+        let overflow = diesel::delete(msl::table)
+            .filter(msl::id.ne_all(newest))
+            .execute(&mut *self.db())
+            .expect("prune send log overflow");
+
+        if expired + overflow > 0 {
+            tracing::trace!("Pruned {} messages from the send log", expired + overflow);
+        }
+        expired + overflow
+    }
+}
@@ -0,0 +1,94 @@
+use super::observer::Observable;
+use crate::orm;
+use crate::schema;
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+
+/// A message was starred or unstarred.
+///
+/// Emitted with the message id as key, next to the update of the message, such that the starred
+/// messages can be followed without watching every message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StarredChange {
+    pub starred: bool,
+}
+
+impl<O: Observable> super::Storage<O> {
+    /// Stars a message, such that it is listed among the starred messages.
+    ///
+    /// Returns false when the message does not exist.
+    #[tracing::instrument(skip(self))]
+    pub fn star_message(&self, message_id: i32) -> bool {
+        self.set_message_starred(message_id, true)
+    }
+
+    /// Removes the star of a message.
+    ///
+    /// Returns false when the message does not exist.
+    #[tracing::instrument(skip(self))]
+    pub fn unstar_message(&self, message_id: i32) -> bool {
+        self.set_message_starred(message_id, false)
+    }
+
+    /// Stars or unstars all revisions of a message, such that the star survives later edits.
+    fn set_message_starred(&self, message_id: i32, starred: bool) -> bool {
+        let Some(message) = self.fetch_message_by_id(message_id) else {
+            tracing::warn!("Tried to star non-existing message {}", message_id);
+            return false;
+        };
+
+        let ids: Vec<i32> = diesel::update(schema::messages::table)
+            .filter(
+                schema::messages::id
+                    .eq(message.original_message_id())
+                    .or(schema::messages::original_message_id.eq(message.original_message_id())),
+            )
+            .set(schema::messages::is_bookmarked.eq(starred))
+            .returning(schema::messages::id)
+            .load(&mut *self.db())
+            .expect("star message");
+
+        for id in ids {
+            self.observe_update(schema::messages::table, id)
+                .with_relation(schema::sessions::table, message.session_id);
+            self.observe_event(id, vec![], StarredChange { starred });
+        }
+        true
+    }
+
+    /// The starred messages of all sessions, most recent first.
+    ///
+    /// Only the latest revision of an edited message is listed.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_starred_messages_augmented(&self) -> Vec<orm::AugmentedMessage> {
+        let messages: Vec<orm::Message> = schema::messages::table
+            .filter(schema::messages::is_bookmarked.eq(true))
+            .filter(
+                schema::messages::latest_revision_id
+                    .is_null()
+                    .or(schema::messages::latest_revision_id.eq(schema::messages::id.nullable())),
+            )
+            .order_by((
+                schema::messages::server_timestamp.desc(),
+                schema::messages::id.desc(),
+            ))
+            .load(&mut *self.db())
+            .expect("starred messages");
+
+        // The messages are augmented per session, which keeps the number of queries
+        // proportional to the number of sessions instead of the number of messages.
+        let mut by_session: BTreeMap<i32, Vec<orm::Message>> = BTreeMap::new();
+        for message in messages {
+            by_session
+                .entry(message.session_id)
+                .or_default()
+                .push(message);
+        }
+        let mut augmented: Vec<orm::AugmentedMessage> = by_session
+            .into_iter()
+            .flat_map(|(sid, messages)| self.augment_messages(sid, messages))
+            .collect();
+        augmented.sort_by(|a, b| (b.server_timestamp, b.id).cmp(&(a.server_timestamp, a.id)));
+        augmented
+    }
+}
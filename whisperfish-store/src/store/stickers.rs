@@ -0,0 +1,264 @@
+use super::observer::{Observable, PrimaryKey};
+use crate::{orm, schema};
+use chrono::prelude::*;
+use diesel::prelude::*;
+use libsignal_service::proto::{Pack, data_message};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+impl<O: Observable> super::Storage<O> {
+    /// Install a decrypted sticker pack.
+    ///
+    /// `files` maps the sticker ids of the manifest (including the cover) onto the
+    /// decrypted sticker files.  A previously installed version of the pack is replaced.
+    #[tracing::instrument(skip(self, pack_key, manifest, files))]
+    pub fn install_sticker_pack(
+        &self,
+        pack_id: &str,
+        pack_key: &[u8],
+        manifest: &Pack,
+        files: &HashMap<u32, PathBuf>,
+    ) -> anyhow::Result<()> {
+        let Some(cover) = manifest.cover.as_ref().or(manifest.stickers.first()) else {
+            anyhow::bail!("sticker pack without stickers");
+        };
+        let cover_id = cover.id();
+
+        // The cover goes first, because the other stickers of the pack refer to it.
+        let rows = std::iter::once((0, cover))
+            .chain(
+                manifest
+                    .stickers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, sticker)| sticker.id() != cover_id),
+            )
+            .map(|(order, sticker)| {
+                let path = files
+                    .get(&sticker.id())
+                    .ok_or_else(|| anyhow::anyhow!("missing file for sticker {}", sticker.id()))?;
+                let length = std::fs::metadata(path)?.len();
+                let path = crate::replace_home_with_tilde(path.to_str().expect("UTF8 path"));
+                Ok((order as i32, sticker, path.into_owned(), length as i32))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let now = Utc::now().naive_utc();
+        let never = DateTime::UNIX_EPOCH.naive_utc();
+
+        self.db().transaction::<_, diesel::result::Error, _>(|db| {
+            use schema::stickers::dsl::*;
+
+            diesel::delete(schema::stickers::table)
+                .filter(schema::stickers::pack_id.eq(pack_id))
+                .execute(db)?;
+
+            for (order, sticker, path, length) in &rows {
+                diesel::insert_into(schema::stickers::table)
+                    .values((
+                        schema::stickers::pack_id.eq(pack_id),
+                        sticker_id.eq(sticker.id() as i32),
+                        cover_sticker_id.eq(cover_id as i32),
+                        key.eq(pack_key),
+                        title.eq(manifest.title()),
+                        author.eq(manifest.author()),
+                        pack_order.eq(*order),
+                        emoji.eq(sticker.emoji()),
+                        content_type.eq(sticker.content_type.as_deref()),
+                        last_used.eq(never),
+                        installed.eq(now),
+                        file_path.eq(path),
+                        file_length.eq(*length),
+                        // The files are stored decrypted.
+                        file_random.eq(Vec::<u8>::new()),
+                    ))
+                    .execute(db)?;
+            }
+
+            Ok(())
+        })?;
+
+        self.observe_insert(schema::stickers::table, PrimaryKey::Unknown);
+
+        Ok(())
+    }
+
+    /// Remove an installed sticker pack and its files.
+    ///
+    /// Stickers that were sent or received keep their own copy as attachment.
+    #[tracing::instrument(skip(self))]
+    pub fn uninstall_sticker_pack(&self, pack_id: &str) -> usize {
+        let removed: Vec<orm::Sticker> = diesel::delete(schema::stickers::table)
+            .filter(schema::stickers::pack_id.eq(pack_id))
+            .returning(schema::stickers::all_columns)
+            .load(&mut *self.db())
+            .expect("db");
+
+        for sticker in &removed {
+            if let Err(e) = std::fs::remove_file(sticker.absolute_file_path().as_ref()) {
+                tracing::warn!(%sticker, "could not remove sticker file: {e}");
+            }
+        }
+
+        if !removed.is_empty() {
+            self.observe_delete(schema::stickers::table, PrimaryKey::Unknown);
+        }
+
+        removed.len()
+    }
+
+    /// The covers of all installed sticker packs, most recently used first.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_sticker_packs(&self) -> Vec<orm::Sticker> {
+        use schema::stickers::dsl::*;
+        stickers
+            .filter(sticker_id.eq(cover_sticker_id))
+            .order_by((last_used.desc(), installed.asc()))
+            .load(&mut *self.db())
+            .expect("db")
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_stickers_for_pack(&self, pid: &str) -> Vec<orm::Sticker> {
+        use schema::stickers::dsl::*;
+        stickers
+            .filter(pack_id.eq(pid))
+            .order_by(pack_order.asc())
+            .load(&mut *self.db())
+            .expect("db")
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_sticker(&self, pid: &str, sid: i32) -> Option<orm::Sticker> {
+        use schema::stickers::dsl::*;
+        stickers
+            .filter(pack_id.eq(pid))
+            .filter(sticker_id.eq(sid))
+            .first(&mut *self.db())
+            .optional()
+            .expect("db")
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn mark_sticker_used(&self, pid: &str, sid: i32) {
+        use schema::stickers::dsl::*;
+        let n = diesel::update(stickers)
+            .filter(pack_id.eq(pid))
+            .filter(sticker_id.eq(sid))
+            .set(last_used.eq(Utc::now().naive_utc()))
+            .execute(&mut *self.db())
+            .expect("db");
+        if n > 0 {
+            self.observe_update(schema::stickers::table, PrimaryKey::Unknown);
+        }
+    }
+
+    /// Save a received sticker as attachment of the message.
+    ///
+    /// Returns the id of the attachment, such that it can be fetched.
+    #[tracing::instrument(skip(self, sticker))]
+    pub fn register_sticker_attachment(
+        &mut self,
+        msg_id: i32,
+        sticker: &data_message::Sticker,
+    ) -> Option<i32> {
+        let (Some(pack_id), Some(pack_key), Some(data)) = (
+            sticker.pack_id.as_deref(),
+            sticker.pack_key.as_deref(),
+            sticker.data.clone(),
+        ) else {
+            tracing::warn!("Sticker without pack or data, dropping it.");
+            return None;
+        };
+
+        let attachment_id = self.register_attachment(msg_id, data);
+        self.set_attachment_sticker(
+            attachment_id,
+            &hex::encode(pack_id),
+            pack_key,
+            sticker.sticker_id() as i32,
+            sticker.emoji.as_deref(),
+        );
+
+        Some(attachment_id)
+    }
+
+    /// Mark an attachment as being a sticker.
+    #[tracing::instrument(skip(self, pack_key))]
+    pub fn set_attachment_sticker(
+        &self,
+        attachment_id: i32,
+        pack_id: &str,
+        pack_key: &[u8],
+        sticker_id: i32,
+        emoji: Option<&str>,
+    ) {
+        use schema::attachments::dsl as att;
+
+        let msg_id = diesel::update(att::attachments)
+            .filter(att::id.eq(attachment_id))
+            .set((
+                att::sticker_pack_id.eq(pack_id),
+                att::sticker_pack_key.eq(pack_key),
+                att::sticker_id.eq(sticker_id),
+                att::sticker_emoji.eq(emoji),
+                att::is_borderless.eq(true),
+            ))
+            .returning(att::message_id)
+            .get_result::<i32>(&mut *self.db())
+            .optional()
+            .expect("db");
+
+        if let Some(msg_id) = msg_id {
+            self.observe_update(schema::attachments::table, attachment_id)
+                .with_relation(schema::messages::table, msg_id);
+        }
+    }
+
+    /// Copy an installed sticker into `destination`, and attach it to the message.
+    #[tracing::instrument(skip(self, sticker, destination), fields(sticker = %sticker))]
+    pub fn insert_local_sticker_attachment(
+        &self,
+        msg_id: i32,
+        sticker: &orm::Sticker,
+        destination: &Path,
+    ) -> anyhow::Result<i32> {
+        let source = sticker.absolute_file_path();
+        let source = Path::new(source.as_ref());
+
+        // The attachment gets its own copy, such that uninstalling the pack
+        // does not break the conversation.
+        let mut copy = destination.join(uuid::Uuid::new_v4().as_simple().to_string());
+        if let Some(ext) = source.extension() {
+            copy.set_extension(ext);
+        }
+        std::fs::copy(source, &copy)?;
+
+        let attachment_id = self.insert_local_attachment(
+            msg_id,
+            Some(sticker.content_type.as_deref().unwrap_or("image/webp")),
+            source,
+            &copy,
+            false,
+        );
+        self.set_attachment_sticker(
+            attachment_id,
+            sticker
+                .pack_id
+                .as_deref()
+                .expect("installed sticker has a pack id"),
+            &sticker.key,
+            sticker.sticker_id,
+            Some(&sticker.emoji),
+        );
+        self.mark_sticker_used(
+            sticker
+                .pack_id
+                .as_deref()
+                .expect("installed sticker has a pack id"),
+            sticker.sticker_id,
+        );
+
+        Ok(attachment_id)
+    }
+}
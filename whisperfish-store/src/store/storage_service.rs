@@ -0,0 +1,968 @@
+//! Storage Service synchronisation.
+//!
+//! The Storage Service keeps an encrypted copy of the contacts, groups and account settings of
+//! an account.  Its contents are addressed by a versioned manifest, which lists the identifiers
+//! of all records.  Every record is encrypted with a key derived from the storage service key,
+//! so the server never sees the plaintext.
+//!
+//! Whisperfish keeps the identifier and the decrypted record of each contact and group in the
+//! `storage_service_id` and `storage_proto` columns, such that local changes can be detected and
+//! written back as new records.
+use super::observer::Observable;
+pub use super::protos::{
+    AccountRecord, ContactRecord, GroupV2Record, ManifestRecord, ReadOperation, StorageItem,
+    StorageItems, StorageManifest, StorageRecord, WriteOperation, account_record, manifest_record,
+    storage_record,
+};
+use super::{GroupV2, Settings, TrustLevel};
+use crate::{orm, schema};
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{Aead, KeyInit};
+use anyhow::Context;
+use base64::prelude::*;
+use diesel::prelude::*;
+use libsignal_service::master_key::StorageServiceKey;
+use libsignal_service::protocol::{Aci, Pni, ServiceId};
+use libsignal_service::zkgroup::api::groups::GroupSecretParams;
+use libsignal_service::zkgroup::{GROUP_MASTER_KEY_LEN, GroupMasterKey, PROFILE_KEY_LEN};
+use phonenumber::PhoneNumber;
+use prost::Message;
+use rand::RngCore;
+use std::collections::{HashMap, HashSet};
+
+pub use manifest_record::identifier::Type as IdentifierType;
+
+/// Length of the random identifiers of storage records.
+pub const STORAGE_ID_LEN: usize = 16;
+/// The Storage Service refuses to return more records in one read operation.
+const MAX_READ_RECORDS: usize = 1024;
+/// How many times a write is retried after losing a race against another device.
+const MAX_WRITE_ATTEMPTS: usize = 3;
+/// Android uses `Long.MAX_VALUE` to indicate "muted forever".
+const MUTED_FOREVER: u64 = i64::MAX as u64;
+
+const AES_GCM_IV_LEN: usize = 12;
+const RECORD_IKM_INFO_PREFIX: &[u8] = b"20240801_SIGNAL_STORAGE_SERVICE_ITEM_";
+
+/// Transport to the Storage Service.
+///
+/// The client implements this over HTTP; tests can implement it on top of an in-memory map.
+#[async_trait::async_trait]
+pub trait StorageServiceApi {
+    /// Fetch the latest manifest, if it is newer than `known_version`.
+    ///
+    /// Returns `None` if there is no manifest, or if `known_version` is up-to-date.
+    async fn read_manifest(
+        &mut self,
+        known_version: Option<u64>,
+    ) -> anyhow::Result<Option<StorageManifest>>;
+
+    /// Fetch the records with the given keys.  Unknown keys are silently skipped.
+    async fn read_records(&mut self, keys: Vec<Vec<u8>>) -> anyhow::Result<Vec<StorageItem>>;
+
+    /// Atomically replace the manifest and the given records.
+    async fn write(&mut self, operation: WriteOperation) -> anyhow::Result<WriteOutcome>;
+}
+
+#[derive(Debug)]
+pub enum WriteOutcome {
+    Written,
+    /// Another device wrote a newer manifest first.
+    Conflict(StorageManifest),
+}
+
+/// Account-wide settings that are stored in the account record, but that are not kept in the
+/// database.  The caller is responsible for applying them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageAccountSettings {
+    pub read_receipts: bool,
+    pub typing_indicators: bool,
+    pub sealed_sender_indicators: bool,
+    pub link_previews: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct StorageSyncOutcome {
+    /// Manifest version the local database is synchronised with.
+    pub manifest_version: u64,
+    /// Sessions of group v2s that were previously unknown.
+    /// Their details still have to be fetched from the server.
+    pub new_group_v2_sessions: Vec<i32>,
+    /// Account settings, set if a new account record was fetched.
+    pub account_settings: Option<StorageAccountSettings>,
+    /// Number of records that were uploaded.
+    pub records_written: usize,
+}
+
+#[derive(Debug)]
+enum LocalRecord {
+    Recipient(i32),
+    GroupV2(String),
+}
+
+/// A local record that diverged from its stored counterpart.
+#[derive(Debug)]
+struct PendingRecord {
+    local: LocalRecord,
+    id_type: IdentifierType,
+    old_id: Option<Vec<u8>>,
+    new_id: Vec<u8>,
+    record: StorageRecord,
+}
+
+impl PendingRecord {
+    /// Returns a new record if `record` differs from the stored `old` record.
+    fn if_changed(
+        local: LocalRecord,
+        id_type: IdentifierType,
+        old_id: Option<Vec<u8>>,
+        old: Option<StorageRecord>,
+        record: storage_record::Record,
+    ) -> Option<Self> {
+        let record = StorageRecord {
+            record: Some(record),
+        };
+        if old_id.is_some() && old.as_ref() == Some(&record) {
+            return None;
+        }
+        Some(Self {
+            local,
+            id_type,
+            old_id,
+            new_id: random_storage_id(),
+            record,
+        })
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    use hmac::{Hmac, KeyInit, Mac};
+
+    let mut mac =
+        Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+fn manifest_key(storage_key: &StorageServiceKey, version: u64) -> [u8; 32] {
+    hmac_sha256(&storage_key.inner, format!("Manifest_{version}").as_bytes())
+}
+
+/// Derive the key of a single record.
+///
+/// Manifests written by recent clients carry a `recordIkm`, from which the item keys are
+/// derived with HKDF.  Older manifests derive the item keys directly from the storage key.
+fn item_key(storage_key: &StorageServiceKey, record_ikm: &[u8], raw_id: &[u8]) -> [u8; 32] {
+    if record_ikm.is_empty() {
+        let id = BASE64_STANDARD.encode(raw_id);
+        hmac_sha256(&storage_key.inner, format!("Item_{id}").as_bytes())
+    } else {
+        let mut info = RECORD_IKM_INFO_PREFIX.to_vec();
+        info.extend_from_slice(raw_id);
+
+        let mut key = [0u8; 32];
+        hkdf::Hkdf::<sha2::Sha256>::new(None, record_ikm)
+            .expand(&info, &mut key)
+            .expect("32 bytes is a valid HKDF output length");
+        key
+    }
+}
+
+/// AES-256-GCM encryption, with the random IV prepended to the ciphertext.
+fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+    let mut iv = [0u8; AES_GCM_IV_LEN];
+    rand::rng().fill_bytes(&mut iv);
+
+    let cipher = Aes256Gcm::new_from_slice(key).expect("32 byte AES key");
+    let nonce = aes_gcm::aead::Nonce::<Aes256Gcm>::try_from(&iv[..]).expect("12 byte IV");
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .expect("AES-GCM encryption of a storage record");
+
+    let mut out = Vec::with_capacity(AES_GCM_IV_LEN + ciphertext.len());
+    out.extend_from_slice(&iv);
+    out.extend_from_slice(&ciphertext);
+    out
+}
+
+fn decrypt(key: &[u8; 32], ciphertext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(ciphertext.len() > AES_GCM_IV_LEN, "ciphertext too short");
+    let (iv, ciphertext) = ciphertext.split_at(AES_GCM_IV_LEN);
+
+    let cipher = Aes256Gcm::new_from_slice(key).expect("32 byte AES key");
+    let nonce = aes_gcm::aead::Nonce::<Aes256Gcm>::try_from(iv).expect("12 byte IV");
+    cipher
+        .decrypt(&nonce, ciphertext)
+        .map_err(|_| anyhow::anyhow!("storage service decryption failed"))
+}
+
+pub fn encrypt_manifest(
+    storage_key: &StorageServiceKey,
+    record: &ManifestRecord,
+) -> StorageManifest {
+    StorageManifest {
+        version: record.version,
+        value: encrypt(
+            &manifest_key(storage_key, record.version),
+            &record.encode_to_vec(),
+        ),
+    }
+}
+
+pub fn decrypt_manifest(
+    storage_key: &StorageServiceKey,
+    manifest: &StorageManifest,
+) -> anyhow::Result<ManifestRecord> {
+    let plaintext = decrypt(
+        &manifest_key(storage_key, manifest.version),
+        &manifest.value,
+    )
+    .with_context(|| format!("decrypting storage manifest version {}", manifest.version))?;
+    Ok(ManifestRecord::decode(plaintext.as_slice())?)
+}
+
+pub fn encrypt_record(
+    storage_key: &StorageServiceKey,
+    record_ikm: &[u8],
+    raw_id: &[u8],
+    record: &StorageRecord,
+) -> StorageItem {
+    StorageItem {
+        key: raw_id.to_vec(),
+        value: encrypt(
+            &item_key(storage_key, record_ikm, raw_id),
+            &record.encode_to_vec(),
+        ),
+    }
+}
+
+pub fn decrypt_record(
+    storage_key: &StorageServiceKey,
+    record_ikm: &[u8],
+    item: &StorageItem,
+) -> anyhow::Result<StorageRecord> {
+    let plaintext = decrypt(&item_key(storage_key, record_ikm, &item.key), &item.value)
+        .context("decrypting storage record")?;
+    Ok(StorageRecord::decode(plaintext.as_slice())?)
+}
+
+fn now_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+fn is_muted(muted_until_timestamp: u64) -> bool {
+    muted_until_timestamp > now_millis()
+}
+
+fn muted_until_timestamp(muted: bool) -> u64 {
+    if muted { MUTED_FOREVER } else { 0 }
+}
+
+fn parse_uuid(text: &str, binary: &[u8]) -> Option<uuid::Uuid> {
+    if let Ok(uuid) = uuid::Uuid::from_slice(binary) {
+        return Some(uuid);
+    }
+    if text.is_empty() {
+        return None;
+    }
+    // PNIs are serialized with their `PNI:` prefix.
+    ServiceId::parse_from_service_id_string(text)
+        .map(|id| id.raw_uuid())
+        .or_else(|| uuid::Uuid::parse_str(text).ok())
+}
+
+fn joined_name(given: &str, family: &str) -> Option<String> {
+    match (given.is_empty(), family.is_empty()) {
+        (false, false) => Some(format!("{given} {family}")),
+        (false, true) => Some(given.to_string()),
+        (true, false) => Some(family.to_string()),
+        (true, true) => None,
+    }
+}
+
+fn random_storage_id() -> Vec<u8> {
+    let mut id = vec![0u8; STORAGE_ID_LEN];
+    rand::rng().fill_bytes(&mut id);
+    id
+}
+
+fn group_master_key(group: &orm::GroupV2) -> Option<Vec<u8>> {
+    hex::decode(&group.master_key)
+        .ok()
+        .filter(|key| key.len() == GROUP_MASTER_KEY_LEN)
+}
+
+fn group_v2_id_from_master_key(master_key: &[u8]) -> Option<String> {
+    let master_key: [u8; GROUP_MASTER_KEY_LEN] = master_key.try_into().ok()?;
+    let secret = GroupSecretParams::derive_from_master_key(GroupMasterKey::new(master_key));
+    Some(hex::encode(secret.get_group_identifier()))
+}
+
+impl<O: Observable + Default> super::Storage<O> {
+    /// The last manifest we synchronised with, if any.
+    ///
+    /// A stored manifest that cannot be decoded is treated as absent, which makes the next
+    /// synchronisation start over from the remote manifest.
+    pub fn fetch_storage_manifest_record(&self) -> Option<ManifestRecord> {
+        let manifest = self.read_setting(Settings::STORAGE_MANIFEST)?;
+        let manifest = BASE64_STANDARD
+            .decode(manifest)
+            .map_err(anyhow::Error::from)
+            .and_then(|manifest| Ok(ManifestRecord::decode(manifest.as_slice())?));
+        match manifest {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                tracing::warn!("Ignoring invalid stored storage manifest: {e}");
+                None
+            }
+        }
+    }
+
+    fn store_storage_manifest_record(&self, manifest: &ManifestRecord) {
+        self.write_setting(
+            Settings::STORAGE_MANIFEST,
+            &BASE64_STANDARD.encode(manifest.encode_to_vec()),
+        );
+    }
+
+    /// Synchronise the local database with the Storage Service.
+    ///
+    /// Fetches the latest manifest, merges the records that are new to us, and uploads the
+    /// records that changed locally since the last synchronisation.
+    #[tracing::instrument(skip(self, api, storage_key))]
+    pub async fn sync_storage_service<A: StorageServiceApi + Send>(
+        &self,
+        api: &mut A,
+        storage_key: &StorageServiceKey,
+    ) -> anyhow::Result<StorageSyncOutcome> {
+        let mut outcome = StorageSyncOutcome::default();
+
+        for _ in 0..MAX_WRITE_ATTEMPTS {
+            let mut manifest = self.fetch_storage_manifest_record();
+            let known_version = manifest.as_ref().map(|m| m.version);
+
+            if let Some(remote) = api.read_manifest(known_version).await? {
+                let remote = decrypt_manifest(storage_key, &remote)?;
+                tracing::info!(
+                    "Merging storage manifest version {} (local: {:?})",
+                    remote.version,
+                    known_version
+                );
+                self.merge_storage_manifest(api, storage_key, &remote, &mut outcome)
+                    .await?;
+                self.store_storage_manifest_record(&remote);
+                manifest = Some(remote);
+            }
+
+            let manifest = manifest.unwrap_or_default();
+            outcome.manifest_version = manifest.version;
+
+            let pending = self.collect_storage_changes(&manifest);
+            if pending.is_empty() {
+                return Ok(outcome);
+            }
+
+            let mut new_manifest = manifest.clone();
+            new_manifest.version += 1;
+            new_manifest.source_device = u32::from(self.config.get_device_id());
+            let stale: HashSet<&[u8]> =
+                pending.iter().filter_map(|p| p.old_id.as_deref()).collect();
+            new_manifest
+                .identifiers
+                .retain(|identifier| !stale.contains(identifier.raw.as_slice()));
+            new_manifest
+                .identifiers
+                .extend(pending.iter().map(|p| manifest_record::Identifier {
+                    raw: p.new_id.clone(),
+                    r#type: p.id_type as i32,
+                }));
+
+            let operation = WriteOperation {
+                manifest: Some(encrypt_manifest(storage_key, &new_manifest)),
+                insert_item: pending
+                    .iter()
+                    .map(|p| {
+                        encrypt_record(storage_key, &new_manifest.record_ikm, &p.new_id, &p.record)
+                    })
+                    .collect(),
+                delete_key: pending.iter().filter_map(|p| p.old_id.clone()).collect(),
+                clear_all: false,
+            };
+
+            match api.write(operation).await? {
+                WriteOutcome::Written => {
+                    tracing::info!(
+                        "Wrote {} storage records in manifest version {}",
+                        pending.len(),
+                        new_manifest.version
+                    );
+                    outcome.records_written += pending.len();
+                    outcome.manifest_version = new_manifest.version;
+                    for record in &pending {
+                        self.set_storage_record(
+                            &record.local,
+                            Some(&record.new_id),
+                            Some(&record.record.encode_to_vec()),
+                        );
+                    }
+                    self.store_storage_manifest_record(&new_manifest);
+                    return Ok(outcome);
+                }
+                WriteOutcome::Conflict(remote) => {
+                    tracing::info!(
+                        "Storage manifest version {} conflicts with remote version {}, retrying",
+                        new_manifest.version,
+                        remote.version
+                    );
+                }
+            }
+        }
+
+        anyhow::bail!("storage service write conflicted {MAX_WRITE_ATTEMPTS} times")
+    }
+
+    fn fetch_known_storage_ids(&self) -> HashSet<Vec<u8>> {
+        let recipient_ids: Vec<Option<Vec<u8>>> = schema::recipients::table
+            .select(schema::recipients::storage_service_id)
+            .filter(schema::recipients::storage_service_id.is_not_null())
+            .load(&mut *self.db())
+            .expect("db");
+        let group_ids: Vec<Option<Vec<u8>>> = schema::group_v2s::table
+            .select(schema::group_v2s::storage_service_id)
+            .filter(schema::group_v2s::storage_service_id.is_not_null())
+            .load(&mut *self.db())
+            .expect("db");
+        recipient_ids
+            .into_iter()
+            .chain(group_ids)
+            .flatten()
+            .collect()
+    }
+
+    /// Forget storage identifiers that are not part of the manifest anymore.
+    ///
+    /// Another device replaced or removed the record; the replacement (if any) is fetched
+    /// separately.
+    fn forget_storage_ids(&self, ids: &[Vec<u8>]) {
+        if ids.is_empty() {
+            return;
+        }
+
+        let recipient_ids: Vec<i32> = diesel::update(
+            schema::recipients::table.filter(schema::recipients::storage_service_id.eq_any(ids)),
+        )
+        .set((
+            schema::recipients::storage_service_id.eq(None::<Vec<u8>>),
+            schema::recipients::storage_proto.eq(None::<Vec<u8>>),
+        ))
+        .returning(schema::recipients::id)
+        .get_results(&mut *self.db())
+        .expect("db");
+        diesel::update(
+            schema::group_v2s::table.filter(schema::group_v2s::storage_service_id.eq_any(ids)),
+        )
+        .set((
+            schema::group_v2s::storage_service_id.eq(None::<Vec<u8>>),
+            schema::group_v2s::storage_proto.eq(None::<Vec<u8>>),
+        ))
+        .execute(&mut *self.db())
+        .expect("db");
+
+        for id in recipient_ids {
+            self.observe_update(schema::recipients::table, id);
+        }
+    }
+
+    fn set_storage_record(
+        &self,
+        local: &LocalRecord,
+        storage_id: Option<&[u8]>,
+        proto: Option<&[u8]>,
+    ) {
+        match local {
+            LocalRecord::Recipient(recipient_id) => {
+                use schema::recipients::dsl::*;
+                diesel::update(recipients.filter(id.eq(recipient_id)))
+                    .set((storage_service_id.eq(storage_id), storage_proto.eq(proto)))
+                    .execute(&mut *self.db())
+                    .expect("db");
+            }
+            LocalRecord::GroupV2(group_id) => {
+                use schema::group_v2s::dsl::*;
+                diesel::update(group_v2s.filter(id.eq(group_id)))
+                    .set((storage_service_id.eq(storage_id), storage_proto.eq(proto)))
+                    .execute(&mut *self.db())
+                    .expect("db");
+            }
+        }
+    }
+
+    async fn merge_storage_manifest<A: StorageServiceApi + Send>(
+        &self,
+        api: &mut A,
+        storage_key: &StorageServiceKey,
+        manifest: &ManifestRecord,
+        outcome: &mut StorageSyncOutcome,
+    ) -> anyhow::Result<()> {
+        let known = self.fetch_known_storage_ids();
+        let remote: HashSet<&[u8]> = manifest
+            .identifiers
+            .iter()
+            .map(|identifier| identifier.raw.as_slice())
+            .collect();
+
+        let removed: Vec<Vec<u8>> = known
+            .iter()
+            .filter(|id| !remote.contains(id.as_slice()))
+            .cloned()
+            .collect();
+        tracing::debug!("{} storage records were removed remotely", removed.len());
+        self.forget_storage_ids(&removed);
+
+        let missing: Vec<Vec<u8>> = manifest
+            .identifiers
+            .iter()
+            .filter(|identifier| {
+                matches!(
+                    identifier.r#type(),
+                    IdentifierType::Contact | IdentifierType::Groupv2 | IdentifierType::Account
+                )
+            })
+            .filter(|identifier| !known.contains(&identifier.raw))
+            .map(|identifier| identifier.raw.clone())
+            .collect();
+        tracing::debug!("Fetching {} new storage records", missing.len());
+
+        for keys in missing.chunks(MAX_READ_RECORDS) {
+            for item in api.read_records(keys.to_vec()).await? {
+                let record = match decrypt_record(storage_key, &manifest.record_ikm, &item) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        tracing::error!("Skipping storage record: {e:#}");
+                        continue;
+                    }
+                };
+                let proto = record.encode_to_vec();
+                match record.record {
+                    Some(storage_record::Record::Contact(contact)) => {
+                        self.merge_contact_record(&item.key, &proto, &contact);
+                    }
+                    Some(storage_record::Record::GroupV2(group)) => {
+                        if let Some(session_id) =
+                            self.merge_group_v2_record(&item.key, &proto, &group)
+                        {
+                            outcome.new_group_v2_sessions.push(session_id);
+                        }
+                    }
+                    Some(storage_record::Record::Account(account)) => {
+                        outcome.account_settings =
+                            self.merge_account_record(&item.key, &proto, &account);
+                    }
+                    Some(storage_record::Record::GroupV1(_)) | None => {
+                        tracing::debug!("Ignoring unsupported storage record");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, proto, contact))]
+    fn merge_contact_record(&self, storage_id: &[u8], proto: &[u8], contact: &ContactRecord) {
+        let aci = parse_uuid(&contact.aci, &contact.aci_binary).map(Aci::from);
+        let pni = parse_uuid(&contact.pni, &contact.pni_binary).map(Pni::from);
+        let e164 = Some(&contact.e164)
+            .filter(|e164| !e164.is_empty())
+            .and_then(|e164| match phonenumber::parse(None, e164) {
+                Ok(e164) => Some(e164),
+                Err(e) => {
+                    tracing::warn!("Ignoring unparsable phone number in contact record: {e}");
+                    None
+                }
+            });
+        if aci.is_none() && pni.is_none() && e164.is_none() {
+            tracing::warn!("Contact record without any identifier, ignoring");
+            return;
+        }
+
+        let recipient = self.merge_and_fetch_recipient(e164, aci, pni, TrustLevel::Uncertain);
+        if self.fetch_self_recipient().map(|r| r.id) == Some(recipient.id) {
+            tracing::debug!("Ignoring contact record of self");
+            return;
+        }
+
+        {
+            use schema::recipients::dsl::*;
+            let mut db = self.db();
+
+            diesel::update(recipients.filter(id.eq(recipient.id)))
+                .set((
+                    storage_service_id.eq(storage_id),
+                    storage_proto.eq(proto),
+                    is_blocked.eq(contact.blocked),
+                    is_accepted
+                        .eq(!contact.blocked && (contact.whitelisted || recipient.is_accepted)),
+                    profile_sharing_enabled.eq(contact.whitelisted),
+                ))
+                .execute(&mut *db)
+                .expect("db");
+
+            // Only trust the profile key if we don't have one yet;
+            // a profile key from a message is more recent.
+            if recipient.profile_key.is_none() && contact.profile_key.len() == PROFILE_KEY_LEN {
+                diesel::update(recipients.filter(id.eq(recipient.id)))
+                    .set((
+                        profile_key.eq(&contact.profile_key),
+                        unidentified_access_mode.eq(orm::UnidentifiedAccessMode::Unknown),
+                    ))
+                    .execute(&mut *db)
+                    .expect("db");
+            }
+
+            if recipient.profile_joined_name.is_none()
+                && let Some(joined) = joined_name(&contact.given_name, &contact.family_name)
+            {
+                diesel::update(recipients.filter(id.eq(recipient.id)))
+                    .set((
+                        profile_given_name.eq(Some(&contact.given_name).filter(|n| !n.is_empty())),
+                        profile_family_name
+                            .eq(Some(&contact.family_name).filter(|n| !n.is_empty())),
+                        profile_joined_name.eq(joined),
+                    ))
+                    .execute(&mut *db)
+                    .expect("db");
+            }
+
+            if recipient.username.is_none() && !contact.username.is_empty() {
+                diesel::update(recipients.filter(id.eq(recipient.id)))
+                    .set(username.eq(&contact.username))
+                    .execute(&mut *db)
+                    .expect("db");
+            }
+        }
+        self.observe_update(schema::recipients::table, recipient.id);
+
+        if let Some(session) = self.fetch_session_by_recipient_id(recipient.id) {
+            self.mark_session_archived(session.id, contact.archived);
+            self.mark_session_muted(session.id, is_muted(contact.muted_until_timestamp));
+        }
+    }
+
+    /// Returns the session id if the group was not known before.
+    #[tracing::instrument(skip(self, proto, record))]
+    fn merge_group_v2_record(
+        &self,
+        storage_id: &[u8],
+        proto: &[u8],
+        record: &GroupV2Record,
+    ) -> Option<i32> {
+        let Ok(master_key) = <[u8; GROUP_MASTER_KEY_LEN]>::try_from(record.master_key.as_slice())
+        else {
+            tracing::warn!("Group v2 record with invalid master key, ignoring");
+            return None;
+        };
+        let group = GroupV2 {
+            secret: GroupSecretParams::derive_from_master_key(GroupMasterKey::new(master_key)),
+            revision: 0,
+        };
+        let is_new = !self.group_v2_exists(&group);
+        let session = self.fetch_or_insert_session_by_group_v2(&group);
+        let group_id = hex::encode(group.secret.get_group_identifier());
+
+        self.set_storage_record(
+            &LocalRecord::GroupV2(group_id.clone()),
+            Some(storage_id),
+            Some(proto),
+        );
+        self.observe_update(schema::group_v2s::table, group_id.clone());
+
+        self.mark_group_v2_blocked(&group_id, record.blocked);
+        self.mark_session_archived(session.id, record.archived);
+        self.mark_session_muted(session.id, is_muted(record.muted_until_timestamp));
+
+        is_new.then_some(session.id)
+    }
+
+    #[tracing::instrument(skip(self, proto, account))]
+    fn merge_account_record(
+        &self,
+        storage_id: &[u8],
+        proto: &[u8],
+        account: &AccountRecord,
+    ) -> Option<StorageAccountSettings> {
+        let Some(self_recipient) = self.fetch_self_recipient() else {
+            tracing::warn!("No self recipient, cannot merge account record");
+            return None;
+        };
+
+        if account.profile_key.len() == PROFILE_KEY_LEN
+            && let Some(aci) = self_recipient.uuid
+        {
+            self.update_profile_key(
+                self_recipient.e164.clone(),
+                Some(Aci::from(aci).into()),
+                &account.profile_key,
+                TrustLevel::Certain,
+            );
+        }
+
+        {
+            use schema::recipients::dsl::*;
+            let mut db = self.db();
+
+            if let Some(joined) = joined_name(&account.given_name, &account.family_name)
+                && self_recipient.profile_joined_name.as_ref() != Some(&joined)
+            {
+                diesel::update(recipients.filter(id.eq(self_recipient.id)))
+                    .set((
+                        profile_given_name.eq(Some(&account.given_name).filter(|n| !n.is_empty())),
+                        profile_family_name
+                            .eq(Some(&account.family_name).filter(|n| !n.is_empty())),
+                        profile_joined_name.eq(joined),
+                    ))
+                    .execute(&mut *db)
+                    .expect("db");
+            }
+        }
+        self.set_storage_record(
+            &LocalRecord::Recipient(self_recipient.id),
+            Some(storage_id),
+            Some(proto),
+        );
+        self.invalidate_self_recipient();
+        self.observe_update(schema::recipients::table, self_recipient.id);
+
+        if let Some(session) = self.fetch_session_by_recipient_id(self_recipient.id) {
+            self.mark_session_archived(session.id, account.note_to_self_archived);
+        }
+
+        let pinned: HashSet<i32> = account
+            .pinned_conversations
+            .iter()
+            .filter_map(|pinned| self.fetch_session_by_pinned_conversation(pinned))
+            .map(|session| session.id)
+            .collect();
+        for session in self.fetch_sessions() {
+            self.mark_session_pinned(session.id, pinned.contains(&session.id));
+        }
+
+        Some(StorageAccountSettings {
+            read_receipts: account.read_receipts,
+            typing_indicators: account.typing_indicators,
+            sealed_sender_indicators: account.sealed_sender_indicators,
+            link_previews: account.link_previews,
+        })
+    }
+
+    fn fetch_session_by_pinned_conversation(
+        &self,
+        pinned: &account_record::PinnedConversation,
+    ) -> Option<orm::Session> {
+        use account_record::pinned_conversation::Identifier;
+        match pinned.identifier.as_ref()? {
+            Identifier::Contact(contact) => {
+                if let Some(service_id) =
+                    ServiceId::parse_from_service_id_string(&contact.service_id)
+                {
+                    self.fetch_session_by_address(&service_id)
+                } else {
+                    let e164: PhoneNumber = phonenumber::parse(None, &contact.e164).ok()?;
+                    self.fetch_session_by_phonenumber(&e164)
+                }
+            }
+            Identifier::GroupMasterKey(master_key) => {
+                self.fetch_session_by_group_v2_id(&group_v2_id_from_master_key(master_key)?)
+            }
+            Identifier::LegacyGroupId(_) => None,
+        }
+    }
+
+    fn pinned_conversation(
+        &self,
+        session: &orm::Session,
+    ) -> Option<account_record::PinnedConversation> {
+        use account_record::pinned_conversation::{Contact, Identifier};
+        let identifier = match &session.r#type {
+            orm::SessionType::DirectMessage(recipient) => Identifier::Contact(Contact {
+                service_id: recipient
+                    .uuid
+                    .map(|aci| Aci::from(aci).service_id_string())
+                    .unwrap_or_default(),
+                e164: recipient
+                    .e164
+                    .as_ref()
+                    .map(PhoneNumber::to_string)
+                    .unwrap_or_default(),
+            }),
+            orm::SessionType::GroupV2(group) => {
+                Identifier::GroupMasterKey(group_master_key(group)?)
+            }
+            orm::SessionType::GroupV1(_) => return None,
+        };
+        Some(account_record::PinnedConversation {
+            identifier: Some(identifier),
+        })
+    }
+
+    /// Compare the local state with the stored records, and build new records for everything
+    /// that diverged.
+    ///
+    /// Fields unknown to us are lost when re-encoding a record, so records are only rewritten
+    /// when one of the fields we manage changed.
+    fn collect_storage_changes(&self, manifest: &ManifestRecord) -> Vec<PendingRecord> {
+        let sessions: Vec<orm::Session> = self.fetch_sessions();
+        let by_recipient: HashMap<i32, &orm::Session> = sessions
+            .iter()
+            .filter_map(|s| match &s.r#type {
+                orm::SessionType::DirectMessage(recipient) => Some((recipient.id, s)),
+                _ => None,
+            })
+            .collect();
+        let by_group: HashMap<&str, &orm::Session> = sessions
+            .iter()
+            .filter_map(|s| match &s.r#type {
+                orm::SessionType::GroupV2(group) => Some((group.id.as_str(), s)),
+                _ => None,
+            })
+            .collect();
+        let self_recipient = self.fetch_self_recipient();
+        let self_id = self_recipient.as_ref().map(|r| r.id);
+
+        let mut pending = Vec::new();
+        let decode = |proto: &Option<Vec<u8>>| {
+            proto
+                .as_deref()
+                .and_then(|proto| StorageRecord::decode(proto).ok())
+        };
+
+        for recipient in self.fetch_recipients() {
+            if Some(recipient.id) == self_id || recipient.uuid.is_none() {
+                continue;
+            }
+            if recipient.storage_service_id.is_none()
+                && !(recipient.is_accepted || recipient.is_blocked || recipient.profile_sharing)
+            {
+                continue;
+            }
+
+            let old = decode(&recipient.storage_proto);
+            let mut contact = match old.as_ref().and_then(|r| r.record.clone()) {
+                Some(storage_record::Record::Contact(contact)) => contact,
+                _ => ContactRecord::default(),
+            };
+            // Identifiers that are already in the record are kept as they are: other clients
+            // may have written them in their binary form, and rewriting them in another form
+            // would make the record differ on every synchronisation.
+            if contact.aci.is_empty() && contact.aci_binary.is_empty() {
+                contact.aci = recipient.uuid.map(|u| u.to_string()).unwrap_or_default();
+            }
+            if contact.e164.is_empty() {
+                contact.e164 = recipient
+                    .e164
+                    .as_ref()
+                    .map(PhoneNumber::to_string)
+                    .unwrap_or_default();
+            }
+            if contact.pni.is_empty() && contact.pni_binary.is_empty() {
+                contact.pni = recipient
+                    .pni
+                    .map(|pni| Pni::from(pni).service_id_string())
+                    .unwrap_or_default();
+            }
+            if let Some(key) = &recipient.profile_key {
+                contact.profile_key = key.clone();
+            }
+            contact.blocked = recipient.is_blocked;
+            contact.whitelisted = recipient.profile_sharing;
+            if let Some(session) = by_recipient.get(&recipient.id) {
+                contact.archived = session.is_archived;
+                if session.is_muted != is_muted(contact.muted_until_timestamp) {
+                    contact.muted_until_timestamp = muted_until_timestamp(session.is_muted);
+                }
+            }
+
+            pending.extend(PendingRecord::if_changed(
+                LocalRecord::Recipient(recipient.id),
+                IdentifierType::Contact,
+                recipient.storage_service_id.clone(),
+                old,
+                storage_record::Record::Contact(contact),
+            ));
+        }
+
+        let groups: Vec<orm::GroupV2> = schema::group_v2s::table.load(&mut *self.db()).expect("db");
+        for group in groups {
+            let Some(master_key) = group_master_key(&group) else {
+                tracing::warn!("Group {} has an invalid master key", group.id);
+                continue;
+            };
+            let old = decode(&group.storage_proto);
+            let mut record = match old.as_ref().and_then(|r| r.record.clone()) {
+                Some(storage_record::Record::GroupV2(record)) => record,
+                _ => GroupV2Record::default(),
+            };
+            record.master_key = master_key;
+            record.blocked = group.is_blocked;
+            if let Some(session) = by_group.get(group.id.as_str()) {
+                record.archived = session.is_archived;
+                if session.is_muted != is_muted(record.muted_until_timestamp) {
+                    record.muted_until_timestamp = muted_until_timestamp(session.is_muted);
+                }
+            }
+
+            pending.extend(PendingRecord::if_changed(
+                LocalRecord::GroupV2(group.id.clone()),
+                IdentifierType::Groupv2,
+                group.storage_service_id.clone(),
+                old,
+                storage_record::Record::GroupV2(record),
+            ));
+        }
+
+        // The account record is only updated if it exists; the primary device creates it.
+        let has_account = manifest
+            .identifiers
+            .iter()
+            .any(|identifier| identifier.r#type() == IdentifierType::Account);
+        if let Some(self_recipient) = self_recipient.filter(|r| r.storage_service_id.is_some())
+            && has_account
+        {
+            let old = decode(&self_recipient.storage_proto);
+            if let Some(storage_record::Record::Account(mut account)) =
+                old.as_ref().and_then(|r| r.record.clone())
+            {
+                if let Some(key) = &self_recipient.profile_key {
+                    account.profile_key = key.clone();
+                }
+                if let Some(session) = by_recipient.get(&self_recipient.id) {
+                    account.note_to_self_archived = session.is_archived;
+                }
+                let mut pinned: Vec<_> = sessions
+                    .iter()
+                    .filter(|s| s.is_pinned)
+                    .filter_map(|s| self.pinned_conversation(s))
+                    .collect();
+                // Keep the remote order of pinned conversations that are still pinned.
+                let order: Vec<_> = account
+                    .pinned_conversations
+                    .iter()
+                    .filter(|p| pinned.contains(p))
+                    .cloned()
+                    .collect();
+                pinned.retain(|p| !order.contains(p));
+                account.pinned_conversations = order.into_iter().chain(pinned).collect();
+
+                pending.extend(PendingRecord::if_changed(
+                    LocalRecord::Recipient(self_recipient.id),
+                    IdentifierType::Account,
+                    self_recipient.storage_service_id.clone(),
+                    old,
+                    storage_record::Record::Account(account),
+                ));
+            }
+        }
+
+        pending
+    }
+}
@@ -0,0 +1,263 @@
+use super::observer::{Observable, PrimaryKey};
+use crate::orm::{DistributionListPrivacyMode, StoryType};
+use crate::{orm, schema};
+use chrono::prelude::*;
+use diesel::prelude::*;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The distribution id of the default "My Story" list.
+pub const MY_STORY_DISTRIBUTION_ID: Uuid = Uuid::nil();
+
+/// Stories disappear a day after they have been sent.
+pub const STORY_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl<O: Observable> super::Storage<O> {
+    /// Fetch the "My Story" distribution list, creating it when it does not exist yet.
+    ///
+    /// Like Signal, it is shared with all connections by default.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_or_insert_my_story(&self) -> orm::DistributionList {
+        if let Some(list) = self.fetch_distribution_list(MY_STORY_DISTRIBUTION_ID) {
+            return list;
+        }
+
+        let list = orm::DistributionList {
+            name: "My Story".into(),
+            distribution_id: MY_STORY_DISTRIBUTION_ID,
+            session_id: None,
+            allows_replies: true,
+            deletion_timestamp: None,
+            is_unknown: false,
+            privacy_mode: DistributionListPrivacyMode::All,
+        };
+        self.insert_distribution_list(&list);
+        list
+    }
+
+    /// Fetch a distribution list, inserting an unknown list if it does not exist.
+    ///
+    /// Stories sent by our other devices refer to lists that may not have been synced yet.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_or_insert_distribution_list(
+        &self,
+        distribution_id: Uuid,
+    ) -> orm::DistributionList {
+        if distribution_id == MY_STORY_DISTRIBUTION_ID {
+            return self.fetch_or_insert_my_story();
+        }
+        if let Some(list) = self.fetch_distribution_list(distribution_id) {
+            return list;
+        }
+
+        let list = orm::DistributionList {
+            // The name is unique, the distribution id will do until we learn better.
+            name: distribution_id.to_string(),
+            distribution_id,
+            session_id: None,
+            allows_replies: true,
+            deletion_timestamp: None,
+            is_unknown: true,
+            privacy_mode: DistributionListPrivacyMode::OnlyWith,
+        };
+        self.insert_distribution_list(&list);
+        list
+    }
+
+    fn insert_distribution_list(&self, list: &orm::DistributionList) {
+        use schema::distribution_lists::dsl::*;
+        diesel::insert_into(distribution_lists)
+            .values((
+                name.eq(&list.name),
+                distribution_id.eq(list.distribution_id.to_string()),
+                session_id.eq(list.session_id),
+                allows_replies.eq(list.allows_replies),
+                deletion_timestamp.eq(list.deletion_timestamp),
+                is_unknown.eq(list.is_unknown),
+                privacy_mode.eq(list.privacy_mode),
+            ))
+            .execute(&mut *self.db())
+            .expect("insert distribution list");
+        self.observe_insert(
+            schema::distribution_lists::table,
+            PrimaryKey::StringRowId(list.distribution_id.to_string()),
+        );
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_distribution_list(&self, distribution_id: Uuid) -> Option<orm::DistributionList> {
+        schema::distribution_lists::table
+            .filter(schema::distribution_lists::distribution_id.eq(distribution_id.to_string()))
+            .first(&mut *self.db())
+            .optional()
+            .expect("db")
+    }
+
+    /// All distribution lists that have not been deleted.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_distribution_lists(&self) -> Vec<orm::DistributionList> {
+        schema::distribution_lists::table
+            .filter(schema::distribution_lists::deletion_timestamp.is_null())
+            .order_by(schema::distribution_lists::name.asc())
+            .load(&mut *self.db())
+            .expect("db")
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_distribution_list_members(
+        &self,
+        distribution_id: Uuid,
+    ) -> Vec<orm::DistributionListMember> {
+        schema::distribution_list_members::table
+            .filter(
+                schema::distribution_list_members::distribution_id.eq(distribution_id.to_string()),
+            )
+            .load(&mut *self.db())
+            .expect("db")
+    }
+
+    /// Add a session to a distribution list.
+    ///
+    /// For an `AllExcept` list, the members are the excluded sessions.
+    #[tracing::instrument(skip(self))]
+    pub fn add_distribution_list_member(&self, distribution_id: Uuid, session_id: i32) {
+        let Some(list) = self.fetch_distribution_list(distribution_id) else {
+            tracing::warn!("unknown distribution list");
+            return;
+        };
+        let inserted = diesel::insert_or_ignore_into(schema::distribution_list_members::table)
+            .values((
+                schema::distribution_list_members::distribution_id.eq(distribution_id.to_string()),
+                schema::distribution_list_members::session_id.eq(session_id),
+                schema::distribution_list_members::privacy_mode.eq(list.privacy_mode),
+            ))
+            .execute(&mut *self.db())
+            .expect("insert distribution list member");
+        if inserted > 0 {
+            self.observe_insert(
+                schema::distribution_list_members::table,
+                PrimaryKey::Unknown,
+            )
+            .with_relation(
+                schema::distribution_lists::table,
+                PrimaryKey::StringRowId(distribution_id.to_string()),
+            );
+        }
+    }
+
+    /// The sessions a story to this distribution list should be sent to.
+    ///
+    /// "All" means all direct sessions with registered, unblocked recipients
+    /// with which we share our profile, or whose message request we accepted.
+    #[tracing::instrument(skip(self, list), fields(distribution_id = %list.distribution_id))]
+    pub fn fetch_story_recipients(&self, list: &orm::DistributionList) -> Vec<orm::Session> {
+        let members: Vec<i32> = self
+            .fetch_distribution_list_members(list.distribution_id)
+            .into_iter()
+            .map(|member| member.session_id)
+            .collect();
+        let self_recipient = self.fetch_self_recipient_id();
+
+        self.fetch_sessions()
+            .into_iter()
+            .filter(|session| match &session.r#type {
+                orm::SessionType::DirectMessage(recipient) => {
+                    recipient.id != self_recipient
+                        && recipient.is_registered
+                        && !recipient.is_blocked
+                        && (recipient.profile_sharing || recipient.is_accepted)
+                }
+                _ => false,
+            })
+            .filter(|session| match list.privacy_mode {
+                DistributionListPrivacyMode::All => true,
+                DistributionListPrivacyMode::AllExcept => !members.contains(&session.id),
+                DistributionListPrivacyMode::OnlyWith => members.contains(&session.id),
+            })
+            .collect()
+    }
+
+    /// Record to which sessions a story was sent, and through which list.
+    #[tracing::instrument(skip(self))]
+    pub fn insert_story_sends(
+        &self,
+        story_id: i32,
+        distribution_id: Uuid,
+        allows_replies: bool,
+        session_ids: &[i32],
+        sent_timestamp: NaiveDateTime,
+    ) {
+        use schema::story_sends::dsl as ss;
+
+        for sid in session_ids {
+            diesel::insert_or_ignore_into(ss::story_sends)
+                .values((
+                    ss::message_id.eq(story_id),
+                    ss::session_id.eq(sid),
+                    ss::sent_timestamp.eq(sent_timestamp),
+                    ss::allows_replies.eq(allows_replies),
+                    ss::distribution_id.eq(distribution_id.to_string()),
+                ))
+                .execute(&mut *self.db())
+                .expect("insert story send");
+        }
+
+        self.observe_insert(schema::story_sends::table, PrimaryKey::Unknown)
+            .with_relation(schema::messages::table, story_id);
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_story_sends(&self, story_id: i32) -> Vec<orm::StorySend> {
+        schema::story_sends::table
+            .filter(schema::story_sends::message_id.eq(story_id))
+            .load(&mut *self.db())
+            .expect("db")
+    }
+
+    /// Find the story a reply refers to.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_story_by_timestamp(
+        &self,
+        author_id: i32,
+        ts: NaiveDateTime,
+    ) -> Option<orm::Message> {
+        use schema::messages::dsl::*;
+        let self_recipient = self.fetch_self_recipient_id();
+
+        let query = messages
+            .filter(server_timestamp.eq(ts))
+            .filter(story_type.ne(StoryType::None))
+            .into_boxed();
+        let query = if author_id == self_recipient {
+            query.filter(is_outbound.eq(true))
+        } else {
+            query.filter(sender_recipient_id.eq(author_id))
+        };
+        query.first(&mut *self.db()).optional().expect("db")
+    }
+
+    /// All stories that did not expire yet, newest first.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_active_stories(&self) -> Vec<orm::Message> {
+        use schema::messages::dsl::*;
+        let cutoff = Utc::now().naive_utc()
+            - chrono::Duration::from_std(STORY_LIFETIME).expect("story lifetime");
+        messages
+            .filter(story_type.ne(StoryType::None))
+            .filter(server_timestamp.gt(cutoff))
+            .order_by(server_timestamp.desc())
+            .load(&mut *self.db())
+            .expect("db")
+    }
+
+    /// The replies to a story, oldest first.
+    #[tracing::instrument(skip(self))]
+    pub fn fetch_story_replies(&self, story_id: i32) -> Vec<orm::Message> {
+        use schema::messages::dsl::*;
+        messages
+            .filter(parent_story_id.eq(story_id))
+            .order_by(server_timestamp.asc())
+            .load(&mut *self.db())
+            .expect("db")
+    }
+}
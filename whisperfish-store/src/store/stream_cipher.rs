@@ -0,0 +1,277 @@
+//! The framing shared by encrypted files and backups.
+//!
+//! A stream starts with an unencrypted header: magic bytes, a version, the parameters of the
+//! format (e.g. a salt) and a random nonce prefix. The contents follow in frames of AES-256-GCM,
+//! following the STREAM construction, and every frame authenticates the header.
+
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{Aead, Nonce, Payload};
+use rand::RngCore;
+use std::io::{self, Read, Write};
+
+const NONCE_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+/// The number of plaintext bytes in every frame but the last.
+pub(super) const FRAME_SIZE: usize = 64 * 1024;
+/// Set on the length of the last frame, such that a truncated stream is recognized.
+const LAST_FRAME: u32 = 1 << 31;
+
+/// A kind of encrypted stream.
+pub(super) struct Format {
+    pub magic: &'static [u8; 8],
+    pub version: u8,
+    /// The number of parameter bytes between the version and the nonce prefix.
+    pub params_len: usize,
+    /// What the stream is called in error messages.
+    pub name: &'static str,
+    /// What the key of the stream is derived from, for error messages.
+    pub key_name: &'static str,
+}
+
+impl Format {
+    pub(super) fn header_len(&self) -> usize {
+        self.magic.len() + 1 + self.params_len + NONCE_PREFIX_LEN
+    }
+
+    fn error(&self, message: impl std::fmt::Display) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} {message}", self.name),
+        )
+    }
+
+    /// A header with the given parameters and a random nonce prefix.
+    pub(super) fn new_header(&'static self, params: &[u8]) -> Header {
+        assert_eq!(params.len(), self.params_len, "parameter length");
+        let mut bytes = Vec::with_capacity(self.header_len());
+        bytes.extend_from_slice(self.magic);
+        bytes.push(self.version);
+        bytes.extend_from_slice(params);
+        bytes.resize(self.header_len(), 0);
+        rand::rng().fill_bytes(&mut bytes[self.header_len() - NONCE_PREFIX_LEN..]);
+        Header {
+            format: self,
+            bytes,
+        }
+    }
+
+    pub(super) fn read_header(&'static self, reader: &mut impl Read) -> io::Result<Header> {
+        let mut bytes = vec![0u8; self.header_len()];
+        reader.read_exact(&mut bytes).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => self.error("has no valid header"),
+            _ => e,
+        })?;
+        if &bytes[..self.magic.len()] != self.magic {
+            return Err(self.error("has no valid header"));
+        }
+        let version = bytes[self.magic.len()];
+        if version != self.version {
+            return Err(self.error(format_args!("has unsupported version {version}")));
+        }
+        Ok(Header {
+            format: self,
+            bytes,
+        })
+    }
+
+    /// Whether `reader` starts with the magic bytes of this format.
+    pub(super) fn is_format(&self, reader: &mut impl Read) -> io::Result<bool> {
+        let mut magic = [0u8; 8];
+        match reader.read_exact(&mut magic) {
+            Ok(()) => Ok(&magic == self.magic),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The unencrypted start of a stream, which is authenticated along with every frame.
+pub(super) struct Header {
+    format: &'static Format,
+    bytes: Vec<u8>,
+}
+
+impl Header {
+    /// The parameters of the format, between the version and the nonce prefix.
+    pub(super) fn params(&self) -> &[u8] {
+        let start = self.format.magic.len() + 1;
+        &self.bytes[start..start + self.format.params_len]
+    }
+
+    /// Every frame has its own nonce, which also says whether it is the last frame.
+    fn nonce(&self, counter: u32, last: bool) -> Nonce<Aes256Gcm> {
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_LEN]
+            .copy_from_slice(&self.bytes[self.bytes.len() - NONCE_PREFIX_LEN..]);
+        nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+        nonce[11] = last as u8;
+        Nonce::<Aes256Gcm>::try_from(&nonce[..]).expect("12 byte nonce")
+    }
+}
+
+/// Encrypts a stream in frames, after writing its header.
+pub(super) struct StreamWriter<W: Write> {
+    inner: W,
+    header: Header,
+    cipher: Aes256Gcm,
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> StreamWriter<W> {
+    pub(super) fn new(mut inner: W, header: Header, cipher: Aes256Gcm) -> io::Result<Self> {
+        inner.write_all(&header.bytes)?;
+        Ok(Self {
+            inner,
+            header,
+            cipher,
+            counter: 0,
+            buffer: Vec::with_capacity(FRAME_SIZE),
+        })
+    }
+
+    fn write_frame(&mut self, last: bool) -> io::Result<()> {
+        let name = self.header.format.name;
+        let nonce = self.header.nonce(self.counter, last);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &self.buffer,
+                    aad: &self.header.bytes,
+                },
+            )
+            .map_err(|_| io::Error::other(format!("{name} encryption failed")))?;
+        let len = ciphertext.len() as u32 | if last { LAST_FRAME } else { 0 };
+        self.inner.write_all(&len.to_le_bytes())?;
+        self.inner.write_all(&ciphertext)?;
+        self.buffer.clear();
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other(format!("{name} too large")))?;
+        Ok(())
+    }
+
+    /// Writes the last frame, and returns the inner writer.
+    pub(super) fn finish(mut self) -> io::Result<W> {
+        self.write_frame(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for StreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(FRAME_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == FRAME_SIZE {
+            self.write_frame(false)?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts and authenticates a stream written by [StreamWriter], whose header was already read.
+pub(super) struct StreamReader<R: Read> {
+    inner: R,
+    header: Header,
+    cipher: Aes256Gcm,
+    counter: u32,
+    buffer: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> StreamReader<R> {
+    pub(super) fn new(inner: R, header: Header, cipher: Aes256Gcm) -> Self {
+        Self {
+            inner,
+            header,
+            cipher,
+            counter: 0,
+            buffer: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+
+    fn read_frame(&mut self) -> io::Result<()> {
+        let format = self.header.format;
+        let truncated = |e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof => format.error("is truncated"),
+            _ => e,
+        };
+
+        let mut len = [0u8; 4];
+        self.inner.read_exact(&mut len).map_err(truncated)?;
+        let len = u32::from_le_bytes(len);
+        let last = len & LAST_FRAME != 0;
+        let len = (len & !LAST_FRAME) as usize;
+        if len > FRAME_SIZE + TAG_LEN {
+            return Err(format.error("frame too large"));
+        }
+
+        let mut ciphertext = vec![0u8; len];
+        self.inner.read_exact(&mut ciphertext).map_err(truncated)?;
+        let nonce = self.header.nonce(self.counter, last);
+        self.buffer = self
+            .cipher
+            .decrypt(
+                &nonce,
+                Payload {
+                    msg: &ciphertext,
+                    aad: &self.header.bytes,
+                },
+            )
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "wrong {}, or the {} is corrupted",
+                        format.key_name, format.name
+                    ),
+                )
+            })?;
+        self.position = 0;
+        self.finished = last;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| format.error("frame counter overflow"))?;
+        Ok(())
+    }
+
+    /// Checks that the whole stream was read, and that nothing follows it.
+    pub(super) fn finish(mut self) -> io::Result<()> {
+        let mut rest = Vec::new();
+        self.read_to_end(&mut rest)?;
+        if !rest.is_empty() {
+            return Err(self.header.format.error("has unexpected data at its end"));
+        }
+        if self.inner.read(&mut [0u8])? != 0 {
+            return Err(self.header.format.error("is followed by unexpected data"));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for StreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.read_frame()?;
+        }
+        let len = buf.len().min(self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
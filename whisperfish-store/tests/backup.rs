@@ -0,0 +1,206 @@
+mod common;
+
+use self::common::*;
+use libsignal_service::proto::AttachmentPointer;
+use libsignal_service::protocol::{Aci, ServiceId};
+use std::sync::Arc;
+use whisperfish_store::config::SignalConfig;
+use whisperfish_store::{NewMessage, StorageLocation, temp};
+
+const PASSWORD: &str = "Some Password";
+const PASSPHRASE: &str = "correct horse battery staple";
+
+#[tokio::test]
+async fn backup_roundtrip() {
+    let config = Arc::new(SignalConfig::default());
+    let location = temp();
+    let mut storage = SimpleStorage::new(
+        config.clone(),
+        &location,
+        Some(PASSWORD),
+        12345,
+        12346,
+        "Some HTTP Password",
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let session = storage.fetch_or_insert_session_by_address(&addr);
+    let message = storage.create_message(&NewMessage {
+        session_id: session.id,
+        source_addr: Some(addr),
+        text: "Back me up".into(),
+        timestamp: chrono::Utc::now().naive_utc(),
+        ..NewMessage::new_incoming()
+    });
+
+    // Attachments may live outside of the storage
+    let elsewhere = tempfile::tempdir().unwrap();
+    let attachment_file = elsewhere.path().join("photo.jpg");
+    std::fs::write(&attachment_file, b"not really a photo").unwrap();
+    let attachment = storage.register_attachment(message.id, AttachmentPointer::default());
+    storage.update_attachment_path(attachment, attachment_file.to_str().unwrap());
+
+    let backup = elsewhere.path().join("whisperfish.backup");
+    storage.export_backup(&backup, PASSPHRASE).unwrap();
+    drop(storage);
+
+    let restore_dir = tempfile::tempdir().unwrap();
+    let restored = StorageLocation::Path(restore_dir.path().join("restored"));
+
+    // The backup needs its passphrase, and leaves nothing behind when restoring fails
+    assert!(
+        SimpleStorage::import_backup(
+            config.clone(),
+            &restored,
+            &backup,
+            "wrong passphrase",
+            Some(PASSWORD.into()),
+        )
+        .await
+        .is_err()
+    );
+    assert!(!restored.exists());
+
+    // Tampering is detected
+    let tampered = elsewhere.path().join("tampered.backup");
+    let mut bytes = std::fs::read(&backup).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    std::fs::write(&tampered, bytes).unwrap();
+    assert!(
+        SimpleStorage::import_backup(
+            config.clone(),
+            &restored,
+            &tampered,
+            PASSPHRASE,
+            Some(PASSWORD.into()),
+        )
+        .await
+        .is_err()
+    );
+
+    // So is truncation
+    let truncated = elsewhere.path().join("truncated.backup");
+    let bytes = std::fs::read(&backup).unwrap();
+    std::fs::write(&truncated, &bytes[..bytes.len() / 2]).unwrap();
+    assert!(
+        SimpleStorage::import_backup(
+            config.clone(),
+            &restored,
+            &truncated,
+            PASSPHRASE,
+            Some(PASSWORD.into()),
+        )
+        .await
+        .is_err()
+    );
+
+    let storage = SimpleStorage::import_backup(
+        config.clone(),
+        &restored,
+        &backup,
+        PASSPHRASE,
+        Some(PASSWORD.into()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(storage.signal_password().unwrap(), "Some HTTP Password");
+
+    let messages = storage.fetch_all_messages(session.id, true);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].text.as_deref(), Some("Back me up"));
+
+    let attachments = storage.fetch_attachments_for_message(messages[0].id);
+    assert_eq!(attachments.len(), 1);
+    let restored_file = attachments[0].attachment_path.clone().unwrap();
+    assert!(
+        restored_file.starts_with(
+            restored
+                .join("storage")
+                .join("attachments")
+                .to_str()
+                .unwrap()
+        )
+    );
+    assert_eq!(std::fs::read(restored_file).unwrap(), b"not really a photo");
+
+    // Restoring over an existing storage is refused
+    assert!(
+        SimpleStorage::import_backup(
+            config,
+            &restored,
+            &backup,
+            PASSPHRASE,
+            Some(PASSWORD.into()),
+        )
+        .await
+        .is_err()
+    );
+}
+
+#[tokio::test]
+async fn backup_home_relative_attachments() {
+    let home = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", home.path().as_os_str());
+    }
+
+    let config = Arc::new(SignalConfig::default());
+    let location = StorageLocation::Path(home.path().join("storage"));
+    let mut storage = SimpleStorage::new(
+        config.clone(),
+        &location,
+        Some(PASSWORD),
+        12345,
+        12346,
+        "Some HTTP Password",
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let session = storage.fetch_or_insert_session_by_address(&addr);
+    let message = storage.create_message(&NewMessage {
+        session_id: session.id,
+        source_addr: Some(addr),
+        text: "At home".into(),
+        timestamp: chrono::Utc::now().naive_utc(),
+        ..NewMessage::new_incoming()
+    });
+
+    // Attachment paths in the home directory are stored relative to it
+    std::fs::write(home.path().join("photo.jpg"), b"not really a photo").unwrap();
+    let attachment = storage.register_attachment(message.id, AttachmentPointer::default());
+    storage.update_attachment_path(attachment, "~/photo.jpg");
+
+    let backup = home.path().join("whisperfish.backup");
+    storage.export_backup(&backup, PASSPHRASE).unwrap();
+    drop(storage);
+
+    let restored = StorageLocation::Path(home.path().join("restored"));
+    let storage = SimpleStorage::import_backup(
+        config,
+        &restored,
+        &backup,
+        PASSPHRASE,
+        Some(PASSWORD.into()),
+    )
+    .await
+    .unwrap();
+
+    let messages = storage.fetch_all_messages(session.id, true);
+    let attachments = storage.fetch_attachments_for_message(messages[0].id);
+    assert_eq!(attachments.len(), 1);
+    let restored_file = attachments[0].attachment_path.clone().unwrap();
+    assert!(restored_file.starts_with("~/restored/"), "{restored_file}");
+    assert_eq!(
+        std::fs::read(whisperfish_store::replace_tilde_with_home(&restored_file).as_ref()).unwrap(),
+        b"not really a photo"
+    );
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use self::common::*;
+use chrono::{Duration, NaiveDateTime};
+use libsignal_service::protocol::{Aci, ServiceId};
+use libsignal_service::zkgroup::GroupMasterKey;
+use libsignal_service::zkgroup::api::groups::GroupSecretParams;
+use rstest::rstest;
+use std::future::Future;
+use uuid::Uuid;
+use whisperfish_store::GroupV2;
+use whisperfish_store::orm::{CallType, EventType, MessageType};
+
+fn call_time(minutes: i64) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str("2024-08-27 09:00:00", "%Y-%m-%d %H:%M:%S").unwrap()
+        + Duration::minutes(minutes)
+}
+
+fn call_messages(storage: &SimpleStorage, session_id: i32) -> Vec<MessageType> {
+    storage
+        .fetch_all_messages(session_id, false)
+        .into_iter()
+        .filter_map(|m| m.message_type)
+        .collect()
+}
+
+#[rstest]
+#[tokio::test]
+async fn merge_call_event_updates_call(storage: impl Future<Output = InMemoryDb>) {
+    let (storage, _temp_dir) = storage.await;
+    let peer =
+        storage.fetch_or_insert_recipient_by_address(&ServiceId::Aci(Aci::from(Uuid::new_v4())));
+
+    let (session, id) = storage.merge_one_to_one_call(
+        1234,
+        call_time(0),
+        peer.id,
+        CallType::Video,
+        false,
+        EventType::Ringing,
+        false,
+    );
+    assert_eq!(
+        call_messages(&storage, session.id),
+        [MessageType::IncomingVideoCall]
+    );
+
+    // The same call, declined on another device
+    let (_, same_id) = storage.merge_one_to_one_call(
+        1234,
+        call_time(0),
+        peer.id,
+        CallType::Video,
+        false,
+        EventType::NotAccepted,
+        false,
+    );
+    assert_eq!(id, same_id);
+    let call = storage.fetch_call(1234, session.id).unwrap();
+    assert_eq!(call.event, EventType::NotAccepted);
+    assert_eq!(call.ringer, peer.id);
+    assert_eq!(storage.fetch_call_log().len(), 1);
+    assert_eq!(storage.fetch_call_log()[0].peer_recipient_id, Some(peer.id));
+}
+
+#[rstest]
+#[tokio::test]
+async fn clear_call_log(storage: impl Future<Output = InMemoryDb>) {
+    let (storage, _temp_dir) = storage.await;
+    let peer =
+        storage.fetch_or_insert_recipient_by_address(&ServiceId::Aci(Aci::from(Uuid::new_v4())));
+
+    for (call_id, minutes) in [(1, 0), (2, 10)] {
+        storage.merge_one_to_one_call(
+            call_id,
+            call_time(minutes),
+            peer.id,
+            CallType::Audio,
+            false,
+            EventType::Accepted,
+            false,
+        );
+    }
+
+    let session = storage.fetch_session_by_recipient_id(peer.id).unwrap();
+    assert_eq!(storage.clear_call_log(call_time(5), None), 1);
+    assert_eq!(
+        call_messages(&storage, session.id),
+        [MessageType::IncomingAudioCall]
+    );
+    let log = storage.fetch_call_log();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].call_id, 2);
+
+    // A late event for a cleared call does not bring it back
+    storage.merge_one_to_one_call(
+        1,
+        call_time(0),
+        peer.id,
+        CallType::Audio,
+        false,
+        EventType::Accepted,
+        false,
+    );
+    assert_eq!(storage.fetch_call_log().len(), 1);
+
+    storage.delete_call(&log[0].inner);
+    assert!(storage.fetch_call_log().is_empty());
+    assert!(call_messages(&storage, session.id).is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn mark_call_log_read(storage: impl Future<Output = InMemoryDb>) {
+    let (storage, _temp_dir) = storage.await;
+    let peer =
+        storage.fetch_or_insert_recipient_by_address(&ServiceId::Aci(Aci::from(Uuid::new_v4())));
+    let other =
+        storage.fetch_or_insert_recipient_by_address(&ServiceId::Aci(Aci::from(Uuid::new_v4())));
+
+    let (session, _) = storage.merge_one_to_one_call(
+        1,
+        call_time(0),
+        peer.id,
+        CallType::Audio,
+        false,
+        EventType::Missed,
+        false,
+    );
+    storage.merge_one_to_one_call(
+        2,
+        call_time(1),
+        other.id,
+        CallType::Audio,
+        false,
+        EventType::Missed,
+        false,
+    );
+    assert_eq!(
+        call_messages(&storage, session.id),
+        [MessageType::MissedAudioCall]
+    );
+    assert!(storage.fetch_call_log().iter().all(|call| !call.is_read));
+
+    assert_eq!(
+        storage.mark_call_log_read(call_time(5), Some(session.id)),
+        1
+    );
+    assert_eq!(storage.mark_call_log_read(call_time(5), None), 1);
+    assert!(storage.fetch_call_log().iter().all(|call| call.is_read));
+}
+
+#[rstest]
+#[tokio::test]
+async fn active_group_call_follows_peek(storage: impl Future<Output = InMemoryDb>) {
+    let (storage, _temp_dir) = storage.await;
+    let ringer =
+        storage.fetch_or_insert_recipient_by_address(&ServiceId::Aci(Aci::from(Uuid::new_v4())));
+    let group = GroupV2 {
+        secret: GroupSecretParams::derive_from_master_key(GroupMasterKey::new([1u8; 32])),
+        revision: 0,
+    };
+    let session = storage.fetch_or_insert_session_by_group_v2(&group);
+
+    storage.set_active_group_call(session.id, Some((42, ringer.id)));
+    // Peeking the same call again does not log it twice
+    storage.set_active_group_call(session.id, Some((42, ringer.id)));
+    let call = storage.fetch_call(42, session.id).unwrap();
+    assert!(call.group_call_active);
+    assert!(call.is_group());
+    assert_eq!(call.ringer, ringer.id);
+    assert!(!call.is_outbound);
+    assert_eq!(
+        call_messages(&storage, session.id),
+        [MessageType::GroupCall]
+    );
+
+    storage.set_group_call_joined(session.id);
+    let call = storage.fetch_call(42, session.id).unwrap();
+    assert!(call.local_joined);
+    assert_eq!(call.event, EventType::Joined);
+
+    storage.set_active_group_call(session.id, None);
+    let call = storage.fetch_call(42, session.id).unwrap();
+    assert!(!call.group_call_active);
+    assert_eq!(storage.fetch_call_log().len(), 1);
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use self::common::*;
+use libsignal_service::proto::{ContactDetails, contact_details};
+use libsignal_service::protocol::Aci;
+use phonenumber::PhoneNumber;
+use prost::Message;
+use std::str::FromStr;
+use uuid::Uuid;
+use whisperfish_store::TrustLevel;
+use whisperfish_store::contacts_sync::parse_contacts_stream;
+
+/// Serialize contacts like a primary device does, avatars following their details.
+fn contacts_stream(contacts: &[(ContactDetails, Option<&[u8]>)]) -> Vec<u8> {
+    let mut stream = Vec::new();
+    for (details, avatar) in contacts {
+        let mut details = details.clone();
+        if let Some(avatar) = avatar {
+            details.avatar = Some(contact_details::Avatar {
+                content_type: Some("image/jpeg".into()),
+                length: Some(avatar.len() as u32),
+            });
+        }
+        details.encode_length_delimited(&mut stream).unwrap();
+        stream.extend_from_slice(avatar.unwrap_or_default());
+    }
+    stream
+}
+
+#[tokio::test]
+async fn parse_contacts_with_avatars() {
+    let alice = ContactDetails {
+        aci: Some(Uuid::new_v4().to_string()),
+        name: Some("Alice".into()),
+        ..Default::default()
+    };
+    let bob = ContactDetails {
+        number: Some("+32474000001".into()),
+        name: Some("Bob".into()),
+        ..Default::default()
+    };
+    let stream = contacts_stream(&[(alice.clone(), Some(b"jpeg")), (bob.clone(), None)]);
+
+    let contacts = parse_contacts_stream(&stream).unwrap();
+    assert_eq!(contacts.len(), 2);
+    assert_eq!(contacts[0].details.name.as_deref(), Some("Alice"));
+    assert_eq!(contacts[0].avatar.as_deref(), Some(&b"jpeg"[..]));
+    assert_eq!(contacts[1].details.number, bob.number);
+    assert!(contacts[1].avatar.is_none());
+
+    // An avatar that is cut short is an error
+    assert!(parse_contacts_stream(&stream[..stream.len() - 2 - bob.encoded_len()]).is_err());
+    assert!(parse_contacts_stream(&[]).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn merge_synced_contacts() {
+    let (storage, _location) = storage().await;
+
+    // A contact with a conversation on the primary device
+    let alice = Uuid::new_v4();
+    let recipient = storage
+        .merge_synced_contact(&ContactDetails {
+            aci_binary: Some(alice.as_bytes().to_vec()),
+            number: Some("+32474000001".into()),
+            name: Some("Alice".into()),
+            expire_timer: Some(3600),
+            expire_timer_version: Some(2),
+            inbox_position: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(recipient.uuid, Some(alice));
+    assert_eq!(
+        recipient.e164,
+        Some(PhoneNumber::from_str("+32474000001").unwrap())
+    );
+    assert_eq!(recipient.profile_joined_name.as_deref(), Some("Alice"));
+    let session = storage.fetch_session_by_recipient_id(recipient.id).unwrap();
+    assert_eq!(
+        session.expiring_message_timeout,
+        Some(std::time::Duration::from_secs(3600))
+    );
+    assert_eq!(session.expire_timer_version, 2);
+
+    // An outdated timer does not override ours
+    storage.merge_synced_contact(&ContactDetails {
+        aci: Some(alice.to_string()),
+        expire_timer: Some(60),
+        expire_timer_version: Some(1),
+        ..Default::default()
+    });
+    let session = storage.fetch_session_by_recipient_id(recipient.id).unwrap();
+    assert_eq!(
+        session.expiring_message_timeout,
+        Some(std::time::Duration::from_secs(3600))
+    );
+
+    // Names don't override the profile
+    let bob = Uuid::new_v4();
+    storage.merge_and_fetch_recipient(None, Some(Aci::from(bob)), None, TrustLevel::Certain);
+    storage.update_profile_details(
+        &bob,
+        &Some("Bob".into()),
+        &Some("Profile".into()),
+        &None,
+        &None,
+    );
+    let bob_recipient = storage
+        .merge_synced_contact(&ContactDetails {
+            aci: Some(bob.to_string()),
+            name: Some("Bobby".into()),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(
+        bob_recipient.profile_joined_name.as_deref(),
+        Some("Bob Profile")
+    );
+    // Contacts without a conversation don't get one
+    assert!(
+        storage
+            .fetch_session_by_recipient_id(bob_recipient.id)
+            .is_none()
+    );
+
+    // Contacts without identifier are ignored
+    assert!(
+        storage
+            .merge_synced_contact(&ContactDetails {
+                name: Some("Nobody".into()),
+                ..Default::default()
+            })
+            .is_none()
+    );
+}
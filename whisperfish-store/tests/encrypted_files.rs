@@ -0,0 +1,160 @@
+mod common;
+
+use self::common::*;
+use rstest::rstest;
+use std::future::Future;
+use std::sync::Arc;
+use whisperfish_store::config::SignalConfig;
+
+async fn encrypted_storage() -> InMemoryDb {
+    let location = whisperfish_store::temp();
+    let storage = SimpleStorage::new(
+        Arc::new(SignalConfig::default()),
+        &location,
+        Some("some password"),
+        12345,
+        12346,
+        "Some Password",
+        None,
+        None,
+    )
+    .await
+    .expect("Failed to initalize storage");
+    (storage, location)
+}
+
+/// Spans a few frames, with a partial last one.
+fn attachment_contents() -> Vec<u8> {
+    (0..200_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn encrypted_attachment_round_trip() {
+    let (storage, _location) = encrypted_storage().await;
+    let dir = tempfile::tempdir().unwrap();
+    let contents = attachment_contents();
+
+    let path = storage
+        .save_attachment(1, dir.path(), "bin", &contents)
+        .await
+        .unwrap();
+
+    let on_disk = std::fs::read(&path).unwrap();
+    assert_ne!(on_disk, contents);
+    assert!(!on_disk.windows(64).any(|w| w == &contents[..64]));
+
+    assert_eq!(storage.read_attachment(&path).await.unwrap(), contents);
+
+    let exported = dir.path().join("exported.bin");
+    assert_eq!(
+        storage.export_attachment(&path, &exported).unwrap(),
+        contents.len() as u64
+    );
+    assert_eq!(std::fs::read(&exported).unwrap(), contents);
+
+    assert_eq!(storage.cached_plaintext_attachment(&path), None);
+    let plaintext = storage.plaintext_attachment(&path).unwrap();
+    assert_ne!(plaintext, path);
+    assert_eq!(std::fs::read(&plaintext).unwrap(), contents);
+    assert_eq!(storage.cached_plaintext_attachment(&path), Some(plaintext));
+}
+
+#[tokio::test]
+async fn avatars_are_encrypted() {
+    let (storage, _location) = encrypted_storage().await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("avatars").join("some-uuid");
+    let contents = attachment_contents();
+
+    storage.save_file(&path, &contents).await.unwrap();
+
+    assert_ne!(std::fs::read(&path).unwrap(), contents);
+    assert_eq!(storage.read_attachment(&path).await.unwrap(), contents);
+}
+
+#[tokio::test]
+async fn tampered_attachment_is_refused() {
+    let (storage, _location) = encrypted_storage().await;
+    let dir = tempfile::tempdir().unwrap();
+    let contents = attachment_contents();
+
+    let path = storage
+        .save_attachment(1, dir.path(), "bin", &contents)
+        .await
+        .unwrap();
+
+    let mut on_disk = std::fs::read(&path).unwrap();
+    let last = on_disk.len() - 1;
+    on_disk[last] ^= 1;
+    std::fs::write(&path, &on_disk).unwrap();
+    assert!(storage.read_attachment(&path).await.is_err());
+
+    // A failed decryption leaves no partial plaintext copy behind, so asking again fails again.
+    assert!(storage.plaintext_attachment(&path).is_err());
+    assert!(storage.plaintext_attachment(&path).is_err());
+
+    // Dropping the last frame is noticed as well.
+    on_disk.truncate(on_disk.len() / 2);
+    std::fs::write(&path, &on_disk).unwrap();
+    assert!(storage.read_attachment(&path).await.is_err());
+}
+
+#[tokio::test]
+async fn existing_attachments_are_encrypted_in_place() {
+    let (storage, _location) = encrypted_storage().await;
+    let dir = tempfile::tempdir().unwrap();
+    let contents = attachment_contents();
+
+    let path = dir.path().join("old-attachment.jpg");
+    std::fs::write(&path, &contents).unwrap();
+    // Plaintext files are still readable before the migration.
+    assert_eq!(storage.read_attachment(&path).await.unwrap(), contents);
+
+    assert_eq!(storage.encrypt_files_in_dir(dir.path()).unwrap(), 1);
+    assert_ne!(std::fs::read(&path).unwrap(), contents);
+    assert_eq!(storage.read_attachment(&path).await.unwrap(), contents);
+
+    // Already encrypted files are left alone.
+    assert_eq!(storage.encrypt_files_in_dir(dir.path()).unwrap(), 0);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    // So are the files in subdirectories, like stickers.
+    let stickers = dir.path().join("stickers").join("pack");
+    std::fs::create_dir_all(&stickers).unwrap();
+    let sticker = stickers.join("1.webp");
+    std::fs::write(&sticker, &contents).unwrap();
+    assert_eq!(storage.encrypt_files_in_dir(dir.path()).unwrap(), 1);
+    assert_ne!(std::fs::read(&sticker).unwrap(), contents);
+    assert_eq!(storage.read_attachment(&sticker).await.unwrap(), contents);
+}
+
+#[rstest]
+#[tokio::test]
+async fn unencrypted_storage_keeps_plaintext_attachments(
+    storage: impl Future<Output = InMemoryDb>,
+) {
+    let (storage, _temp_dir) = storage.await;
+    let dir = tempfile::tempdir().unwrap();
+    let contents = attachment_contents();
+
+    let path = storage
+        .save_attachment(1, dir.path(), "bin", &contents)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), contents);
+    assert_eq!(storage.plaintext_attachment(&path).unwrap(), path);
+    assert_eq!(storage.encrypt_files_in_dir(dir.path()).unwrap(), 0);
+}
+
+#[tokio::test]
+async fn attachment_is_saved_from_reader() {
+    let (storage, _location) = encrypted_storage().await;
+    let dir = tempfile::tempdir().unwrap();
+    let contents = attachment_contents();
+
+    let path = storage
+        .save_attachment_from_reader(1, dir.path(), "bin", &mut &contents[..])
+        .unwrap();
+    assert_ne!(std::fs::read(&path).unwrap(), contents);
+    assert_eq!(storage.read_attachment(&path).await.unwrap(), contents);
+}
@@ -0,0 +1,738 @@
+mod common;
+
+use self::common::*;
+use libsignal_service::groups_v2::Role;
+use libsignal_service::proto::{self, GroupAttributeBlob, group_attribute_blob};
+use libsignal_service::protocol::{Aci, ServiceId};
+use libsignal_service::push_service::DEFAULT_DEVICE_ID;
+use libsignal_service::zkgroup::api::groups::GroupSecretParams;
+use libsignal_service::zkgroup::profiles::{
+    ExpiringProfileKeyCredentialPresentation, ExpiringProfileKeyCredentialResponse, ProfileKey,
+    ProfileKeyCredentialRequest, ProfileKeyVersion,
+};
+use libsignal_service::zkgroup::{self, GroupMasterKey, ServerSecretParams, Timestamp};
+use phonenumber::PhoneNumber;
+use prost::Message;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use uuid::Uuid;
+use whisperfish_store::config::SignalConfig;
+use whisperfish_store::groups::{
+    GroupInviteLink, GroupV2Action, GroupsServiceApi, NewGroupV2, RevisionConflict,
+    fetch_group_invite_preview,
+};
+use whisperfish_store::orm::AccessRequired;
+use whisperfish_store::{TrustLevel, orm, temp};
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+fn now() -> Timestamp {
+    Timestamp::from_epoch_seconds(chrono::Utc::now().timestamp() as u64)
+}
+
+/// In-memory stand-in for the group server, and the profiles it issues credentials for.
+struct FakeGroupsServer {
+    params: ServerSecretParams,
+    profiles: HashMap<Aci, ProfileKey>,
+    avatars: HashMap<String, Vec<u8>>,
+    group: Option<proto::Group>,
+    changes: Vec<proto::group_change::Actions>,
+}
+
+impl FakeGroupsServer {
+    fn new() -> Self {
+        Self {
+            params: ServerSecretParams::generate([1u8; 32]),
+            profiles: HashMap::new(),
+            avatars: HashMap::new(),
+            group: None,
+            changes: Vec::new(),
+        }
+    }
+
+    /// Verify a member's credential presentation, and tell whom it belongs to.
+    fn present(&self, secret: &GroupSecretParams, member: &proto::Member) -> Aci {
+        let presentation: ExpiringProfileKeyCredentialPresentation =
+            zkgroup::deserialize(&member.presentation).unwrap();
+        self.params
+            .verify_expiring_profile_key_credential_presentation(
+                secret.get_public_params(),
+                &presentation,
+                now(),
+            )
+            .unwrap();
+        match secret
+            .decrypt_service_id(presentation.get_uuid_ciphertext())
+            .unwrap()
+        {
+            ServiceId::Aci(aci) => aci,
+            other => panic!("member is not an ACI: {:?}", other),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl GroupsServiceApi for FakeGroupsServer {
+    async fn fetch_profile_key_credential(
+        &mut self,
+        aci: Aci,
+        version: ProfileKeyVersion,
+        request: ProfileKeyCredentialRequest,
+    ) -> anyhow::Result<Option<ExpiringProfileKeyCredentialResponse>> {
+        let Some(profile_key) = self.profiles.get(&aci) else {
+            return Ok(None);
+        };
+        if zkgroup::serialize(&profile_key.get_profile_key_version(aci))
+            != zkgroup::serialize(&version)
+        {
+            return Ok(None);
+        }
+
+        let today = now().epoch_seconds() / SECONDS_PER_DAY * SECONDS_PER_DAY;
+        let expiration = Timestamp::from_epoch_seconds(today + 2 * SECONDS_PER_DAY);
+        let response = self
+            .params
+            .issue_expiring_profile_key_credential(
+                [2u8; 32],
+                &request,
+                aci,
+                profile_key.get_commitment(aci),
+                expiration,
+            )
+            .unwrap();
+        Ok(Some(response))
+    }
+
+    async fn upload_avatar(
+        &mut self,
+        _secret: &GroupSecretParams,
+        encrypted_avatar: Vec<u8>,
+    ) -> anyhow::Result<String> {
+        let key = format!("groups/avatar-{}", self.avatars.len());
+        self.avatars.insert(key.clone(), encrypted_avatar);
+        Ok(key)
+    }
+
+    async fn create_group(
+        &mut self,
+        _secret: &GroupSecretParams,
+        group: proto::Group,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(self.group.is_none(), "group already exists");
+        self.group = Some(group);
+        Ok(())
+    }
+
+    async fn modify_group(
+        &mut self,
+        _secret: &GroupSecretParams,
+        actions: proto::group_change::Actions,
+    ) -> anyhow::Result<proto::GroupChange> {
+        let group = self.group.as_mut().expect("group exists");
+        if actions.version != group.version + 1 {
+            return Err(RevisionConflict.into());
+        }
+        group.version = actions.version;
+        if let Some(modify) = &actions.modify_invite_link_password {
+            group.invite_link_password = modify.invite_link_password.clone();
+        }
+        if let Some(modify) = &actions.modify_add_from_invite_link_access {
+            group
+                .access_control
+                .get_or_insert_with(Default::default)
+                .add_from_invite_link = modify.add_from_invite_link_access;
+        }
+        let change = proto::GroupChange {
+            actions: actions.encode_to_vec(),
+            ..Default::default()
+        };
+        self.changes.push(actions);
+        Ok(change)
+    }
+
+    async fn fetch_group_join_info(
+        &mut self,
+        _secret: &GroupSecretParams,
+        password: &[u8],
+    ) -> anyhow::Result<proto::GroupJoinInfo> {
+        let group = self.group.as_ref().expect("group exists");
+        anyhow::ensure!(group.invite_link_password == password, "wrong password");
+        Ok(proto::GroupJoinInfo {
+            public_key: group.public_key.clone(),
+            title: group.title.clone(),
+            description: group.description.clone(),
+            avatar: group.avatar.clone(),
+            member_count: group.members.len() as u32,
+            add_from_invite_link: group
+                .access_control
+                .as_ref()
+                .map(|access| access.add_from_invite_link)
+                .unwrap_or_default(),
+            version: group.version,
+            pending_admin_approval: false,
+            ..Default::default()
+        })
+    }
+
+    async fn join_group(
+        &mut self,
+        secret: &GroupSecretParams,
+        password: &[u8],
+        actions: proto::group_change::Actions,
+    ) -> anyhow::Result<proto::GroupChange> {
+        let group = self.group.as_ref().expect("group exists");
+        anyhow::ensure!(group.invite_link_password == password, "wrong password");
+        let access = group
+            .access_control
+            .as_ref()
+            .map(|access| access.add_from_invite_link)
+            .unwrap_or_default();
+        anyhow::ensure!(
+            AccessRequired::from(access) == AccessRequired::Any,
+            "joining requires approval"
+        );
+        for added in &actions.add_members {
+            self.present(secret, added.added.as_ref().unwrap());
+        }
+        let added: Vec<_> = actions
+            .add_members
+            .iter()
+            .filter_map(|action| action.added.clone())
+            .collect();
+        let change = self.modify_group(secret, actions).await?;
+        self.group.as_mut().unwrap().members.extend(added);
+        Ok(change)
+    }
+}
+
+fn decrypt_attribute(secret: &GroupSecretParams, blob: &[u8]) -> group_attribute_blob::Content {
+    let blob = secret.decrypt_blob_with_padding(blob).unwrap();
+    GroupAttributeBlob::decode(&blob[..])
+        .unwrap()
+        .content
+        .unwrap()
+}
+
+/// Storage for an account with an ACI, which is needed to be a group member.
+async fn storage_with_self() -> (InMemoryDb, Aci) {
+    let location = temp();
+    let config = Arc::new(SignalConfig::default());
+    let self_aci = Aci::from(Uuid::new_v4());
+    config.set_aci(self_aci.into());
+    config.set_tel(PhoneNumber::from_str("+32474000000").unwrap());
+    config.set_device_id(*DEFAULT_DEVICE_ID);
+    let storage = SimpleStorage::new(
+        config,
+        &location,
+        None,
+        12345,
+        12346,
+        "Some Password",
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    ((storage, location), self_aci)
+}
+
+#[tokio::test]
+async fn create_group_v2() {
+    let ((storage, _location), self_aci) = storage_with_self().await;
+
+    let mut server = FakeGroupsServer::new();
+    let server_params = server.params.get_public_params();
+    let self_profile_key = ProfileKey::create([1u8; 32]);
+    server.profiles.insert(self_aci, self_profile_key);
+    storage.update_profile_key(
+        None,
+        Some(self_aci.into()),
+        &self_profile_key.get_bytes(),
+        TrustLevel::Certain,
+    );
+
+    let mut recipient = |profile_key: Option<ProfileKey>| {
+        let aci = Aci::from(Uuid::new_v4());
+        let recipient = match profile_key {
+            Some(profile_key) => {
+                server.profiles.insert(aci, profile_key);
+                storage
+                    .update_profile_key(
+                        None,
+                        Some(aci.into()),
+                        &profile_key.get_bytes(),
+                        TrustLevel::Certain,
+                    )
+                    .0
+            }
+            None => storage.fetch_or_insert_recipient_by_address(&aci.into()),
+        };
+        (aci, recipient)
+    };
+    let (alice, alice_recipient) = recipient(Some(ProfileKey::create([2u8; 32])));
+    let (bob, bob_recipient) = recipient(None);
+    let (charlie, charlie_recipient) = recipient(Some(ProfileKey::create([3u8; 32])));
+    // Charlie's profile key is out of date, so no credential can be issued for it.
+    server
+        .profiles
+        .insert(charlie, ProfileKey::create([4u8; 32]));
+
+    // A group needs a title
+    let untitled = storage
+        .create_group_v2(
+            &mut server,
+            &server_params,
+            NewGroupV2 {
+                title: "  ".into(),
+                ..Default::default()
+            },
+        )
+        .await;
+    assert!(untitled.is_err());
+    assert!(server.group.is_none());
+
+    let created = storage
+        .create_group_v2(
+            &mut server,
+            &server_params,
+            NewGroupV2 {
+                title: "Hikers".into(),
+                description: Some("Up the hill".into()),
+                avatar: Some(b"not really a picture".to_vec()),
+                expire_timer: Some(3600),
+                members: vec![alice_recipient, bob_recipient, charlie_recipient],
+            },
+        )
+        .await
+        .unwrap();
+
+    // The server has the encrypted group
+    let secret = GroupSecretParams::derive_from_master_key(GroupMasterKey::new(created.master_key));
+    let group = server.group.clone().unwrap();
+    assert_eq!(group.version, 0);
+    assert_eq!(
+        group.public_key,
+        zkgroup::serialize(&secret.get_public_params())
+    );
+    assert_eq!(
+        decrypt_attribute(&secret, &group.title),
+        group_attribute_blob::Content::Title("Hikers".into())
+    );
+    assert_eq!(
+        decrypt_attribute(&secret, &group.description),
+        group_attribute_blob::Content::DescriptionText("Up the hill".into())
+    );
+    assert_eq!(
+        decrypt_attribute(&secret, &group.disappearing_messages_timer),
+        group_attribute_blob::Content::DisappearingMessagesDuration(3600)
+    );
+    assert_eq!(
+        decrypt_attribute(&secret, &server.avatars[&group.avatar]),
+        group_attribute_blob::Content::Avatar(b"not really a picture".to_vec())
+    );
+
+    let members: Vec<(Aci, i32)> = group
+        .members
+        .iter()
+        .map(|member| (server.present(&secret, member), member.role))
+        .collect();
+    assert_eq!(
+        members,
+        vec![
+            (self_aci, Role::Administrator as i32),
+            (alice, Role::Default as i32)
+        ]
+    );
+    let pending: Vec<ServiceId> = group
+        .members_pending_profile_key
+        .iter()
+        .map(|pending| {
+            let user_id = zkgroup::deserialize(&pending.member.as_ref().unwrap().user_id).unwrap();
+            secret.decrypt_service_id(user_id).unwrap()
+        })
+        .collect();
+    assert_eq!(
+        pending,
+        vec![ServiceId::from(bob), ServiceId::from(charlie)]
+    );
+
+    // And it is stored locally
+    let orm::SessionType::GroupV2(group_v2) = &created.session.r#type else {
+        panic!("not a group session");
+    };
+    assert_eq!(group_v2.id, hex::encode(secret.get_group_identifier()));
+    assert_eq!(group_v2.name, "Hikers");
+    assert_eq!(group_v2.description.as_deref(), Some("Up the hill"));
+    assert_eq!(group_v2.avatar.as_deref(), Some(group.avatar.as_str()));
+    assert_eq!(
+        created.session.expiring_message_timeout,
+        Some(std::time::Duration::from_secs(3600))
+    );
+
+    let self_member = storage.fetch_group_v2_self_member(&group_v2.id).unwrap();
+    assert_eq!(self_member.role, Role::Administrator as i32);
+    let mut members: Vec<_> = storage
+        .fetch_group_members_by_group_v2_id(&group_v2.id)
+        .into_iter()
+        .map(|(_, recipient)| recipient.uuid.unwrap())
+        .collect();
+    members.sort();
+    let mut expected = vec![Uuid::from(self_aci), Uuid::from(alice)];
+    expected.sort();
+    assert_eq!(members, expected);
+    assert!(
+        storage
+            .fetch_group_v2_pending_member(&group_v2.id, Some(bob), None)
+            .is_some()
+    );
+    assert!(
+        storage
+            .fetch_group_v2_pending_member(&group_v2.id, Some(charlie), None)
+            .is_some()
+    );
+}
+
+#[tokio::test]
+async fn modify_group_v2() {
+    let ((storage, _location), self_aci) = storage_with_self().await;
+
+    let mut server = FakeGroupsServer::new();
+    let server_params = server.params.get_public_params();
+    let self_profile_key = ProfileKey::create([1u8; 32]);
+    server.profiles.insert(self_aci, self_profile_key);
+    storage.update_profile_key(
+        None,
+        Some(self_aci.into()),
+        &self_profile_key.get_bytes(),
+        TrustLevel::Certain,
+    );
+    let alice = Aci::from(Uuid::new_v4());
+    let alice_profile_key = ProfileKey::create([2u8; 32]);
+    server.profiles.insert(alice, alice_profile_key);
+    let (alice_recipient, _) = storage.update_profile_key(
+        None,
+        Some(alice.into()),
+        &alice_profile_key.get_bytes(),
+        TrustLevel::Certain,
+    );
+
+    let created = storage
+        .create_group_v2(
+            &mut server,
+            &server_params,
+            NewGroupV2 {
+                title: "Hikers".into(),
+                description: None,
+                avatar: None,
+                expire_timer: None,
+                members: vec![alice_recipient.clone()],
+            },
+        )
+        .await
+        .unwrap();
+    let secret = GroupSecretParams::derive_from_master_key(GroupMasterKey::new(created.master_key));
+    let group_id = hex::encode(secret.get_group_identifier());
+    let group_v2 = || storage.fetch_group_by_group_v2_id(&group_id).unwrap();
+
+    // A new title is encrypted for the next revision, and stored locally
+    let context = storage
+        .modify_group_v2(
+            &mut server,
+            &server_params,
+            &group_v2(),
+            GroupV2Action::Title("Climbers".into()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(context.revision, Some(1));
+    assert_eq!(context.master_key.as_deref(), Some(&created.master_key[..]));
+    let change = &server.changes[0];
+    assert_eq!(change.group_id, secret.get_group_identifier().to_vec());
+    assert_eq!(
+        decrypt_attribute(&secret, &change.modify_title.as_ref().unwrap().title),
+        group_attribute_blob::Content::Title("Climbers".into())
+    );
+    assert_eq!(group_v2().name, "Climbers");
+    assert_eq!(group_v2().revision, 1);
+
+    // Alice is promoted
+    storage
+        .modify_group_v2(
+            &mut server,
+            &server_params,
+            &group_v2(),
+            GroupV2Action::SetMemberRole(alice_recipient.clone(), Role::Administrator),
+        )
+        .await
+        .unwrap();
+    let role = &server.changes[1].modify_member_roles[0];
+    assert_eq!(role.role, Role::Administrator as i32);
+    let user_id = zkgroup::deserialize(&role.user_id).unwrap();
+    assert_eq!(
+        secret.decrypt_service_id(user_id).unwrap(),
+        ServiceId::from(alice)
+    );
+    let (alice_member, _) = storage
+        .fetch_group_members_by_group_v2_id(&group_id)
+        .into_iter()
+        .find(|(_, recipient)| recipient.id == alice_recipient.id)
+        .unwrap();
+    assert_eq!(alice_member.role, Role::Administrator as i32);
+
+    // Banning Alice also removes her from the group
+    storage
+        .modify_group_v2(
+            &mut server,
+            &server_params,
+            &group_v2(),
+            GroupV2Action::BanMember(alice_recipient.clone()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(server.changes[2].delete_members.len(), 1);
+    assert_eq!(server.changes[2].add_banned_members.len(), 1);
+    assert!(
+        storage
+            .fetch_group_members_by_group_v2_id(&group_id)
+            .iter()
+            .all(|(_, recipient)| recipient.id != alice_recipient.id)
+    );
+    assert!(
+        storage
+            .fetch_group_v2_banned_member(&group_v2(), &alice.into())
+            .is_some()
+    );
+    assert_eq!(group_v2().revision, 3);
+
+    // A change against an outdated revision is refused, such that the group can be refetched
+    server.group.as_mut().unwrap().version += 1;
+    let conflict = storage
+        .modify_group_v2(
+            &mut server,
+            &server_params,
+            &group_v2(),
+            GroupV2Action::Title("Ramblers".into()),
+        )
+        .await
+        .unwrap_err();
+    assert!(conflict.downcast_ref::<RevisionConflict>().is_some());
+    assert_eq!(server.changes.len(), 3);
+    assert_eq!(group_v2().name, "Climbers");
+
+    // Changes that need more access than we have are refused before reaching the server
+    storage.update_group_v2_member_role(&group_v2(), self_aci, Role::Default);
+    let refused = storage
+        .modify_group_v2(
+            &mut server,
+            &server_params,
+            &group_v2(),
+            GroupV2Action::AnnouncementsOnly(true),
+        )
+        .await;
+    assert!(refused.is_err());
+    assert_eq!(server.changes.len(), 3);
+}
+
+#[tokio::test]
+async fn join_group_v2_by_invite_link() {
+    let ((storage, _location), self_aci) = storage_with_self().await;
+
+    let mut server = FakeGroupsServer::new();
+    let server_params = server.params.get_public_params();
+    let self_profile_key = ProfileKey::create([1u8; 32]);
+    server.profiles.insert(self_aci, self_profile_key);
+    storage.update_profile_key(
+        None,
+        Some(self_aci.into()),
+        &self_profile_key.get_bytes(),
+        TrustLevel::Certain,
+    );
+
+    let created = storage
+        .create_group_v2(
+            &mut server,
+            &server_params,
+            NewGroupV2 {
+                title: "Hikers".into(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let secret = GroupSecretParams::derive_from_master_key(GroupMasterKey::new(created.master_key));
+    let group_id = hex::encode(secret.get_group_identifier());
+    let group_v2 = || storage.fetch_group_by_group_v2_id(&group_id).unwrap();
+
+    // New groups don't have a link to share
+    assert!(GroupInviteLink::of_group(&group_v2()).is_none());
+
+    // Enabling the link generates its password
+    storage
+        .modify_group_v2(
+            &mut server,
+            &server_params,
+            &group_v2(),
+            GroupV2Action::InviteLinkAccess(AccessRequired::Any),
+        )
+        .await
+        .unwrap();
+    let link = GroupInviteLink::of_group(&group_v2()).unwrap();
+    assert_eq!(link.master_key, created.master_key);
+    assert_eq!(
+        server.group.as_ref().unwrap().invite_link_password,
+        link.password
+    );
+    assert_eq!(GroupInviteLink::parse(&link.to_url()).unwrap(), link);
+    assert_eq!(
+        GroupInviteLink::parse(&link.to_url().replace("https://", "sgnl://")).unwrap(),
+        link
+    );
+    assert!(GroupInviteLink::parse("https://signal.org/#abc").is_err());
+
+    // Someone else previews and joins the group through the link
+    let ((joiner, _joiner_location), joiner_aci) = storage_with_self().await;
+    let joiner_profile_key = ProfileKey::create([3u8; 32]);
+    server.profiles.insert(joiner_aci, joiner_profile_key);
+    joiner.update_profile_key(
+        None,
+        Some(joiner_aci.into()),
+        &joiner_profile_key.get_bytes(),
+        TrustLevel::Certain,
+    );
+
+    let preview = fetch_group_invite_preview(&mut server, &link)
+        .await
+        .unwrap();
+    assert_eq!(preview.title, "Hikers");
+    assert_eq!(preview.member_count, 1);
+    assert!(!preview.requires_approval);
+
+    let joined = joiner
+        .join_group_v2(&mut server, &server_params, &link)
+        .await
+        .unwrap();
+    let context = joined.context.unwrap();
+    assert_eq!(context.revision, Some(2));
+    let change = server.changes.last().unwrap();
+    assert_eq!(change.add_members.len(), 1);
+    assert!(change.add_members[0].join_from_invite_link);
+    let joined_group = joiner.fetch_group_by_group_v2_id(&group_id).unwrap();
+    assert_eq!(joined_group.name, "Hikers");
+    assert_eq!(joined_group.revision, 2);
+    assert!(
+        joiner
+            .fetch_group_members_by_group_v2_id(&group_id)
+            .iter()
+            .any(|(_, recipient)| recipient.uuid == Some(joiner_aci.into()))
+    );
+
+    // Resetting the link makes the old one useless
+    storage
+        .modify_group_v2(
+            &mut server,
+            &server_params,
+            &group_v2(),
+            GroupV2Action::ResetInviteLink,
+        )
+        .await
+        .unwrap();
+    assert_ne!(GroupInviteLink::of_group(&group_v2()).unwrap(), link);
+    assert!(
+        fetch_group_invite_preview(&mut server, &link)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn leave_and_block_group_v2() {
+    let ((storage, _location), self_aci) = storage_with_self().await;
+
+    let mut server = FakeGroupsServer::new();
+    let server_params = server.params.get_public_params();
+    let self_profile_key = ProfileKey::create([1u8; 32]);
+    server.profiles.insert(self_aci, self_profile_key);
+    storage.update_profile_key(
+        None,
+        Some(self_aci.into()),
+        &self_profile_key.get_bytes(),
+        TrustLevel::Certain,
+    );
+    let alice = Aci::from(Uuid::new_v4());
+    let alice_profile_key = ProfileKey::create([2u8; 32]);
+    server.profiles.insert(alice, alice_profile_key);
+    let (alice_recipient, _) = storage.update_profile_key(
+        None,
+        Some(alice.into()),
+        &alice_profile_key.get_bytes(),
+        TrustLevel::Certain,
+    );
+
+    let created = storage
+        .create_group_v2(
+            &mut server,
+            &server_params,
+            NewGroupV2 {
+                title: "Hikers".into(),
+                members: vec![alice_recipient.clone()],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let secret = GroupSecretParams::derive_from_master_key(GroupMasterKey::new(created.master_key));
+    let group_id = hex::encode(secret.get_group_identifier());
+    let group_v2 = || storage.fetch_group_by_group_v2_id(&group_id).unwrap();
+
+    // As the last administrator, leaving hands the group over to Alice
+    storage
+        .modify_group_v2(
+            &mut server,
+            &server_params,
+            &group_v2(),
+            GroupV2Action::Leave,
+        )
+        .await
+        .unwrap();
+    let change = &server.changes[0];
+    assert_eq!(change.delete_members.len(), 1);
+    let deleted = zkgroup::deserialize(&change.delete_members[0].deleted_user_id).unwrap();
+    assert_eq!(
+        secret.decrypt_service_id(deleted).unwrap(),
+        ServiceId::from(self_aci)
+    );
+    assert_eq!(change.modify_member_roles.len(), 1);
+    assert_eq!(
+        change.modify_member_roles[0].role,
+        Role::Administrator as i32
+    );
+    let members = storage.fetch_group_members_by_group_v2_id(&group_id);
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0].1.id, alice_recipient.id);
+    assert_eq!(members[0].0.role, Role::Administrator as i32);
+    assert!(storage.fetch_group_v2_self_member(&group_id).is_none());
+
+    // We are no member anymore
+    let refused = storage
+        .modify_group_v2(
+            &mut server,
+            &server_params,
+            &group_v2(),
+            GroupV2Action::Leave,
+        )
+        .await;
+    assert!(refused.is_err());
+
+    // Blocked groups are listed by their identifier, for the Blocked sync message
+    assert!(!group_v2().is_blocked);
+    assert!(storage.fetch_blocked_group_v2_ids().is_empty());
+    storage.mark_group_v2_blocked(&group_id, true);
+    assert!(group_v2().is_blocked);
+    assert_eq!(
+        storage.fetch_blocked_group_v2_ids(),
+        vec![secret.get_group_identifier().to_vec()]
+    );
+    storage.mark_group_v2_blocked(&group_id, false);
+    assert!(storage.fetch_blocked_group_v2_ids().is_empty());
+}
@@ -12,6 +12,7 @@ mod tests {
     use rstest::rstest;
 
     use whisperfish_store::config::SignalConfig;
+    use whisperfish_store::orm::{MessageType, VerifiedStatus};
     use whisperfish_store::{Settings, Storage, StorageLocation};
 
     use crate::common::{DummyObservatory, SimpleStorage};
@@ -203,7 +204,6 @@ mod tests {
         );
     }
 
-    // Direction does not matter yet
     #[rstest(password, case(Some("some password")), case(None))]
     #[tokio::test]
     async fn is_trusted_identity(password: Option<&str>) {
@@ -211,38 +211,155 @@ mod tests {
         let (storage, _tempdir) = create_example_storage(password, None).await.unwrap();
 
         // We need two identity keys and two addresses
-        let (_, addr1) = create_random_protocol_address();
+        let (svc1, addr1) = create_random_protocol_address();
         let key1 = create_random_identity_key();
         let key2 = create_random_identity_key();
 
-        let mut storage = storage.aci_storage();
+        let mut aci_storage = storage.aci_storage();
 
         // Test trust on first use
+        for direction in [Direction::Receiving, Direction::Sending] {
+            assert!(
+                aci_storage
+                    .is_trusted_identity(&addr1, &key1, direction)
+                    .await
+                    .unwrap()
+            );
+        }
+
+        // Test inserted key
+        aci_storage.save_identity(&addr1, &key1).await.unwrap();
         assert!(
-            storage
-                .is_trusted_identity(&addr1, &key1, Direction::Receiving)
+            aci_storage
+                .is_trusted_identity(&addr1, &key1, Direction::Sending)
                 .await
                 .unwrap()
         );
 
-        // Test inserted key
-        storage.save_identity(&addr1, &key1).await.unwrap();
+        // Test wrong key: incoming messages are always accepted,
+        // but nothing is sent to another key than the stored one.
         assert!(
-            storage
-                .is_trusted_identity(&addr1, &key1, Direction::Receiving)
+            aci_storage
+                .is_trusted_identity(&addr1, &key2, Direction::Receiving)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !aci_storage
+                .is_trusted_identity(&addr1, &key2, Direction::Sending)
                 .await
                 .unwrap()
         );
 
-        // Test wrong key
+        // Test wrong key of a verified identity
+        assert!(storage.set_identity_verified_status(&svc1, &key1, VerifiedStatus::Verified));
         assert!(
-            !storage
+            aci_storage
+                .is_trusted_identity(&addr1, &key1, Direction::Sending)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !aci_storage
+                .is_trusted_identity(&addr1, &key2, Direction::Sending)
+                .await
+                .unwrap()
+        );
+        assert!(
+            aci_storage
                 .is_trusted_identity(&addr1, &key2, Direction::Receiving)
                 .await
                 .unwrap()
         );
     }
 
+    #[rstest(password, case(Some("some password")), case(None))]
+    #[tokio::test]
+    async fn verified_identity_key_change(password: Option<&str>) {
+        let (storage, _tempdir) = create_example_storage(password, None).await.unwrap();
+
+        let (svc1, addr1) = create_random_protocol_address();
+        let key1 = create_random_identity_key();
+        let key2 = create_random_identity_key();
+
+        let mut aci_storage = storage.aci_storage();
+        aci_storage.save_identity(&addr1, &key1).await.unwrap();
+
+        let recipient = storage.fetch_or_insert_recipient_by_address(&svc1);
+        let session = storage.fetch_or_insert_session_by_recipient_id(recipient.id);
+
+        // Verification only applies to the key that was verified
+        assert!(!storage.set_identity_verified_status(&svc1, &key2, VerifiedStatus::Verified));
+        assert!(storage.set_identity_verified_status(&svc1, &key1, VerifiedStatus::Verified));
+        assert_eq!(
+            storage
+                .fetch_identity_record(&svc1)
+                .unwrap()
+                .verified_status(),
+            VerifiedStatus::Verified
+        );
+
+        // A new key makes the identity unverified, and warns in the conversation
+        assert_eq!(
+            aci_storage.save_identity(&addr1, &key2).await.unwrap(),
+            IdentityChange::ReplacedExisting
+        );
+        let record = storage.fetch_identity_record(&svc1).unwrap();
+        assert_eq!(record.identity_key(), key2);
+        assert_eq!(record.verified_status(), VerifiedStatus::Unverified);
+        let messages = storage.fetch_all_messages(session.id, false);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].message_type,
+            Some(MessageType::IdentityKeyChange)
+        );
+
+        // Sending is blocked until the user accepts the new key
+        assert!(
+            !aci_storage
+                .is_trusted_identity(&addr1, &key2, Direction::Sending)
+                .await
+                .unwrap()
+        );
+        assert!(storage.accept_identity_key_change(&svc1));
+        assert!(
+            aci_storage
+                .is_trusted_identity(&addr1, &key2, Direction::Sending)
+                .await
+                .unwrap()
+        );
+        assert!(!storage.accept_identity_key_change(&svc1));
+
+        // A verified identity that changes key while sending
+        aci_storage.save_identity(&addr1, &key2).await.unwrap();
+        assert!(storage.set_identity_verified_status(&svc1, &key2, VerifiedStatus::Verified));
+        assert!(!storage.mark_identity_key_changed(&svc1));
+        assert_eq!(
+            storage
+                .fetch_identity_record(&svc1)
+                .unwrap()
+                .verified_status(),
+            VerifiedStatus::Unverified
+        );
+        assert_eq!(storage.fetch_all_messages(session.id, false).len(), 2);
+        // and is only warned about once
+        assert!(!storage.mark_identity_key_changed(&svc1));
+        assert_eq!(storage.fetch_all_messages(session.id, false).len(), 2);
+
+        // An identity that was never verified gets its new key trusted when sending again
+        assert!(storage.accept_identity_key_change(&svc1));
+        aci_storage.save_identity(&addr1, &key1).await.unwrap();
+        assert!(storage.mark_identity_key_changed(&svc1));
+        assert!(storage.fetch_identity_record(&svc1).is_none());
+        assert!(
+            aci_storage
+                .is_trusted_identity(&addr1, &key2, Direction::Sending)
+                .await
+                .unwrap()
+        );
+        assert_eq!(storage.fetch_all_messages(session.id, false).len(), 3);
+    }
+
     #[rstest(password, case(Some("some password")), case(None))]
     #[tokio::test]
     async fn save_retrieve_prekey(password: Option<&str>) {
@@ -605,4 +722,87 @@ mod tests {
                 .is_none()
         );
     }
+
+    #[rstest(password, case(Some("some password")), case(None))]
+    #[tokio::test]
+    async fn replace_pni_identity(password: Option<&str>) {
+        let (storage, _tempdir) = create_example_storage(password, None).await.unwrap();
+
+        let old_prekey = create_random_prekey();
+        let old_signed_prekey = create_random_signed_prekey();
+        let aci_prekey = create_random_prekey();
+
+        let mut pni_storage = storage.pni_storage();
+        pni_storage
+            .save_pre_key(old_prekey.id().unwrap(), &old_prekey)
+            .await
+            .unwrap();
+        pni_storage
+            .save_signed_pre_key(old_signed_prekey.id().unwrap(), &old_signed_prekey)
+            .await
+            .unwrap();
+        storage
+            .aci_storage()
+            .save_pre_key(aci_prekey.id().unwrap(), &aci_prekey)
+            .await
+            .unwrap();
+
+        let key_pair = IdentityKeyPair::generate(&mut rand::rng());
+        let signed_prekey = create_random_signed_prekey();
+        let kyber_prekey = KyberPreKeyRecord::generate(
+            kem::KeyType::Kyber1024,
+            KyberPreKeyId::from(7),
+            key_pair.private_key(),
+        )
+        .unwrap();
+        pni_storage
+            .replace_identity(key_pair, 4321, &signed_prekey, &kyber_prekey)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            pni_storage
+                .get_identity_key_pair()
+                .await
+                .unwrap()
+                .serialize(),
+            key_pair.serialize()
+        );
+        assert_eq!(pni_storage.get_local_registration_id().await.unwrap(), 4321);
+
+        // Only the new signed and last resort prekeys are left of the PNI identity
+        assert!(
+            pni_storage
+                .get_pre_key(old_prekey.id().unwrap())
+                .await
+                .is_err()
+        );
+        assert!(
+            pni_storage
+                .get_signed_pre_key(old_signed_prekey.id().unwrap())
+                .await
+                .is_err()
+        );
+        assert!(
+            pni_storage
+                .get_signed_pre_key(signed_prekey.id().unwrap())
+                .await
+                .is_ok()
+        );
+        assert!(
+            pni_storage
+                .get_kyber_pre_key(KyberPreKeyId::from(7))
+                .await
+                .is_ok()
+        );
+
+        // The ACI identity is left alone
+        assert!(
+            storage
+                .aci_storage()
+                .get_pre_key(aci_prekey.id().unwrap())
+                .await
+                .is_ok()
+        );
+    }
 }
@@ -2,10 +2,13 @@ mod common;
 
 use self::common::*;
 use ::phonenumber::PhoneNumber;
-use libsignal_service::protocol::{Aci, Pni, ServiceId};
+use libsignal_service::protocol::{
+    Aci, DeviceId, Pni, ProtocolAddress, ServiceId, SessionRecord, SessionStore,
+};
 use rstest::{fixture, rstest};
 use std::{future::Future, str::FromStr};
 use uuid::uuid;
+use whisperfish_store::orm::MessageType;
 
 // Define all ACIs, PNIs and E164s beforehand
 // so we don't have to assert_ne any of them.
@@ -68,6 +71,36 @@ fn verify(
     assert_eq!(r.e164.as_ref(), tel, "e164's should be equal");
 }
 
+/// Gives the recipient a direct conversation, and an encrypted session with `pni`.
+async fn give_pni_session(
+    storage: &SimpleStorage,
+    r: &whisperfish_store::orm::Recipient,
+    pni: Pni,
+) -> i32 {
+    let addr = ProtocolAddress::new(
+        ServiceId::from(pni).service_id_string(),
+        DeviceId::new(1).unwrap(),
+    );
+    storage
+        .aci_storage()
+        .store_session(&addr, &SessionRecord::new_fresh())
+        .await
+        .unwrap();
+    storage.fetch_or_insert_session_by_recipient_id(r.id).id
+}
+
+/// The recipient events in a conversation, with the phone number they mention.
+fn recipient_events(
+    storage: &SimpleStorage,
+    session_id: i32,
+) -> Vec<(MessageType, Option<String>)> {
+    storage
+        .fetch_all_messages(session_id, false)
+        .into_iter()
+        .filter_map(|m| Some((m.message_type?, m.text.filter(|t| !t.is_empty()))))
+        .collect()
+}
+
 #[fixture]
 fn storage_with_e164_recipient(
     storage: impl Future<Output = InMemoryDb>,
@@ -193,6 +226,37 @@ mod merge_and_fetch {
         verify(&r1, Id::Nz, Some(&e164(1)), None, Some(&aci(1)));
         assert_eq!(storage.fetch_recipients().len(), 1);
     }
+
+    #[rstest]
+    #[tokio::test]
+    async fn trusted_number_change(storage: impl Future<Output = InMemoryDb>) {
+        let (storage, _temp_dir) = storage.await;
+
+        let r1 = storage.merge_and_fetch_recipient(Some(e164(1)), Some(aci(1)), None, TrustLevel::Certain);
+        let session_id = storage.fetch_or_insert_session_by_recipient_id(r1.id).id;
+
+        let r2 = storage.merge_and_fetch_recipient(Some(e164(2)), Some(aci(1)), None, TrustLevel::Certain);
+        verify(&r2, Id::Eq(r1.id), Some(&e164(2)), None, Some(&aci(1)));
+        assert_eq!(recipient_events(&storage, session_id), [(MessageType::NumberChange, None)]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn trusted_thread_merge(storage: impl Future<Output = InMemoryDb>) {
+        let (storage, _temp_dir) = storage.await;
+
+        let r1 = storage.fetch_or_insert_recipient_by_phonenumber(&e164(1));
+        storage.fetch_or_insert_session_by_recipient_id(r1.id);
+        let r2 = storage.fetch_or_insert_recipient_by_address(&ServiceId::from(aci(1)));
+        let session_id = storage.fetch_or_insert_session_by_recipient_id(r2.id).id;
+
+        let r3 = storage.merge_and_fetch_recipient(Some(e164(1)), Some(aci(1)), None, TrustLevel::Certain);
+        verify(&r3, Id::Eq(r2.id), Some(&e164(1)), None, Some(&aci(1)));
+        assert_eq!(
+            recipient_events(&storage, session_id),
+            [(MessageType::ThreadMerge, Some(e164(1).to_string()))]
+        );
+    }
 }
 
 #[rustfmt::skip]
@@ -1145,91 +1209,88 @@ mod merge_and_fetch_conflicting_recipients {
     #[rstest]
     #[tokio::test]
     /// Signal Android: "pni matches, pni+aci provided, pni session"
-    /// Same data outcome as the no-session variant; SSE check omitted (session API TODO).
     async fn pni_matches_pni_plus_aci_provided_pni_session(storage: impl Future<Output = InMemoryDb>) {
         let (storage, _temp_dir) = storage.await;
 
         // given(E164_A, PNI_A, null, pniSession = true)
-        // TODO: set up PNI session for pni once session API is available
         let r1 = storage.merge_and_fetch_recipient(Some(e164(1)), None, Some(pni(1)), TrustLevel::Uncertain);
+        let session_id = give_pni_session(&storage, &r1, pni(1)).await;
 
         // process(null, PNI_A, ACI_A)
         let r2 = storage.merge_and_fetch_recipient(None, Some(aci(1)), Some(pni(1)), TrustLevel::Uncertain);
         verify(&r2, Id::Eq(r1.id), Some(&e164(1)), Some(&pni(1)), Some(&aci(1)));
 
-        // TODO: expectSessionSwitchoverEvent(E164_A)
+        assert_eq!(recipient_events(&storage, session_id), [(MessageType::SessionSwitchover, Some(e164(1).to_string()))]);
     }
 
     #[rstest]
     #[tokio::test]
     /// Signal Android: "e164 and pni matches, all provided, new aci, existing pni session"
-    /// Same data outcome as the no-session variant; SSE check omitted (session API TODO).
     async fn e164_and_pni_matches_all_provided_new_aci_existing_pni_session(storage: impl Future<Output = InMemoryDb>) {
         let (storage, _temp_dir) = storage.await;
 
         // given(E164_A, PNI_A, null, pniSession = true)
-        // TODO: set up PNI session for pni once session API is available
         let r1 = storage.merge_and_fetch_recipient(Some(e164(1)), None, Some(pni(1)), TrustLevel::Uncertain);
+        let session_id = give_pni_session(&storage, &r1, pni(1)).await;
 
         // process(E164_A, PNI_A, ACI_A)
         let r2 = storage.merge_and_fetch_recipient(Some(e164(1)), Some(aci(1)), Some(pni(1)), TrustLevel::Uncertain);
         verify(&r2, Id::Eq(r1.id), Some(&e164(1)), Some(&pni(1)), Some(&aci(1)));
 
-        // TODO: expectSessionSwitchoverEvent(E164_A)
+        assert_eq!(recipient_events(&storage, session_id), [(MessageType::SessionSwitchover, Some(e164(1).to_string()))]);
     }
 
     #[rstest]
     #[tokio::test]
     /// Signal Android: "pni matches, all provided, new e164 and aci, existing pni session"
-    /// Same data outcome as the no-session variant; SSE check omitted (session API TODO).
     async fn pni_matches_all_provided_new_e164_and_aci_existing_pni_session(storage: impl Future<Output = InMemoryDb>) {
         let (storage, _temp_dir) = storage.await;
 
         // given(null, PNI_A, null, pniSession = true)
-        // TODO: set up PNI session for pni once session API is available
         let r1 = storage.merge_and_fetch_recipient(None, None, Some(pni(1)), TrustLevel::Uncertain);
+        let session_id = give_pni_session(&storage, &r1, pni(1)).await;
 
         // process(E164_A, PNI_A, ACI_A)
         let r2 = storage.merge_and_fetch_recipient(Some(e164(1)), Some(aci(1)), Some(pni(1)), TrustLevel::Uncertain);
         verify(&r2, Id::Eq(r1.id), Some(&e164(1)), Some(&pni(1)), Some(&aci(1)));
 
-        // TODO: expectSessionSwitchoverEvent(E164_A)
+        // The PNI-only recipient had no phone number to mention
+        assert_eq!(recipient_events(&storage, session_id), [(MessageType::SessionSwitchover, None)]);
     }
 
     #[rstest]
     #[tokio::test]
     /// Signal Android: "e164 and pni matches, all provided, existing pni session"
-    /// Same data outcome as the no-session variant; SSE check omitted (session API TODO).
     async fn e164_and_pni_matches_all_provided_existing_pni_session(storage: impl Future<Output = InMemoryDb>) {
         let (storage, _temp_dir) = storage.await;
 
         // given(E164_A, PNI_A, null, pniSession = true)
-        // TODO: set up PNI session for pni once session API is available
         let r1 = storage.merge_and_fetch_recipient(Some(e164(1)), None, Some(pni(1)), TrustLevel::Uncertain);
+        let session_id = give_pni_session(&storage, &r1, pni(1)).await;
 
         // process(E164_A, PNI_A, ACI_A)
         let r2 = storage.merge_and_fetch_recipient(Some(e164(1)), Some(aci(1)), Some(pni(1)), TrustLevel::Uncertain);
         verify(&r2, Id::Eq(r1.id), Some(&e164(1)), Some(&pni(1)), Some(&aci(1)));
 
-        // TODO: expectSessionSwitchoverEvent(E164_A)
+        assert_eq!(recipient_events(&storage, session_id), [(MessageType::SessionSwitchover, Some(e164(1).to_string()))]);
     }
 
     #[rstest]
     #[tokio::test]
     /// Signal Android: "pni matches, all provided, existing pni session"
-    /// Same data outcome as the no-session variant; SSE check omitted (session API TODO).
     async fn pni_matches_all_provided_existing_pni_session(storage: impl Future<Output = InMemoryDb>) {
         let (storage, _temp_dir) = storage.await;
 
         // given(null, PNI_A, null, pniSession = true)
-        // TODO: set up PNI session for pni once session API is available
         let r1 = storage.merge_and_fetch_recipient(None, None, Some(pni(1)), TrustLevel::Uncertain);
+        let session_id = give_pni_session(&storage, &r1, pni(1)).await;
 
         // process(E164_A, PNI_A, ACI_A)
         let r2 = storage.merge_and_fetch_recipient(Some(e164(1)), Some(aci(1)), Some(pni(1)), TrustLevel::Uncertain);
         verify(&r2, Id::Eq(r1.id), Some(&e164(1)), Some(&pni(1)), Some(&aci(1)));
 
-        // TODO: expectSessionSwitchoverEvent(E164_A)
+        // The PNI-only recipient had no phone number to mention
+        assert_eq!(recipient_events(&storage, session_id), [(MessageType::SessionSwitchover, None)]);
     }
 
     // -------------------------------------------------------------------------
@@ -0,0 +1,60 @@
+mod common;
+
+use self::common::*;
+use chrono::{Duration, Utc};
+use libsignal_service::protocol::{Aci, ServiceId};
+use rstest::rstest;
+use std::future::Future;
+use uuid::Uuid;
+use whisperfish_store::send_log::SEND_LOG_MAX_AGE;
+
+#[rstest]
+#[tokio::test]
+async fn send_log_per_recipient(storage: impl Future<Output = InMemoryDb>) {
+    let (storage, _temp_dir) = storage.await;
+    let alice =
+        storage.fetch_or_insert_recipient_by_address(&ServiceId::Aci(Aci::from(Uuid::new_v4())));
+    let bob =
+        storage.fetch_or_insert_recipient_by_address(&ServiceId::Aci(Aci::from(Uuid::new_v4())));
+    let carol =
+        storage.fetch_or_insert_recipient_by_address(&ServiceId::Aci(Aci::from(Uuid::new_v4())));
+
+    let sent = Utc::now().naive_utc();
+    storage.insert_send_log(sent, b"group message", &[alice.id, bob.id]);
+
+    assert_eq!(
+        storage.fetch_send_log(sent, alice.id).as_deref(),
+        Some(&b"group message"[..])
+    );
+    assert_eq!(
+        storage.fetch_send_log(sent, bob.id).as_deref(),
+        Some(&b"group message"[..])
+    );
+    assert_eq!(storage.fetch_send_log(sent, carol.id), None);
+    assert_eq!(
+        storage.fetch_send_log(sent - Duration::milliseconds(1), alice.id),
+        None
+    );
+
+    // Recent messages stay in the log, until they are too old or too many.
+    assert_eq!(storage.prune_send_log(), 0);
+    assert!(storage.fetch_send_log(sent, alice.id).is_some());
+}
+
+#[rstest]
+#[tokio::test]
+async fn send_log_is_pruned(storage: impl Future<Output = InMemoryDb>) {
+    let (storage, _temp_dir) = storage.await;
+    let alice =
+        storage.fetch_or_insert_recipient_by_address(&ServiceId::Aci(Aci::from(Uuid::new_v4())));
+
+    let now = Utc::now().naive_utc();
+    let old = now - SEND_LOG_MAX_AGE - Duration::minutes(1);
+    storage.insert_send_log(old, b"old message", &[alice.id]);
+    // Too old to be resent
+    assert_eq!(storage.fetch_send_log(old, alice.id), None);
+
+    storage.insert_send_log(now, b"new message", &[alice.id]);
+    assert_eq!(storage.prune_send_log(), 0);
+    assert!(storage.fetch_send_log(now, alice.id).is_some());
+}
@@ -0,0 +1,449 @@
+mod common;
+
+use self::common::*;
+use libsignal_service::master_key::StorageServiceKey;
+use libsignal_service::protocol::{Aci, ServiceId};
+use libsignal_service::push_service::DEFAULT_DEVICE_ID;
+use libsignal_service::zkgroup::GroupMasterKey;
+use libsignal_service::zkgroup::api::groups::GroupSecretParams;
+use phonenumber::PhoneNumber;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use uuid::Uuid;
+use whisperfish_store::config::SignalConfig;
+use whisperfish_store::storage_service::*;
+use whisperfish_store::temp;
+
+/// In-memory stand-in for the Storage Service.
+#[derive(Default)]
+struct FakeStorageServer {
+    manifest: Option<StorageManifest>,
+    items: HashMap<Vec<u8>, Vec<u8>>,
+    /// Manifest to return on the next write, to simulate another device winning the race.
+    race: Option<(StorageManifest, Vec<StorageItem>)>,
+}
+
+impl FakeStorageServer {
+    fn version(&self) -> u64 {
+        self.manifest.as_ref().map(|m| m.version).unwrap_or(0)
+    }
+
+    fn manifest_record(&self, key: &StorageServiceKey) -> ManifestRecord {
+        decrypt_manifest(key, self.manifest.as_ref().unwrap()).unwrap()
+    }
+
+    fn records(&self, key: &StorageServiceKey) -> Vec<StorageRecord> {
+        let manifest = self.manifest_record(key);
+        manifest
+            .identifiers
+            .iter()
+            .map(|id| {
+                let item = StorageItem {
+                    key: id.raw.clone(),
+                    value: self.items[&id.raw].clone(),
+                };
+                decrypt_record(key, &manifest.record_ikm, &item).unwrap()
+            })
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageServiceApi for FakeStorageServer {
+    async fn read_manifest(
+        &mut self,
+        known_version: Option<u64>,
+    ) -> anyhow::Result<Option<StorageManifest>> {
+        Ok(self
+            .manifest
+            .clone()
+            .filter(|m| known_version.is_none_or(|known| m.version > known)))
+    }
+
+    async fn read_records(&mut self, keys: Vec<Vec<u8>>) -> anyhow::Result<Vec<StorageItem>> {
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| {
+                let value = self.items.get(&key)?.clone();
+                Some(StorageItem { key, value })
+            })
+            .collect())
+    }
+
+    async fn write(&mut self, operation: WriteOperation) -> anyhow::Result<WriteOutcome> {
+        if let Some((manifest, items)) = self.race.take() {
+            for item in items {
+                self.items.insert(item.key, item.value);
+            }
+            self.manifest = Some(manifest);
+        }
+
+        let manifest = operation.manifest.unwrap();
+        if manifest.version != self.version() + 1 {
+            return Ok(WriteOutcome::Conflict(self.manifest.clone().unwrap()));
+        }
+        for key in operation.delete_key {
+            self.items.remove(&key);
+        }
+        for item in operation.insert_item {
+            self.items.insert(item.key, item.value);
+        }
+        self.manifest = Some(manifest);
+        Ok(WriteOutcome::Written)
+    }
+}
+
+fn storage_key() -> StorageServiceKey {
+    StorageServiceKey::from_slice(&[42u8; 32]).unwrap()
+}
+
+fn contact_record(aci: Uuid, e164: &str) -> StorageRecord {
+    StorageRecord {
+        record: Some(storage_record::Record::Contact(ContactRecord {
+            aci: aci.to_string(),
+            e164: e164.into(),
+            profile_key: vec![3u8; 32],
+            given_name: "Alice".into(),
+            family_name: "Example".into(),
+            whitelisted: true,
+            ..Default::default()
+        })),
+    }
+}
+
+fn group_record(master_key: [u8; 32]) -> StorageRecord {
+    StorageRecord {
+        record: Some(storage_record::Record::GroupV2(GroupV2Record {
+            master_key: master_key.to_vec(),
+            archived: true,
+            ..Default::default()
+        })),
+    }
+}
+
+/// Build a server holding `records`, with the manifest at `version`.
+fn server_with(
+    key: &StorageServiceKey,
+    version: u64,
+    record_ikm: Vec<u8>,
+    records: Vec<(IdentifierType, StorageRecord)>,
+) -> FakeStorageServer {
+    let mut server = FakeStorageServer::default();
+    let mut manifest = ManifestRecord {
+        version,
+        source_device: 2,
+        record_ikm,
+        ..Default::default()
+    };
+    for (i, (id_type, record)) in records.into_iter().enumerate() {
+        let raw = vec![i as u8 + 1; STORAGE_ID_LEN];
+        let item = encrypt_record(key, &manifest.record_ikm, &raw, &record);
+        server.items.insert(item.key, item.value);
+        manifest.identifiers.push(manifest_record::Identifier {
+            raw,
+            r#type: id_type as i32,
+        });
+    }
+    server.manifest = Some(encrypt_manifest(key, &manifest));
+    server
+}
+
+async fn storage_with_self() -> InMemoryDb {
+    let location = temp();
+    let config = Arc::new(SignalConfig::default());
+    config.set_aci(Uuid::new_v4());
+    config.set_tel(PhoneNumber::from_str("+32474000000").unwrap());
+    config.set_device_id(*DEFAULT_DEVICE_ID);
+
+    let storage = SimpleStorage::new(
+        config,
+        &location,
+        None,
+        12345,
+        12346,
+        "Some Password",
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    storage.fetch_self_recipient().expect("self recipient");
+    (storage, location)
+}
+
+#[tokio::test]
+async fn merge_contacts_and_groups() {
+    let (storage, _temp_dir) = storage_with_self().await;
+    let key = storage_key();
+
+    let aci = Uuid::new_v4();
+    let master_key = [9u8; 32];
+    let mut server = server_with(
+        &key,
+        5,
+        Vec::new(),
+        vec![
+            (IdentifierType::Contact, contact_record(aci, "+32474000001")),
+            (IdentifierType::Groupv2, group_record(master_key)),
+        ],
+    );
+
+    let outcome = storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+    assert_eq!(outcome.manifest_version, 5);
+    assert_eq!(outcome.records_written, 0);
+    assert_eq!(outcome.new_group_v2_sessions.len(), 1);
+
+    let recipient = storage
+        .fetch_recipient(&ServiceId::from(Aci::from(aci)))
+        .expect("merged recipient");
+    assert_eq!(
+        recipient.storage_service_id,
+        Some(vec![1u8; STORAGE_ID_LEN])
+    );
+    assert_eq!(recipient.profile_key, Some(vec![3u8; 32]));
+    assert_eq!(
+        recipient.profile_joined_name.as_deref(),
+        Some("Alice Example")
+    );
+    assert_eq!(
+        recipient.e164,
+        Some(PhoneNumber::from_str("+32474000001").unwrap())
+    );
+    assert!(recipient.profile_sharing);
+    assert!(recipient.is_accepted);
+
+    let secret = GroupSecretParams::derive_from_master_key(GroupMasterKey::new(master_key));
+    let group_id = hex::encode(secret.get_group_identifier());
+    let session = storage
+        .fetch_session_by_group_v2_id(&group_id)
+        .expect("group session");
+    assert_eq!(outcome.new_group_v2_sessions, vec![session.id]);
+    assert!(session.is_archived);
+    assert_eq!(
+        session.unwrap_group_v2().storage_service_id,
+        Some(vec![2u8; STORAGE_ID_LEN])
+    );
+
+    // A second sync without remote changes is a no-op.
+    let outcome = storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+    assert_eq!(outcome.manifest_version, 5);
+    assert_eq!(outcome.records_written, 0);
+    assert!(outcome.new_group_v2_sessions.is_empty());
+}
+
+#[tokio::test]
+async fn merge_records_with_record_ikm() {
+    let (storage, _temp_dir) = storage_with_self().await;
+    let key = storage_key();
+
+    let aci = Uuid::new_v4();
+    let mut server = server_with(
+        &key,
+        1,
+        vec![7u8; 32],
+        vec![(IdentifierType::Contact, contact_record(aci, "+32474000001"))],
+    );
+
+    storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+    let recipient = storage
+        .fetch_recipient(&ServiceId::from(Aci::from(aci)))
+        .expect("merged recipient");
+    assert_eq!(
+        recipient.storage_service_id,
+        Some(vec![1u8; STORAGE_ID_LEN])
+    );
+}
+
+#[tokio::test]
+async fn write_back_local_changes() {
+    let (storage, _temp_dir) = storage_with_self().await;
+    let key = storage_key();
+
+    let aci = Uuid::new_v4();
+    let mut server = server_with(
+        &key,
+        3,
+        vec![7u8; 32],
+        vec![(IdentifierType::Contact, contact_record(aci, "+32474000001"))],
+    );
+    storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+
+    let recipient = storage
+        .fetch_recipient(&ServiceId::from(Aci::from(aci)))
+        .unwrap();
+    storage.mark_recipient_blocked_by_id(recipient.id);
+
+    let outcome = storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+    assert_eq!(outcome.manifest_version, 4);
+    assert_eq!(outcome.records_written, 1);
+
+    // The old record is replaced by a new one with a fresh identifier.
+    let manifest = server.manifest_record(&key);
+    assert_eq!(manifest.version, 4);
+    assert_eq!(manifest.record_ikm, vec![7u8; 32]);
+    assert_eq!(manifest.identifiers.len(), 1);
+    assert_ne!(manifest.identifiers[0].raw, vec![1u8; STORAGE_ID_LEN]);
+    assert!(!server.items.contains_key(&vec![1u8; STORAGE_ID_LEN]));
+
+    let records = server.records(&key);
+    let Some(storage_record::Record::Contact(contact)) = &records[0].record else {
+        panic!("expected a contact record");
+    };
+    assert!(contact.blocked);
+    assert_eq!(contact.aci, aci.to_string());
+    assert_eq!(contact.given_name, "Alice");
+
+    let recipient = storage.fetch_recipient_by_id(recipient.id).unwrap();
+    assert_eq!(
+        recipient.storage_service_id.as_ref(),
+        Some(&manifest.identifiers[0].raw)
+    );
+
+    // Nothing changed since, so nothing is written.
+    let outcome = storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+    assert_eq!(outcome.records_written, 0);
+}
+
+#[tokio::test]
+async fn write_conflict_is_retried() {
+    let (storage, _temp_dir) = storage_with_self().await;
+    let key = storage_key();
+
+    let mut server = server_with(&key, 1, Vec::new(), Vec::new());
+
+    let aci = Uuid::new_v4();
+    let recipient = storage.merge_and_fetch_recipient(
+        None,
+        Some(Aci::from(aci)),
+        None,
+        whisperfish_store::TrustLevel::Certain,
+    );
+    storage.mark_recipient_blocked_by_id(recipient.id);
+
+    // Another device writes version 2 with a new group before we do.
+    let master_key = [5u8; 32];
+    let raced = server_with(
+        &key,
+        2,
+        Vec::new(),
+        vec![(IdentifierType::Groupv2, group_record(master_key))],
+    );
+    server.race = Some((
+        raced.manifest.clone().unwrap(),
+        raced
+            .items
+            .iter()
+            .map(|(k, v)| StorageItem {
+                key: k.clone(),
+                value: v.clone(),
+            })
+            .collect(),
+    ));
+
+    let outcome = storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+    assert_eq!(outcome.manifest_version, 3);
+    assert_eq!(outcome.new_group_v2_sessions.len(), 1);
+    // The blocked contact, and the group record that was not known locally yet
+    // before the race are both part of the final manifest.
+    let manifest = server.manifest_record(&key);
+    assert_eq!(manifest.identifiers.len(), 2);
+    assert!(
+        manifest
+            .identifiers
+            .iter()
+            .any(|id| id.raw == vec![1u8; STORAGE_ID_LEN])
+    );
+}
+
+#[tokio::test]
+async fn corrupt_stored_manifest_is_ignored() {
+    let (storage, _temp_dir) = storage_with_self().await;
+    let key = storage_key();
+    storage.write_setting(
+        whisperfish_store::Settings::STORAGE_MANIFEST,
+        "not a manifest",
+    );
+    assert!(storage.fetch_storage_manifest_record().is_none());
+
+    let aci = Uuid::new_v4();
+    let mut server = server_with(
+        &key,
+        1,
+        Vec::new(),
+        vec![(IdentifierType::Contact, contact_record(aci, "+32474000001"))],
+    );
+    let outcome = storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+    assert_eq!(outcome.manifest_version, 1);
+    assert_eq!(
+        storage.fetch_storage_manifest_record().map(|m| m.version),
+        Some(1)
+    );
+}
+
+#[tokio::test]
+async fn binary_identifiers_are_kept() {
+    let (storage, _temp_dir) = storage_with_self().await;
+    let key = storage_key();
+
+    let aci = Uuid::new_v4();
+    let mut record = contact_record(aci, "+32474000001");
+    if let Some(storage_record::Record::Contact(contact)) = &mut record.record {
+        contact.aci = String::new();
+        contact.aci_binary = aci.as_bytes().to_vec();
+    }
+    let mut server = server_with(&key, 1, Vec::new(), vec![(IdentifierType::Contact, record)]);
+    storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+    let recipient = storage
+        .fetch_recipient(&ServiceId::from(Aci::from(aci)))
+        .unwrap();
+
+    // The record only carries the binary ACI, which is not a local change.
+    let outcome = storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+    assert_eq!(outcome.records_written, 0);
+
+    storage.mark_recipient_blocked_by_id(recipient.id);
+    let outcome = storage
+        .sync_storage_service(&mut server, &key)
+        .await
+        .unwrap();
+    assert_eq!(outcome.records_written, 1);
+
+    let records = server.records(&key);
+    let Some(storage_record::Record::Contact(contact)) = &records[0].record else {
+        panic!("expected a contact record");
+    };
+    assert!(contact.blocked);
+    assert_eq!(contact.aci, "");
+    assert_eq!(contact.aci_binary, aci.as_bytes().to_vec());
+}
@@ -15,6 +15,7 @@ use std::future::Future;
 use std::sync::Arc;
 use whisperfish_store::config::SignalConfig;
 use whisperfish_store::orm::{Receipt, Recipient, StoryType, UnidentifiedAccessMode};
+use whisperfish_store::search::SearchQuery;
 use whisperfish_store::{GroupV1, NewMessage, naive_chrono_to_millis};
 
 #[rstest]
@@ -141,6 +142,9 @@ async fn process_message_exists_session_source(storage: impl Future<Output = InM
             story_type: StoryType::None,
             body_ranges: None,
             message_type: None,
+            parent_story_id: None,
+            is_view_once: false,
+            schedule_send_time: None,
 
             edit: None,
         };
@@ -180,73 +184,144 @@ async fn message_searching(storage: impl Future<Output = InMemoryDb>) {
         let _ = storage.create_message(&new_message);
     }
 
-    let mut res;
-    let mut iter;
-    // case match
-    res = storage.search_messages(&String::from("test"), None);
-    iter = res.iter();
-    assert_eq!(2, res.len());
-    assert_eq!(
-        &String::from("100% test"),
-        iter.next().and_then(|m| m.text.as_ref()).unwrap()
-    );
-    assert_eq!(
-        &String::from("test"),
-        iter.next().and_then(|m| m.text.as_ref()).unwrap()
-    );
+    let search = |text: &str, session_id: Option<i32>| -> Vec<String> {
+        storage
+            .search(&SearchQuery {
+                text: text.into(),
+                session_id,
+                ..Default::default()
+            })
+            .into_iter()
+            .map(|hit| hit.message.text.unwrap())
+            .collect()
+    };
+
+    // case match, shorter messages rank higher
+    assert_eq!(search("test", None), ["test", "100% test"]);
 
     // case insensitive
-    res = storage.search_messages(&String::from("TEST"), None);
-    iter = res.iter();
-    assert_eq!(2, res.len());
-    assert_eq!(
-        &String::from("100% test"),
-        iter.next().and_then(|m| m.text.as_ref()).unwrap()
-    );
-    assert_eq!(
-        &String::from("test"),
-        iter.next().and_then(|m| m.text.as_ref()).unwrap()
-    );
+    assert_eq!(search("TEST", None), ["test", "100% test"]);
 
     // no matches
-    res = storage.search_messages(&String::from("noting matches"), None);
-    assert_eq!(0, res.len());
+    assert!(search("noting matches", None).is_empty());
 
-    // use wildcard character %
-    res = storage.search_messages(&String::from("100%"), None);
-    iter = res.iter();
-    assert_eq!(1, res.len());
-    assert_eq!(
-        &String::from("100% test"),
-        iter.next().and_then(|m| m.text.as_ref()).unwrap()
-    );
-
-    // use escape character '
-    res = storage.search_messages(&String::from("it's"), None);
-    iter = res.iter();
-    assert_eq!(1, res.len());
-    assert_eq!(
-        &String::from("trust me bro it's fine"),
-        iter.next().and_then(|m| m.text.as_ref()).unwrap()
-    );
+    // punctuation is not part of words
+    assert_eq!(search("100%", None), ["100% test"]);
+    assert_eq!(search("it's", None), ["trust me bro it's fine"]);
 
     // bad actor
-    res = storage.search_messages(&String::from("'; DROP TABLE messages;\n --"), None);
-    assert_eq!(0, res.len());
-    res = storage.search_messages(&String::from("fine"), None);
-    assert_eq!(1, res.len());
+    assert!(search("'; DROP TABLE messages;\n --", None).is_empty());
+    assert!(search("\"unbalanced AND (NEAR", None).is_empty());
+    assert!(search("*", None).is_empty());
+    assert_eq!(search("fine", None).len(), 1);
 
     // same session
-    res = storage.search_messages(&String::from("bro"), Some(sess1.id));
-    assert_eq!(1, res.len());
+    assert_eq!(search("bro", Some(sess1.id)).len(), 1);
 
     // wrong session
-    res = storage.search_messages(&String::from("trust"), Some(sess1.id + 1));
-    assert_eq!(0, res.len());
+    assert!(search("trust", Some(sess1.id + 1)).is_empty());
 
-    // too short string
-    res = storage.search_messages(&String::from("t"), Some(sess1.id));
-    assert_eq!(0, res.len());
+    // whole words, unless searching for a prefix
+    assert!(search("t", Some(sess1.id)).is_empty());
+    assert_eq!(search("t*", Some(sess1.id)).len(), 3);
+
+    // phrases
+    assert_eq!(search("\"bro it's\"", None), ["trust me bro it's fine"]);
+    assert!(search("\"it's bro\"", None).is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn message_search_filters(storage: impl Future<Output = InMemoryDb>) {
+    use libsignal_service::proto::AttachmentPointer;
+
+    let (mut storage, _temp_dir) = storage.await;
+
+    let addr1 = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let addr2 = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let sess1 = storage.fetch_or_insert_session_by_address(&addr1);
+    let rcpt2 = storage.fetch_or_insert_recipient_by_address(&addr2);
+
+    let create = |source: ServiceId, text: &str, second: i64| {
+        storage.create_message(&NewMessage {
+            session_id: sess1.id,
+            source_addr: Some(source),
+            text: text.into(),
+            timestamp: Utc.timestamp_opt(second, 0).unwrap().naive_utc(),
+            ..NewMessage::new_incoming()
+        })
+    };
+    let first = create(addr1, "Shall we go hiking tomorrow?", 10);
+    let second = create(addr2, "Hiking sounds great", 20);
+    let photo = create(addr2, "", 30);
+
+    // Captions and transcriptions of attachments are indexed too
+    let attachment = storage.register_attachment(
+        photo.id,
+        AttachmentPointer {
+            caption: Some("Hiking trail map".into()),
+            ..Default::default()
+        },
+    );
+    storage.update_transcription(attachment, "Look at those mountains", None);
+    let hits = storage.search(&SearchQuery {
+        text: "mountains".into(),
+        ..Default::default()
+    });
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].message.id, photo.id);
+
+    // Snippets mark the matched terms
+    let hits = storage.search(&SearchQuery {
+        text: "great".into(),
+        ..Default::default()
+    });
+    assert_eq!(hits[0].snippet, "Hiking sounds \u{2}great\u{3}");
+
+    let search = |query: SearchQuery| -> Vec<i32> {
+        storage
+            .search(&SearchQuery {
+                text: "hiking".into(),
+                ..query
+            })
+            .into_iter()
+            .map(|hit| hit.message.id)
+            .collect()
+    };
+    let mut all = search(SearchQuery::default());
+    all.sort();
+    assert_eq!(all, [first.id, second.id, photo.id]);
+
+    let by_sender = search(SearchQuery {
+        sender_recipient_id: Some(rcpt2.id),
+        ..Default::default()
+    });
+    assert_eq!(by_sender.len(), 2);
+    assert!(!by_sender.contains(&first.id));
+
+    let in_range = search(SearchQuery {
+        after: Some(Utc.timestamp_opt(15, 0).unwrap().naive_utc()),
+        before: Some(Utc.timestamp_opt(25, 0).unwrap().naive_utc()),
+        ..Default::default()
+    });
+    assert_eq!(in_range, [second.id]);
+
+    let with_attachment = search(SearchQuery {
+        has_attachment: Some(true),
+        ..Default::default()
+    });
+    assert_eq!(with_attachment, [photo.id]);
+    let without_attachment = search(SearchQuery {
+        has_attachment: Some(false),
+        limit: Some(1),
+        ..Default::default()
+    });
+    assert_eq!(without_attachment.len(), 1);
+    assert_ne!(without_attachment[0], photo.id);
+
+    // Deleted messages are gone from the index
+    storage.delete_message(second.id);
+    assert_eq!(search(SearchQuery::default()).len(), 2);
 }
 
 #[rstest]
@@ -278,6 +353,9 @@ async fn test_two_edits(storage: impl Future<Output = InMemoryDb>) {
         story_type: StoryType::None,
         body_ranges: None,
         message_type: None,
+        parent_story_id: None,
+        is_view_once: false,
+        schedule_send_time: None,
 
         edit: None,
     };
@@ -312,6 +390,9 @@ async fn test_two_edits(storage: impl Future<Output = InMemoryDb>) {
         story_type: StoryType::None,
         body_ranges: None,
         message_type: None,
+        parent_story_id: None,
+        is_view_once: false,
+        schedule_send_time: None,
 
         edit: Some(&msg),
     };
@@ -339,6 +420,9 @@ async fn test_two_edits(storage: impl Future<Output = InMemoryDb>) {
         story_type: StoryType::None,
         body_ranges: None,
         message_type: None,
+        parent_story_id: None,
+        is_view_once: false,
+        schedule_send_time: None,
 
         edit: Some(&msg),
     };
@@ -391,6 +475,9 @@ async fn dev_message_update(storage: impl Future<Output = InMemoryDb>) {
         story_type: StoryType::None,
         body_ranges: None,
         message_type: None,
+        parent_story_id: None,
+        is_view_once: false,
+        schedule_send_time: None,
 
         edit: None,
     };
@@ -422,6 +509,9 @@ async fn dev_message_update(storage: impl Future<Output = InMemoryDb>) {
         story_type: StoryType::None,
         body_ranges: None,
         message_type: None,
+        parent_story_id: None,
+        is_view_once: false,
+        schedule_send_time: None,
 
         edit: None,
     };
@@ -476,6 +566,9 @@ async fn process_inbound_group_message_without_sender(storage: impl Future<Outpu
         story_type: StoryType::None,
         body_ranges: None,
         message_type: None,
+        parent_story_id: None,
+        is_view_once: false,
+        schedule_send_time: None,
 
         edit: None,
     };
@@ -527,6 +620,9 @@ async fn process_outbound_group_message_without_sender(storage: impl Future<Outp
         story_type: StoryType::None,
         body_ranges: None,
         message_type: None,
+        parent_story_id: None,
+        is_view_once: false,
+        schedule_send_time: None,
 
         edit: None,
     };
@@ -598,6 +694,9 @@ async fn process_message_with_group(storage: impl Future<Output = InMemoryDb>) {
         story_type: StoryType::None,
         body_ranges: None,
         message_type: None,
+        parent_story_id: None,
+        is_view_once: false,
+        schedule_send_time: None,
 
         edit: None,
     };
@@ -856,6 +955,9 @@ async fn test_recipient_actions() {
         story_type: StoryType::None,
         body_ranges: None,
         message_type: None,
+        parent_story_id: None,
+        is_view_once: false,
+        schedule_send_time: None,
 
         edit: None,
     };
@@ -1577,6 +1679,9 @@ async fn various_storage_functions() {
         story_type: StoryType::None,
         body_ranges: None,
         message_type: None,
+        parent_story_id: None,
+        is_view_once: false,
+        schedule_send_time: None,
         edit: None,
     });
     let mut msg = storage.fetch_last_message_by_session_id(s1.id).unwrap();
@@ -1913,3 +2018,894 @@ fn master_key_smoke_test() {
         .inner;
     assert_eq!(master_a, master_b);
 }
+
+#[rstest]
+#[tokio::test]
+async fn link_previews(storage: impl Future<Output = InMemoryDb>) {
+    use libsignal_service::proto::{AttachmentPointer, Preview};
+
+    let (mut storage, _temp_dir) = storage.await;
+
+    let addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let rcpt = storage.fetch_or_insert_recipient_by_address(&addr);
+    let session = storage.fetch_or_insert_session_by_recipient_id(rcpt.id);
+
+    let msg = storage.create_message(&NewMessage {
+        session_id: session.id,
+        source_addr: Some(addr),
+        text: "Look: https://example.org/cats".into(),
+        timestamp: Utc::now().naive_utc(),
+        expire_timer_version: session.expire_timer_version,
+        ..NewMessage::new_incoming()
+    });
+
+    let attachment_id = storage.register_attachment(msg.id, AttachmentPointer::default());
+    let thumbnail_id = storage
+        .register_link_preview(
+            msg.id,
+            &Preview {
+                url: Some("https://example.org/cats".into()),
+                title: Some("Cats".into()),
+                description: Some(String::new()),
+                image: Some(AttachmentPointer {
+                    content_type: Some("image/jpeg".into()),
+                    ..Default::default()
+                }),
+                date: None,
+            },
+        )
+        .expect("thumbnail attachment");
+    assert_ne!(attachment_id, thumbnail_id);
+
+    // The thumbnail is not one of the message's attachments
+    let attachments = storage.fetch_attachments_for_message(msg.id);
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(attachments[0].id, attachment_id);
+
+    let previews = storage.fetch_link_previews_for_message(msg.id);
+    assert_eq!(previews.len(), 1);
+    let (preview, thumbnail) = &previews[0];
+    assert_eq!(preview.url, "https://example.org/cats");
+    assert_eq!(preview.title.as_deref(), Some("Cats"));
+    assert_eq!(preview.description, None);
+    assert_eq!(thumbnail.as_ref().map(|a| a.id), Some(thumbnail_id));
+
+    let augmented = storage.fetch_augmented_message(msg.id).unwrap();
+    assert_eq!(augmented.attachments, 1);
+    assert!(augmented.has_link_preview());
+    assert_eq!(
+        augmented.link_preview_url(),
+        Some("https://example.org/cats")
+    );
+    assert_eq!(augmented.link_preview_title(), Some("Cats"));
+    assert_eq!(augmented.link_preview_thumbnail(), None);
+
+    let all = storage.fetch_all_messages_augmented(session.id, true);
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0].attachments, 1);
+    assert_eq!(all[0].link_preview_title(), Some("Cats"));
+
+    // Previews without url are dropped
+    assert_eq!(
+        storage.register_link_preview(msg.id, &Preview::default()),
+        None
+    );
+    assert_eq!(storage.fetch_link_previews_for_message(msg.id).len(), 1);
+
+    assert!(storage.delete_message(msg.id));
+    assert!(storage.fetch_link_previews_for_message(msg.id).is_empty());
+    assert!(storage.fetch_attachment(thumbnail_id).is_none());
+}
+
+#[rstest]
+#[tokio::test]
+async fn sticker_packs(storage: impl Future<Output = InMemoryDb>) {
+    use libsignal_service::proto::{AttachmentPointer, Pack, data_message, pack};
+    use std::collections::HashMap;
+
+    let (mut storage, temp_dir) = storage.await;
+
+    let sticker = |id: u32, emoji: &str| pack::Sticker {
+        id: Some(id),
+        emoji: Some(emoji.into()),
+        content_type: Some("image/webp".into()),
+    };
+    let manifest = Pack {
+        title: Some("Cats".into()),
+        author: Some("Whisperfish".into()),
+        cover: Some(sticker(1, "🐈")),
+        stickers: vec![sticker(0, "😺"), sticker(1, "🐈"), sticker(2, "😿")],
+    };
+    let files: HashMap<u32, std::path::PathBuf> = (0..3)
+        .map(|id| {
+            let path = temp_dir.join(format!("{id}.webp"));
+            std::fs::write(&path, [id as u8; 16]).unwrap();
+            (id, path)
+        })
+        .collect();
+
+    storage
+        .install_sticker_pack("cafe", &[7; 32], &manifest, &files)
+        .unwrap();
+    // Installing again replaces the pack
+    storage
+        .install_sticker_pack("cafe", &[7; 32], &manifest, &files)
+        .unwrap();
+
+    let packs = storage.fetch_sticker_packs();
+    assert_eq!(packs.len(), 1);
+    assert!(packs[0].is_cover());
+    assert_eq!(packs[0].sticker_id, 1);
+    assert_eq!(packs[0].title, "Cats");
+
+    let stickers = storage.fetch_stickers_for_pack("cafe");
+    assert_eq!(
+        stickers.iter().map(|s| s.sticker_id).collect::<Vec<_>>(),
+        vec![1, 0, 2]
+    );
+    assert!(stickers.iter().all(|s| s.file_length == 16));
+
+    // Receive a sticker of a pack that is not installed
+    let addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let rcpt = storage.fetch_or_insert_recipient_by_address(&addr);
+    let session = storage.fetch_or_insert_session_by_recipient_id(rcpt.id);
+    let msg = storage.create_message(&NewMessage {
+        session_id: session.id,
+        source_addr: Some(addr),
+        text: "🐶".into(),
+        timestamp: Utc::now().naive_utc(),
+        expire_timer_version: session.expire_timer_version,
+        ..NewMessage::new_incoming()
+    });
+    let attachment_id = storage
+        .register_sticker_attachment(
+            msg.id,
+            &data_message::Sticker {
+                pack_id: Some(vec![0xbe, 0xef]),
+                pack_key: Some(vec![8; 32]),
+                sticker_id: Some(3),
+                data: Some(AttachmentPointer::default()),
+                emoji: Some("🐶".into()),
+            },
+        )
+        .expect("sticker attachment");
+    let attachment = storage.fetch_attachment(attachment_id).unwrap();
+    assert_eq!(attachment.sticker_pack_id.as_deref(), Some("beef"));
+    assert_eq!(attachment.sticker_id, Some(3));
+    assert_eq!(attachment.sticker_emoji.as_deref(), Some("🐶"));
+
+    // Sending a sticker copies the file
+    let sticker = storage.fetch_sticker("cafe", 2).unwrap();
+    let attachment_id = storage
+        .insert_local_sticker_attachment(msg.id, &sticker, &temp_dir)
+        .unwrap();
+    let attachment = storage.fetch_attachment(attachment_id).unwrap();
+    assert_eq!(attachment.sticker_pack_id.as_deref(), Some("cafe"));
+    assert_ne!(
+        attachment.absolute_attachment_path().unwrap(),
+        sticker.absolute_file_path()
+    );
+    assert!(storage.fetch_sticker("cafe", 2).unwrap().last_used > sticker.last_used);
+
+    assert_eq!(storage.uninstall_sticker_pack("cafe"), 3);
+    assert!(storage.fetch_sticker_packs().is_empty());
+    assert!(!files[&0].exists());
+    // The sent sticker is still there
+    assert!(storage.fetch_attachment(attachment_id).is_some());
+}
+
+#[tokio::test]
+async fn stories() {
+    use whisperfish_store::naive_chrono_rounded_down;
+    use whisperfish_store::orm::DistributionListPrivacyMode;
+    use whisperfish_store::stories::{MY_STORY_DISTRIBUTION_ID, STORY_LIFETIME};
+
+    let location = whisperfish_store::temp();
+    let config = Arc::new(SignalConfig::default());
+    let storage = SimpleStorage::new(
+        config.clone(),
+        &location,
+        None,
+        12345,
+        12346,
+        "Some Password",
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let own_aci = uuid::Uuid::new_v4();
+    config.set_aci(own_aci);
+    let own_recipient = storage.merge_and_fetch_self_recipient(None, Some(own_aci.into()), None);
+    let self_session = storage.fetch_or_insert_session_by_recipient_id(own_recipient.id);
+
+    let my_story = storage.fetch_or_insert_my_story();
+    assert_eq!(my_story.distribution_id, MY_STORY_DISTRIBUTION_ID);
+    assert_eq!(my_story.privacy_mode, DistributionListPrivacyMode::All);
+    assert_eq!(storage.fetch_distribution_lists().len(), 1);
+
+    // Only accepted recipients see our stories
+    let friend_addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let stranger_addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    storage.mark_recipient_accepted(&friend_addr);
+    let friend = storage.fetch_or_insert_recipient_by_address(&friend_addr);
+    let stranger = storage.fetch_or_insert_recipient_by_address(&stranger_addr);
+    let friend_session = storage.fetch_or_insert_session_by_recipient_id(friend.id);
+    storage.fetch_or_insert_session_by_recipient_id(stranger.id);
+
+    let recipients = storage.fetch_story_recipients(&my_story);
+    assert_eq!(
+        recipients.iter().map(|s| s.id).collect::<Vec<_>>(),
+        vec![friend_session.id]
+    );
+
+    let now = naive_chrono_rounded_down(Utc::now().naive_utc());
+    let story = storage.create_message(&NewMessage {
+        session_id: self_session.id,
+        source_addr: Some(ServiceId::from(Aci::from(own_aci))),
+        text: "Hello world".into(),
+        timestamp: now,
+        story_type: StoryType::TextStoryWithReplies,
+        expires_in: Some(STORY_LIFETIME),
+        expiry_started: Some(now),
+        ..NewMessage::new_outgoing()
+    });
+    assert!(story.story_allows_replies());
+    assert!(story.is_text_story());
+
+    storage.insert_story_sends(
+        story.id,
+        MY_STORY_DISTRIBUTION_ID,
+        true,
+        &[friend_session.id],
+        now,
+    );
+    let sends = storage.fetch_story_sends(story.id);
+    assert_eq!(sends.len(), 1);
+    assert_eq!(sends[0].session_id, friend_session.id);
+    assert_eq!(sends[0].distribution_id, MY_STORY_DISTRIBUTION_ID);
+
+    // Stories are not part of the conversation
+    assert!(storage.fetch_all_messages(self_session.id, true).is_empty());
+    assert!(
+        storage
+            .fetch_last_message_by_session_id(self_session.id)
+            .is_none()
+    );
+    assert_eq!(storage.fetch_active_stories().len(), 1);
+
+    assert_eq!(
+        storage
+            .fetch_story_by_timestamp(own_recipient.id, now)
+            .map(|s| s.id),
+        Some(story.id)
+    );
+    assert!(storage.fetch_story_by_timestamp(friend.id, now).is_none());
+
+    // Replies are threaded to the story, and do show up in the conversation
+    let reply = storage.create_message(&NewMessage {
+        session_id: friend_session.id,
+        source_addr: Some(friend_addr),
+        text: "Nice".into(),
+        timestamp: Utc::now().naive_utc(),
+        parent_story_id: Some(story.id),
+        ..NewMessage::new_incoming()
+    });
+    assert_eq!(
+        storage
+            .fetch_story_replies(story.id)
+            .iter()
+            .map(|m| m.id)
+            .collect::<Vec<_>>(),
+        vec![reply.id]
+    );
+    assert_eq!(storage.fetch_all_messages(friend_session.id, true).len(), 1);
+
+    // Excluding the friend from My Story
+    storage.add_distribution_list_member(MY_STORY_DISTRIBUTION_ID, friend_session.id);
+    assert!(storage.fetch_story_recipients(&my_story).is_empty());
+
+    // Lists referred to by our other devices
+    let other = uuid::Uuid::new_v4();
+    let list = storage.fetch_or_insert_distribution_list(other);
+    assert!(list.is_unknown);
+    assert_eq!(list.privacy_mode, DistributionListPrivacyMode::OnlyWith);
+    assert!(storage.fetch_story_recipients(&list).is_empty());
+    storage.add_distribution_list_member(other, friend_session.id);
+    assert_eq!(storage.fetch_story_recipients(&list).len(), 1);
+    assert_eq!(
+        storage.fetch_or_insert_distribution_list(other).name,
+        list.name
+    );
+    assert_eq!(storage.fetch_distribution_lists().len(), 2);
+}
+
+#[rstest]
+#[tokio::test]
+async fn editable_messages(storage: impl Future<Output = InMemoryDb>) {
+    use whisperfish_store::edits::{MAX_MESSAGE_EDITS, MESSAGE_EDIT_WINDOW};
+    use whisperfish_store::orm::Message;
+
+    let (storage, _temp_dir) = storage.await;
+
+    let addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let rcpt = storage.fetch_or_insert_recipient_by_address(&addr);
+    let session = storage.fetch_or_insert_session_by_recipient_id(rcpt.id);
+    let now = Utc::now().naive_utc();
+
+    let send = |text: &str, timestamp: NaiveDateTime, edit: Option<&Message>| {
+        storage.create_message(&NewMessage {
+            session_id: session.id,
+            text: text.into(),
+            timestamp,
+            sent: true,
+            edit,
+            ..NewMessage::new_outgoing()
+        })
+    };
+
+    let original = send("Helo", now, None);
+    let (o, latest) = storage.fetch_editable_message(original.id, now).unwrap();
+    assert_eq!(o.id, original.id);
+    assert_eq!(latest.id, original.id);
+
+    // Only during the edit window
+    let later = now + chrono::Duration::from_std(MESSAGE_EDIT_WINDOW).unwrap();
+    assert!(
+        storage
+            .fetch_editable_message(original.id, later + chrono::Duration::seconds(1))
+            .is_err()
+    );
+
+    // Only our own, sent messages
+    let incoming = storage.create_message(&NewMessage {
+        session_id: session.id,
+        source_addr: Some(addr),
+        text: "Hi".into(),
+        timestamp: now,
+        ..NewMessage::new_incoming()
+    });
+    assert!(storage.fetch_editable_message(incoming.id, now).is_err());
+    let queued = storage.create_message(&NewMessage {
+        session_id: session.id,
+        text: "Not yet".into(),
+        timestamp: now,
+        ..NewMessage::new_outgoing()
+    });
+    assert!(storage.fetch_editable_message(queued.id, now).is_err());
+
+    // Any revision can be edited, up to the limit
+    let mut latest_id = original.id;
+    for i in 1..=MAX_MESSAGE_EDITS {
+        let (original, _latest) = storage.fetch_editable_message(latest_id, now).unwrap();
+        let edit = send(
+            &format!("Hello {i}"),
+            now + chrono::Duration::milliseconds(i as i64),
+            Some(&original),
+        );
+        assert_eq!(edit.revision, i);
+        latest_id = edit.id;
+    }
+    assert_eq!(
+        storage
+            .fetch_message_by_id(original.id)
+            .unwrap()
+            .latest_revision_id(),
+        latest_id
+    );
+    assert!(storage.fetch_editable_message(original.id, now).is_err());
+    assert!(storage.fetch_editable_message(latest_id, now).is_err());
+}
+
+#[rstest]
+#[tokio::test]
+async fn delete_conversation_for_me() {
+    use whisperfish_store::delete_for_me::MessageAddress;
+
+    let location = whisperfish_store::temp();
+    let config = Arc::new(SignalConfig::default());
+    let mut storage = SimpleStorage::new(
+        config.clone(),
+        &location,
+        None,
+        12345,
+        12346,
+        "Some Password",
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let own_aci = uuid::Uuid::new_v4();
+    config.set_aci(own_aci);
+    let own_recipient = storage.merge_and_fetch_self_recipient(None, Some(own_aci.into()), None);
+
+    let addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let rcpt = storage.fetch_or_insert_recipient_by_address(&addr);
+    let session = storage.fetch_or_insert_session_by_recipient_id(rcpt.id);
+    let now = Utc::now().naive_utc();
+
+    let mut messages = Vec::new();
+    for i in 0..4 {
+        let timestamp = now + chrono::Duration::seconds(i);
+        let message = if i % 2 == 0 {
+            storage.create_message(&NewMessage {
+                session_id: session.id,
+                source_addr: Some(addr),
+                text: format!("Incoming {i}"),
+                timestamp,
+                ..NewMessage::new_incoming()
+            })
+        } else {
+            storage.create_message(&NewMessage {
+                session_id: session.id,
+                text: format!("Outgoing {i}"),
+                timestamp,
+                sent: true,
+                expires_in: Some(std::time::Duration::from_secs(3600)),
+                ..NewMessage::new_outgoing()
+            })
+        };
+        messages.push(message);
+    }
+
+    // Messages are addressed by author and sent timestamp
+    let addresses: Vec<MessageAddress> = messages
+        .iter()
+        .map(|message| storage.message_address(message).unwrap())
+        .collect();
+    assert_eq!(addresses[0].author_id, rcpt.id);
+    assert_eq!(addresses[1].author_id, own_recipient.id);
+    for (message, address) in messages.iter().zip(&addresses) {
+        assert_eq!(
+            storage
+                .fetch_message_by_address(Some(session.id), *address)
+                .unwrap()
+                .id,
+            message.id
+        );
+    }
+    let wrong_author = MessageAddress {
+        author_id: own_recipient.id,
+        ..addresses[0]
+    };
+    assert!(
+        storage
+            .fetch_message_by_address(Some(session.id), wrong_author)
+            .is_none()
+    );
+
+    // Our own messages with the same timestamp are told apart by their conversation
+    let other_addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let other_session = storage.fetch_or_insert_session_by_address(&other_addr);
+    let other_message = storage.create_message(&NewMessage {
+        session_id: other_session.id,
+        text: "Outgoing elsewhere".into(),
+        timestamp: messages[1].server_timestamp,
+        sent: true,
+        ..NewMessage::new_outgoing()
+    });
+    assert_eq!(
+        storage
+            .fetch_message_by_address(Some(other_session.id), addresses[1])
+            .unwrap()
+            .id,
+        other_message.id
+    );
+    assert_eq!(
+        storage
+            .fetch_message_by_address(Some(session.id), addresses[1])
+            .unwrap()
+            .id,
+        messages[1].id
+    );
+
+    let most_recent = storage.fetch_most_recent_messages(session.id, false);
+    assert_eq!(
+        most_recent.iter().map(|m| m.id).collect::<Vec<_>>(),
+        messages.iter().rev().map(|m| m.id).collect::<Vec<_>>()
+    );
+    let non_expiring = storage.fetch_most_recent_messages(session.id, true);
+    assert_eq!(
+        non_expiring.iter().map(|m| m.id).collect::<Vec<_>>(),
+        vec![messages[2].id, messages[0].id]
+    );
+
+    // Unknown messages delete nothing
+    let unknown = MessageAddress {
+        author_id: rcpt.id,
+        sent_timestamp: now - chrono::Duration::days(1),
+    };
+    assert!(!storage.delete_conversation_for_me(session.id, &[unknown], &[], true));
+    assert_eq!(storage.fetch_all_messages(session.id, false).len(), 4);
+
+    // The newest known message decides; later messages are kept, and so is the session.
+    assert!(storage.delete_conversation_for_me(
+        session.id,
+        &[unknown, addresses[0], addresses[1]],
+        &[],
+        true
+    ));
+    let remaining = storage.fetch_all_messages(session.id, false);
+    assert_eq!(
+        remaining.iter().map(|m| m.id).collect::<Vec<_>>(),
+        vec![messages[3].id, messages[2].id]
+    );
+    assert!(storage.fetch_session_by_id(session.id).is_some());
+
+    // Falls back to the non-expiring messages
+    assert!(storage.delete_conversation_for_me(session.id, &[unknown], &[addresses[2]], true));
+    let remaining = storage.fetch_all_messages(session.id, false);
+    assert_eq!(
+        remaining.iter().map(|m| m.id).collect::<Vec<_>>(),
+        vec![messages[3].id]
+    );
+
+    // A full delete removes the emptied session
+    assert!(storage.delete_conversation_for_me(session.id, &[addresses[3]], &[], true));
+    assert!(storage.fetch_all_messages(session.id, false).is_empty());
+    assert!(storage.fetch_session_by_id(session.id).is_none());
+}
+
+#[rstest]
+#[tokio::test]
+async fn viewed_messages(storage: impl Future<Output = InMemoryDb>) {
+    use libsignal_service::proto::AttachmentPointer;
+
+    let (mut storage, _temp_dir) = storage.await;
+
+    let addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let rcpt = storage.fetch_or_insert_recipient_by_address(&addr);
+    let session = storage.fetch_or_insert_session_by_recipient_id(rcpt.id);
+    let now = Utc::now().naive_utc();
+
+    // Viewed receipts for our own messages
+    let outgoing = storage.create_message(&NewMessage {
+        session_id: session.id,
+        text: "Listen to this".into(),
+        timestamp: now,
+        sent: true,
+        ..NewMessage::new_outgoing()
+    });
+    let viewed_1 = now + chrono::Duration::seconds(1);
+    let viewed_2 = now + chrono::Duration::seconds(2);
+    assert_eq!(
+        storage
+            .mark_messages_viewed(addr, vec![outgoing.server_timestamp], viewed_1)
+            .len(),
+        1
+    );
+    // A later receipt doesn't update the previous one
+    storage.mark_messages_viewed(addr, vec![outgoing.server_timestamp], viewed_2);
+    let receipts = storage.fetch_message_receipts(outgoing.id);
+    assert_eq!(receipts.len(), 1);
+    assert_eq!(receipts[0].0.viewed, Some(viewed_1));
+    assert_eq!(storage.count_message_receipts(outgoing.id).viewed, 1);
+
+    // Viewing a voice note keeps it
+    let voice_note = storage.create_message(&NewMessage {
+        session_id: session.id,
+        source_addr: Some(addr),
+        timestamp: now + chrono::Duration::seconds(3),
+        ..NewMessage::new_incoming()
+    });
+    storage.register_attachment(voice_note.id, AttachmentPointer::default());
+    let viewed = storage.mark_message_viewed(voice_note.id, now).unwrap();
+    assert!(viewed.is_viewed());
+    assert_eq!(
+        storage.fetch_attachments_for_message(voice_note.id).len(),
+        1
+    );
+    assert!(storage.mark_message_viewed(voice_note.id, now).is_none());
+
+    // View-once media is gone after viewing it
+    let view_once = storage.create_message(&NewMessage {
+        session_id: session.id,
+        source_addr: Some(addr),
+        timestamp: now + chrono::Duration::seconds(4),
+        is_view_once: true,
+        ..NewMessage::new_incoming()
+    });
+    storage.register_attachment(view_once.id, AttachmentPointer::default());
+    assert!(view_once.is_view_once);
+    assert!(!view_once.is_viewed());
+    assert!(storage.mark_message_viewed(view_once.id, now).is_some());
+    assert!(
+        storage
+            .fetch_attachments_for_message(view_once.id)
+            .is_empty()
+    );
+    assert!(
+        storage
+            .fetch_message_by_id(view_once.id)
+            .unwrap()
+            .is_viewed()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn message_pagination(storage: impl Future<Output = InMemoryDb>) {
+    use whisperfish_store::pagination::MessageCursor;
+
+    let (storage, _temp_dir) = storage.await;
+
+    let addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let session = storage.fetch_or_insert_session_by_address(&addr);
+
+    // Messages 2 and 3 share a timestamp, and message 4 gets edited.
+    let seconds = [1, 2, 3, 3, 4, 5];
+    let mut ids = Vec::new();
+    for (i, second) in seconds.iter().enumerate() {
+        let msg = storage.create_message(&NewMessage {
+            session_id: session.id,
+            source_addr: Some(addr),
+            text: format!("Message {i}"),
+            timestamp: Utc.timestamp_opt(*second, 0).unwrap().naive_utc(),
+            ..NewMessage::new_incoming()
+        });
+        ids.push(msg.id);
+    }
+    let original = storage.fetch_message_by_id(ids[4]).unwrap();
+    let edit = storage.create_message(&NewMessage {
+        session_id: session.id,
+        source_addr: Some(addr),
+        text: "Message 4, edited".into(),
+        timestamp: Utc.timestamp_opt(6, 0).unwrap().naive_utc(),
+        edit: Some(&original),
+        ..NewMessage::new_incoming()
+    });
+    // The edit replaces the original message, at its own timestamp.
+    let expected = [edit.id, ids[5], ids[3], ids[2], ids[1], ids[0]];
+    let message_ids = |messages: Vec<whisperfish_store::orm::Message>| {
+        messages.iter().map(|m| m.id).collect::<Vec<_>>()
+    };
+
+    // Paging backwards from the newest message
+    let newest = storage.fetch_messages_before(session.id, None, 3);
+    assert_eq!(message_ids(newest.clone()), expected[..3]);
+    let cursor = MessageCursor::from(newest.last().unwrap());
+    let older = storage.fetch_messages_before(session.id, Some(cursor), 3);
+    assert_eq!(message_ids(older.clone()), expected[3..]);
+    let cursor = MessageCursor::from(older.last().unwrap());
+    assert!(
+        storage
+            .fetch_messages_before(session.id, Some(cursor), 3)
+            .is_empty()
+    );
+
+    // Paging forwards, which splits the messages with equal timestamps
+    let all = storage.fetch_messages_before(session.id, None, 10);
+    assert_eq!(message_ids(all.clone()), expected);
+    let cursor = MessageCursor::from(all.last().unwrap());
+    let newer = storage.fetch_messages_after(session.id, cursor, 2);
+    assert_eq!(message_ids(newer.clone()), expected[3..5]);
+    let cursor = MessageCursor::from(&newer[0]);
+    let newer = storage.fetch_messages_after(session.id, cursor, 10);
+    assert_eq!(message_ids(newer), expected[..3]);
+
+    // The span between two messages, including both
+    let between = storage.fetch_messages_augmented_between(
+        session.id,
+        Some(MessageCursor::from(&all[1])),
+        MessageCursor::from(&all[3]),
+    );
+    let between: Vec<_> = between.iter().map(|m| m.id).collect();
+    assert_eq!(between, expected[1..4]);
+    let between =
+        storage.fetch_messages_augmented_between(session.id, None, MessageCursor::from(&all[1]));
+    let between: Vec<_> = between.iter().map(|m| m.id).collect();
+    assert_eq!(between, expected[..2]);
+
+    // The window around a message, also when addressed by an old revision
+    let around = storage.fetch_messages_augmented_around(session.id, ids[2], 1);
+    let around: Vec<_> = around.iter().map(|m| m.id).collect();
+    assert_eq!(around, expected[2..5]);
+    let around = storage.fetch_messages_augmented_around(session.id, ids[4], 1);
+    let around: Vec<_> = around.iter().map(|m| m.id).collect();
+    assert_eq!(around, expected[..2]);
+
+    // Messages of other sessions are not found
+    let other = storage
+        .fetch_or_insert_session_by_address(&ServiceId::from(Aci::from(uuid::Uuid::new_v4())));
+    assert!(
+        storage
+            .fetch_messages_augmented_around(other.id, ids[2], 1)
+            .is_empty()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn transcription_with_language(storage: impl Future<Output = InMemoryDb>) {
+    use libsignal_service::proto::AttachmentPointer;
+
+    let (mut storage, _temp_dir) = storage.await;
+
+    let addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let rcpt = storage.fetch_or_insert_recipient_by_address(&addr);
+    let session = storage.fetch_or_insert_session_by_recipient_id(rcpt.id);
+    let msg = storage.create_message(&NewMessage {
+        session_id: session.id,
+        source_addr: Some(addr),
+        timestamp: Utc::now().naive_utc(),
+        ..NewMessage::new_incoming()
+    });
+    let attachment_id = storage.register_attachment(msg.id, AttachmentPointer::default());
+
+    // Partial transcriptions come without a language
+    storage.update_transcription(attachment_id, "Hallo", None);
+    let attachment = storage.fetch_attachment(attachment_id).unwrap();
+    assert_eq!(attachment.transcription.as_deref(), Some("Hallo"));
+    assert_eq!(attachment.transcription_language, None);
+
+    storage.update_transcription(attachment_id, "Hallo wereld", Some("nl"));
+    let attachment = storage.fetch_attachment(attachment_id).unwrap();
+    assert_eq!(attachment.transcription.as_deref(), Some("Hallo wereld"));
+    assert_eq!(attachment.transcription_language.as_deref(), Some("nl"));
+
+    storage.clear_transcription(attachment_id);
+    let attachment = storage.fetch_attachment(attachment_id).unwrap();
+    assert_eq!(attachment.transcription, None);
+    assert_eq!(attachment.transcription_language, None);
+}
+
+#[rstest]
+#[tokio::test]
+async fn scheduled_messages(storage: impl Future<Output = InMemoryDb>) {
+    let (mut storage, _temp_dir) = storage.await;
+
+    let addr = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let rcpt = storage.fetch_or_insert_recipient_by_address(&addr);
+    let session = storage.fetch_or_insert_session_by_recipient_id(rcpt.id);
+    let now = Utc::now().naive_utc();
+
+    let ordinary = storage.create_message(&NewMessage {
+        session_id: session.id,
+        text: "Now".into(),
+        ..NewMessage::new_outgoing()
+    });
+    let later = storage.create_message(&NewMessage {
+        session_id: session.id,
+        text: "Later".into(),
+        schedule_send_time: Some(now + chrono::Duration::hours(2)),
+        ..NewMessage::new_outgoing()
+    });
+    let soon = storage.create_message(&NewMessage {
+        session_id: session.id,
+        text: "Soon".into(),
+        schedule_send_time: Some(now + chrono::Duration::hours(1)),
+        ..NewMessage::new_outgoing()
+    });
+
+    // Scheduled messages stay out of the conversation until they are sent
+    let conversation: Vec<i32> = storage
+        .fetch_all_messages(session.id, true)
+        .iter()
+        .map(|m| m.id)
+        .collect();
+    assert_eq!(conversation, [ordinary.id]);
+    assert_eq!(
+        storage
+            .fetch_last_message_by_session_id(session.id)
+            .unwrap()
+            .id,
+        ordinary.id
+    );
+    let scheduled: Vec<i32> = storage
+        .fetch_scheduled_messages(session.id)
+        .iter()
+        .map(|m| m.id)
+        .collect();
+    assert_eq!(scheduled, [soon.id, later.id]);
+
+    assert_eq!(
+        storage.fetch_next_scheduled_message_id().map(|(id, _)| id),
+        Some(soon.id)
+    );
+    assert!(storage.fetch_due_scheduled_message_ids().is_empty());
+
+    // Editing and rescheduling only works before sending
+    assert!(storage.update_scheduled_message_text(later.id, "Right now"));
+    assert!(storage.reschedule_message(later.id, now - chrono::Duration::minutes(1)));
+    assert!(!storage.reschedule_message(ordinary.id, now));
+    assert_eq!(storage.fetch_due_scheduled_message_ids(), [later.id]);
+
+    assert!(storage.dispatch_scheduled_message(later.id));
+    assert!(!storage.dispatch_scheduled_message(later.id));
+    let sent = storage.fetch_message_by_id(later.id).unwrap();
+    assert_eq!(sent.text.as_deref(), Some("Right now"));
+    assert_eq!(sent.schedule_send_time, None);
+    assert!(sent.server_timestamp >= now);
+    assert_eq!(
+        storage
+            .fetch_last_message_by_session_id(session.id)
+            .unwrap()
+            .id,
+        later.id
+    );
+    assert!(!storage.update_scheduled_message_text(later.id, "Too late"));
+
+    assert!(storage.cancel_scheduled_message(soon.id));
+    assert!(storage.fetch_message_by_id(soon.id).is_none());
+    assert!(storage.fetch_scheduled_messages(session.id).is_empty());
+    assert_eq!(storage.fetch_next_scheduled_message_id(), None);
+}
+
+#[rstest]
+#[tokio::test]
+async fn starred_messages(storage: impl Future<Output = InMemoryDb>) {
+    let (storage, _temp_dir) = storage.await;
+
+    let addr1 = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let addr2 = ServiceId::from(Aci::from(uuid::Uuid::new_v4()));
+    let sess1 = storage.fetch_or_insert_session_by_address(&addr1);
+    let sess2 = storage.fetch_or_insert_session_by_address(&addr2);
+
+    let first = storage.create_message(&NewMessage {
+        session_id: sess1.id,
+        source_addr: Some(addr1),
+        text: "Remember this".into(),
+        timestamp: Utc.timestamp_opt(1, 0).unwrap().naive_utc(),
+        ..NewMessage::new_incoming()
+    });
+    let second = storage.create_message(&NewMessage {
+        session_id: sess2.id,
+        source_addr: Some(addr2),
+        text: "And this".into(),
+        timestamp: Utc.timestamp_opt(2, 0).unwrap().naive_utc(),
+        ..NewMessage::new_incoming()
+    });
+    storage.create_message(&NewMessage {
+        session_id: sess2.id,
+        source_addr: Some(addr2),
+        text: "But not this".into(),
+        timestamp: Utc.timestamp_opt(3, 0).unwrap().naive_utc(),
+        ..NewMessage::new_incoming()
+    });
+    assert!(storage.fetch_starred_messages_augmented().is_empty());
+
+    assert!(storage.star_message(first.id));
+    assert!(storage.star_message(second.id));
+    assert!(!storage.star_message(9999));
+    let starred: Vec<(i32, i32)> = storage
+        .fetch_starred_messages_augmented()
+        .iter()
+        .map(|m| (m.id, m.session_id))
+        .collect();
+    assert_eq!(starred, [(second.id, sess2.id), (first.id, sess1.id)]);
+
+    // The star moves along with an edit
+    let edited = storage.create_message(&NewMessage {
+        session_id: sess1.id,
+        source_addr: Some(addr1),
+        text: "Remember this, really".into(),
+        timestamp: Utc.timestamp_opt(4, 0).unwrap().naive_utc(),
+        edit: Some(&first),
+        ..NewMessage::new_incoming()
+    });
+    assert!(edited.is_bookmarked);
+    let starred: Vec<i32> = storage
+        .fetch_starred_messages_augmented()
+        .iter()
+        .map(|m| m.id)
+        .collect();
+    assert_eq!(starred, [second.id, edited.id]);
+
+    // Unstarring an older revision unstars the edited message too
+    assert!(storage.unstar_message(first.id));
+    assert!(
+        !storage
+            .fetch_message_by_id(edited.id)
+            .unwrap()
+            .is_bookmarked
+    );
+    let starred: Vec<i32> = storage
+        .fetch_starred_messages_augmented()
+        .iter()
+        .map(|m| m.id)
+        .collect();
+    assert_eq!(starred, [second.id]);
+}
@@ -0,0 +1,82 @@
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use std::{path::PathBuf, sync::Arc};
+use whisperfish::{config::SignalConfig, store};
+
+/// Exports and restores encrypted backups of the Whisperfish storage.
+#[derive(Parser, Debug)]
+#[command(name = "whisperfish-backup", author, version, about, long_about = None)]
+struct Opts {
+    /// Whisperfish storage password
+    #[arg(short, long)]
+    password: Option<String>,
+
+    /// Path of the storage, instead of the default location
+    #[arg(short, long)]
+    storage: Option<PathBuf>,
+
+    /// Passphrase that encrypts the backup
+    #[arg(long, env = "WHISPERFISH_BACKUP_PASSPHRASE")]
+    passphrase: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write a backup of the storage
+    Export {
+        /// Path of the backup file
+        #[arg(value_parser)]
+        backup: PathBuf,
+    },
+    /// Restore a backup into an empty storage location
+    Import {
+        /// Path of the backup file
+        #[arg(value_parser)]
+        backup: PathBuf,
+    },
+}
+
+#[actix_rt::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let opt: Opts = Parser::parse_from(std::env::args_os());
+
+    let config = match SignalConfig::read_from_file() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Config file not found: {}", e);
+            SignalConfig::default()
+        }
+    };
+    let config = Arc::new(config);
+
+    let location = match opt.storage {
+        Some(path) => store::StorageLocation::Path(path),
+        None => store::default_location()?,
+    };
+
+    match opt.command {
+        Command::Export { backup } => {
+            let storage = store::Storage::open(config, &location, opt.password)
+                .await
+                .context("Opening storage")?;
+            storage.export_backup(&backup, &opt.passphrase)?;
+            println!("Wrote backup to {}", backup.display());
+        }
+        Command::Import { backup } => {
+            store::Storage::import_backup(
+                config,
+                &location,
+                &backup,
+                &opt.passphrase,
+                opt.password,
+            )
+            .await?;
+            println!("Restored backup into {}", location.display());
+        }
+    }
+
+    Ok(())
+}
@@ -35,6 +35,9 @@ pub struct SettingsBridge {
     show_phone_number: qt_property!(bool; READ get_show_phone_number WRITE set_show_phone_number NOTIFY show_phone_number_changed),
     share_phone_number: qt_property!(bool; READ get_share_phone_number WRITE set_share_phone_number NOTIFY share_phone_number_changed),
     transcribe_voice_notes: qt_property!(bool; READ get_transcribe_voice_notes WRITE set_transcribe_voice_notes NOTIFY transcribe_voice_notes_changed),
+    transcription_backend: qt_property!(String; READ get_transcription_backend WRITE set_transcription_backend NOTIFY transcription_backend_changed),
+    transcription_command: qt_property!(String; READ get_transcription_command WRITE set_transcription_command NOTIFY transcription_command_changed),
+    transcription_language: qt_property!(String; READ get_transcription_language WRITE set_transcription_language NOTIFY transcription_language_changed),
 
     // These will be mirrored to `config.yml` at Whisperfish exit
     verbose: qt_property!(bool; READ get_verbose WRITE set_verbose NOTIFY verbose_changed),
@@ -77,6 +80,9 @@ pub struct SettingsBridge {
     attachment_quality_changed: qt_signal!(value: String),
 
     transcribe_voice_notes_changed: qt_signal!(value: bool),
+    transcription_backend_changed: qt_signal!(value: String),
+    transcription_command_changed: qt_signal!(value: String),
+    transcription_language_changed: qt_signal!(value: String),
     last_version_changed: qt_signal!(value: String),
     next_update_time_changed: qt_signal!(value: String),
 }
@@ -115,6 +121,9 @@ impl Default for SettingsBridge {
             show_phone_number: true,
             share_phone_number: false,
             transcribe_voice_notes: false,
+            transcription_backend: Default::default(),
+            transcription_command: Default::default(),
+            transcription_language: Default::default(),
 
             verbose: false,
 
@@ -149,6 +158,9 @@ impl Default for SettingsBridge {
             show_phone_number_changed: Default::default(),
             share_phone_number_changed: Default::default(),
             transcribe_voice_notes_changed: Default::default(),
+            transcription_backend_changed: Default::default(),
+            transcription_command_changed: Default::default(),
+            transcription_language_changed: Default::default(),
             attachment_quality_changed: Default::default(),
             attachment_quality: Default::default(),
 
@@ -283,6 +295,18 @@ impl SettingsBridge {
         self.get_bool("transcribe_voice_notes")
     }
 
+    pub fn get_transcription_backend(&self) -> String {
+        self.get_string("transcription_backend")
+    }
+
+    pub fn get_transcription_command(&self) -> String {
+        self.get_string("transcription_command")
+    }
+
+    pub fn get_transcription_language(&self) -> String {
+        self.get_string("transcription_language")
+    }
+
     pub fn get_verbose(&self) -> bool {
         self.get_bool("verbose")
     }
@@ -398,6 +422,21 @@ impl SettingsBridge {
         self.transcribe_voice_notes_changed(value);
     }
 
+    pub fn set_transcription_backend(&mut self, value: String) {
+        self.set_string("transcription_backend", &value);
+        self.transcription_backend_changed(value);
+    }
+
+    pub fn set_transcription_command(&mut self, value: String) {
+        self.set_string("transcription_command", &value);
+        self.transcription_command_changed(value);
+    }
+
+    pub fn set_transcription_language(&mut self, value: String) {
+        self.set_string("transcription_language", &value);
+        self.transcription_language_changed(value);
+    }
+
     pub fn set_verbose(&mut self, value: bool) {
         self.set_bool("verbose", value);
         self.verbose_changed(value);
@@ -484,6 +523,9 @@ impl SettingsBridge {
         self.set_bool_if_unset("attachment_log", false);
         self.set_bool_if_unset("quit_on_ui_close", true);
         self.set_bool_if_unset("transcribe_voice_notes", false);
+        self.set_string_if_unset("transcription_backend", "speech-note");
+        self.set_string_if_unset("transcription_command", "");
+        self.set_string_if_unset("transcription_language", "auto");
         self.set_string_if_unset("country_code", "");
         self.set_string_if_unset(
             "avatar_dir",
@@ -5,7 +5,7 @@ use actix::prelude::*;
 use qmeta_async::with_executor;
 use qmetaobject::prelude::*;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::sync::Weak;
 
@@ -36,6 +36,11 @@ pub struct AppState {
 
     isHarbour: qt_method!(fn(&self) -> bool),
     isEncrypted: qt_method!(fn(&self) -> bool),
+    attachmentImage: qt_method!(fn(&self, path: QString) -> QString),
+    plaintextAttachment: qt_method!(fn(&self, path: QString) -> QString),
+    plaintextAttachmentReady: qt_signal!(path: QString, plaintext: QString),
+    /// Attachment files that are being decrypted for [Self::plaintextAttachment].
+    pending_plaintext: RefCell<HashSet<String>>,
 
     messageCount: qt_method!(fn(&self) -> i32),
     sessionCount: qt_method!(fn(&self) -> i32),
@@ -51,6 +56,9 @@ pub struct AppState {
     /// models (e.g. `CreateConversation`) via the auto-injected `app`
     /// property, hence living here rather than on `WhisperfishApp`.
     pub username_resolver: RefCell<Option<actix::Addr<crate::worker::username::UsernameResolver>>>,
+    /// Address of the [`crate::worker::ClientActor`], for QML-constructed observing models
+    /// that act on the account, like `Group`.
+    pub client_actor: RefCell<Option<actix::Addr<crate::worker::ClientActor>>>,
     // XXX Is this really thread safe?
     pub rustlegraphs: Rc<RefCell<HashMap<String, Weak<rustlegraph::Vizualizer>>>>,
 
@@ -141,6 +149,69 @@ impl AppState {
         self.storage.borrow().as_ref().unwrap().is_encrypted()
     }
 
+    /// The image source of an attachment or avatar file, which goes through the decrypting image
+    /// provider when the storage is encrypted.
+    #[allow(non_snake_case)]
+    #[with_executor]
+    fn attachmentImage(&mut self, path: QString) -> QString {
+        let path = path.to_string();
+        let encrypted = self
+            .storage
+            .borrow()
+            .as_ref()
+            .is_some_and(|s| s.is_encrypted());
+        if path.is_empty() || !encrypted {
+            return path.into();
+        }
+        crate::qattachmentimageprovider::attachment_url(&path).into()
+    }
+
+    /// A path to the plaintext contents of an attachment file, for consumers that cannot go
+    /// through the image provider, like media players and other applications.
+    ///
+    /// Encrypted files are decrypted on the thread pool, which can take a while for large files.
+    /// Until then, this returns an empty string, and `plaintextAttachmentReady` is emitted when
+    /// the plaintext file is there. An empty plaintext path means decrypting failed.
+    #[allow(non_snake_case)]
+    #[with_executor]
+    fn plaintextAttachment(&mut self, path: QString) -> QString {
+        let path = path.to_string();
+        if path.is_empty() {
+            return path.into();
+        }
+        let storage = self.storage.borrow().clone().unwrap();
+        if let Some(plaintext) = storage.cached_plaintext_attachment(&path) {
+            return plaintext.to_string_lossy().into_owned().into();
+        }
+        if !self.pending_plaintext.borrow_mut().insert(path.clone()) {
+            return QString::default();
+        }
+
+        let this = QPointer::from(&*self);
+        actix::spawn(async move {
+            let plaintext = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || storage.plaintext_attachment(&path))
+                    .await
+                    .expect("threadpool")
+            };
+            let plaintext = match plaintext {
+                Ok(plaintext) => plaintext.to_string_lossy().into_owned(),
+                Err(e) => {
+                    tracing::error!("Could not decrypt attachment {}: {:#}", path, e);
+                    String::new()
+                }
+            };
+            let Some(this) = this.as_pinned() else {
+                return;
+            };
+            let this = this.borrow();
+            this.pending_plaintext.borrow_mut().remove(&path);
+            this.plaintextAttachmentReady(path.into(), plaintext.into());
+        });
+        QString::default()
+    }
+
     #[allow(non_snake_case)]
     #[with_executor]
     fn messageCount(&mut self) -> i32 {
@@ -228,8 +299,13 @@ impl AppState {
 
             storage: RefCell::default(),
             username_resolver: RefCell::default(),
+            client_actor: RefCell::default(),
             rustlegraphs: Rc::new(RefCell::new(HashMap::new())),
             isEncrypted: Default::default(),
+            attachmentImage: Default::default(),
+            plaintextAttachment: Default::default(),
+            plaintextAttachmentReady: Default::default(),
+            pending_plaintext: RefCell::default(),
 
             messageCount: Default::default(),
             sessionCount: Default::default(),
@@ -368,7 +444,11 @@ pub fn run(config: crate::config::SignalConfig) -> Result<(), anyhow::Error> {
             qml_register_type::<model::Reactions>(uri, 1, 0, cstr!("Reactions"));
             qml_register_type::<model::GroupedReactions>(uri, 1, 0, cstr!("GroupedReactions"));
             qml_register_type::<model::Receipts>(uri, 1, 0, cstr!("Receipts"));
+            qml_register_type::<model::Stories>(uri, 1, 0, cstr!("Stories"));
             qml_register_type::<model::TypingModel>(uri, 1, 0, cstr!("TypingModel"));
+            qml_register_type::<model::CallLog>(uri, 1, 0, cstr!("CallLog"));
+            qml_register_type::<model::ScheduledMessages>(uri, 1, 0, cstr!("ScheduledMessages"));
+            qml_register_type::<model::StarredMessages>(uri, 1, 0, cstr!("StarredMessages"));
         }
 
         let mut app = QmlApp::application("harbour-whisperfish".into());
@@ -380,6 +460,11 @@ pub fn run(config: crate::config::SignalConfig) -> Result<(), anyhow::Error> {
         app.install_default_translator().unwrap();
 
         let app_state = AppState::new();
+        let attachment_storage = crate::qattachmentimageprovider::StorageSlot::default();
+        crate::qattachmentimageprovider::install(app.engine(), attachment_storage.clone());
+        app_state.deferred_with_storage(move |storage| {
+            *attachment_storage.write().expect("storage lock") = Some(storage);
+        });
         crate::qblurhashimageprovider::install(app.engine());
         crate::qrustlegraphimageprovider::install(app.engine(), app_state.rustlegraphs.clone());
 
@@ -388,6 +473,11 @@ pub fn run(config: crate::config::SignalConfig) -> Result<(), anyhow::Error> {
         app.set_object_property("SessionModel".into(), session_methods.pinned());
         let client_actor =
             worker::ClientActor::new(&mut app, std::sync::Arc::clone(&config))?.start();
+        session_methods.pinned().borrow_mut().client_actor = Some(client_actor.clone());
+        app_state
+            .client_actor
+            .borrow_mut()
+            .get_or_insert(client_actor.clone());
         // Username resolver is a standalone subactor (no ClientActor
         // dependency): username/link lookups use the unidentified websocket,
         // which needs no credentials. It receives `StorageReady` alongside
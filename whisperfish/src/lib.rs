@@ -5,6 +5,7 @@ pub mod gui;
 pub mod methods;
 pub mod model;
 pub mod platform;
+pub mod qattachmentimageprovider;
 pub mod qblurhashimageprovider;
 pub mod qrustlegraphimageprovider;
 pub mod qtlog;
@@ -2,15 +2,18 @@
 
 use crate::worker::ClientActor;
 use crate::worker::{
-    DeleteMessage, DeleteMessageForAll, ExportAttachment, NewAttachment, QueueExpiryUpdate,
-    QueueMessage, SendReaction,
+    DeleteMessage, DeleteMessageForAll, ExportAttachment, MarkMessageViewed, NewAttachment,
+    QueueEdit, QueueExpiryUpdate, QueueMessage, SendReaction, SendStory, StarMessage,
 };
 use actix::prelude::*;
+use chrono::NaiveDateTime;
 use futures::prelude::*;
 use qmeta_async::with_executor;
 use qmetaobject::QMetaType;
 use qmetaobject::prelude::*;
 use qttypes::{QVariantList, QVariantMap};
+use whisperfish_store::body_ranges::{AssociatedValue, BodyRange};
+use whisperfish_store::millis_to_naive_chrono;
 
 pub fn pad_fingerprint(fp: &mut String) {
     if fp.len() == 60 {
@@ -21,6 +24,49 @@ pub fn pad_fingerprint(fp: &mut String) {
     }
 }
 
+/// Parse body ranges in the format the message model hands them to QML.
+fn body_ranges_from_qvariantlist(mut ranges_qml: QVariantList) -> Vec<BodyRange> {
+    let mut ranges = vec![];
+
+    while !ranges_qml.is_empty() {
+        let Some(range) = QVariantMap::from_qvariant(ranges_qml.remove(0)) else {
+            continue;
+        };
+        let associated_value =
+            QVariantMap::from_qvariant(range.value("associatedValue".into(), QVariant::default()))
+                .unwrap_or_default();
+        let associated_value = match associated_value
+            .value("type".into(), QVariant::default())
+            .to_qstring()
+            .to_string()
+            .as_str()
+        {
+            "mention" => AssociatedValue::MentionUuid(
+                associated_value
+                    .value("mention".into(), QVariant::default())
+                    .to_qstring()
+                    .to_string(),
+            ),
+            "style" => AssociatedValue::Style(
+                associated_value
+                    .value("style".into(), QVariant::default())
+                    .to_int() as i32,
+            ),
+            other => {
+                tracing::warn!("Ignoring body range of unknown type {other:?}");
+                continue;
+            }
+        };
+        ranges.push(BodyRange {
+            start: range.value("start".into(), QVariant::default()).to_int() as i32,
+            length: range.value("length".into(), QVariant::default()).to_int() as i32,
+            associated_value: Some(associated_value),
+        });
+    }
+
+    ranges
+}
+
 #[derive(QObject, Default)]
 pub struct MessageMethods {
     base: qt_base_class!(trait QObject),
@@ -37,7 +83,22 @@ pub struct MessageMethods {
             is_voice_note: bool,
         )
     ),
+    createScheduledMessage: qt_method!(
+        fn(
+            &self,
+            session_id: i32,
+            message: QString,
+            attachment: QVariantList,
+            quote: i32,
+            is_voice_note: bool,
+            send_time: f64,
+        )
+    ),
+    createStickerMessage:
+        qt_method!(fn(&self, session_id: i32, pack_id: QString, sticker_id: i32, quote: i32)),
     createExpiryUpdate: qt_method!(fn(&self, session_id: i32, expires_in: i32)),
+    createTextStory: qt_method!(fn(&self, distribution_id: QString, text: QString)),
+    editMessage: qt_method!(fn(&self, message_id: i32, text: QString, body_ranges: QVariantList)),
 
     sendMessage: qt_method!(fn(&self, mid: i32)),
     sendReaction:
@@ -45,8 +106,11 @@ pub struct MessageMethods {
     endSession: qt_method!(fn(&self, recipient_id: i32)),
 
     remove: qt_method!(fn(&self, id: i32)),
+    star: qt_method!(fn(&self, id: i32, starred: bool)),
     removeForAll: qt_method!(fn(&self, id: i32)),
 
+    markViewed: qt_method!(fn(&self, id: i32)),
+
     exportAttachment: qt_method!(fn(&self, attachment_id: i32)),
 }
 
@@ -57,10 +121,51 @@ impl MessageMethods {
         &mut self,
         session_id: i32,
         message: QString,
-        mut attachments_qml: QVariantList,
+        attachments_qml: QVariantList,
         quote: i32,
         _add: bool,
         is_voice_note: bool,
+    ) {
+        self.queue_message(
+            session_id,
+            message,
+            attachments_qml,
+            quote,
+            is_voice_note,
+            None,
+        );
+    }
+
+    /// Store a message that is sent at `send_time`, in milliseconds since the epoch.
+    #[with_executor]
+    #[tracing::instrument(skip(self))]
+    fn createScheduledMessage(
+        &mut self,
+        session_id: i32,
+        message: QString,
+        attachments_qml: QVariantList,
+        quote: i32,
+        is_voice_note: bool,
+        send_time: f64,
+    ) {
+        self.queue_message(
+            session_id,
+            message,
+            attachments_qml,
+            quote,
+            is_voice_note,
+            Some(millis_to_naive_chrono(send_time as u64)),
+        );
+    }
+
+    fn queue_message(
+        &mut self,
+        session_id: i32,
+        message: QString,
+        mut attachments_qml: QVariantList,
+        quote: i32,
+        is_voice_note: bool,
+        schedule_send_time: Option<NaiveDateTime>,
     ) {
         let message = message.to_string();
         let mut attachments: Vec<NewAttachment> = vec![];
@@ -94,6 +199,80 @@ impl MessageMethods {
                     attachments,
                     quote,
                     is_voice_note,
+                    sticker: None,
+                    schedule_send_time,
+                })
+                .map(Result::unwrap),
+        );
+    }
+
+    #[with_executor]
+    #[tracing::instrument(skip(self))]
+    fn createStickerMessage(
+        &mut self,
+        session_id: i32,
+        pack_id: QString,
+        sticker_id: i32,
+        quote: i32,
+    ) {
+        actix::spawn(
+            self.client_actor
+                .as_ref()
+                .unwrap()
+                .send(QueueMessage {
+                    session_id,
+                    message: String::new(),
+                    attachments: vec![],
+                    quote,
+                    is_voice_note: false,
+                    sticker: Some((pack_id.to_string(), sticker_id)),
+                    schedule_send_time: None,
+                })
+                .map(Result::unwrap),
+        );
+    }
+
+    /// Replace the text of a sent message, and send the edit.
+    #[with_executor]
+    #[tracing::instrument(skip(self, text, body_ranges))]
+    fn editMessage(&mut self, message_id: i32, text: QString, body_ranges: QVariantList) {
+        actix::spawn(
+            self.client_actor
+                .as_ref()
+                .unwrap()
+                .send(QueueEdit {
+                    message_id,
+                    text: text.to_string(),
+                    body_ranges: body_ranges_from_qvariantlist(body_ranges),
+                })
+                .map(Result::unwrap),
+        );
+    }
+
+    /// Send a text story to a distribution list.
+    ///
+    /// An empty distribution id refers to "My Story".
+    #[with_executor]
+    #[tracing::instrument(skip(self, text))]
+    fn createTextStory(&mut self, distribution_id: QString, text: QString) {
+        let distribution_id = if distribution_id.is_empty() {
+            crate::store::stories::MY_STORY_DISTRIBUTION_ID
+        } else {
+            match uuid::Uuid::parse_str(&distribution_id.to_string()) {
+                Ok(id) => id,
+                Err(e) => {
+                    tracing::error!("Invalid distribution id: {e}");
+                    return;
+                }
+            }
+        };
+        actix::spawn(
+            self.client_actor
+                .as_ref()
+                .unwrap()
+                .send(SendStory {
+                    distribution_id,
+                    text: text.to_string(),
                 })
                 .map(Result::unwrap),
         );
@@ -176,6 +355,24 @@ impl MessageMethods {
         tracing::trace!("Dispatched DeleteMessage({})", id);
     }
 
+    /// Star or unstar a message, and all its revisions.
+    #[with_executor]
+    #[tracing::instrument(skip(self))]
+    pub fn star(&self, id: i32, starred: bool) {
+        actix::spawn(
+            self.client_actor
+                .as_ref()
+                .unwrap()
+                .send(StarMessage {
+                    message_id: id,
+                    starred,
+                })
+                .map(Result::unwrap),
+        );
+
+        tracing::trace!("Dispatched StarMessage({}, {})", id, starred);
+    }
+
     /// Remove a message from everyone and from the database.
     #[with_executor]
     #[tracing::instrument(skip(self))]
@@ -191,6 +388,23 @@ impl MessageMethods {
         tracing::trace!("Dispatched DeleteMessageRemotely({})", id);
     }
 
+    /// Mark a voice note or view-once message as viewed.
+    ///
+    /// View-once media can not be opened again afterwards.
+    #[with_executor]
+    #[tracing::instrument(skip(self))]
+    pub fn markViewed(&self, id: i32) {
+        actix::spawn(
+            self.client_actor
+                .as_ref()
+                .unwrap()
+                .send(MarkMessageViewed(id))
+                .map(Result::unwrap),
+        );
+
+        tracing::trace!("Dispatched MarkMessageViewed({})", id);
+    }
+
     #[with_executor]
     #[tracing::instrument(skip(self))]
     pub fn exportAttachment(&self, attachment_id: i32) {
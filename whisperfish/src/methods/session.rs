@@ -2,12 +2,15 @@
 
 use std::sync::OnceLock;
 
+use actix::prelude::*;
+use futures::prelude::*;
 use qmeta_async::with_executor;
 use qmetaobject::prelude::*;
 use whisperfish_store::NewMessage;
 use whisperfish_store::orm::MessageType;
 
 use crate::store::Storage;
+use crate::worker::{ClientActor, DeleteSession};
 
 #[derive(QObject, Default)]
 pub struct SessionMethods {
@@ -18,6 +21,7 @@ pub struct SessionMethods {
     /// silently dropped — the same risk profile as the old actor, which
     /// would have panicked if a mutation beat `StorageReady`.
     pub storage: OnceLock<Storage>,
+    pub client_actor: Option<Addr<ClientActor>>,
 
     remove: qt_method!(fn(&self, id: i32)),
 
@@ -49,7 +53,13 @@ impl SessionMethods {
             tracing::warn!(session_id = id, "DeleteSession dropped: storage not ready");
             return;
         };
-        storage.delete_session(id);
+        match &self.client_actor {
+            // Let our other devices know about the deleted conversation.
+            Some(client_actor) => {
+                actix::spawn(client_actor.send(DeleteSession(id)).map(Result::unwrap));
+            }
+            None => storage.delete_session(id),
+        }
     }
 
     #[with_executor]
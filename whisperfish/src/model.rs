@@ -221,6 +221,7 @@ macro_rules! define_model_roles {
 
 mod active_model;
 pub mod attachment;
+pub mod call_log;
 #[cfg(feature = "calling")]
 pub mod calling;
 pub mod contact;
@@ -233,7 +234,10 @@ pub mod reactions;
 pub mod receipts;
 pub mod recipient;
 pub mod rustlegraph;
+pub mod scheduled_messages;
 pub mod sessions;
+pub mod starred_messages;
+pub mod stories;
 pub mod typing;
 pub mod username_lookup;
 #[cfg(feature = "voice-note-recording")]
@@ -245,6 +249,7 @@ use std::time::Duration;
 
 pub use self::active_model::*;
 pub use self::attachment::*;
+pub use self::call_log::*;
 #[cfg(feature = "calling")]
 pub use self::calling::*;
 pub use self::contact::*;
@@ -258,7 +263,10 @@ pub use self::reactions::*;
 pub use self::receipts::*;
 pub use self::recipient::*;
 pub use self::rustlegraph::*;
+pub use self::scheduled_messages::*;
 pub use self::sessions::*;
+pub use self::starred_messages::*;
+pub use self::stories::*;
 pub use self::typing::*;
 pub use self::username_lookup::*;
 #[cfg(feature = "voice-note-recording")]
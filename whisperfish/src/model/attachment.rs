@@ -18,6 +18,7 @@ use std::process::Command;
         is_voice_note IsVoiceNote,
 
         transcription Transcription,
+        transcription_language TranscriptionLanguage,
     })
 )]
 #[derive(Default, QObject)]
@@ -95,6 +96,7 @@ define_model_roles! {
         VisualHash(visual_hash via qstring_from_option): "visual_hash",
         IsVoiceNote(is_voice_note):                      "is_voice_note",
         Transcription(transcription via qstring_from_option): "transcription",
+        TranscriptionLanguage(transcription_language via qstring_from_option): "transcription_language",
         Size(size via Option::unwrap_or_default):        "size",
         DownloadLength(download_length via Option::unwrap_or_default): "download_length",
         DownloadedPercentage(fn downloaded_percentage(&self)): "downloaded_percentage",
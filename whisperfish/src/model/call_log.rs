@@ -0,0 +1,133 @@
+#![allow(non_snake_case)]
+
+use std::collections::HashMap;
+
+use crate::model::*;
+use crate::store::Storage;
+use crate::store::observer::{EventObserving, Interest};
+use crate::worker::{ClearCallLog, MarkCallLogRead};
+use qmetaobject::prelude::*;
+use whisperfish_store::schema;
+use whisperfish_store::store::orm;
+
+/// QML-constructable object that lists the calls that were not cleared, most recent first.
+#[observing_model]
+#[derive(Default, QObject)]
+pub struct CallLog {
+    base: qt_base_class!(trait QObject),
+
+    #[qt_property(READ: calls, NOTIFY: calls_changed)]
+    calls: QVariant,
+    #[qt_property(READ: call_count, NOTIFY: calls_changed)]
+    count: i32,
+    #[qt_property(READ: unread_count, NOTIFY: calls_changed)]
+    unreadCount: i32,
+
+    call_list: QObjectBox<CallLogModel>,
+
+    calls_changed: qt_signal!(),
+
+    clear: qt_method!(fn(&self)),
+    markAllRead: qt_method!(fn(&self)),
+}
+
+impl EventObserving for CallLog {
+    type Context = ModelContext<Self>;
+
+    fn observe(&mut self, ctx: Self::Context, _event: crate::store::observer::Event) {
+        self.call_list.pinned().borrow_mut().load_all(ctx.storage());
+        self.calls_changed();
+    }
+
+    fn interests(&self) -> Vec<Interest> {
+        vec![Interest::whole_table(schema::calls::table)]
+    }
+}
+
+define_model_roles! {
+    enum CallRoles for orm::AugmentedCall {
+        Id(inner.id): "id",
+        SessionId(inner.session_id): "sessionId",
+        PeerId(peer_recipient_id via int_from_i32_option): "peerId",
+        RingerId(inner.ringer): "ringerId",
+        Timestamp(inner.timestamp via qdatetime_from_naive): "timestamp",
+        IsOutbound(inner.is_outbound): "outgoing",
+        IsRead(inner.is_read): "isRead",
+        IsVideo(fn is_video(&self)): "isVideo",
+        IsGroup(fn is_group(&self)): "isGroup",
+        IsMissed(fn is_missed(&self)): "isMissed",
+        IsRinging(fn is_ringing(&self)): "isRinging",
+    }
+}
+
+impl CallLog {
+    fn init(&mut self, ctx: ModelContext<Self>) {
+        self.call_list.pinned().borrow_mut().load_all(ctx.storage());
+        self.calls_changed();
+    }
+
+    fn calls(&self, _ctx: Option<ModelContext<Self>>) -> QVariant {
+        self.call_list.pinned().into()
+    }
+
+    fn call_count(&self, _ctx: Option<ModelContext<Self>>) -> i32 {
+        self.call_list.pinned().borrow().row_count()
+    }
+
+    fn unread_count(&self, _ctx: Option<ModelContext<Self>>) -> i32 {
+        self.call_list.pinned().borrow().unread_count()
+    }
+
+    fn clear(&self) {
+        match self.client_actor() {
+            Some(addr) => addr.do_send(ClearCallLog),
+            None => tracing::error!("ClientActor not available to clear the call log"),
+        }
+    }
+
+    fn markAllRead(&self) {
+        match self.client_actor() {
+            Some(addr) => addr.do_send(MarkCallLogRead),
+            None => tracing::error!("ClientActor not available to mark the call log read"),
+        }
+    }
+
+    fn client_actor(&self) -> Option<actix::Addr<crate::worker::ClientActor>> {
+        self._app
+            .as_pinned()
+            .and_then(|app| app.borrow().client_actor.borrow().clone())
+    }
+}
+
+#[derive(QObject, Default)]
+pub struct CallLogModel {
+    base: qt_base_class!(trait QAbstractListModel),
+    calls: Vec<orm::AugmentedCall>,
+}
+
+impl CallLogModel {
+    #[tracing::instrument(level = "trace", skip(self, storage))]
+    fn load_all(&mut self, storage: Storage) {
+        self.begin_reset_model();
+        self.calls = storage.fetch_call_log();
+        self.end_reset_model();
+    }
+
+    fn unread_count(&self) -> i32 {
+        self.calls.iter().filter(|call| !call.is_read).count() as i32
+    }
+}
+
+impl QAbstractListModel for CallLogModel {
+    fn row_count(&self) -> i32 {
+        self.calls.len() as i32
+    }
+
+    fn data(&self, index: QModelIndex, role: i32) -> QVariant {
+        CallRoles::from(role).get(&self.calls[index.row() as usize])
+    }
+
+    fn role_names(&self) -> HashMap<i32, QByteArray> {
+        CallRoles::role_names()
+    }
+}
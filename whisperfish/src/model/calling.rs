@@ -1,6 +1,8 @@
+#![allow(non_snake_case)]
+
 use crate::worker::{
     ClientActor,
-    client::{AnswerCall, HangupCall, InitiateCall},
+    client::{AnswerCall, HangupCall, InitiateCall, JoinGroupCall, LeaveGroupCall},
 };
 use actix::Addr;
 use qmetaobject::prelude::*;
@@ -26,12 +28,18 @@ pub struct Calls {
     // Recipient id that's currently calling
     ringing_recipient_id: qt_property!(i32; NOTIFY ringing_changed ALIAS ringingRecipientId),
 
+    // Session id of the group call we are in, -1 if none
+    group_call_session_id: qt_property!(i32; NOTIFY group_call_changed ALIAS groupCallSessionId),
+
     ringing_changed: qt_signal!(),
     hungup: qt_signal!(),
+    group_call_changed: qt_signal!(),
 
     answer: qt_method!(fn(&self)),
     hangup: qt_method!(fn(&self)),
     call: qt_method!(fn(&self, recipient_id: i32, video: bool)),
+    joinGroupCall: qt_method!(fn(&self, session_id: i32)),
+    leaveGroupCall: qt_method!(fn(&self)),
 }
 
 impl Calls {
@@ -44,12 +52,16 @@ impl Calls {
             call_id: None,
             ringing_recipient_id: -1,
             direction: -1,
+            group_call_session_id: -1,
 
             ringing_changed: Default::default(),
             hungup: Default::default(),
+            group_call_changed: Default::default(),
             answer: Default::default(),
             hangup: Default::default(),
             call: Default::default(),
+            joinGroupCall: Default::default(),
+            leaveGroupCall: Default::default(),
         }
     }
 
@@ -123,6 +135,17 @@ impl Calls {
         }
     }
 
+    pub fn handle_group_state(&mut self, session_id: i32, joined: bool) {
+        if joined {
+            self.group_call_session_id = session_id;
+        } else if self.group_call_session_id == session_id {
+            self.group_call_session_id = -1;
+        } else {
+            return;
+        }
+        self.group_call_changed();
+    }
+
     pub fn call(&self, recipient_id: i32, video: bool) {
         self.client().do_send(InitiateCall {
             recipient_id,
@@ -149,4 +172,18 @@ impl Calls {
         };
         self.client().do_send(HangupCall { call_id });
     }
+
+    fn joinGroupCall(&self, session_id: i32) {
+        self.client().do_send(JoinGroupCall { session_id });
+    }
+
+    fn leaveGroupCall(&self) {
+        if self.group_call_session_id == -1 {
+            tracing::error!("No group call to leave");
+            return;
+        }
+        self.client().do_send(LeaveGroupCall {
+            session_id: self.group_call_session_id,
+        });
+    }
 }
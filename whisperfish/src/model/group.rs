@@ -5,10 +5,13 @@ use std::collections::HashMap;
 use crate::model::*;
 use crate::store::Storage;
 use crate::store::observer::{EventObserving, Interest};
-use crate::store::orm::{GroupV1Member, GroupV2Member};
+use crate::store::orm::{GroupV1Member, GroupV2Member, GroupV2RequestingMember};
+use crate::worker::{ModifyGroupV2, SetGroupV2Avatar};
+use libsignal_service::groups_v2::Role;
 use qmeta_async::with_executor;
 use qmetaobject::prelude::*;
 use uuid::Uuid;
+use whisperfish_store::groups::{GroupInviteLink, GroupV2Action};
 use whisperfish_store::schema;
 use whisperfish_store::store::orm;
 
@@ -50,11 +53,52 @@ pub struct Group {
     #[qt_property(READ: is_terminated, NOTIFY: group_changed)]
     isTerminated: bool,
 
+    #[qt_property(READ: is_blocked, NOTIFY: group_changed)]
+    blocked: bool,
+
+    #[qt_property(READ: has_self_as_pending_member, NOTIFY: group_changed)]
+    hasSelfAsPendingMember: bool,
+
+    #[qt_property(READ: requesting_members, NOTIFY: members_model_changed)]
+    requestingMembers: QVariant,
+
+    #[qt_property(READ: invite_link, NOTIFY: group_changed)]
+    inviteLink: QString,
+
+    #[qt_property(READ: invite_link_access, NOTIFY: group_changed)]
+    inviteLinkAccess: i32,
+
     membership_list: QObjectBox<GroupMembershipListModel>,
+    requesting_list: QObjectBox<GroupMembershipListModel>,
+    self_pending: bool,
 
     group_changed: qt_signal!(),
     members_model_changed: qt_signal!(),
 
+    addMember: qt_method!(fn(&self, recipientId: i32)),
+    removeMember: qt_method!(fn(&self, recipientId: i32)),
+    setAdmin: qt_method!(fn(&self, recipientId: i32, admin: bool)),
+    banMember: qt_method!(fn(&self, recipientId: i32)),
+    unbanMember: qt_method!(fn(&self, recipientId: i32)),
+    setTitle: qt_method!(fn(&self, title: QString)),
+    setDescription: qt_method!(fn(&self, description: QString)),
+    /// Set the avatar to the image at `path`, or remove it if `path` is empty.
+    setAvatar: qt_method!(fn(&self, path: QString)),
+    setExpireTimer: qt_method!(fn(&self, seconds: i32)),
+    setAnnouncementsOnly: qt_method!(fn(&self, enabled: bool)),
+    setAttributesAccess: qt_method!(fn(&self, access: i32)),
+    setMembersAccess: qt_method!(fn(&self, access: i32)),
+    setMemberLabelAccess: qt_method!(fn(&self, access: i32)),
+    setMemberLabel: qt_method!(fn(&self, label: QString, emoji: QString)),
+    acceptInvitation: qt_method!(fn(&self)),
+    declineInvitation: qt_method!(fn(&self)),
+    approveRequest: qt_method!(fn(&self, recipientId: i32)),
+    denyRequest: qt_method!(fn(&self, recipientId: i32)),
+    /// Who may join through the invite link; `Unsatisfiable` (4) disables the link.
+    setInviteLinkAccess: qt_method!(fn(&self, access: i32)),
+    resetInviteLink: qt_method!(fn(&self)),
+    leave: qt_method!(fn(&self)),
+
     own_aci: Option<Uuid>,
 }
 
@@ -86,7 +130,21 @@ impl EventObserving for Group {
                 None
             }
         });
-        let members = new_members.chain(
+        let invitations = self.id.iter().filter(|id| id.len() == 64).flat_map(|id| {
+            [
+                Interest::whole_table_with_relation(
+                    schema::group_v2_pending_members::table,
+                    schema::group_v2s::table,
+                    id.clone(),
+                ),
+                Interest::whole_table_with_relation(
+                    schema::group_v2_requesting_members::table,
+                    schema::group_v2s::table,
+                    id.clone(),
+                ),
+            ]
+        });
+        let members = new_members.chain(invitations).chain(
             membership_list
                 .borrow()
                 .content
@@ -151,6 +209,35 @@ impl Group {
         self.group_v2.as_ref().is_some_and(|g| g.terminated)
     }
 
+    fn is_blocked(&self, _ctx: Option<ModelContext<Self>>) -> bool {
+        self.group_v2.as_ref().is_some_and(|g| g.is_blocked)
+    }
+
+    /// Check if we are invited to the group, but did not accept yet.
+    fn has_self_as_pending_member(&self, _ctx: Option<ModelContext<Self>>) -> bool {
+        self.self_pending
+    }
+
+    fn requesting_members(&self, _ctx: Option<ModelContext<Self>>) -> QVariant {
+        self.requesting_list.pinned().into()
+    }
+
+    fn invite_link(&self, _ctx: Option<ModelContext<Self>>) -> QString {
+        self.group_v2
+            .as_ref()
+            .and_then(GroupInviteLink::of_group)
+            .map(|link| link.to_url())
+            .unwrap_or_default()
+            .into()
+    }
+
+    fn invite_link_access(&self, _ctx: Option<ModelContext<Self>>) -> i32 {
+        self.group_v2
+            .as_ref()
+            .map(|g| g.access_required_for_add_from_invite_link)
+            .unwrap_or_default()
+    }
+
     #[with_executor]
     #[tracing::instrument(skip(self, ctx))]
     fn set_group_id(&mut self, ctx: Option<ModelContext<Self>>, id: QString) {
@@ -160,6 +247,148 @@ impl Group {
         }
     }
 
+    /// Submit a change to the group server, through the client actor.
+    fn client_actor(&self) -> Option<actix::Addr<crate::worker::ClientActor>> {
+        self._app
+            .as_pinned()
+            .and_then(|app| app.borrow().client_actor.borrow().clone())
+    }
+
+    fn modify(&self, action: GroupV2Action) {
+        let Some(group_v2) = &self.group_v2 else {
+            tracing::error!("Only GroupV2 groups can be changed");
+            return;
+        };
+        match self.client_actor() {
+            Some(addr) => addr.do_send(ModifyGroupV2 {
+                group_id: group_v2.id.clone(),
+                action,
+            }),
+            None => tracing::error!("ClientActor not available to change group"),
+        }
+    }
+
+    fn modify_member(
+        &self,
+        recipient_id: i32,
+        action: impl FnOnce(orm::Recipient) -> GroupV2Action,
+    ) {
+        match self.storage().fetch_recipient_by_id(recipient_id) {
+            Some(recipient) => self.modify(action(recipient)),
+            None => tracing::error!("No recipient with id {}", recipient_id),
+        }
+    }
+
+    fn addMember(&self, recipientId: i32) {
+        self.modify_member(recipientId, GroupV2Action::AddMember);
+    }
+
+    fn removeMember(&self, recipientId: i32) {
+        self.modify_member(recipientId, GroupV2Action::RemoveMember);
+    }
+
+    fn setAdmin(&self, recipientId: i32, admin: bool) {
+        let role = if admin {
+            Role::Administrator
+        } else {
+            Role::Default
+        };
+        self.modify_member(recipientId, |r| GroupV2Action::SetMemberRole(r, role));
+    }
+
+    fn banMember(&self, recipientId: i32) {
+        self.modify_member(recipientId, GroupV2Action::BanMember);
+    }
+
+    fn unbanMember(&self, recipientId: i32) {
+        self.modify_member(recipientId, GroupV2Action::UnbanMember);
+    }
+
+    fn setTitle(&self, title: QString) {
+        self.modify(GroupV2Action::Title(title.to_string()));
+    }
+
+    fn setDescription(&self, description: QString) {
+        let description = description.to_string();
+        self.modify(GroupV2Action::Description(
+            Some(description).filter(|d| !d.is_empty()),
+        ));
+    }
+
+    fn setAvatar(&self, path: QString) {
+        let Some(group_v2) = &self.group_v2 else {
+            tracing::error!("Only GroupV2 groups can be changed");
+            return;
+        };
+        let path = path.to_string();
+        // The image is read by the client actor, off the GUI thread.
+        match self.client_actor() {
+            Some(addr) => addr.do_send(SetGroupV2Avatar {
+                group_id: group_v2.id.clone(),
+                avatar_path: Some(path).filter(|path| !path.is_empty()),
+            }),
+            None => tracing::error!("ClientActor not available to change group"),
+        }
+    }
+
+    fn setExpireTimer(&self, seconds: i32) {
+        self.modify(GroupV2Action::ExpireTimer(
+            Some(seconds as u32).filter(|_| seconds > 0),
+        ));
+    }
+
+    fn setAnnouncementsOnly(&self, enabled: bool) {
+        self.modify(GroupV2Action::AnnouncementsOnly(enabled));
+    }
+
+    fn setAttributesAccess(&self, access: i32) {
+        self.modify(GroupV2Action::AttributesAccess(access.into()));
+    }
+
+    fn setMembersAccess(&self, access: i32) {
+        self.modify(GroupV2Action::MembersAccess(access.into()));
+    }
+
+    fn setMemberLabelAccess(&self, access: i32) {
+        self.modify(GroupV2Action::MemberLabelAccess(access.into()));
+    }
+
+    fn setMemberLabel(&self, label: QString, emoji: QString) {
+        let (label, emoji) = (label.to_string(), emoji.to_string());
+        self.modify(GroupV2Action::MemberLabel {
+            label: Some(label).filter(|l| !l.is_empty()),
+            emoji: Some(emoji).filter(|e| !e.is_empty()),
+        });
+    }
+
+    fn acceptInvitation(&self) {
+        self.modify(GroupV2Action::AcceptInvitation);
+    }
+
+    fn declineInvitation(&self) {
+        self.modify(GroupV2Action::DeclineInvitation);
+    }
+
+    fn approveRequest(&self, recipientId: i32) {
+        self.modify_member(recipientId, GroupV2Action::ApproveRequest);
+    }
+
+    fn denyRequest(&self, recipientId: i32) {
+        self.modify_member(recipientId, GroupV2Action::DenyRequest);
+    }
+
+    fn setInviteLinkAccess(&self, access: i32) {
+        self.modify(GroupV2Action::InviteLinkAccess(access.into()));
+    }
+
+    fn resetInviteLink(&self) {
+        self.modify(GroupV2Action::ResetInviteLink);
+    }
+
+    fn leave(&self) {
+        self.modify(GroupV2Action::Leave);
+    }
+
     fn init(&mut self, ctx: ModelContext<Self>) {
         let storage = ctx.storage();
         self.own_aci = storage
@@ -168,6 +397,8 @@ impl Group {
         if let Some(id) = &self.id {
             self.group_v1 = None;
             self.group_v2 = None;
+            self.self_pending = false;
+            self.requesting_list.pinned().borrow_mut().clear();
             if id.len() == 32 {
                 self.group_v1 = storage.fetch_group_by_group_v1_id(id);
                 self.membership_list
@@ -176,6 +407,11 @@ impl Group {
                     .load_v1(storage, id);
             } else if id.len() == 64 {
                 self.group_v2 = storage.fetch_group_by_group_v2_id(id);
+                self.self_pending = storage.fetch_group_v2_self_pending_member(id).is_some();
+                self.requesting_list
+                    .pinned()
+                    .borrow_mut()
+                    .load_v2_requesting(storage.clone(), id);
                 self.membership_list
                     .pinned()
                     .borrow_mut()
@@ -191,6 +427,8 @@ impl Group {
 pub enum GroupMembership {
     V1(GroupV1Member),
     V2(GroupV2Member),
+    /// Asked to join a GroupV2 through its invite link.
+    Requesting(GroupV2RequestingMember),
 }
 
 impl GroupMembership {
@@ -198,26 +436,27 @@ impl GroupMembership {
         match self {
             Self::V1(v1) => v1.member_since,
             Self::V2(v2) => Some(v2.member_since),
+            Self::Requesting(requesting) => Some(requesting.timestamp),
         }
     }
 
     fn role(&self) -> i32 {
         match self {
-            Self::V1(_v1) => -1,
+            Self::V1(_v1) | Self::Requesting(_) => -1,
             Self::V2(v2) => v2.role,
         }
     }
 
     fn label(&self) -> Option<&str> {
         match self {
-            Self::V1(_v1) => None,
+            Self::V1(_v1) | Self::Requesting(_) => None,
             Self::V2(v2) => v2.label.as_deref(),
         }
     }
 
     fn label_emoji(&self) -> Option<&str> {
         match self {
-            Self::V1(_v1) => None,
+            Self::V1(_v1) | Self::Requesting(_) => None,
             Self::V2(v2) => v2.label_emoji.as_deref(),
         }
     }
@@ -250,6 +489,16 @@ impl GroupMembershipListModel {
         self.end_reset_model();
     }
 
+    fn load_v2_requesting(&mut self, storage: Storage, id: &str) {
+        self.begin_reset_model();
+        self.content = storage
+            .fetch_group_v2_requesting_members(id)
+            .into_iter()
+            .map(|(membership, member)| (GroupMembership::Requesting(membership), member))
+            .collect();
+        self.end_reset_model();
+    }
+
     fn clear(&mut self) {
         self.begin_reset_model();
         self.content.clear();
@@ -3,6 +3,7 @@
 use crate::model::*;
 use crate::store::Storage;
 use crate::store::observer::{EventObserving, Interest, PrimaryKey};
+use crate::store::pagination::MessageCursor;
 use libsignal_service::groups_v2::Role;
 use qmetaobject::QObjectBox;
 use qmetaobject::{QMetaType, prelude::*};
@@ -39,6 +40,14 @@ use whisperfish_store::store::orm;
         hasStrikeThrough HasStrikeThrough,
 
         expiresIn ExpiresIn,
+
+        hasLinkPreview HasLinkPreview,
+        linkPreviewUrl LinkPreviewUrl,
+        linkPreviewTitle LinkPreviewTitle,
+        linkPreviewDescription LinkPreviewDescription,
+        linkPreviewThumbnail LinkPreviewThumbnail,
+
+        starred Starred,
     })
 )]
 #[derive(Default, QObject)]
@@ -78,8 +87,17 @@ impl EventObserving for Message {
     fn observe(&mut self, ctx: Self::Context, event: crate::store::observer::Event) {
         if let Some(id) = self.message_id {
             if let Some(attachment_id) = event.relation_key_for(schema::attachments::table) {
+                let is_link_preview_thumbnail = self
+                    .augmented_message
+                    .as_ref()
+                    .and_then(|m| m.link_preview.as_ref())
+                    .and_then(|(preview, _)| preview.attachment_id)
+                    .is_some_and(|thumbnail| Some(thumbnail) == attachment_id.as_i32());
                 // XXX Maybe we should just provide the session ID to match in the ActixEvent
-                if self
+                if is_link_preview_thumbnail {
+                    self.fetch(ctx.storage(), id);
+                    self.message_changed();
+                } else if self
                     .attachment_list_model
                     .pinned()
                     .borrow()
@@ -416,7 +434,7 @@ impl Session {
         self.message_list
             .pinned()
             .borrow_mut()
-            .load_all(storage, id);
+            .load_newest(storage, id);
         self.session_changed();
         if was_valid != self.get_valid(None) {
             self.valid_changed();
@@ -488,7 +506,7 @@ impl Session {
 }
 
 define_model_roles! {
-    enum MessageRoles for orm::AugmentedMessage {
+    pub(super) enum MessageRoles for orm::AugmentedMessage {
         Id(id):                                               "id",
         SessionId(session_id):                                "sessionId",
         Message(text via qstring_from_option):                "message",
@@ -532,9 +550,18 @@ define_model_roles! {
         Attachments(fn attachments(&self)):                   "attachments",
         Reactions(fn reactions(&self)):                       "reactions",
         IsVoiceNote(is_voice_note):                           "isVoiceNote",
+        IsViewOnce(is_view_once):                             "isViewOnce",
+        Viewed(fn is_viewed(&self)):                          "viewed", // Is the voice note or view-once media viewed by self
+
+        HasLinkPreview(fn has_link_preview(&self)):           "hasLinkPreview",
+        LinkPreviewUrl(fn link_preview_url(&self) via qstring_from_option): "linkPreviewUrl",
+        LinkPreviewTitle(fn link_preview_title(&self) via qstring_from_option): "linkPreviewTitle",
+        LinkPreviewDescription(fn link_preview_description(&self) via qstring_from_option): "linkPreviewDescription",
+        LinkPreviewThumbnail(fn link_preview_thumbnail(&self) via qstring_from_option): "linkPreviewThumbnail",
 
         IsLatestRevision(fn is_latest_revision(&self)):       "isLatestRevision",
         IsEdited(fn is_edited(&self)):                        "isEdited",
+        Starred(is_bookmarked):                               "starred",
     }
 }
 
@@ -576,22 +603,158 @@ fn body_ranges_qvariantlist(
         .collect()
 }
 
+/// The number of messages that are loaded into the conversation view at once.
+const MESSAGE_WINDOW_SIZE: i64 = 100;
+
+/// A window onto the messages of a conversation, newest first.
+///
+/// Only part of a conversation is loaded at a time. Older messages are loaded when scrolling
+/// up through `fetchMore`, and newer ones through `fetchNewer` after jumping to an older message.
 #[derive(QObject, Default)]
 pub struct MessageListModel {
     base: qt_base_class!(trait QAbstractListModel),
+    storage: Option<Storage>,
+    session_id: Option<i32>,
     messages: Vec<orm::AugmentedMessage>,
+    /// Whether the session contains messages older than the loaded ones.
+    has_older: bool,
+    /// Whether the session contains messages newer than the loaded ones.
+    has_newer: bool,
 
     findMessageIndex: qt_method!(fn(&self, messageId: i32) -> i32),
+    loadAround: qt_method!(fn(&mut self, messageId: i32) -> i32),
+    canFetchMore: qt_method!(fn(&self) -> bool),
+    fetchMore: qt_method!(fn(&mut self)),
+    canFetchNewer: qt_method!(fn(&self) -> bool),
+    fetchNewer: qt_method!(fn(&mut self)),
 }
 
 impl MessageListModel {
-    fn load_all(&mut self, storage: Storage, id: i32) {
+    /// Load the newest messages of a session.
+    fn load_newest(&mut self, storage: Storage, id: i32) {
         self.begin_reset_model();
-        self.messages = storage
-            .fetch_all_messages_augmented(id, true)
-            .into_iter()
-            .collect();
+        self.messages = storage.fetch_messages_augmented_before(id, None, MESSAGE_WINDOW_SIZE);
+        self.has_older = self.messages.len() as i64 == MESSAGE_WINDOW_SIZE;
+        self.has_newer = false;
+        self.storage = Some(storage);
+        self.session_id = Some(id);
+        self.end_reset_model();
+    }
+
+    /// Load the messages surrounding `message_id`, and return its index.
+    fn load_around(&mut self, storage: Storage, id: i32, message_id: i32) -> Option<usize> {
+        let messages = storage.fetch_messages_augmented_around(id, message_id, MESSAGE_WINDOW_SIZE);
+        let latest_revision_id = storage
+            .fetch_message_by_id(message_id)
+            .map(|message| message.latest_revision_id())?;
+        let pos = messages
+            .iter()
+            .position(|message| message.id == latest_revision_id)?;
+
+        self.begin_reset_model();
+        self.has_newer = pos as i64 == MESSAGE_WINDOW_SIZE;
+        self.has_older = (messages.len() - pos - 1) as i64 == MESSAGE_WINDOW_SIZE;
+        self.messages = messages;
+        self.storage = Some(storage);
+        self.session_id = Some(id);
         self.end_reset_model();
+        Some(pos)
+    }
+
+    /// Reload the loaded span of messages, from the newest to the oldest loaded message.
+    ///
+    /// Without newer messages, the span extends to the newest message of the session.
+    fn reload(&mut self, storage: Storage, id: i32) {
+        let (Some(newest), Some(oldest)) = (self.messages.first(), self.messages.last()) else {
+            self.load_newest(storage, id);
+            return;
+        };
+        let newest = self.has_newer.then(|| MessageCursor::from(&newest.inner));
+        let oldest = MessageCursor::from(&oldest.inner);
+        let messages = storage.fetch_messages_augmented_between(id, newest, oldest);
+        if messages.is_empty() {
+            self.load_newest(storage, id);
+            return;
+        }
+
+        self.begin_reset_model();
+        self.messages = messages;
+        self.end_reset_model();
+    }
+
+    fn canFetchMore(&self) -> bool {
+        self.has_older
+    }
+
+    fn fetchMore(&mut self) {
+        let (Some(storage), Some(id), true) =
+            (self.storage.clone(), self.session_id, self.has_older)
+        else {
+            return;
+        };
+        let cursor = self
+            .messages
+            .last()
+            .map(|message| MessageCursor::from(&message.inner));
+        let older = storage.fetch_messages_augmented_before(id, cursor, MESSAGE_WINDOW_SIZE);
+        self.has_older = older.len() as i64 == MESSAGE_WINDOW_SIZE;
+        if older.is_empty() {
+            return;
+        }
+
+        let len = self.messages.len() as i32;
+        self.begin_insert_rows(len, len + older.len() as i32 - 1);
+        self.messages.extend(older);
+        self.end_insert_rows();
+    }
+
+    fn canFetchNewer(&self) -> bool {
+        self.has_newer
+    }
+
+    fn fetchNewer(&mut self) {
+        let (Some(storage), Some(id), true) =
+            (self.storage.clone(), self.session_id, self.has_newer)
+        else {
+            return;
+        };
+        let Some(cursor) = self
+            .messages
+            .first()
+            .map(|message| MessageCursor::from(&message.inner))
+        else {
+            self.load_newest(storage, id);
+            return;
+        };
+        let newer = storage.fetch_messages_augmented_after(id, cursor, MESSAGE_WINDOW_SIZE);
+        self.has_newer = newer.len() as i64 == MESSAGE_WINDOW_SIZE;
+        if newer.is_empty() {
+            return;
+        }
+
+        self.begin_insert_rows(0, newer.len() as i32 - 1);
+        self.messages.splice(0..0, newer);
+        self.end_insert_rows();
+    }
+
+    /// Make sure the message is loaded, and return its index.
+    ///
+    /// When the message lies outside of the loaded window, the messages surrounding it are loaded
+    /// instead. Returns -1 when the message is not part of the conversation.
+    fn loadAround(&mut self, messageId: i32) -> i32 {
+        if let Some(pos) = self.messages.iter().position(|msg| msg.id == messageId) {
+            return pos as _;
+        }
+        let (Some(storage), Some(id)) = (self.storage.clone(), self.session_id) else {
+            return -1;
+        };
+        match self.load_around(storage, id, messageId) {
+            Some(pos) => pos as _,
+            None => {
+                tracing::warn!("Message id {messageId} not found in session {id}");
+                -1
+            }
+        }
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
@@ -626,6 +789,11 @@ impl MessageListModel {
                 tracing::trace!("Ignoring message insert/update for different session.");
                 return;
             }
+            if message.schedule_send_time.is_some() {
+                // Listed by the ScheduledMessagesModel until it is sent.
+                tracing::trace!("Ignoring message insert/update for a scheduled message.");
+                return;
+            }
             let pos = self.messages.binary_search_by_key(
                 &std::cmp::Reverse((message.server_timestamp, message.id)),
                 |message| std::cmp::Reverse((message.server_timestamp, message.id)),
@@ -649,6 +817,21 @@ impl MessageListModel {
                     // Don't insert old revisions.
                     tracing::debug!("Handling message edit for an old edit, no-op.");
                 }
+                Err(0) if self.has_newer => {
+                    // The message is newer than the loaded window.  Our own messages should
+                    // show up right away, so jump back to the newest messages for those.
+                    if message.is_outbound {
+                        tracing::debug!("Handling outgoing message outside window, reloading.");
+                        self.load_newest(storage, session_id);
+                    } else {
+                        tracing::trace!("Ignoring insertion newer than the loaded window.");
+                    }
+                }
+                Err(insertion_index)
+                    if insertion_index == self.messages.len() && self.has_older =>
+                {
+                    tracing::trace!("Ignoring insertion older than the loaded window.");
+                }
                 Err(insertion_index) => {
                     // Insert the message, because it's the latest revision.
                     tracing::debug!("Handling insertion event");
@@ -664,7 +847,7 @@ impl MessageListModel {
             "Falling back to reloading the whole MessageListModel for event {:?}",
             event
         );
-        self.load_all(storage, session_id);
+        self.reload(storage, session_id);
     }
 
     fn findMessageIndex(&self, messageId: i32) -> i32 {
@@ -3,6 +3,7 @@
 use crate::model::*;
 use crate::store::observer::{EventObserving, Interest};
 use crate::store::orm;
+use crate::worker::MarkIdentityVerified;
 use futures::TryFutureExt;
 use libsignal_service::ServiceIdExt;
 use libsignal_service::protocol::{Aci, DeviceId, SessionStore};
@@ -90,6 +91,16 @@ pub struct Recipient {
     )]
     session_is_post_quantum: bool,
 
+    /// The [`VerifiedStatus`](orm::VerifiedStatus) of the recipient's identity key.
+    #[qt_property(
+        READ: get_verified_status,
+        NOTIFY: recipient_changed,
+        ALIAS: verifiedStatus,
+    )]
+    verified_status: i32,
+
+    markVerified: qt_method!(fn(&self, verified: bool)),
+
     recipient_changed: qt_signal!(),
     fingerprint_changed: qt_signal!(),
 }
@@ -215,6 +226,12 @@ impl Recipient {
                 None
             };
 
+            self.verified_status = recipient
+                .as_ref()
+                .and_then(|r| r.to_aci_service_address())
+                .and_then(|aci| storage.fetch_identity_record(&aci))
+                .map(|record| record.verified_status)
+                .unwrap_or_default();
             self.recipient = recipient;
             self.recipient_changed();
 
@@ -295,6 +312,29 @@ impl Recipient {
         }
     }
 
+    fn get_verified_status(&self, _ctx: Option<ModelContext<Self>>) -> i32 {
+        self.verified_status
+    }
+
+    /// Mark the safety number as verified, or not.  Unmarking a changed key accepts it.
+    fn markVerified(&self, verified: bool) {
+        let Some(recipient_id) = self.recipient_id else {
+            tracing::error!("No recipient to verify");
+            return;
+        };
+        let client_actor = self
+            ._app
+            .as_pinned()
+            .and_then(|app| app.borrow().client_actor.borrow().clone());
+        match client_actor {
+            Some(addr) => addr.do_send(MarkIdentityVerified {
+                recipient_id,
+                verified,
+            }),
+            None => tracing::error!("ClientActor not available to verify recipient"),
+        }
+    }
+
     fn session_is_post_quantum(&self, _ctx: Option<ModelContext<Self>>) -> bool {
         const KYBER_AWARE_MESSAGE_VERSION: u32 = 4;
 
@@ -106,7 +106,7 @@ impl RustleGraph {
 
         let vizualizer = {
             // Fetch params, don't hold across await point.
-            let params = {
+            let (params, storage) = {
                 let Some(this) = this.as_pinned() else {
                     tracing::debug!("object dropped, aborting load");
                     return;
@@ -118,7 +118,17 @@ impl RustleGraph {
                     return;
                 }
 
-                this.borrow().vizualizer_params()
+                let Some(storage) = this
+                    .borrow()
+                    .app
+                    .as_pinned()
+                    .and_then(|app| app.borrow().storage.borrow().clone())
+                else {
+                    tracing::debug!("storage not ready, aborting load");
+                    return;
+                };
+
+                (this.borrow().vizualizer_params(), storage)
             };
 
             tracing::debug!(
@@ -130,7 +140,12 @@ impl RustleGraph {
             let content_type = attachment.content_type.clone();
             let filename = std::path::PathBuf::from(path.into_owned());
             let vizualizer = tokio::task::spawn_blocking(move || {
+                // The decoder needs a file, so encrypted voice notes are decrypted first.
+                let filename = storage
+                    .plaintext_attachment(&filename)
+                    .map_err(|e| format!("{e:#}"))?;
                 Vizualizer::from_file(params, Some(&content_type), &filename)
+                    .map_err(|e| e.to_string())
             })
             .await
             .expect("threadpool");
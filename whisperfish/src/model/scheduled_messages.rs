@@ -0,0 +1,169 @@
+#![allow(non_snake_case)]
+
+use std::collections::HashMap;
+
+use crate::model::*;
+use crate::store::Storage;
+use crate::store::observer::{EventObserving, Interest};
+use crate::worker::{CancelScheduledMessage, EditScheduledMessage, RescheduleMessage};
+use qmetaobject::prelude::*;
+use whisperfish_store::millis_to_naive_chrono;
+use whisperfish_store::schema;
+use whisperfish_store::store::orm;
+
+/// QML-constructable object that lists the messages of a session that are not sent yet,
+/// the first to be sent first.
+#[observing_model]
+#[derive(Default, QObject)]
+pub struct ScheduledMessages {
+    base: qt_base_class!(trait QObject),
+    session_id: Option<i32>,
+
+    #[qt_property(
+        READ: get_session_id,
+        WRITE: set_session_id,
+        NOTIFY: messages_changed,
+    )]
+    sessionId: i32,
+    #[qt_property(READ: messages, NOTIFY: messages_changed)]
+    messages: QVariant,
+    #[qt_property(READ: message_count, NOTIFY: messages_changed)]
+    count: i32,
+
+    message_list: QObjectBox<ScheduledMessageListModel>,
+
+    messages_changed: qt_signal!(),
+
+    reschedule: qt_method!(fn(&self, message_id: i32, send_time: f64)),
+    edit: qt_method!(fn(&self, message_id: i32, text: QString)),
+    cancel: qt_method!(fn(&self, message_id: i32)),
+}
+
+impl EventObserving for ScheduledMessages {
+    type Context = ModelContext<Self>;
+
+    fn observe(&mut self, ctx: Self::Context, _event: crate::store::observer::Event) {
+        if let Some(session_id) = self.session_id {
+            self.fetch(ctx.storage(), session_id);
+            self.messages_changed();
+        }
+    }
+
+    fn interests(&self) -> Vec<Interest> {
+        self.session_id
+            .into_iter()
+            .map(|id| {
+                Interest::whole_table_with_relation(
+                    schema::messages::table,
+                    schema::sessions::table,
+                    id,
+                )
+            })
+            .collect()
+    }
+}
+
+define_model_roles! {
+    enum ScheduledMessageRoles for orm::Message {
+        Id(id): "id",
+        Message(text via qstring_from_option): "message",
+        ScheduleSendTime(schedule_send_time via qdatetime_from_naive_option): "scheduleSendTime",
+    }
+}
+
+impl ScheduledMessages {
+    fn get_session_id(&self, _ctx: Option<ModelContext<Self>>) -> i32 {
+        self.session_id.unwrap_or(-1)
+    }
+
+    fn set_session_id(&mut self, ctx: Option<ModelContext<Self>>, id: i32) {
+        self.session_id = Some(id);
+        if let Some(ctx) = ctx {
+            self.fetch(ctx.storage(), id);
+        }
+    }
+
+    fn init(&mut self, ctx: ModelContext<Self>) {
+        if let Some(id) = self.session_id {
+            self.fetch(ctx.storage(), id);
+            self.messages_changed();
+        }
+    }
+
+    fn fetch(&mut self, storage: Storage, session_id: i32) {
+        self.message_list
+            .pinned()
+            .borrow_mut()
+            .load_all(storage, session_id);
+    }
+
+    fn messages(&self, _ctx: Option<ModelContext<Self>>) -> QVariant {
+        self.message_list.pinned().into()
+    }
+
+    fn message_count(&self, _ctx: Option<ModelContext<Self>>) -> i32 {
+        self.message_list.pinned().borrow().row_count()
+    }
+
+    /// Move a message to `send_time`, in milliseconds since the epoch.
+    fn reschedule(&self, message_id: i32, send_time: f64) {
+        let send_time = millis_to_naive_chrono(send_time as u64);
+        match self.client_actor() {
+            Some(addr) => addr.do_send(RescheduleMessage {
+                message_id,
+                send_time,
+            }),
+            None => tracing::error!("ClientActor not available to reschedule a message"),
+        }
+    }
+
+    fn edit(&self, message_id: i32, text: QString) {
+        let text = text.to_string();
+        match self.client_actor() {
+            Some(addr) => addr.do_send(EditScheduledMessage { message_id, text }),
+            None => tracing::error!("ClientActor not available to edit a scheduled message"),
+        }
+    }
+
+    fn cancel(&self, message_id: i32) {
+        match self.client_actor() {
+            Some(addr) => addr.do_send(CancelScheduledMessage { message_id }),
+            None => tracing::error!("ClientActor not available to cancel a scheduled message"),
+        }
+    }
+
+    fn client_actor(&self) -> Option<actix::Addr<crate::worker::ClientActor>> {
+        self._app
+            .as_pinned()
+            .and_then(|app| app.borrow().client_actor.borrow().clone())
+    }
+}
+
+#[derive(QObject, Default)]
+pub struct ScheduledMessageListModel {
+    base: qt_base_class!(trait QAbstractListModel),
+    messages: Vec<orm::Message>,
+}
+
+impl ScheduledMessageListModel {
+    #[tracing::instrument(level = "trace", skip(self, storage))]
+    fn load_all(&mut self, storage: Storage, session_id: i32) {
+        self.begin_reset_model();
+        self.messages = storage.fetch_scheduled_messages(session_id);
+        self.end_reset_model();
+    }
+}
+
+impl QAbstractListModel for ScheduledMessageListModel {
+    fn row_count(&self) -> i32 {
+        self.messages.len() as i32
+    }
+
+    fn data(&self, index: QModelIndex, role: i32) -> QVariant {
+        ScheduledMessageRoles::from(role).get(&self.messages[index.row() as usize])
+    }
+
+    fn role_names(&self) -> HashMap<i32, QByteArray> {
+        ScheduledMessageRoles::role_names()
+    }
+}
@@ -0,0 +1,120 @@
+#![allow(non_snake_case)]
+
+use std::collections::HashMap;
+
+use crate::model::*;
+use crate::store::observer::{EventObserving, Interest};
+use crate::store::{StarredChange, Storage};
+use crate::worker::StarMessage;
+use qmetaobject::prelude::*;
+use whisperfish_store::schema;
+use whisperfish_store::store::orm;
+
+/// QML-constructable object that lists the starred messages of all sessions, most recent first.
+#[observing_model]
+#[derive(Default, QObject)]
+pub struct StarredMessages {
+    base: qt_base_class!(trait QObject),
+
+    #[qt_property(READ: messages, NOTIFY: messages_changed)]
+    messages: QVariant,
+    #[qt_property(READ: message_count, NOTIFY: messages_changed)]
+    count: i32,
+
+    message_list: QObjectBox<StarredMessagesModel>,
+
+    messages_changed: qt_signal!(),
+
+    unstar: qt_method!(fn(&self, message_id: i32)),
+}
+
+impl EventObserving for StarredMessages {
+    type Context = ModelContext<Self>;
+
+    fn observe(&mut self, ctx: Self::Context, _event: crate::store::observer::Event) {
+        self.message_list
+            .pinned()
+            .borrow_mut()
+            .load_all(ctx.storage());
+        self.messages_changed();
+        self.update_interests();
+    }
+
+    fn interests(&self) -> Vec<Interest> {
+        // Newly starred messages are announced by their own event, such that only the messages
+        // that are already listed need to be watched.
+        std::iter::once(Interest::on::<StarredChange>())
+            .chain(
+                self.message_list
+                    .pinned()
+                    .borrow()
+                    .messages
+                    .iter()
+                    .map(|message| Interest::row(schema::messages::table, message.id)),
+            )
+            .collect()
+    }
+}
+
+impl StarredMessages {
+    fn init(&mut self, ctx: ModelContext<Self>) {
+        self.message_list
+            .pinned()
+            .borrow_mut()
+            .load_all(ctx.storage());
+        self.messages_changed();
+    }
+
+    fn messages(&self, _ctx: Option<ModelContext<Self>>) -> QVariant {
+        self.message_list.pinned().into()
+    }
+
+    fn message_count(&self, _ctx: Option<ModelContext<Self>>) -> i32 {
+        self.message_list.pinned().borrow().row_count()
+    }
+
+    fn unstar(&self, message_id: i32) {
+        match self.client_actor() {
+            Some(addr) => addr.do_send(StarMessage {
+                message_id,
+                starred: false,
+            }),
+            None => tracing::error!("ClientActor not available to unstar a message"),
+        }
+    }
+
+    fn client_actor(&self) -> Option<actix::Addr<crate::worker::ClientActor>> {
+        self._app
+            .as_pinned()
+            .and_then(|app| app.borrow().client_actor.borrow().clone())
+    }
+}
+
+#[derive(QObject, Default)]
+pub struct StarredMessagesModel {
+    base: qt_base_class!(trait QAbstractListModel),
+    messages: Vec<orm::AugmentedMessage>,
+}
+
+impl StarredMessagesModel {
+    #[tracing::instrument(level = "trace", skip(self, storage))]
+    fn load_all(&mut self, storage: Storage) {
+        self.begin_reset_model();
+        self.messages = storage.fetch_starred_messages_augmented();
+        self.end_reset_model();
+    }
+}
+
+impl QAbstractListModel for StarredMessagesModel {
+    fn row_count(&self) -> i32 {
+        self.messages.len() as i32
+    }
+
+    fn data(&self, index: QModelIndex, role: i32) -> QVariant {
+        MessageRoles::from(role).get(&self.messages[index.row() as usize])
+    }
+
+    fn role_names(&self) -> HashMap<i32, QByteArray> {
+        MessageRoles::role_names()
+    }
+}
@@ -0,0 +1,130 @@
+#![allow(non_snake_case)]
+
+use std::collections::HashMap;
+
+use crate::model::*;
+use crate::store::Storage;
+use crate::store::observer::{EventObserving, Interest};
+use qmetaobject::prelude::*;
+use whisperfish_store::schema;
+use whisperfish_store::store::orm;
+
+/// QML-constructable object that lists the stories that did not expire yet.
+#[observing_model]
+#[derive(Default, QObject)]
+pub struct Stories {
+    base: qt_base_class!(trait QObject),
+
+    #[qt_property(READ: stories, NOTIFY: stories_changed)]
+    stories: QVariant,
+    #[qt_property(READ: story_count, NOTIFY: stories_changed)]
+    count: i32,
+
+    story_list: QObjectBox<StoryListModel>,
+
+    stories_changed: qt_signal!(),
+}
+
+impl EventObserving for Stories {
+    type Context = ModelContext<Self>;
+
+    fn observe(&mut self, ctx: Self::Context, event: crate::store::observer::Event) {
+        // Most messages are not stories: only reload for the ones that are, or were.
+        if event.for_table(schema::messages::table) {
+            if let Some(id) = event.key().as_i32() {
+                let listed = self.story_list.pinned().borrow().contains(id);
+                let is_story = || {
+                    ctx.storage()
+                        .fetch_message_by_id(id)
+                        .is_some_and(|message| message.story_type.is_story())
+                };
+                if !listed && (event.is_delete() || !is_story()) {
+                    return;
+                }
+            }
+        }
+        self.story_list
+            .pinned()
+            .borrow_mut()
+            .load_all(ctx.storage());
+        self.stories_changed();
+    }
+
+    fn interests(&self) -> Vec<Interest> {
+        vec![
+            Interest::whole_table(schema::messages::table),
+            Interest::whole_table(schema::story_sends::table),
+        ]
+    }
+}
+
+define_model_roles! {
+    pub(super) enum StoryRoles for orm::AugmentedMessage [with offset 100] {
+        AllowsReplies(fn story_allows_replies(&self)): "allowsReplies",
+        IsTextStory(fn is_text_story(&self)):          "isTextStory",
+    }
+}
+
+impl Stories {
+    fn story_count(&self, _ctx: Option<ModelContext<Self>>) -> i32 {
+        self.story_list.pinned().borrow().row_count()
+    }
+
+    fn init(&mut self, ctx: ModelContext<Self>) {
+        self.story_list
+            .pinned()
+            .borrow_mut()
+            .load_all(ctx.storage());
+        self.stories_changed();
+    }
+
+    fn stories(&self, _ctx: Option<ModelContext<Self>>) -> QVariant {
+        self.story_list.pinned().into()
+    }
+}
+
+#[derive(QObject, Default)]
+pub struct StoryListModel {
+    base: qt_base_class!(trait QAbstractListModel),
+    stories: Vec<orm::AugmentedMessage>,
+}
+
+impl StoryListModel {
+    #[tracing::instrument(level = "trace", skip(self, storage))]
+    fn load_all(&mut self, storage: Storage) {
+        self.begin_reset_model();
+        self.stories = storage
+            .fetch_active_stories()
+            .into_iter()
+            .filter_map(|story| storage.fetch_augmented_message(story.id))
+            .collect();
+        self.end_reset_model();
+    }
+
+    fn contains(&self, message_id: i32) -> bool {
+        self.stories.iter().any(|story| story.id == message_id)
+    }
+}
+
+impl QAbstractListModel for StoryListModel {
+    fn row_count(&self) -> i32 {
+        self.stories.len() as i32
+    }
+
+    fn data(&self, index: QModelIndex, role: i32) -> QVariant {
+        const OFFSET: i32 = 100;
+        let story = &self.stories[index.row() as usize];
+        if role >= OFFSET {
+            StoryRoles::from(role - OFFSET).get(story)
+        } else {
+            MessageRoles::from(role).get(story)
+        }
+    }
+
+    fn role_names(&self) -> HashMap<i32, QByteArray> {
+        MessageRoles::role_names()
+            .into_iter()
+            .chain(StoryRoles::role_names())
+            .collect()
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::{Arc, RwLock};
+
+use crate::platform::QQmlEngine;
+use crate::store::Storage;
+use cpp::cpp;
+use qttypes::{QByteArray, QString};
+
+/// The storage, once it is opened. The provider is installed before the storage is unlocked.
+pub type StorageSlot = Arc<RwLock<Option<Storage>>>;
+
+/// Installs the `image://attachment/<percent-encoded path>` provider, which decrypts attachment
+/// files as they are loaded.
+pub fn install(app: &mut QQmlEngine, storage: StorageSlot) {
+    let storage: *mut StorageSlot = Box::leak(Box::new(storage));
+    cpp!(unsafe [app as "QQmlEngine *", storage as "void *"] {
+        app->addImageProvider(QLatin1String("attachment"), new AttachmentImageProvider(storage));
+    });
+}
+
+/// The `image://attachment` URL of an attachment file.
+pub fn attachment_url(path: &str) -> String {
+    format!(
+        "image://attachment/{}",
+        percent_encoding::utf8_percent_encode(path, percent_encoding::NON_ALPHANUMERIC)
+    )
+}
+
+cpp! {{
+    #include <QtCore/QBuffer>
+    #include <QtGui/QImageReader>
+    #include <QtQuick/QQuickImageProvider>
+
+    class AttachmentImageProvider : public QQuickImageProvider
+    {
+        void *ctx;
+    public:
+        AttachmentImageProvider(void *ctx)
+                   : QQuickImageProvider(QQuickImageProvider::Image),
+                     ctx(ctx)
+        {
+        }
+        AttachmentImageProvider(AttachmentImageProvider &other) = delete;
+
+        ~AttachmentImageProvider() {
+            rust!(WF_attachment_provider_destructor [
+                ctx: *mut StorageSlot as "void *"
+            ] {
+                // Explicit drop because of must_use
+                unsafe { drop(Box::<StorageSlot>::from_raw(ctx)); }
+            });
+        }
+
+        QImage requestImage(const QString &id, QSize *size, const QSize &requestedSize) override
+        {
+            QByteArray data;
+            QByteArray *datap = &data;
+
+            int ret = rust!(WF_read_attachment_image [
+                id : &QString as "const QString &",
+                datap : &mut QByteArray as "QByteArray *",
+                ctx : *mut StorageSlot as "void *"
+            ] -> i32 as "int" {
+                let id = id.to_string();
+                let path = match percent_encoding::percent_decode_str(&id).decode_utf8() {
+                    Ok(path) => path,
+                    Err(e) => {
+                        tracing::warn!("Could not percent-decode attachment path {}: {}", id, e);
+                        return -1;
+                    }
+                };
+
+                let storage = unsafe { ctx.as_ref().expect("no null pointers") };
+                let Some(storage) = storage.read().expect("storage lock").clone() else {
+                    tracing::warn!("Attachment image requested before the storage was opened");
+                    return -2;
+                };
+
+                match storage.read_attachment_sync(path.as_ref()) {
+                    Ok(contents) => {
+                        *datap = QByteArray::from(&contents[..]);
+                        0
+                    }
+                    Err(e) => {
+                        tracing::warn!("Could not read attachment image: {:#}", e);
+                        -3
+                    }
+                }
+            });
+
+            if (ret != 0) {
+                return QImage();
+            }
+
+            QBuffer buffer(&data);
+            QImageReader reader(&buffer);
+            reader.setAutoTransform(true);
+            QImage img = reader.read();
+
+            if (size)
+               *size = img.size();
+
+            if (!img.isNull() && requestedSize.width() > 0 && requestedSize.height() > 0) {
+                img = img.scaled(requestedSize, Qt::KeepAspectRatioByExpanding, Qt::SmoothTransformation);
+            }
+
+            return img;
+        }
+    };
+} }
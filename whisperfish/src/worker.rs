@@ -2,6 +2,7 @@ pub mod client;
 
 mod message_expiry;
 mod profile_refresh;
+mod scheduled_messages;
 mod setup;
 pub mod username;
 
@@ -4,24 +4,50 @@ pub mod migrations;
 mod attachment;
 #[cfg(feature = "calling")]
 mod call;
+mod call_log;
+mod change_number;
+mod contacts_sync;
+mod delete_for_me;
 mod early_receipt_cache;
+mod groups_service;
 mod groupv2;
+mod link_preview;
 mod linked_devices;
 mod message_expiry;
 mod profile_upload;
 pub mod resize_image;
+mod retry_receipt;
+mod scheduled_messages;
 mod service_error_ext;
+mod sticker;
+mod storage_service;
+mod story;
 mod unidentified;
+mod verified;
+mod viewed;
 #[cfg(feature = "voice-note-transcription")]
 mod voice_note_transcription;
 use service_error_ext::*;
 
+pub use self::call_log::*;
+pub use self::change_number::*;
+pub use self::contacts_sync::*;
+pub use self::delete_for_me::*;
 use self::early_receipt_cache::EarlyReceiptCache;
+pub use self::groups_service::*;
 pub use self::groupv2::*;
+pub use self::link_preview::*;
 pub use self::linked_devices::*;
 use self::migrations::MigrationCondVar;
 pub use self::profile_upload::*;
+use self::retry_receipt::*;
+pub use self::scheduled_messages::*;
+pub use self::sticker::*;
+pub use self::storage_service::*;
+pub use self::story::*;
 use self::unidentified::UnidentifiedCertificates;
+pub use self::verified::*;
+pub use self::viewed::*;
 use anyhow::anyhow;
 use attachment::FetchAttachment;
 use image::GenericImageView;
@@ -32,8 +58,9 @@ use libsignal_service::groups_v2::Role;
 use libsignal_service::libsignal_account_keys::AccountEntropyPool;
 use libsignal_service::messagepipe::Incoming;
 use libsignal_service::proto::NullMessage;
+use libsignal_service::proto::StoryContext;
 use libsignal_service::proto::SyncMessage;
-use libsignal_service::proto::data_message::{Delete, Quote};
+use libsignal_service::proto::data_message::{Delete, Quote, Sticker};
 use libsignal_service::proto::sync_message::Blocked;
 use libsignal_service::proto::sync_message::Configuration;
 use libsignal_service::proto::sync_message::Content as SyncMessageContent;
@@ -54,6 +81,7 @@ use qttypes::QVariantMap;
 use tracing_futures::Instrument;
 use uuid::Uuid;
 use whisperfish_store::TrustLevel;
+use whisperfish_store::groups::GroupV2Action;
 
 use tokio::sync::watch;
 use whisperfish_store::millis_to_naive_chrono;
@@ -67,6 +95,7 @@ use whisperfish_store::orm::shorten;
 use zkgroup::profiles::ProfileKey;
 
 use super::message_expiry::ExpiredMessagesStream;
+use super::scheduled_messages::ScheduledMessagesStream;
 use crate::config::SettingsBridge;
 use crate::gui::StorageReady;
 #[cfg(feature = "calling")]
@@ -78,6 +107,7 @@ use crate::store::AciOrPniStorage;
 use crate::store::Storage;
 use crate::store::observer::{Relation, Subject};
 use crate::store::orm::UnidentifiedAccessMode;
+use crate::store::search::{MATCH_END, MATCH_START, SearchHit, SearchQuery};
 use crate::worker::client::early_receipt_cache::CachedReceipt;
 use crate::worker::client::unidentified::CertType;
 use crate::worker::profile_refresh::ProfileUpdater;
@@ -92,7 +122,8 @@ use libsignal_service::configuration::SignalServers;
 use libsignal_service::content::DataMessageFlags;
 use libsignal_service::content::sync_message::Request as SyncRequest;
 use libsignal_service::content::{
-    ContentBody, DataMessage, GroupContextV2, Metadata, Reaction, TypingMessage, sync_message,
+    AttachmentPointer, ContentBody, DataMessage, EditMessage, GroupContextV2, Metadata, Reaction,
+    TypingMessage, sync_message,
 };
 use libsignal_service::prelude::*;
 use libsignal_service::proto::ReceiptMessage;
@@ -154,18 +185,24 @@ pub struct QueueMessage {
     pub attachments: Vec<NewAttachment>,
     pub quote: i32,
     pub is_voice_note: bool,
+    /// Pack id and sticker id of an installed sticker to send.
+    pub sticker: Option<(String, i32)>,
+    /// Holds the message back until this time.
+    pub schedule_send_time: Option<NaiveDateTime>,
 }
 
 impl Display for QueueMessage {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         write!(
             f,
-            "QueueMessage {{ session_id: {}, message: \"{}\", quote: {}, attachments: \"{:?}\", is_voice_note: {} }}",
+            "QueueMessage {{ session_id: {}, message: \"{}\", quote: {}, attachments: \"{:?}\", is_voice_note: {}, sticker: {:?}, schedule_send_time: {:?} }}",
             &self.session_id,
             shorten(&self.message, 9),
             &self.quote,
             &self.attachments,
             &self.is_voice_note,
+            &self.sticker,
+            &self.schedule_send_time,
         )
     }
 }
@@ -191,6 +228,27 @@ impl Display for QueueExpiryUpdate {
     }
 }
 
+/// Edit the text of one of our messages, and send the new revision.
+#[derive(actix::Message, Debug)]
+#[rtype(result = "()")]
+pub struct QueueEdit {
+    pub message_id: i32,
+    pub text: String,
+    pub body_ranges: Vec<crate::store::body_ranges::BodyRange>,
+}
+
+impl Display for QueueEdit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(
+            f,
+            "QueueEdit {{ message_id: {}, text: \"{}\", body_ranges: {} }}",
+            &self.message_id,
+            shorten(&self.text, 9),
+            self.body_ranges.len(),
+        )
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 /// Enqueue a message on socket by message id.
@@ -302,6 +360,8 @@ pub struct ClientWorker {
 
     #[cfg(feature = "voice-note-transcription")]
     transcribeVoiceNote: qt_method!(fn(&self, message_id: i32)),
+    #[cfg(feature = "voice-note-transcription")]
+    cancelVoiceNoteTranscription: qt_method!(fn(&self, message_id: i32)),
 
     connected: qt_property!(bool; NOTIFY connectedChanged),
     queueEmpty: qt_property!(bool; NOTIFY queueEmptyChanged),
@@ -319,6 +379,30 @@ pub struct ClientWorker {
     compact_db: qt_method!(fn(&self)),
 
     refresh_group_v2: qt_method!(fn(&self, session_id: usize)),
+    createGroupV2: qt_method!(
+        fn(
+            &self,
+            title: String,
+            description: String,
+            avatar_path: String,
+            expire_timer: u32,
+            members: String,
+        )
+    ),
+    groupV2Created: qt_signal!(session_id: i32),
+    groupV2CreationFailed: qt_signal!(),
+    previewGroupInviteLink: qt_method!(fn(&self, url: String)),
+    groupInvitePreviewed: qt_signal!(
+        title: QString,
+        description: QString,
+        member_count: u32,
+        requires_approval: bool,
+        pending_approval: bool
+    ),
+    groupInvitePreviewFailed: qt_signal!(),
+    joinGroupV2: qt_method!(fn(&self, url: String)),
+    groupV2Joined: qt_signal!(session_id: i32, pending_approval: bool),
+    groupV2JoinFailed: qt_signal!(),
 
     fetchAttachment: qt_method!(fn(&self, attachment_id: i32)),
     delete_file: qt_method!(fn(&self, file_name: String)),
@@ -338,6 +422,7 @@ pub struct ClientWorker {
 
     sendConfiguration: qt_method!(fn(&self)),
     handleMessageRequest: qt_method!(fn(&self, recipient_aci: String, action: String)),
+    handleGroupInvite: qt_method!(fn(&self, group_id: String, action: String)),
 
     updateAnnouncementsOnlyMode: qt_method!(fn(&self, group_id: String, enabled: bool)),
 }
@@ -453,15 +538,18 @@ pub struct ClientActor {
     /// GroupV2 ids for which a full `RequestGroupV2Info` refresh is already in
     /// flight, used to dedup the self-healing revision-check path.
     group_refresh_in_flight: HashSet<[u8; 32]>,
+    storage_sync_state: storage_service::StorageSyncState,
 
     start_time: DateTime<Local>,
 
     profile_updater: Option<Addr<ProfileUpdater>>,
     message_expiry_notification_handle: Option<tokio::sync::mpsc::UnboundedSender<()>>,
+    scheduled_messages_notification_handle: Option<tokio::sync::mpsc::UnboundedSender<()>>,
 
     registration_session: Option<RegistrationSessionMetadataResponse>,
 
     settings: SettingsBridge,
+    link_preview_fetcher: std::sync::Arc<dyn LinkPreviewFetcher>,
 
     #[cfg(feature = "calling")]
     call_state: Option<call::WhisperfishCallManager>,
@@ -469,7 +557,7 @@ pub struct ClientActor {
 
 fn whisperfish_device_capabilities() -> DeviceCapabilities {
     DeviceCapabilities {
-        storage: false,
+        storage: true,
         transfer: false,
         attachment_backfill: false,
         spqr: true,
@@ -604,21 +692,50 @@ impl ClientActor {
             early_receipt_cache: EarlyReceiptCache::new(),
 
             group_refresh_in_flight: Default::default(),
+            storage_sync_state: Default::default(),
 
             start_time: Local::now(),
 
             profile_updater: None,
             message_expiry_notification_handle: None,
+            scheduled_messages_notification_handle: None,
 
             registration_session: None,
 
             settings: SettingsBridge::default(),
+            link_preview_fetcher: std::sync::Arc::new(HttpLinkPreviewFetcher::default()),
 
             #[cfg(feature = "calling")]
             call_state: None,
         })
     }
 
+    /// Replace the way link previews are retrieved for outgoing messages.
+    pub fn set_link_preview_fetcher(&mut self, fetcher: std::sync::Arc<dyn LinkPreviewFetcher>) {
+        self.link_preview_fetcher = fetcher;
+    }
+
+    /// Send a newly queued message, after resizing its images, unless it is scheduled for later.
+    fn dispatch_queued_message(
+        ctx: &mut <Self as Actor>::Context,
+        message_id: i32,
+        resize_list: Vec<i32>,
+        scheduled: bool,
+    ) {
+        if resize_list.is_empty() && scheduled {
+            tracing::debug!("Scheduled SendMessage");
+        } else if resize_list.is_empty() {
+            tracing::debug!("Immediate SendMessage");
+            ctx.notify(SendMessage(message_id));
+        } else {
+            tracing::debug!("Delayed SendMessage");
+            ctx.notify(resize_image::NewAttachmentResize {
+                message_id,
+                attachment_ids: resize_list,
+            });
+        }
+    }
+
     pub fn profile_updater(&mut self) -> Addr<ProfileUpdater> {
         self.profile_updater.clone().unwrap_or_else(|| {
             ProfileUpdater::new(
@@ -940,12 +1057,6 @@ impl ClientActor {
             }
         }
 
-        if !msg.preview.is_empty() {
-            tracing::warn!(
-                "Message contains preview data, which is not yet saved nor displayed. Please upvote issue #695"
-            );
-        }
-
         // Determine the message type and text body contents.
         // Message is visibly inserted to chat if MessageType is set
         // and/or alternative (i.e. placeholder) body text is given.
@@ -979,13 +1090,10 @@ impl ClientActor {
             }
             (None, None)
         } else if let Some(sticker) = &msg.sticker {
-            tracing::warn!(
-                "Received a sticker, but they are currently unsupported. Please upvote issue #14."
-            );
             tracing::trace!("{:?}", sticker);
             (
                 Some(MessageType::Sticker),
-                Some(sticker.emoji.as_ref().unwrap().to_owned()),
+                Some(sticker.emoji.clone().unwrap_or_default()),
             )
         } else if msg.payment.is_some() {
             // TODO: Save some info about payments?
@@ -1050,6 +1158,17 @@ impl ClientActor {
                 revision: group_v2.revision(),
             };
 
+            if !is_sync_sent
+                && storage
+                    .fetch_group_by_group_v2_id(&hex::encode(
+                        store_v2.secret.get_group_identifier(),
+                    ))
+                    .is_some_and(|group| group.is_blocked)
+            {
+                tracing::debug!("Message in a blocked group, dropping it.");
+                return false;
+            }
+
             let group_existed = storage.group_v2_exists(&store_v2);
             let session = storage.fetch_or_insert_session_by_group_v2(&store_v2);
 
@@ -1136,6 +1255,15 @@ impl ClientActor {
             session
         };
 
+        // With calling support, group calls show up through the call log instead.
+        #[cfg(feature = "calling")]
+        if msg.group_call_update.is_some() && session.is_group_v2() {
+            ctx.notify(PeekGroupCall {
+                session_id: session.id,
+            });
+            return is_valid;
+        }
+
         // Make sure attachment message without text gets inserted
         if !msg.attachments.is_empty() && alt_body.is_none() {
             alt_body = Some("".into());
@@ -1188,6 +1316,12 @@ impl ClientActor {
             msg.timestamp()
         });
 
+        let parent_story_id = msg
+            .story_context
+            .as_ref()
+            .and_then(|context| self.fetch_story_for_context(context))
+            .map(|story| story.id);
+
         let new_message = crate::store::NewMessage {
             source_addr,
             text: body.to_owned(),
@@ -1209,6 +1343,9 @@ impl ClientActor {
             server_guid: metadata.server_guid,
             body_ranges,
             message_type,
+            parent_story_id,
+            is_view_once: msg.is_view_once(),
+            schedule_send_time: None,
 
             edit: original_message.as_ref(),
         };
@@ -1253,6 +1390,21 @@ impl ClientActor {
             }
         }
 
+        if let Some(sticker) = &msg.sticker {
+            // A sticker is the message itself, so it is always fetched.
+            if let Some(attachment_id) = storage.register_sticker_attachment(message.id, sticker) {
+                ctx.notify(FetchAttachment { attachment_id });
+            }
+        }
+
+        for preview in &msg.preview {
+            // Thumbnails are small, and part of how the message is displayed,
+            // so they are always fetched.
+            if let Some(attachment_id) = storage.register_link_preview(message.id, preview) {
+                ctx.notify(FetchAttachment { attachment_id });
+            }
+        }
+
         self.inner
             .pinned()
             .borrow_mut()
@@ -1314,18 +1466,41 @@ impl ClientActor {
                         let mut db = storage.db();
                         recipients.load(&mut *db)?
                     };
+                    // The direct conversations, by recipient, with their position in the inbox.
+                    let inbox: HashMap<i32, (u32, orm::Session)> = storage
+                        .fetch_all_sessions_augmented()
+                        .into_iter()
+                        .enumerate()
+                        .filter_map(|(position, session)| match &session.inner.r#type {
+                            SessionType::DirectMessage(recipient) => {
+                                Some((recipient.id, (position as u32, session.inner.clone())))
+                            }
+                            _ => None,
+                        })
+                        .collect();
 
-                    let contacts = recipients.into_iter().map(|recipient| {
+                    let contacts = recipients
+                        .into_iter()
+                        .filter(|recipient| recipient.uuid.is_some() || recipient.e164.is_some())
+                        .map(|recipient| {
+                            let session = inbox.get(&recipient.id);
                             ContactDetails {
-                                // XXX: expire timer from dm session
                                 number: recipient.e164.as_ref().map(PhoneNumber::to_string),
                                 aci: recipient.uuid.as_ref().map(Uuid::to_string),
                                 aci_binary: recipient.uuid.as_ref().map(Uuid::as_bytes).map(Vec::from),
                                 name: recipient.profile_joined_name.clone(),
-                                // XXX other profile stuff
+                                expire_timer: session.and_then(|(_, session)| {
+                                    session.expiring_message_timeout.map(|t| t.as_secs() as u32)
+                                }),
+                                expire_timer_version: session
+                                    .map(|(_, session)| session.expire_timer_version as u32),
+                                inbox_position: session.map(|(position, _)| *position),
+                                // Avatars would have to follow the details in the stream,
+                                // which the sender does not support.
+                                avatar: None,
                                 ..Default::default()
                             }
-                    });
+                        });
 
                     sender.send_contact_details(&local_addr.into(), None, contacts, false, true).await?;
                 },
@@ -1346,7 +1521,7 @@ impl ClientActor {
                         numbers: storage.fetch_blocked_numbers().into_iter().map(|e| e.to_string()).collect_vec(),
                         acis: blocked_acis.iter().map(|e| e.to_string()).collect_vec(),
                         acis_binary: blocked_acis.iter().map(|e| e.as_bytes().to_vec()).collect_vec(),
-                        group_ids: Vec::new(), // Group V1
+                        group_ids: storage.fetch_blocked_group_v2_ids(),
                     };
                     sender.send_sync_message(blocked).await?;
                 }
@@ -1406,11 +1581,25 @@ impl ClientActor {
             }
             true
         } else if let Some(group_id) = &response.group_id {
-            tracing::warn!(
-                "Group message request responses are not yet implemented. {:?}. Please upvote bug #327",
-                group_id
-            );
-            false
+            // Our other device leaves the group itself; we learn about that through the group.
+            let group_id = hex::encode(group_id);
+            match response.r#type() {
+                MessageRequestAction::Accept => storage.mark_group_v2_blocked(&group_id, false),
+                MessageRequestAction::Block
+                | MessageRequestAction::BlockAndDelete
+                | MessageRequestAction::BlockAndSpam => {
+                    storage.mark_group_v2_blocked(&group_id, true)
+                }
+                _ => {
+                    tracing::warn!(
+                        "unhandled response type {:?} for group {}",
+                        response.r#type(),
+                        group_id
+                    );
+                    return false;
+                }
+            }
+            true
         } else {
             tracing::warn!(
                 "Unhandle message request response: {:?}. Please upvote bug #324",
@@ -1475,6 +1664,9 @@ impl ClientActor {
         }
     }
 
+    /// Handles a decrypted message.
+    ///
+    /// `updated_pni` is our new PNI, set by the server on the envelope that carries a number change.
     #[tracing::instrument(
         level = "debug",
         skip(self, ctx),
@@ -1483,6 +1675,7 @@ impl ClientActor {
     fn process_envelope(
         &mut self,
         Content { body, metadata }: Content,
+        updated_pni: Option<Pni>,
         ctx: &mut <Self as Actor>::Context,
     ) {
         let storage = self.storage.clone().expect("storage initialized");
@@ -1493,7 +1686,11 @@ impl ClientActor {
                 tracing::trace!("Ignoring NullMessage");
             }
             ContentBody::DecryptionErrorMessage(message) => {
-                tracing::error!(?message, "ignoring decryption error");
+                ctx.notify(RetryReceipt {
+                    sender: metadata.sender,
+                    sender_device: metadata.sender_device,
+                    message,
+                });
             }
             ContentBody::DataMessage(message) => {
                 let is_valid = self.handle_message(
@@ -1569,12 +1766,12 @@ impl ClientActor {
                     }
                 }
                 if !message.viewed.is_empty() {
-                    tracing::error!("SyncMessage viewed is not implemented");
-                    tracing::debug!("{:?}", message.viewed);
+                    tracing::trace!("SyncMessage viewed");
+                    self.handle_sync_viewed(&message.viewed);
                 }
                 if !message.sticker_pack_operation.is_empty() {
-                    tracing::error!("SyncMessage sticker pack operation is not implemented");
-                    tracing::debug!("{:?}", message.sticker_pack_operation);
+                    tracing::trace!("SyncMessage sticker pack operation");
+                    self.handle_sticker_pack_operations(ctx, message.sticker_pack_operation);
                 }
                 let Some(content) = message.content else {
                     return;
@@ -1584,7 +1781,8 @@ impl ClientActor {
                         tracing::trace!("SyncMessage sent");
                         // These are messages sent through a paired device.
                         let address = sent.parse_destination_service_id();
-                        if address.is_none() {
+                        // Stories are sent to many recipients, they have no destination.
+                        if address.is_none() && sent.story_message.is_none() {
                             tracing::error!(
                                 "Unparsable ServiceId: {:?}/{:?}",
                                 sent.destination_service_id,
@@ -1633,8 +1831,7 @@ impl ClientActor {
                                 Some(millis_to_naive_chrono(edit)),
                             );
                         } else if let Some(story_message) = &sent.story_message {
-                            tracing::error!("SyncMessage story message is not implemented (#580)");
-                            tracing::trace!("{story_message:?}");
+                            self.handle_sync_sent_story(ctx, &sent, story_message);
                         } else {
                             tracing::error!("SyncMessage sent with unhandled content: {sent:?}");
                         }
@@ -1644,31 +1841,27 @@ impl ClientActor {
                         self.handle_sync_request(metadata, request);
                     }
                     SyncMessageContent::ViewOnceOpen(opened) => {
-                        tracing::error!("SyncMessage view once open is not implemented");
-                        tracing::debug!("{opened:?}");
+                        tracing::trace!("SyncMessage view once open");
+                        self.handle_view_once_open(&opened);
                     }
-                    SyncMessageContent::FetchLatest(fetch) => {
-                        match fetch.r#type() {
-                            LatestType::Unknown => {
-                                tracing::error!("SyncMessage fetch latest unknown is unimplemented")
-                            }
-                            LatestType::LocalProfile => {
-                                tracing::trace!("Scheduling local profile refresh");
-                                ctx.notify(RefreshOwnProfile { force: true });
-                            }
-                            LatestType::StorageManifest => {
-                                // XXX
-                                tracing::error!(
-                                    "SyncMessage fetch latest storage manifest is unimplemented"
-                                )
-                            }
-                            LatestType::SubscriptionStatus => {
-                                tracing::error!(
-                                    "SyncMessage fetch latest subscription status is unimplemented"
-                                )
-                            }
+                    SyncMessageContent::FetchLatest(fetch) => match fetch.r#type() {
+                        LatestType::Unknown => {
+                            tracing::error!("SyncMessage fetch latest unknown is unimplemented")
                         }
-                    }
+                        LatestType::LocalProfile => {
+                            tracing::trace!("Scheduling local profile refresh");
+                            ctx.notify(RefreshOwnProfile { force: true });
+                        }
+                        LatestType::StorageManifest => {
+                            tracing::trace!("Scheduling storage service sync");
+                            ctx.notify(SyncStorageService);
+                        }
+                        LatestType::SubscriptionStatus => {
+                            tracing::error!(
+                                "SyncMessage fetch latest subscription status is unimplemented"
+                            )
+                        }
+                    },
                     SyncMessageContent::MessageRequestResponse(response) => {
                         self.handle_message_request_response(&response);
                     }
@@ -1688,7 +1881,7 @@ impl ClientActor {
                                     let storage_key =
                                         StorageServiceKey::from_master_key(&master_key);
                                     storage.store_storage_service_key(Some(&storage_key));
-                                    // XXX Storage Service Manifest
+                                    ctx.notify(SyncStorageService);
                                 }
                                 Err(e) => {
                                     // XXX Send SyncMessage::Keys request later?
@@ -1714,21 +1907,41 @@ impl ClientActor {
                             };
                             storage.mark_recipient_blocked_by_address(&service_id);
                         }
-                        if !blocked.group_ids.is_empty() {
-                            tracing::error!("Blocking groups is not implemented");
+                        // The message lists all blocked groups, so the others are unblocked.
+                        for group_id in storage.fetch_blocked_group_v2_ids() {
+                            if !blocked.group_ids.contains(&group_id) {
+                                storage.mark_group_v2_blocked(&hex::encode(group_id), false);
+                            }
+                        }
+                        for group_id in blocked.group_ids {
+                            if group_id.len() != zkgroup::GROUP_IDENTIFIER_LEN {
+                                tracing::warn!(
+                                    "Sync blocked: ignoring group of {} bytes",
+                                    group_id.len()
+                                );
+                                continue;
+                            }
+                            storage.mark_group_v2_blocked(&hex::encode(group_id), true);
                         }
                     }
-                    SyncMessageContent::Contacts(contacts) => {
-                        tracing::error!("SyncMessage contacts is not implemented");
-                        tracing::debug!("{contacts:?}");
-                    }
+                    SyncMessageContent::Contacts(contacts) => match contacts.blob {
+                        Some(blob) => ctx.notify(ImportContacts { blob }),
+                        None => tracing::warn!("Contacts sync message without blob"),
+                    },
                     SyncMessageContent::Verified(verified) => {
-                        tracing::error!("SyncMessage verified is not implemented");
-                        tracing::debug!("{verified:?}");
+                        self.handle_sync_verified(&verified);
                     }
                     SyncMessageContent::PniChangeNumber(pni_change_number) => {
-                        tracing::error!("SyncMessage pni change number is not implemented");
-                        tracing::debug!("{pni_change_number:?}");
+                        if is_primary {
+                            tracing::warn!("Ignoring number change sync on primary device");
+                        } else if let Some(pni) = updated_pni {
+                            ctx.notify(ChangeNumber {
+                                change: pni_change_number,
+                                pni,
+                            });
+                        } else {
+                            tracing::warn!("Ignoring number change sync without updated PNI");
+                        }
                     }
                     SyncMessageContent::Configuration(conf) => {
                         let mut settings = SettingsBridge::default();
@@ -1752,20 +1965,20 @@ impl ClientActor {
                         tracing::debug!("{payment:?}");
                     }
                     SyncMessageContent::DeleteForMe(delete) => {
-                        tracing::error!("SyncMessage delete for me is not implemented");
-                        tracing::debug!("{delete:?}");
+                        tracing::trace!("Sync delete for me: {delete:?}");
+                        self.handle_delete_for_me(delete);
                     }
                     SyncMessageContent::CallEvent(event) => {
-                        tracing::error!("SyncMessage call event is not implemented");
-                        tracing::debug!("{event:?}");
+                        tracing::trace!("Sync call event: {event:?}");
+                        self.handle_sync_call_event(&event);
                     }
                     SyncMessageContent::CallLinkUpdate(update) => {
                         tracing::error!("SyncMessage call link update is not implemented");
                         tracing::debug!("{update:?}");
                     }
                     SyncMessageContent::CallLogEvent(event) => {
-                        tracing::error!("SyncMessage call log event is not implemented");
-                        tracing::debug!("{event:?}");
+                        tracing::trace!("Sync call log event: {event:?}");
+                        self.handle_sync_call_log_event(&event);
                     }
                     SyncMessageContent::AttachmentBackfillRequest(attachment_backfill_request) => {
                         tracing::error!(
@@ -1870,9 +2083,33 @@ impl ClientActor {
                         }
                     }
                     ReceiptType::Viewed => {
-                        tracing::warn!(
-                            "Viewed receipts are not yet implemented. Please upvote issue #670"
-                        );
+                        if self.settings.get_enable_read_receipts() {
+                            tracing::debug!(
+                                "{:?} viewed {} message(s)",
+                                metadata.sender.service_id_string(),
+                                timestamps.len(),
+                            );
+
+                            let updated = storage.mark_messages_viewed(
+                                metadata.sender,
+                                timestamps,
+                                rcpt_timestamp,
+                            );
+
+                            for updated in updated {
+                                // Remove matched timestamps from cache
+                                self.early_receipt_cache
+                                    .take(naive_chrono_to_millis(updated.timestamp));
+
+                                // Notify UI
+                                self.inner
+                                    .pinned()
+                                    .borrow_mut()
+                                    .messageReceipt(updated.session_id, updated.message_id)
+                            }
+                        } else {
+                            tracing::debug!("Ignoring DeliveryMessage(Viewed)");
+                        }
                     }
                 }
             }
@@ -1887,8 +2124,7 @@ impl ClientActor {
                 }
             }
             ContentBody::StoryMessage(story) => {
-                tracing::error!("Received a Story, which is not yet implemented (#580)");
-                tracing::trace!("{story:?}");
+                self.handle_story_message(ctx, &metadata, story);
             }
             ContentBody::PniSignatureMessage(pni) => {
                 tracing::error!("Received a PniSignatureMessage, which is not yet implemented.");
@@ -1974,17 +2210,49 @@ impl Handler<QueueMessage> for ClientActor {
         } else {
             None
         };
+        // Quoting a story is replying to it.
+        let (quote, parent_story) = match quote {
+            Some(quote) if quote.story_type.is_story() => (None, Some(quote)),
+            quote => (quote, None),
+        };
+
+        let sticker = match &msg.sticker {
+            Some((pack_id, sticker_id)) => {
+                let Some(sticker) = storage.fetch_sticker(pack_id, *sticker_id) else {
+                    tracing::error!(%pack_id, sticker_id, "Sticker is not installed, not sending");
+                    return;
+                };
+                Some(sticker)
+            }
+            None => None,
+        };
 
         let inserted_msg = storage.create_message(&crate::store::NewMessage {
             session_id: msg.session_id,
             source_addr: storage.fetch_self_service_address_aci(),
-            text: msg.message,
+            text: match &sticker {
+                Some(sticker) => sticker.emoji.clone(),
+                None => msg.message,
+            },
             quote_timestamp: quote.map(|msg| naive_chrono_to_millis(msg.server_timestamp)),
             expires_in: session.expiring_message_timeout,
             expire_timer_version: session.expire_timer_version,
+            message_type: sticker.as_ref().map(|_| MessageType::Sticker),
+            parent_story_id: parent_story.map(|story| story.id),
+            schedule_send_time: msg.schedule_send_time,
             ..crate::store::NewMessage::new_outgoing()
         });
 
+        if let Some(sticker) = &sticker {
+            // Stickers are sent as-is; they are not resized.
+            let dir = PathBuf::from(self.settings.get_string("attachment_dir"));
+            if let Err(e) = storage.insert_local_sticker_attachment(inserted_msg.id, sticker, &dir)
+            {
+                tracing::error!("Could not attach sticker: {e:#}");
+                return;
+            }
+        }
+
         let mut resize_list = vec![];
 
         for attachment in &msg.attachments {
@@ -1998,7 +2266,6 @@ impl Handler<QueueMessage> for ClientActor {
             let resizeable = attachment.mime_type.eq("image/jpeg")
                 || attachment.mime_type.eq("image/png")
                 || attachment.mime_type.eq("image/jpg");
-            // XXX What about stickers? We must ignore them here.
             if resizeable {
                 resize_list.push(att_id);
             }
@@ -2011,6 +2278,7 @@ impl Handler<QueueMessage> for ClientActor {
             if self.settings.get_transcribe_voice_notes() {
                 ctx.notify(voice_note_transcription::TranscribeVoiceNote {
                     message_id: inserted_msg.id,
+                    language: None,
                 });
             }
         }
@@ -2019,16 +2287,52 @@ impl Handler<QueueMessage> for ClientActor {
             h.send(()).expect("send message expiry notification");
         }
 
-        if resize_list.is_empty() {
-            tracing::debug!("Immediate SendMessage");
-            ctx.notify(SendMessage(inserted_msg.id));
-        } else {
-            tracing::debug!("Delayed SendMessage");
-            ctx.notify(resize_image::NewAttachmentResize {
-                message_id: inserted_msg.id,
-                attachment_ids: resize_list,
-            });
+        if msg.schedule_send_time.is_some()
+            && let Some(h) = self.scheduled_messages_notification_handle.as_ref()
+        {
+            h.send(()).expect("send scheduled messages notification");
         }
+
+        let scheduled = msg.schedule_send_time.is_some();
+        let preview_url = self
+            .settings
+            .get_enable_link_previews()
+            .then(|| {
+                link_preview_url(
+                    &inserted_msg,
+                    sticker.is_some() || !msg.attachments.is_empty(),
+                )
+            })
+            .flatten();
+        let Some(url) = preview_url else {
+            Self::dispatch_queued_message(ctx, inserted_msg.id, resize_list, scheduled);
+            return;
+        };
+
+        tracing::debug!("Generating link preview before sending");
+        let mut storage = storage.clone();
+        let fetcher = self.link_preview_fetcher.clone();
+        let attachment_dir = PathBuf::from(self.settings.get_string("attachment_dir"));
+        let message_id = inserted_msg.id;
+        ctx.spawn(
+            async move {
+                if let Err(e) = generate_link_preview(
+                    &mut storage,
+                    fetcher.as_ref(),
+                    message_id,
+                    &url,
+                    &attachment_dir,
+                )
+                .await
+                {
+                    tracing::warn!("Could not generate a link preview, sending without: {e:#}");
+                }
+            }
+            .into_actor(self)
+            .map(move |(), _act, ctx| {
+                Self::dispatch_queued_message(ctx, message_id, resize_list, scheduled)
+            }),
+        );
     }
 }
 
@@ -2105,6 +2409,43 @@ impl Handler<QueueExpiryUpdate> for ClientActor {
     }
 }
 
+impl Handler<QueueEdit> for ClientActor {
+    type Result = ();
+
+    fn handle(&mut self, edit: QueueEdit, ctx: &mut Self::Context) -> Self::Result {
+        let _span = tracing::trace_span!("QueueEdit", %edit).entered();
+        let storage = self.storage.as_mut().unwrap();
+
+        let (original, latest) =
+            match storage.fetch_editable_message(edit.message_id, Utc::now().naive_utc()) {
+                Ok(x) => x,
+                Err(e) => {
+                    tracing::error!("Cannot edit message: {e:#}");
+                    return;
+                }
+            };
+
+        let quote_timestamp = latest
+            .quote_id
+            .and_then(|id| storage.fetch_message_by_id(id))
+            .map(|quote| naive_chrono_to_millis(quote.server_timestamp));
+        let inserted_msg = storage.create_message(&crate::store::NewMessage {
+            session_id: original.session_id,
+            source_addr: storage.fetch_self_service_address_aci(),
+            text: edit.text,
+            quote_timestamp,
+            expires_in: latest.expires_in.map(|x| Duration::from_secs(x as u64)),
+            expire_timer_version: latest.expire_timer_version,
+            body_ranges: crate::store::body_ranges::serialize_local(edit.body_ranges),
+            // Edits always refer to the original message.
+            edit: Some(&original),
+            ..crate::store::NewMessage::new_outgoing()
+        });
+
+        ctx.notify(SendMessage(inserted_msg.id));
+    }
+}
+
 impl Handler<SendMessage> for ClientActor {
     type Result = ResponseActFuture<Self, ()>;
 
@@ -2112,7 +2453,7 @@ impl Handler<SendMessage> for ClientActor {
     fn handle(&mut self, SendMessage(mid): SendMessage, ctx: &mut Self::Context) -> Self::Result {
         let _span = tracing::info_span!("ClientActor::SendMessage", message_id = mid).entered();
         let sender = self.message_sender();
-        let storage = self.storage.as_mut().unwrap().clone();
+        let mut storage = self.storage.as_mut().unwrap().clone();
         let msg = storage.fetch_augmented_message(mid).unwrap();
         let session = storage.fetch_session_by_id(msg.session_id).unwrap();
         let session_id = session.id;
@@ -2121,6 +2462,10 @@ impl Handler<SendMessage> for ClientActor {
             tracing::warn!("Message already sent, refusing to retransmit.");
             return Box::pin(async {}.into_actor(self).map(|_, _, _| ()));
         }
+        if let Some(schedule_send_time) = msg.schedule_send_time {
+            tracing::debug!(%schedule_send_time, "Message is scheduled for later, not sending yet.");
+            return Box::pin(async {}.into_actor(self).map(|_, _, _| ()));
+        }
 
         tracing::trace!("Sending for session: {}", session);
         tracing::trace!("Sending message: {}", msg.inner);
@@ -2170,10 +2515,29 @@ impl Handler<SendMessage> for ClientActor {
                         }
                     });
 
+                let story_context = msg
+                    .parent_story_id
+                    .and_then(|story_id| storage.fetch_message_by_id(story_id))
+                    .map(|story| {
+                        let author = if story.is_outbound {
+                            Some(Uuid::from(self_addr))
+                        } else {
+                            story
+                                .sender_recipient_id
+                                .and_then(|x| storage.fetch_recipient_by_id(x))
+                                .and_then(|r| r.uuid)
+                        };
+                        StoryContext {
+                            author_aci: author.as_ref().map(Uuid::to_string),
+                            author_aci_binary: author.as_ref().map(Uuid::as_bytes).map(Vec::from),
+                            sent_timestamp: Some(naive_chrono_to_millis(story.server_timestamp)),
+                        }
+                    });
+
                 let mut content = DataMessage {
-                    // Don't send body in "contol messages"
+                    // Don't send body in "contol messages", nor with stickers
                     body: match msg.flags {
-                        0 => msg.text.clone(),
+                        0 if msg.message_type != Some(MessageType::Sticker) => msg.text.clone(),
                         _ => None,
                     },
                     flags: if msg.flags != 0 {
@@ -2188,6 +2552,7 @@ impl Handler<SendMessage> for ClientActor {
 
                     profile_key: storage.fetch_self_recipient_profile_key(),
                     quote,
+                    story_context,
                     expire_timer: if msg.expire_timer_version > 0 {
                         msg.expires_in.map(|x| x as u32).or(Some(0))
                     } else {
@@ -2198,6 +2563,20 @@ impl Handler<SendMessage> for ClientActor {
                     ..Default::default()
                 };
 
+                // The original message of an edit, its attachments are sent along.
+                let edit_target = msg
+                    .original_message_id
+                    .filter(|&id| id != msg.id)
+                    .and_then(|id| storage.fetch_message_by_id(id));
+                if let Some(original) = &edit_target {
+                    for attachment in storage.fetch_attachments_for_message(original.id) {
+                        match attachment.pointer.as_deref().map(AttachmentPointer::decode) {
+                            Some(Ok(ptr)) => content.attachments.push(ptr),
+                            _ => tracing::warn!("Attachment {} was not uploaded, not sending it along with the edit", attachment.id),
+                        }
+                    }
+                }
+
                 let attachments = storage.fetch_attachments_for_message(msg.id);
 
                 for mut attachment in attachments {
@@ -2205,7 +2584,7 @@ impl Handler<SendMessage> for ClientActor {
                         .absolute_attachment_path()
                         .expect("attachment path when uploading");
                     let contents =
-                        tokio::fs::read(attachment_path.as_ref())
+                        storage.read_attachment(&*attachment_path)
                             .await
                             .context("reading attachment")?;
 
@@ -2262,9 +2641,40 @@ impl Handler<SendMessage> for ClientActor {
                         }
                     };
                     storage.store_attachment_pointer(attachment.id, &ptr);
-                    content.attachments.push(ptr);
+                    match attachment.sticker_pack_id.as_deref().map(hex::decode) {
+                        Some(Ok(pack_id)) => {
+                            content.sticker = Some(Sticker {
+                                pack_id: Some(pack_id),
+                                pack_key: attachment.sticker_pack_key,
+                                sticker_id: attachment.sticker_id.map(|x| x as u32),
+                                data: Some(ptr),
+                                emoji: attachment.sticker_emoji,
+                            });
+                        }
+                        Some(Err(e)) => {
+                            tracing::warn!("Invalid sticker pack id for attachment {}, sending it as a plain attachment: {e}", attachment.id);
+                            content.attachments.push(ptr);
+                        }
+                        None => content.attachments.push(ptr),
+                    }
+                }
+
+                match link_preview_for_message(&mut sender, &mut storage, &msg).await {
+                    Ok(Some(preview)) => content.preview.push(preview),
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!("Could not send the link preview, sending without: {e:#}"),
                 }
 
+                // Our other devices get the transcript of the edit from the message sender.
+                let content: ContentBody = match &edit_target {
+                    Some(original) => EditMessage {
+                        target_sent_timestamp: Some(naive_chrono_to_millis(original.server_timestamp)),
+                        data_message: Some(content),
+                    }
+                    .into(),
+                    None => content.into(),
+                };
+
                 let res = addr
                     .send(DeliverMessage {
                         content,
@@ -2327,6 +2737,7 @@ impl Handler<SendMessage> for ClientActor {
                         } else {
                             storage.fail_message(mid);
                             let result_count = results.len();
+                            let mut resend = false;
                             for error in results.into_iter().filter_map(Result::err) {
                                 tracing::error!("Could not deliver message: {}", error);
                                 match error {
@@ -2357,11 +2768,19 @@ impl Handler<SendMessage> for ClientActor {
 
                                         tracing::trace!("Removed {} device session(s)", num);
                                     },
+                                    MessageSenderError::UntrustedIdentity { address } => {
+                                        // Only the new key of a verified contact needs confirmation.
+                                        resend |= storage.mark_identity_key_changed(&address);
+                                    },
                                     _ => {
                                         tracing::error!("The above error goes unhandled.");
                                     }
                                 };
                             }
+                            if resend {
+                                tracing::info!("Sending again to the new identity key");
+                                addr.do_send(SendMessage(mid));
+                            }
                             tracing::error!("Successfully delivered message to {} out of {} recipients", successes, result_count);
                             anyhow::bail!("Could not deliver message.")
                         }
@@ -2809,6 +3228,7 @@ impl<T: Into<ContentBody>> Handler<DeliverMessage<T>> for ClientActor {
             for_story,
         } = msg;
         let content = content.into();
+        let send_log = send_log_content(&content);
 
         tracing::trace!("Transmitting {:?} with timestamp {}", content, timestamp);
 
@@ -2905,6 +3325,9 @@ impl<T: Into<ContentBody>> Handler<DeliverMessage<T>> for ClientActor {
                     ]
                 }
             };
+            if let Some(send_log) = send_log {
+                log_sent_message(&storage, timestamp, &send_log, &results);
+            }
             Ok(results)
         })
     }
@@ -3010,6 +3433,17 @@ impl Handler<Restart> for ClientActor {
             self.message_expiry_notification_handle = Some(message_expiry_notification_handle);
         }
 
+        if self.scheduled_messages_notification_handle.is_none() {
+            let (scheduled_messages_notification_handle, scheduled_messages_notification) =
+                tokio::sync::mpsc::unbounded_channel();
+            ctx.add_stream(ScheduledMessagesStream::new(
+                self.storage.clone().unwrap(),
+                scheduled_messages_notification,
+            ));
+            self.scheduled_messages_notification_handle =
+                Some(scheduled_messages_notification_handle);
+        }
+
         if let Some(handle) = self.message_stream_handle.take() {
             ctx.cancel_future(handle);
         }
@@ -3050,6 +3484,7 @@ impl Handler<Restart> for ClientActor {
                         unidentified::RotateUnidentifiedCertificates,
                         Duration::from_secs(10),
                     );
+                    ctx.notify_later(SyncStorageService, Duration::from_secs(15));
                     act.message_stream_handle = Some(
                         ctx.add_stream(
                             pipe.stream()
@@ -3168,9 +3603,16 @@ impl StreamHandler<Result<Incoming, ServiceError>> for ClientActor {
                 Some(service_id.to_protocol_address(DeviceId::try_from(device_id).ok()?))
             });
 
+        let updated_pni = msg
+            .updated_pni
+            .as_deref()
+            .and_then(|pni| Uuid::parse_str(pni.trim_start_matches("PNI:")).ok())
+            .map(Pni::from);
+
         let mut cipher = self.cipher(incoming_address.kind());
 
         let storage = self.storage.clone().expect("initialized storage");
+        let identity = incoming_address.kind();
 
         self.initial_queue_process_state.observe_guid(guid);
 
@@ -3200,7 +3642,16 @@ impl StreamHandler<Result<Incoming, ServiceError>> for ClientActor {
                             };
 
                             let _span = tracing::warn_span!("handling NoSenderKeyState", %distribution_id, authenticated_sender=%sender).entered();
-                            let _ = this.send(ResetSession::Device(sender)).await;
+                            // The retry receipt archives the session itself; only without one is
+                            // the session reset.
+                            match retry_receipt(&msg, sender.device_id()) {
+                                Some(message) => {
+                                    let _ = this.send(RequestResend { identity, sender, message }).await;
+                                }
+                                None => {
+                                    let _ = this.send(ResetSession::Device(sender)).await;
+                                }
+                            }
 
                             tracing::info!("dropping envelope");
                             break None;
@@ -3213,7 +3664,16 @@ impl StreamHandler<Result<Incoming, ServiceError>> for ClientActor {
                             sender: Some(sender),
                         })) => {
                             let _span = tracing::warn_span!("handling NoSenderKeyState", %distribution_id, sealed_sender=%sender).entered();
-                            let _ = this.send(ResetSession::Device(sender)).await;
+                            // The retry receipt archives the session itself; only without one is
+                            // the session reset.
+                            match retry_receipt(&msg, sender.device_id()) {
+                                Some(message) => {
+                                    let _ = this.send(RequestResend { identity, sender, message }).await;
+                                }
+                                None => {
+                                    let _ = this.send(ResetSession::Device(sender)).await;
+                                }
+                            }
 
                             tracing::info!("dropping envelope");
                             break None;
@@ -3232,7 +3692,16 @@ impl StreamHandler<Result<Incoming, ServiceError>> for ClientActor {
                             sender: Some(_),
                         }))  => {
                             let _span = tracing::warn_span!("session not found", %sender).entered();
-                            let _ = this.send(ResetSession::Device(sender)).await;
+                            // The retry receipt archives the session itself; only without one is
+                            // the session reset.
+                            match retry_receipt(&msg, sender.device_id()) {
+                                Some(message) => {
+                                    let _ = this.send(RequestResend { identity, sender, message }).await;
+                                }
+                                None => {
+                                    let _ = this.send(ResetSession::Device(sender)).await;
+                                }
+                            }
 
                             tracing::info!("dropping envelope");
                             break None;
@@ -3272,6 +3741,33 @@ impl StreamHandler<Result<Incoming, ServiceError>> for ClientActor {
                                 break None;
                             }
                         }
+                        // Ask the sender to resend messages we failed to decrypt.
+                        Err(ServiceError::SignalProtocolError(SignalProtocolError::InvalidMessage(original_type, reason))) => {
+                            let Some(Ok(sender)) = sender_address else {
+                                tracing::warn!(?original_type, reason, "invalid message without valid clear-text sender");
+                                break None;
+                            };
+
+                            let _span = tracing::warn_span!("invalid message", ?original_type, reason, authenticated_sender=%sender).entered();
+                            if let Some(message) = retry_receipt(&msg, sender.device_id()) {
+                                let _ = this.send(RequestResend { identity, sender, message }).await;
+                            }
+
+                            tracing::info!("dropping envelope");
+                            break None;
+                        }
+                        Err(ServiceError::SealedSenderDecryptionError(SealedSenderDecryptionError {
+                            inner: SignalProtocolError::InvalidMessage(original_type, reason),
+                            sender: Some(sender),
+                        })) => {
+                            let _span = tracing::warn_span!("invalid message", ?original_type, reason, sealed_sender=%sender).entered();
+                            if let Some(message) = retry_receipt(&msg, sender.device_id()) {
+                                let _ = this.send(RequestResend { identity, sender, message }).await;
+                            }
+
+                            tracing::info!("dropping envelope");
+                            break None;
+                        }
                         Err(e) => {
                             tracing::error!("Error opening envelope: {:?}", e);
                             break None;
@@ -3288,7 +3784,7 @@ impl StreamHandler<Result<Incoming, ServiceError>> for ClientActor {
             .into_actor(self)
             .map(move |content, act, ctx| {
                 if let Some(content) = content {
-                    act.process_envelope(content, ctx);
+                    act.process_envelope(content, updated_pni, ctx);
                 }
 
                 act.initial_queue_process_state
@@ -3975,6 +4471,39 @@ impl ClientWorker {
         }
     }
 
+    #[with_executor]
+    #[allow(non_snake_case)]
+    fn handleGroupInvite(&self, group_id: String, action: String) {
+        let Some(group) = hex::decode(&group_id)
+            .ok()
+            .and_then(|group| group.try_into().ok())
+        else {
+            tracing::warn!("QML requested unparsable group id for group accept/block");
+            return;
+        };
+        let action = match action.as_str() {
+            "accept" => MessageRequestAction::Accept,
+            "block" => MessageRequestAction::Block,
+            _ => {
+                tracing::warn!(
+                    "Unrecognized group message request handle action: {}",
+                    action
+                );
+                return;
+            }
+        };
+        actix::spawn(
+            self.actor
+                .as_ref()
+                .unwrap()
+                .send(MessageRequestAnswer {
+                    thread: ThreadIdentifier::Group(group),
+                    action,
+                })
+                .map(Result::unwrap),
+        );
+    }
+
     #[with_executor]
     #[allow(non_snake_case)]
     fn updateAnnouncementsOnlyMode(&self, group_id: String, enabled: bool) {
@@ -4100,8 +4629,12 @@ impl Handler<ProcessCachedReceipts> for ClientActor {
                         millis_to_naive_chrono(metadata.client_timestamp.timestamp_millis() as u64),
                     );
                 }
-                _ => {
-                    tracing::warn!("Unimplemented ReceiptType: {:?}", r#type);
+                ReceiptType::Viewed => {
+                    let _ = storage.mark_messages_viewed(
+                        metadata.sender,
+                        timestamps,
+                        millis_to_naive_chrono(metadata.client_timestamp.timestamp_millis() as u64),
+                    );
                 }
             }
         }
@@ -4194,6 +4727,33 @@ impl Handler<ProofAccepted> for ClientActor {
     }
 }
 
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct StarMessage {
+    pub message_id: i32,
+    pub starred: bool,
+}
+
+impl Handler<StarMessage> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        StarMessage {
+            message_id,
+            starred,
+        }: StarMessage,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let storage = self.storage.as_ref().unwrap();
+        if starred {
+            storage.star_message(message_id);
+        } else {
+            storage.unstar_message(message_id);
+        }
+    }
+}
+
 #[derive(actix::Message)]
 #[rtype(result = "()")]
 pub struct DeleteMessage(pub i32);
@@ -4204,9 +4764,19 @@ impl Handler<DeleteMessage> for ClientActor {
     fn handle(
         &mut self,
         DeleteMessage(id): DeleteMessage,
-        _ctx: &mut Self::Context,
+        ctx: &mut Self::Context,
     ) -> Self::Result {
-        self.storage.as_mut().unwrap().delete_message(id);
+        let mut storage = self.storage.clone().unwrap();
+        let Some(message) = storage.fetch_message_by_id(id) else {
+            tracing::warn!("Tried to delete non-existing message {id}");
+            return;
+        };
+        let session = storage
+            .fetch_session_by_id(message.session_id)
+            .expect("session of message to delete");
+        if storage.delete_message(id) {
+            self.sync_message_delete(ctx, &session, &message);
+        }
     }
 }
 
@@ -4377,8 +4947,9 @@ impl Handler<ExportAttachment> for ClientActor {
             }
             let target = target_file.to_str().unwrap();
 
-            match std::fs::copy(source, target) {
-                Err(e) => tracing::trace!("Copying attachment failed: {}", e),
+            // Encrypted attachments are decrypted on the way out.
+            match storage.export_attachment(source, target) {
+                Err(e) => tracing::trace!("Copying attachment failed: {:#}", e),
                 Ok(size) => {
                     tracing::trace!(
                         "Attachent {} {} file exported to {} ({} bytes)",
@@ -4458,28 +5029,12 @@ impl Handler<UpdateAnnouncementsOnly> for ClientActor {
     fn handle(
         &mut self,
         UpdateAnnouncementsOnly { group_id, enabled }: UpdateAnnouncementsOnly,
-        _ctx: &mut Self::Context,
+        ctx: &mut Self::Context,
     ) {
-        let storage = self.storage.as_mut().unwrap().clone();
-        let Some(group_v2) = storage.fetch_group_by_group_v2_id(&group_id) else {
-            tracing::error!("No such group: '{}'", group_id);
-            return;
-        };
-        let Some(self_member) = storage.fetch_group_v2_self_member(&group_id) else {
-            tracing::error!("You are not a member of the group '{}'", group_v2.name);
-            return;
-        };
-        if self_member.role < libsignal_service::proto::member::Role::Administrator as i32 {
-            tracing::error!("You are not admin in the group '{}'", group_v2.name);
-            return;
-        }
-        storage.update_group_v2_announcement_only(&group_v2, enabled);
-        storage.observe_update(
-            whisperfish_store::schema::group_v2s::dsl::group_v2s,
+        ctx.notify(ModifyGroupV2 {
             group_id,
-        );
-        // TODO: libsignal-service-rs doesn't support sending group updates
-        tracing::warn!("Sending group changes is not yet supported. Changes are local only.");
+            action: GroupV2Action::AnnouncementsOnly(enabled),
+        });
     }
 }
 
@@ -4497,12 +5052,12 @@ impl Handler<MessageRequestAnswer> for ClientActor {
     fn handle(
         &mut self,
         MessageRequestAnswer { thread, action }: MessageRequestAnswer,
-        _ctx: &mut Self::Context,
+        ctx: &mut Self::Context,
     ) {
         let storage = self.storage.as_mut().unwrap().clone();
-        match thread {
+        match &thread {
             ThreadIdentifier::Aci(aci) => {
-                let address = Aci::from(aci);
+                let address = Aci::from(*aci);
                 match action {
                     MessageRequestAction::Accept => {
                         storage.mark_recipient_accepted(&address.into());
@@ -4516,11 +5071,27 @@ impl Handler<MessageRequestAnswer> for ClientActor {
                     }
                 }
             }
-            ThreadIdentifier::Group(_group) => {
-                tracing::warn!(
-                    "Group message request responses are not yet implemented. Please upvote bug #327"
-                );
-                return;
+            ThreadIdentifier::Group(group) => {
+                let group_id = hex::encode(group);
+                match action {
+                    MessageRequestAction::Accept => {
+                        storage.mark_group_v2_blocked(&group_id, false);
+                    }
+                    MessageRequestAction::Block => {
+                        storage.mark_group_v2_blocked(&group_id, true);
+                        // Blocking a group also means leaving it.
+                        if storage.fetch_group_v2_self_member(&group_id).is_some() {
+                            ctx.notify(ModifyGroupV2 {
+                                group_id,
+                                action: GroupV2Action::Leave,
+                            });
+                        }
+                    }
+                    _ => {
+                        tracing::error!("Unimplemented message request action: {:?}", action);
+                        return;
+                    }
+                }
             }
         }
 
@@ -4545,6 +5116,19 @@ impl Handler<MessageRequestAnswer> for ClientActor {
     }
 }
 
+/// The number of best matching messages that are shown as search results.
+const SEARCH_RESULT_LIMIT: i64 = 200;
+
+/// Escape a search snippet for rich text, and highlight the matched terms.
+fn snippet_to_html(snippet: &str) -> String {
+    snippet
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace(MATCH_START, "<b>")
+        .replace(MATCH_END, "</b>")
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 /// Search with message contents (or clear results if not given),
@@ -4571,9 +5155,14 @@ impl Handler<Search> for ClientActor {
         };
 
         let storage = self.storage.as_mut().unwrap().clone();
-        let messages = storage.search_messages(&search_text, session_id);
+        let hits = storage.search(&SearchQuery {
+            text: search_text,
+            session_id,
+            limit: Some(SEARCH_RESULT_LIMIT),
+            ..Default::default()
+        });
 
-        if messages.is_empty() {
+        if hits.is_empty() {
             self.inner.pinned().borrow_mut().searchResults = search_results;
             self.inner.pinned().borrow_mut().searchResultsChanged();
             return;
@@ -4592,7 +5181,12 @@ impl Handler<Search> for ClientActor {
             r_map.insert(r.id, r);
         }
 
-        for m in messages {
+        for SearchHit {
+            message: m,
+            snippet,
+            ..
+        } in hits
+        {
             let Some(s) = s_map.get(&m.session_id) else {
                 tracing::error!(
                     "Session {} for message {} doesn't exist?",
@@ -4645,7 +5239,8 @@ impl Handler<Search> for ClientActor {
             result.insert("isOutbound".into(), QVariant::from(m.is_outbound));
             result.insert("chatName".into(), chat_name.to_qvariant());
             result.insert("senderName".into(), sender_name.to_qvariant());
-            result.insert("text".into(), m.text.unwrap().to_qvariant());
+            result.insert("text".into(), m.text.unwrap_or_default().to_qvariant());
+            result.insert("snippet".into(), snippet_to_html(&snippet).to_qvariant());
             result.insert(
                 "timestamp".into(),
                 m.server_timestamp.to_string().to_qvariant(),
@@ -4670,10 +5265,25 @@ mod tests {
             message: "Lorem ipsum dolor sit amet".into(),
             quote: 12,
             is_voice_note: false,
+            sticker: None,
+            schedule_send_time: None,
+        };
+        assert_eq!(
+            format!("{}", q),
+            "QueueMessage { session_id: 8, message: \"Lorem ips...\", quote: 12, attachments: \"[]\", is_voice_note: false, sticker: None, schedule_send_time: None }"
+        );
+    }
+
+    #[test]
+    fn queue_edit() {
+        let q = QueueEdit {
+            message_id: 4,
+            text: "Lorem ipsum dolor sit amet".into(),
+            body_ranges: vec![],
         };
         assert_eq!(
             format!("{}", q),
-            "QueueMessage { session_id: 8, message: \"Lorem ips...\", quote: 12, attachments: \"[]\", is_voice_note: false }"
+            "QueueEdit { message_id: 4, text: \"Lorem ips...\", body_ranges: 0 }"
         );
     }
 
@@ -4689,10 +5299,12 @@ mod tests {
             message: "Lorem ipsum dolor sit amet".into(),
             quote: 12,
             is_voice_note: false,
+            sticker: None,
+            schedule_send_time: None,
         };
         assert_eq!(
             format!("{}", q),
-            "QueueMessage { session_id: 8, message: \"Lorem ips...\", quote: 12, attachments: \"[NewAttachment { path: \"/path/to/pic.jpg\", mime_type: \"image/jpeg\" }]\", is_voice_note: false }"
+            "QueueMessage { session_id: 8, message: \"Lorem ips...\", quote: 12, attachments: \"[NewAttachment { path: \"/path/to/pic.jpg\", mime_type: \"image/jpeg\" }]\", is_voice_note: false, sticker: None, schedule_send_time: None }"
         );
     }
 
@@ -4714,10 +5326,12 @@ mod tests {
             message: "Lorem ipsum dolor sit amet".into(),
             quote: 12,
             is_voice_note: false,
+            sticker: None,
+            schedule_send_time: None,
         };
         assert_eq!(
             format!("{}", q),
-            "QueueMessage { session_id: 8, message: \"Lorem ips...\", quote: 12, attachments: \"[NewAttachment { path: \"/path/to/pic.jpg\", mime_type: \"image/jpeg\" }, NewAttachment { path: \"/path/to/audio.mp3\", mime_type: \"audio/mpeg\" }]\", is_voice_note: false }"
+            "QueueMessage { session_id: 8, message: \"Lorem ips...\", quote: 12, attachments: \"[NewAttachment { path: \"/path/to/pic.jpg\", mime_type: \"image/jpeg\" }, NewAttachment { path: \"/path/to/audio.mp3\", mime_type: \"audio/mpeg\" }]\", is_voice_note: false, sticker: None, schedule_send_time: None }"
         );
     }
 }
@@ -1,11 +1,24 @@
 use super::ClientActor;
 use actix::prelude::*;
 use anyhow::Context;
+use libsignal_service::configuration::Endpoint;
+use libsignal_service::proto::attachment_pointer::AttachmentIdentifier;
+use libsignal_service::push_service::HttpAuthOverride;
 use libsignal_service::{content::AttachmentPointer, prelude::*};
 use mime_classifier::{ApacheBugFlag, LoadContext, MimeClassifier, NoSniffFlag};
-use std::io::Write;
-use std::path::PathBuf;
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::{Method, StatusCode};
+use std::future::Future;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing_futures::Instrument;
+use whisperfish_store::store::attachment_download::{
+    AttachmentReader, CiphertextVerifier, partial_attachment_path,
+};
+
+/// How often an interrupted download is resumed before giving up.
+const MAX_RESUMES: usize = 5;
 
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -30,7 +43,7 @@ impl Handler<FetchAttachment> for ClientActor {
 
         let client_addr = ctx.address();
 
-        let mut service = self.unauthenticated_service();
+        let service = self.unauthenticated_service();
         let storage = self.storage.clone().unwrap();
 
         let attachment = storage
@@ -93,94 +106,69 @@ impl Handler<FetchAttachment> for ClientActor {
             .update_attachment_progress(attachment_id, 0)
             .expect("update attachment progress");
 
+        let partial = partial_attachment_path(&dest, attachment_id);
+
         Box::pin(
             async move {
-                use futures::io::AsyncReadExt;
-                use libsignal_service::attachment_cipher::*;
-
-                let mut stream = loop {
-                    let r = service.get_attachment(&ptr).await;
-                    match r {
-                        Ok(stream) => break stream,
-                        Err(ServiceError::Timeout { .. }) => {
-                            tracing::warn!("get_attachment timed out, retrying")
-                        }
-                        Err(e) => return Err(e.into()),
-                    }
-                };
+                let key = attachment_key(&ptr)?;
+                let size = ptr.size.map(u64::from);
 
-                // We need the whole file for the crypto to check out 😢
-                let actual_len = ptr.size.unwrap() as usize;
-                let mut ciphertext = Vec::with_capacity(actual_len);
-
-                let mut stream_len = 0;
-                let mut buf = vec![0u8; 128 * 1024];
-                let mut bytes_since_previous_report = 0;
-                loop {
-                    let read = stream.read(&mut buf).await?;
-                    bytes_since_previous_report += read;
-                    stream_len += read;
-                    ciphertext.extend_from_slice(&buf[..read]);
-                    assert_eq!(stream_len, ciphertext.len());
-
-                    // Report progress if more than 0.5% has been downloaded
-                    if bytes_since_previous_report > actual_len / 200 {
-                        storage.update_attachment_progress(attachment_id, stream_len)?;
-                        bytes_since_previous_report = 0;
-                    }
+                let ciphertext_len =
+                    download_verified(&service, &ptr, &key, &partial, |downloaded| {
+                        // Only bytes that reached the partial file are reported.
+                        if let Err(e) =
+                            storage.update_attachment_progress(attachment_id, downloaded as usize)
+                        {
+                            tracing::warn!("Could not update attachment progress: {e}");
+                        }
+                    })
+                    .await?;
 
-                    if read == 0 {
-                        break;
-                    }
-                }
+                let saved = {
+                    let storage = storage.clone();
+                    let partial = partial.clone();
+                    tokio::task::spawn_blocking(move || -> anyhow::Result<PathBuf> {
+                        let mut reader = AttachmentReader::new(
+                            std::io::BufReader::new(std::fs::File::open(&partial)?),
+                            &key,
+                            ciphertext_len,
+                            size,
+                        )?;
 
-                let key_material = ptr.key();
-                assert_eq!(
-                    key_material.len(),
-                    64,
-                    "key material for attachments is ought to be 64 bytes"
-                );
-                let mut key = [0u8; 64];
-                key.copy_from_slice(key_material);
-                let mut ciphertext = tokio::task::spawn_blocking(move || {
-                    decrypt_in_place(key, &mut ciphertext).expect("attachment decryption");
-                    ciphertext
-                })
-                .await
-                .context("decryption threadpoool")?;
-
-                // Signal puts exponentially increasing padding at the end
-                // to prevent some distinguishing attacks, so it has to be truncated.
-                if stream_len > actual_len {
-                    tracing::info!(
-                        "The attachment contains {} bytes of padding",
-                        (stream_len - actual_len)
-                    );
-                    tracing::info!("Truncating from {} to {} bytes", stream_len, actual_len);
-                    ciphertext.truncate(actual_len);
-                }
-
-                // Signal Desktop sometimes sends a JPEG image with .png extension,
-                // so double check the received .png image, and rename it if necessary.
-                if ext == "png" {
-                    tracing::trace!("Checking for JPEG with .png extension...");
-                    let classifier = MimeClassifier::new();
-                    let computed_type = classifier.classify(
-                        LoadContext::Image,
-                        NoSniffFlag::Off,
-                        ApacheBugFlag::Off,
-                        &None,
-                        &ciphertext as &[u8],
-                    );
-                    if computed_type == mime::IMAGE_JPEG {
-                        tracing::info!("Received JPEG file with .png suffix, renaming to .jpg");
-                        ext = "jpg".into();
-                    }
-                }
+                        // Signal Desktop sometimes sends a JPEG image with .png extension,
+                        // so double check the received .png image, and rename it if necessary.
+                        let mut head = Vec::new();
+                        (&mut reader).take(512).read_to_end(&mut head)?;
+                        if ext == "png" {
+                            tracing::trace!("Checking for JPEG with .png extension...");
+                            let classifier = MimeClassifier::new();
+                            let computed_type = classifier.classify(
+                                LoadContext::Image,
+                                NoSniffFlag::Off,
+                                ApacheBugFlag::Off,
+                                &None,
+                                &head,
+                            );
+                            if computed_type == mime::IMAGE_JPEG {
+                                tracing::info!(
+                                    "Received JPEG file with .png suffix, renaming to .jpg"
+                                );
+                                ext = "jpg".into();
+                            }
+                        }
 
-                let _attachment_path = storage
-                    .save_attachment(attachment_id, &dest, &ext, &ciphertext)
-                    .await?;
+                        storage.save_attachment_from_reader(
+                            attachment_id,
+                            &dest,
+                            &ext,
+                            &mut std::io::Cursor::new(head).chain(reader),
+                        )
+                    })
+                    .await
+                    .context("decryption threadpool")?
+                };
+                tokio::fs::remove_file(&partial).await.ok();
+                let _attachment_path = saved?;
 
                 client_addr
                     .send(AttachmentDownloaded {
@@ -197,6 +185,7 @@ impl Handler<FetchAttachment> for ClientActor {
                         client_addr
                             .send(super::voice_note_transcription::TranscribeVoiceNote {
                                 message_id,
+                                language: None,
                             })
                             .await?;
                     }
@@ -237,6 +226,220 @@ impl Handler<FetchAttachment> for ClientActor {
     }
 }
 
+/// The key material of an attachment, for decrypting and authenticating it.
+fn attachment_key(ptr: &AttachmentPointer) -> anyhow::Result<[u8; 64]> {
+    ptr.key()
+        .try_into()
+        .context("key material for attachments is ought to be 64 bytes")
+}
+
+/// Downloads the ciphertext of an attachment into `partial`, resuming an earlier download, and
+/// checks it against its MAC and the digest of the pointer.
+///
+/// Returns the length of the ciphertext. A corrupted download is removed, because resuming it
+/// would not fix it.
+async fn download_verified(
+    service: &PushService,
+    ptr: &AttachmentPointer,
+    key: &[u8; 64],
+    partial: &Path,
+    progress: impl FnMut(u64),
+) -> anyhow::Result<u64> {
+    let cdn_path = match ptr.attachment_identifier.as_ref() {
+        Some(AttachmentIdentifier::CdnId(id)) => format!("attachments/{id}"),
+        Some(AttachmentIdentifier::CdnKey(key)) => format!("attachments/{key}"),
+        None => anyhow::bail!("attachment pointer without CDN location"),
+    };
+    let cdn_number = ptr.cdn_number();
+
+    let verifier = download_resumable(
+        partial,
+        key,
+        |offset| {
+            let request = service
+                .request(
+                    Method::GET,
+                    Endpoint::cdn(cdn_number, &cdn_path),
+                    HttpAuthOverride::Unidentified,
+                )
+                .map(|request| request.header(RANGE, format!("bytes={offset}-")));
+            async move { Ok(request?.send().await?) }
+        },
+        progress,
+        u64::from(ptr.size.unwrap_or(0)) / 200,
+    )
+    .await?;
+
+    let ciphertext_len = verifier.len();
+    if let Err(e) = verifier.verify(ptr.digest.as_deref()) {
+        tokio::fs::remove_file(partial).await.ok();
+        return Err(e);
+    }
+    Ok(ciphertext_len)
+}
+
+/// Downloads, verifies and decrypts an attachment as a whole, for attachments that are
+/// processed right away instead of being saved, like the contacts of a sync message.
+///
+/// The ciphertext goes through `partial`, which is removed afterwards.
+pub(super) async fn download_attachment_contents(
+    service: &PushService,
+    ptr: &AttachmentPointer,
+    partial: &Path,
+) -> anyhow::Result<Vec<u8>> {
+    let key = attachment_key(ptr)?;
+    let ciphertext_len = download_verified(service, ptr, &key, partial, |_| {}).await?;
+
+    let contents = {
+        let partial = partial.to_owned();
+        let size = ptr.size.map(u64::from);
+        tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<u8>> {
+            let mut reader = AttachmentReader::new(
+                std::io::BufReader::new(std::fs::File::open(&partial)?),
+                &key,
+                ciphertext_len,
+                size,
+            )?;
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents)?;
+            Ok(contents)
+        })
+        .await
+        .context("decryption threadpool")?
+    };
+    tokio::fs::remove_file(partial).await.ok();
+    contents
+}
+
+/// Downloads attachment ciphertext into `partial`, resuming from what is already there.
+///
+/// The partial file decides where a download resumes, not the `download_length` of the
+/// attachment, which only reports progress and is reset when Whisperfish starts.
+///
+/// `request` is called with the offset to continue from, and should send a request with a
+/// matching `Range` header. `progress` is called with the number of bytes on disk, every
+/// `report_every` bytes. The returned verifier has seen the complete ciphertext.
+///
+/// Failing requests, e.g. timeouts, and interrupted responses are retried up to
+/// [MAX_RESUMES] times in total.
+async fn download_resumable<F, Fut>(
+    partial: &Path,
+    key: &[u8; 64],
+    mut request: F,
+    mut progress: impl FnMut(u64),
+    report_every: u64,
+) -> anyhow::Result<CiphertextVerifier>
+where
+    F: FnMut(u64) -> Fut,
+    Fut: Future<Output = anyhow::Result<reqwest::Response>>,
+{
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    if let Some(parent) = partial.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(partial)
+        .await?;
+
+    // Whatever is left from an earlier attempt has to be hashed again.
+    let mut verifier = CiphertextVerifier::new(key);
+    {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let read = file.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            verifier.update(&buf[..read]);
+        }
+    }
+    if !verifier.is_empty() {
+        tracing::info!("Resuming attachment download at {} bytes", verifier.len());
+        progress(verifier.len());
+    }
+
+    let mut resumes = 0;
+    loop {
+        let offset = verifier.len();
+        let interrupted = match request(offset).await {
+            Ok(mut response) => {
+                match response.status() {
+                    StatusCode::PARTIAL_CONTENT => {
+                        let start = response
+                            .headers()
+                            .get(CONTENT_RANGE)
+                            .and_then(|range| range.to_str().ok())
+                            .and_then(|range| range.strip_prefix("bytes "))
+                            .and_then(|range| range.split('-').next())
+                            .and_then(|start| start.parse::<u64>().ok());
+                        anyhow::ensure!(
+                            start == Some(offset),
+                            "CDN resumed attachment at {start:?} instead of {offset}"
+                        );
+                    }
+                    StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+                        // The partial file already holds everything.
+                        file.sync_all().await?;
+                        return Ok(verifier);
+                    }
+                    StatusCode::OK => {
+                        if offset > 0 {
+                            tracing::warn!("CDN ignored the range request, restarting download");
+                            file.set_len(0).await?;
+                            file.rewind().await?;
+                            verifier = CiphertextVerifier::new(key);
+                        }
+                    }
+                    status => anyhow::bail!("Unexpected status {status} fetching attachment"),
+                }
+
+                let mut since_report = 0;
+                let interrupted = loop {
+                    match response.chunk().await {
+                        Ok(Some(chunk)) => {
+                            file.write_all(&chunk).await?;
+                            verifier.update(&chunk);
+                            since_report += chunk.len() as u64;
+                            if since_report > report_every {
+                                file.flush().await?;
+                                progress(verifier.len());
+                                since_report = 0;
+                            }
+                        }
+                        Ok(None) => break None,
+                        Err(e) => break Some(anyhow::Error::from(e)),
+                    }
+                };
+                file.flush().await?;
+                progress(verifier.len());
+                interrupted
+            }
+            Err(e) => Some(e),
+        };
+
+        match interrupted {
+            None => {
+                file.sync_all().await?;
+                return Ok(verifier);
+            }
+            Some(e) if resumes < MAX_RESUMES => {
+                resumes += 1;
+                tracing::warn!(
+                    "Attachment download interrupted at {} bytes ({e:#}), resuming",
+                    verifier.len()
+                );
+                tokio::time::sleep(Duration::from_millis(500 * resumes as u64)).await;
+            }
+            Some(e) => return Err(e),
+        }
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 struct AttachmentDownloaded {
@@ -262,3 +465,230 @@ impl Handler<AttachmentDownloaded> for ClientActor {
             .attachmentDownloaded(session_id, message_id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Digest;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// How a connection to the CDN stand-in behaves.
+    #[derive(Clone, Copy)]
+    enum Behaviour {
+        /// Honour the range, but drop the connection after this many bytes.
+        DropAfter(usize),
+        /// Honour the range.
+        Complete,
+        /// Ignore the range, and send everything.
+        IgnoreRange,
+        /// Close the connection without responding.
+        Refuse,
+    }
+
+    /// A CDN stand-in that serves `body`, behaving as given for each subsequent connection.
+    async fn serve(body: Vec<u8>, behaviours: Vec<Behaviour>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/attachment", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+
+        tokio::spawn(async move {
+            for behaviour in behaviours {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let read = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                if let Behaviour::Refuse = behaviour {
+                    continue;
+                }
+                let request = String::from_utf8(request).unwrap().to_ascii_lowercase();
+                let offset = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok())
+                    .unwrap_or(0);
+
+                let (head, rest) = match behaviour {
+                    Behaviour::IgnoreRange => (
+                        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()),
+                        &body[..],
+                    ),
+                    _ if offset == 0 => (
+                        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()),
+                        &body[..],
+                    ),
+                    _ => (
+                        format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                            offset,
+                            body.len() - 1,
+                            body.len(),
+                            body.len() - offset
+                        ),
+                        &body[offset..],
+                    ),
+                };
+                let rest = match behaviour {
+                    Behaviour::DropAfter(n) => &rest[..n.min(rest.len())],
+                    _ => rest,
+                };
+                socket.write_all(head.as_bytes()).await.unwrap();
+                socket.write_all(rest).await.unwrap();
+                socket.shutdown().await.ok();
+            }
+        });
+
+        (url, connections)
+    }
+
+    fn encrypted_attachment(key: &[u8; 64], plaintext: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut ciphertext = plaintext.to_vec();
+        libsignal_service::attachment_cipher::encrypt_in_place([7u8; 16], *key, &mut ciphertext);
+        let digest = sha2::Sha256::digest(&ciphertext).to_vec();
+        (ciphertext, digest)
+    }
+
+    async fn download(
+        url: &str,
+        partial: &Path,
+        key: &[u8; 64],
+    ) -> anyhow::Result<CiphertextVerifier> {
+        let client = reqwest::Client::new();
+        download_resumable(
+            partial,
+            key,
+            |offset| {
+                let request = client
+                    .get(url)
+                    .header(RANGE, format!("bytes={offset}-"))
+                    .send();
+                async move { Ok(request.await?) }
+            },
+            |_| {},
+            1024,
+        )
+        .await
+    }
+
+    fn decrypt(partial: &Path, key: &[u8; 64], len: u64, plaintext_len: usize) -> Vec<u8> {
+        let mut reader = AttachmentReader::new(
+            std::fs::File::open(partial).unwrap(),
+            key,
+            len,
+            Some(plaintext_len as u64),
+        )
+        .unwrap();
+        let mut plaintext = Vec::new();
+        reader.read_to_end(&mut plaintext).unwrap();
+        plaintext
+    }
+
+    #[actix_rt::test]
+    async fn interrupted_download_is_resumed() {
+        let key = [42u8; 64];
+        let plaintext: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let (ciphertext, digest) = encrypted_attachment(&key, &plaintext);
+
+        let (url, connections) = serve(
+            ciphertext.clone(),
+            vec![
+                Behaviour::DropAfter(100_000),
+                Behaviour::DropAfter(50_000),
+                Behaviour::Complete,
+            ],
+        )
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let partial = partial_attachment_path(dir.path(), 1);
+
+        let verifier = download(&url, &partial, &key).await.unwrap();
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        assert_eq!(verifier.len(), ciphertext.len() as u64);
+        verifier.verify(Some(&digest)).unwrap();
+
+        assert_eq!(std::fs::read(&partial).unwrap(), ciphertext);
+        assert_eq!(
+            decrypt(&partial, &key, ciphertext.len() as u64, plaintext.len()),
+            plaintext
+        );
+    }
+
+    #[actix_rt::test]
+    async fn failed_resume_request_is_retried() {
+        let key = [42u8; 64];
+        let plaintext: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let (ciphertext, digest) = encrypted_attachment(&key, &plaintext);
+
+        let (url, connections) = serve(
+            ciphertext.clone(),
+            vec![
+                Behaviour::DropAfter(100_000),
+                Behaviour::Refuse,
+                Behaviour::Complete,
+            ],
+        )
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let partial = partial_attachment_path(dir.path(), 1);
+
+        let verifier = download(&url, &partial, &key).await.unwrap();
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        verifier.verify(Some(&digest)).unwrap();
+        assert_eq!(std::fs::read(&partial).unwrap(), ciphertext);
+    }
+
+    #[actix_rt::test]
+    async fn failing_requests_give_up_eventually() {
+        let key = [42u8; 64];
+        let (ciphertext, _digest) = encrypted_attachment(&key, &[1u8; 5000]);
+
+        let (url, connections) = serve(ciphertext, vec![Behaviour::Refuse; MAX_RESUMES + 1]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let partial = partial_attachment_path(dir.path(), 1);
+
+        assert!(download(&url, &partial, &key).await.is_err());
+        assert_eq!(connections.load(Ordering::SeqCst), MAX_RESUMES + 1);
+    }
+
+    #[actix_rt::test]
+    async fn download_restarts_when_range_is_ignored() {
+        let key = [42u8; 64];
+        let plaintext: Vec<u8> = (0..100_000u32).map(|i| (i % 13) as u8).collect();
+        let (ciphertext, digest) = encrypted_attachment(&key, &plaintext);
+
+        let (url, _connections) = serve(ciphertext.clone(), vec![Behaviour::IgnoreRange]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let partial = partial_attachment_path(dir.path(), 1);
+
+        // Left over from an earlier attempt.
+        std::fs::create_dir_all(partial.parent().unwrap()).unwrap();
+        std::fs::write(&partial, &ciphertext[..1000]).unwrap();
+
+        let verifier = download(&url, &partial, &key).await.unwrap();
+        verifier.verify(Some(&digest)).unwrap();
+        assert_eq!(std::fs::read(&partial).unwrap(), ciphertext);
+    }
+
+    #[actix_rt::test]
+    async fn corrupted_download_fails_verification() {
+        let key = [42u8; 64];
+        let plaintext = vec![1u8; 5000];
+        let (mut ciphertext, digest) = encrypted_attachment(&key, &plaintext);
+        ciphertext[100] ^= 1;
+
+        let (url, _connections) = serve(ciphertext, vec![Behaviour::Complete]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let partial = partial_attachment_path(dir.path(), 1);
+
+        let verifier = download(&url, &partial, &key).await.unwrap();
+        assert!(verifier.verify(Some(&digest)).is_err());
+    }
+}
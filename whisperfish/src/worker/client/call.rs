@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::convert::TryFrom;
 
 use crate::store::orm::{self, Recipient};
@@ -17,28 +18,48 @@ use ringrtc::{
     common::{CallId, CallMediaType},
     core::{
         call_manager::CallManager,
+        group_call::ClientId,
         signaling::{
             IceCandidate, ReceivedAnswer, ReceivedBusy, ReceivedHangup, ReceivedIce, ReceivedOffer,
         },
     },
     lite::http::DelegatingClient,
+    webrtc::peer_connection_factory::{AudioConfig, PeerConnectionFactory},
 };
 
 use super::{Notification, message_notification};
 
 mod call_manager;
+mod group_call;
+
+pub use self::group_call::{JoinGroupCall, LeaveGroupCall, PeekGroupCall};
 
-#[derive(Debug)]
 pub(super) struct WhisperfishCallManager {
     manager: CallManager<ringrtc::native::NativePlatform>,
+    http_client: DelegatingClient,
+    factory: PeerConnectionFactory,
+    /// The session of each group call we are in.
+    group_calls: HashMap<ClientId, i32>,
+    /// The session of each group call peek in flight.
+    peeks: HashMap<u32, i32>,
+    next_peek_id: u32,
 }
 
 impl WhisperfishCallManager {
     pub fn new(client: actix::Addr<super::ClientActor>) -> Self {
-        let platform = call_manager::new_native_platform(client).unwrap();
-        let client = DelegatingClient::new(call_manager::WhisperfishRingRtcHttpClient::default());
+        let factory = PeerConnectionFactory::new(&AudioConfig::default(), false)
+            .expect("initialized peer connection factory");
+        let platform = call_manager::new_native_platform(client.clone(), factory.clone()).unwrap();
+        let http_client =
+            DelegatingClient::new(call_manager::WhisperfishRingRtcHttpClient::new(client));
         Self {
-            manager: CallManager::new(platform, client).expect("initialized call manager"),
+            manager: CallManager::new(platform, http_client.clone())
+                .expect("initialized call manager"),
+            http_client,
+            factory,
+            group_calls: HashMap::new(),
+            peeks: HashMap::new(),
+            next_peek_id: 0,
         }
     }
 }
@@ -451,7 +472,7 @@ fn call_type_from_call_media_type(media_type: &CallMediaType) -> orm::CallType {
 impl Handler<CallState> for super::ClientActor {
     type Result = ();
 
-    #[tracing::instrument(skip(self, _ctx))]
+    #[tracing::instrument(skip(self, ctx))]
     fn handle(
         &mut self,
         CallState {
@@ -459,7 +480,7 @@ impl Handler<CallState> for super::ClientActor {
             call_id,
             state,
         }: CallState,
-        _ctx: &mut Self::Context,
+        ctx: &mut Self::Context,
     ) -> Self::Result {
         // Map the call state to the message and call storage
         let storage = self.storage.as_ref().expect("initialized storage");
@@ -526,8 +547,10 @@ impl Handler<CallState> for super::ClientActor {
                     }
                 }
             }
+            ringrtc::native::CallState::Connected => {
+                self.sync_call_event(ctx, call_id.into(), orm::EventType::Accepted);
+            }
             ringrtc::native::CallState::Ringing
-            | ringrtc::native::CallState::Connected
             | ringrtc::native::CallState::Connecting
             | ringrtc::native::CallState::Concluded => tracing::error!("unimplemented call state"),
         }
@@ -543,11 +566,11 @@ impl Handler<CallState> for super::ClientActor {
 impl Handler<AnswerCall> for super::ClientActor {
     type Result = ();
 
-    #[tracing::instrument(skip(self, _ctx))]
+    #[tracing::instrument(skip(self, ctx))]
     fn handle(
         &mut self,
         AnswerCall { call_id }: AnswerCall,
-        _ctx: &mut Self::Context,
+        ctx: &mut Self::Context,
     ) -> Self::Result {
         tracing::info!("accepting call");
         let call_state = self.call_state();
@@ -555,6 +578,7 @@ impl Handler<AnswerCall> for super::ClientActor {
             .manager
             .accept_call(call_id)
             .expect("answered call");
+        self.sync_call_event(ctx, call_id.into(), orm::EventType::Accepted);
         // call_state
         //     .manager
         //     .proceed(
@@ -570,14 +594,23 @@ impl Handler<AnswerCall> for super::ClientActor {
 impl Handler<HangupCall> for super::ClientActor {
     type Result = ();
 
-    #[tracing::instrument(skip(self, _ctx))]
+    #[tracing::instrument(skip(self, ctx))]
     fn handle(
         &mut self,
         HangupCall { call_id }: HangupCall,
-        _ctx: &mut Self::Context,
+        ctx: &mut Self::Context,
     ) -> Self::Result {
         tracing::info!("declining call");
         self.call_state().manager.hangup().expect("declined call");
+
+        // Hanging up a call that never connected declines or cancels it.
+        let storage = self.storage.as_ref().expect("initialized storage");
+        if storage
+            .fetch_call_by_call_id(call_id.into())
+            .is_some_and(|call| call.is_ringing())
+        {
+            self.sync_call_event(ctx, call_id.into(), orm::EventType::NotAccepted);
+        }
     }
 }
 
@@ -1,16 +1,31 @@
+use super::group_call::{
+    GroupCallUpdate, OpaqueDestination, RingRtcHttpRequest, SendOpaqueCallMessage,
+};
 use libsignal_service::proto::CallMessage;
 use ringrtc::{
     lite::http,
     native::{CallStateHandler, GroupUpdateHandler, SignalingSender},
-    webrtc::peer_connection_factory::{AudioConfig, PeerConnectionFactory},
+    webrtc::peer_connection_factory::PeerConnectionFactory,
 };
 
-#[derive(Default, Debug)]
-pub struct WhisperfishRingRtcHttpClient {}
+/// Performs ringrtc's HTTP requests through the client actor.
+#[derive(Debug)]
+pub struct WhisperfishRingRtcHttpClient {
+    client: actix::Addr<crate::worker::ClientActor>,
+}
+
+impl WhisperfishRingRtcHttpClient {
+    pub fn new(client: actix::Addr<crate::worker::ClientActor>) -> Self {
+        Self { client }
+    }
+}
 
 impl http::Delegate for WhisperfishRingRtcHttpClient {
-    fn send_request(&self, _request_id: u32, _request: http::Request) {
-        todo!()
+    fn send_request(&self, request_id: u32, request: http::Request) {
+        self.client.do_send(RingRtcHttpRequest {
+            request_id,
+            request,
+        });
     }
 }
 
@@ -135,23 +150,39 @@ impl SignalingSender for WhisperfishSignalingSender {
         Ok(())
     }
 
+    #[tracing::instrument(skip(self, recipient_id, message))]
     fn send_call_message(
         &self,
-        _recipient_id: ringrtc::lite::sfu::UserId,
-        _message: Vec<u8>,
-        _urgency: ringrtc::core::group_call::SignalingMessageUrgency,
+        recipient_id: ringrtc::lite::sfu::UserId,
+        message: Vec<u8>,
+        urgency: ringrtc::core::group_call::SignalingMessageUrgency,
     ) -> ringrtc::common::Result<()> {
-        todo!("group calls")
+        self.client.do_send(SendOpaqueCallMessage {
+            destination: OpaqueDestination::User(recipient_id),
+            data: message,
+            urgency,
+        });
+        Ok(())
     }
 
+    #[tracing::instrument(skip(self, group_id, message, recipients_override))]
     fn send_call_message_to_group(
         &self,
-        _group_id: ringrtc::core::group_call::GroupId,
-        _message: Vec<u8>,
-        _urgency: ringrtc::core::group_call::SignalingMessageUrgency,
-        _recipients_override: std::collections::HashSet<ringrtc::lite::sfu::UserId>,
+        group_id: ringrtc::core::group_call::GroupId,
+        message: Vec<u8>,
+        urgency: ringrtc::core::group_call::SignalingMessageUrgency,
+        recipients_override: std::collections::HashSet<ringrtc::lite::sfu::UserId>,
     ) -> ringrtc::common::Result<()> {
-        todo!("group calls")
+        if !recipients_override.is_empty() {
+            // XXX Only used for group rings to a subset of the members.
+            tracing::warn!("Sending group call message to the whole group instead of a subset");
+        }
+        self.client.do_send(SendOpaqueCallMessage {
+            destination: OpaqueDestination::Group(group_id),
+            data: message,
+            urgency,
+        });
+        Ok(())
     }
 }
 
@@ -230,23 +261,23 @@ impl CallStateHandler for WhisperfishStateHandler {
 
 #[derive(Debug)]
 struct WhisperfishGroupUpdateHandler {
-    #[allow(unused)]
     client: actix::Addr<crate::worker::ClientActor>,
 }
 
 impl GroupUpdateHandler for WhisperfishGroupUpdateHandler {
     fn handle_group_update(
         &self,
-        _update: ringrtc::native::GroupUpdate,
+        update: ringrtc::native::GroupUpdate,
     ) -> ringrtc::common::Result<()> {
-        todo!()
+        self.client.do_send(GroupCallUpdate(update));
+        Ok(())
     }
 }
 
 pub fn new_native_platform(
     client: actix::Addr<crate::worker::ClientActor>,
+    connection_factory: PeerConnectionFactory,
 ) -> anyhow::Result<ringrtc::native::NativePlatform> {
-    let connection_factory = PeerConnectionFactory::new(&AudioConfig::default(), false)?;
     let signaling_sender = Box::new(WhisperfishSignalingSender {
         client: client.clone(),
     });
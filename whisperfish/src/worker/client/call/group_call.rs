@@ -0,0 +1,505 @@
+use super::super::{ClientActor, DeliverMessage, DeliveryRecipient};
+use crate::store::orm;
+use actix::prelude::*;
+use anyhow::Context as _;
+use chrono::Utc;
+use libsignal_service::configuration::Endpoint;
+use libsignal_service::proto::{CallMessage, call_message};
+use libsignal_service::protocol::{Aci, ServiceId};
+use libsignal_service::push_service::HttpAuthOverride;
+use ringrtc::common::CallId;
+use ringrtc::core::group_call::{
+    ClientId, GroupId, GroupMember, JoinState, SignalingMessageUrgency,
+};
+use ringrtc::lite::{http, sfu::PeekInfo, sfu::UserId};
+use ringrtc::native::GroupUpdate;
+use tracing_futures::Instrument;
+use uuid::Uuid;
+use zkgroup::GroupMasterKey;
+use zkgroup::groups::GroupSecretParams;
+
+/// The selective forwarding unit that hosts Signal group calls.
+const SFU_URL: &str = "https://sfu.voip.signal.org";
+
+/// An HTTP request that ringrtc wants to make, mostly to the SFU.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(super) struct RingRtcHttpRequest {
+    pub request_id: u32,
+    pub request: http::Request,
+}
+
+/// Where an opaque group call message should go.
+pub(super) enum OpaqueDestination {
+    User(UserId),
+    Group(GroupId),
+}
+
+/// A group call message from ringrtc, to be wrapped in an opaque call message.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(super) struct SendOpaqueCallMessage {
+    pub destination: OpaqueDestination,
+    pub data: Vec<u8>,
+    pub urgency: SignalingMessageUrgency,
+}
+
+/// Something happened to a group call, or to a peek at one.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(super) struct GroupCallUpdate(pub GroupUpdate);
+
+/// Ask the SFU whether a call is going on in a group.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PeekGroupCall {
+    pub session_id: i32,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct JoinGroupCall {
+    pub session_id: i32,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeaveGroupCall {
+    pub session_id: i32,
+}
+
+/// The group behind a session, with what ringrtc needs to know about it.
+struct CallGroup {
+    session_id: i32,
+    group_id: GroupId,
+    master_key: GroupMasterKey,
+    members: Vec<GroupMember>,
+}
+
+impl ClientActor {
+    fn call_group(&self, session_id: i32) -> anyhow::Result<CallGroup> {
+        let storage = self.storage.as_ref().expect("initialized storage");
+        let Some(orm::SessionType::GroupV2(group)) =
+            storage.fetch_session_by_id(session_id).map(|s| s.r#type)
+        else {
+            anyhow::bail!("Session {session_id} is not a GroupV2 group");
+        };
+        let key = hex::decode(&group.master_key)
+            .map_err(anyhow::Error::from)
+            .and_then(|key| {
+                <[u8; zkgroup::GROUP_MASTER_KEY_LEN]>::try_from(key)
+                    .map_err(|_| anyhow::anyhow!("wrong master key length"))
+            })
+            .with_context(|| format!("invalid master key for group {}", group.id))?;
+        let group_id =
+            hex::decode(&group.id).with_context(|| format!("invalid group id {}", group.id))?;
+        let master_key = GroupMasterKey::new(key);
+        let secret = GroupSecretParams::derive_from_master_key(master_key);
+
+        // The SFU knows members by their ACI encrypted with the group secret.
+        let members = storage
+            .fetch_group_members_by_group_v2_id(&group.id)
+            .into_iter()
+            .filter_map(|(_member, recipient)| recipient.uuid)
+            .map(|uuid| GroupMember {
+                user_id: uuid.as_bytes().to_vec(),
+                member_id: zkgroup::serialize(
+                    &secret.encrypt_service_id(ServiceId::Aci(Aci::from(uuid))),
+                ),
+            })
+            .collect();
+
+        Ok(CallGroup {
+            session_id,
+            group_id,
+            master_key,
+            members,
+        })
+    }
+
+    fn logged_call_group(&self, session_id: i32) -> Option<CallGroup> {
+        self.call_group(session_id)
+            .inspect_err(|e| tracing::error!("Cannot call group of session {session_id}: {e:#}"))
+            .ok()
+    }
+
+    fn group_call_session(&mut self, client_id: ClientId) -> Option<i32> {
+        let session_id = self.call_state().group_calls.get(&client_id).copied();
+        if session_id.is_none() {
+            tracing::warn!("Unknown group call client {client_id}");
+        }
+        session_id
+    }
+
+    /// Record whether a call is going on in the group, and who started it.
+    fn handle_peek_info(&mut self, session_id: i32, peek_info: &PeekInfo) {
+        let storage = self.storage.as_ref().expect("initialized storage");
+        let active_call = peek_info.era_id.as_deref().map(|era_id| {
+            let ringer = peek_info
+                .creator
+                .as_deref()
+                .and_then(|aci| Uuid::from_slice(aci).ok())
+                .map(|aci| {
+                    storage
+                        .fetch_or_insert_recipient_by_address(&ServiceId::Aci(aci.into()))
+                        .id
+                })
+                .unwrap_or_else(|| storage.fetch_self_recipient_id());
+            (u64::from(CallId::from_era_id(era_id)), ringer)
+        });
+        tracing::debug!(
+            session_id,
+            devices = peek_info.devices.len(),
+            "group call {}",
+            if active_call.is_some() {
+                "active"
+            } else {
+                "inactive"
+            }
+        );
+        storage.set_active_group_call(session_id, active_call);
+    }
+}
+
+impl Handler<RingRtcHttpRequest> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    #[tracing::instrument(skip(self, request, _ctx))]
+    fn handle(
+        &mut self,
+        RingRtcHttpRequest {
+            request_id,
+            request,
+        }: RingRtcHttpRequest,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let mut service = self.unauthenticated_service();
+
+        Box::pin(
+            async move {
+                let method = match request.method {
+                    http::Method::Get => reqwest::Method::GET,
+                    http::Method::Put => reqwest::Method::PUT,
+                    http::Method::Post => reqwest::Method::POST,
+                    http::Method::Delete => reqwest::Method::DELETE,
+                };
+                // The SFU authenticates with the headers ringrtc sets.
+                let mut builder = service.request(
+                    method,
+                    Endpoint::Absolute(request.url.parse()?),
+                    HttpAuthOverride::NoOverride,
+                )?;
+                for (name, value) in request.headers {
+                    builder = builder.header(name, value);
+                }
+                if let Some(body) = request.body {
+                    builder = builder.body(body);
+                }
+                let response = builder.send().await?;
+                let status = response.status().as_u16();
+                let body = response.bytes().await?.to_vec();
+                Ok(http::Response {
+                    status: status.into(),
+                    body,
+                })
+            }
+            .into_actor(self)
+            .map(move |res: anyhow::Result<http::Response>, act, _ctx| {
+                let response = match res {
+                    Ok(response) => Some(response),
+                    Err(e) => {
+                        tracing::warn!("ringrtc HTTP request failed: {e:#}");
+                        None
+                    }
+                };
+                act.call_state()
+                    .http_client
+                    .received_response(request_id, response);
+            }),
+        )
+    }
+}
+
+impl Handler<SendOpaqueCallMessage> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        SendOpaqueCallMessage {
+            destination,
+            data,
+            urgency,
+        }: SendOpaqueCallMessage,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let storage = self.storage.as_ref().expect("initialized storage");
+        // Messages to a single participant don't need a conversation with them.
+        let destination: Option<DeliveryRecipient> = match destination {
+            OpaqueDestination::User(user_id) => Uuid::from_slice(&user_id)
+                .ok()
+                .map(|aci| ServiceId::Aci(aci.into()).into()),
+            OpaqueDestination::Group(group_id) => storage
+                .fetch_session_by_group_v2_id(&hex::encode(group_id))
+                .map(|session| session.r#type.into()),
+        };
+        let Some(destination) = destination else {
+            tracing::warn!("No recipient to send the group call message to");
+            return Box::pin(async {}.into_actor(self));
+        };
+
+        let urgency = match urgency {
+            SignalingMessageUrgency::Droppable => call_message::opaque::Urgency::Droppable,
+            SignalingMessageUrgency::HandleImmediately => {
+                call_message::opaque::Urgency::HandleImmediately
+            }
+        };
+        let content = CallMessage {
+            opaque: Some(call_message::Opaque {
+                data: Some(data),
+                urgency: Some(urgency as i32),
+            }),
+            ..Default::default()
+        };
+
+        let now = Utc::now();
+        self.transient_timestamps
+            .insert(now.timestamp_millis() as u64);
+        let delivery = ctx.address().send(DeliverMessage {
+            content,
+            online: false,
+            timestamp: now.timestamp_millis() as u64,
+            destination,
+            for_story: false,
+        });
+        Box::pin(
+            async move {
+                match delivery.await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => tracing::warn!("Could not send group call message: {e:#}"),
+                    Err(e) => tracing::error!("Could not send group call message: {e}"),
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl Handler<GroupCallUpdate> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        GroupCallUpdate(update): GroupCallUpdate,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        match update {
+            GroupUpdate::RequestMembershipProof(client_id) => {
+                let Some(group) = self
+                    .group_call_session(client_id)
+                    .and_then(|session_id| self.logged_call_group(session_id))
+                else {
+                    return;
+                };
+                let token = self.fetch_group_call_token(group.master_key);
+                ctx.spawn(
+                    token
+                        .instrument(tracing::info_span!("group call membership proof"))
+                        .into_actor(self)
+                        .map(move |token, act, _ctx| match token {
+                            Ok(token) => {
+                                if let Err(e) = act
+                                    .call_state()
+                                    .manager
+                                    .set_membership_proof(client_id, token)
+                                {
+                                    tracing::error!(
+                                        "Could not set group call membership proof: {e:#}"
+                                    );
+                                }
+                            }
+                            Err(e) => tracing::error!("Could not fetch group call token: {e:#}"),
+                        }),
+                );
+            }
+            GroupUpdate::RequestGroupMembers(client_id) => {
+                let Some(group) = self
+                    .group_call_session(client_id)
+                    .and_then(|session_id| self.logged_call_group(session_id))
+                else {
+                    return;
+                };
+                if let Err(e) = self
+                    .call_state()
+                    .manager
+                    .set_group_members(client_id, group.members)
+                {
+                    tracing::error!("Could not set group call members: {e:#}");
+                }
+            }
+            GroupUpdate::JoinStateChanged(client_id, join_state) => {
+                let Some(session_id) = self.group_call_session(client_id) else {
+                    return;
+                };
+                let joined = matches!(join_state, JoinState::Joined(_));
+                if joined {
+                    let storage = self.storage.as_ref().expect("initialized storage");
+                    storage.set_group_call_joined(session_id);
+                }
+                self.calls_model
+                    .pinned()
+                    .borrow_mut()
+                    .handle_group_state(session_id, joined);
+            }
+            GroupUpdate::PeekChanged {
+                client_id,
+                peek_info,
+            } => {
+                if let Some(session_id) = self.group_call_session(client_id) {
+                    self.handle_peek_info(session_id, &peek_info);
+                }
+            }
+            GroupUpdate::PeekResult {
+                request_id,
+                peek_result,
+            } => {
+                let Some(session_id) = self.call_state().peeks.remove(&request_id) else {
+                    tracing::warn!("Peek result for unknown request {request_id}");
+                    return;
+                };
+                match peek_result {
+                    Ok(peek_info) => self.handle_peek_info(session_id, &peek_info),
+                    Err(status) => tracing::warn!("Could not peek group call: {status:?}"),
+                }
+            }
+            GroupUpdate::Ended(client_id, reason) => {
+                tracing::info!("Group call ended: {reason:?}");
+                let call_state = self.call_state();
+                let session_id = call_state.group_calls.remove(&client_id);
+                if let Err(e) = call_state.manager.delete_group_call_client(client_id) {
+                    tracing::error!("Could not delete group call client: {e:#}");
+                }
+                if let Some(session_id) = session_id {
+                    self.calls_model
+                        .pinned()
+                        .borrow_mut()
+                        .handle_group_state(session_id, false);
+                    ctx.notify(PeekGroupCall { session_id });
+                }
+            }
+            _ => tracing::trace!("Unhandled group call update"),
+        }
+    }
+}
+
+impl Handler<PeekGroupCall> for ClientActor {
+    type Result = ();
+
+    #[tracing::instrument(skip(self, ctx))]
+    fn handle(
+        &mut self,
+        PeekGroupCall { session_id }: PeekGroupCall,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let Some(group) = self.logged_call_group(session_id) else {
+            return;
+        };
+        let token = self.fetch_group_call_token(group.master_key);
+        ctx.spawn(
+            token
+                .instrument(tracing::info_span!("peek group call"))
+                .into_actor(self)
+                .map(move |token, act, _ctx| {
+                    let token = match token {
+                        Ok(token) => token,
+                        Err(e) => {
+                            tracing::error!("Could not fetch group call token: {e:#}");
+                            return;
+                        }
+                    };
+                    let call_state = act.call_state();
+                    let request_id = call_state.next_peek_id;
+                    call_state.next_peek_id = call_state.next_peek_id.wrapping_add(1);
+                    call_state.peeks.insert(request_id, group.session_id);
+                    call_state.manager.peek_group_call(
+                        request_id,
+                        SFU_URL.into(),
+                        token,
+                        group.members,
+                    );
+                }),
+        );
+    }
+}
+
+impl Handler<JoinGroupCall> for ClientActor {
+    type Result = ();
+
+    #[tracing::instrument(skip(self, _ctx))]
+    fn handle(
+        &mut self,
+        JoinGroupCall { session_id }: JoinGroupCall,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let Some(group) = self.logged_call_group(session_id) else {
+            return;
+        };
+        let call_state = self.call_state();
+        if call_state.group_calls.values().any(|&id| id == session_id) {
+            tracing::warn!("Already in the group call");
+            return;
+        }
+
+        let result = (|| {
+            let audio_track = call_state.factory.create_outgoing_audio_track()?;
+            let client_id = call_state.manager.create_group_call_client(
+                group.group_id,
+                SFU_URL.into(),
+                Vec::new(), // HKDF extra info, only for call links
+                None,       // audio levels interval
+                Some(call_state.factory.clone()),
+                audio_track,
+                None, // no video yet
+                None,
+            )?;
+            call_state.manager.connect(client_id)?;
+            call_state.manager.join(client_id)?;
+            anyhow::Ok(client_id)
+        })();
+        match result {
+            Ok(client_id) => {
+                tracing::info!("Joining group call");
+                call_state.group_calls.insert(client_id, session_id);
+            }
+            Err(e) => tracing::error!("Could not join group call: {e:#}"),
+        }
+    }
+}
+
+impl Handler<LeaveGroupCall> for ClientActor {
+    type Result = ();
+
+    #[tracing::instrument(skip(self, _ctx))]
+    fn handle(
+        &mut self,
+        LeaveGroupCall { session_id }: LeaveGroupCall,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let call_state = self.call_state();
+        let Some(client_id) = call_state
+            .group_calls
+            .iter()
+            .find_map(|(&client_id, &id)| (id == session_id).then_some(client_id))
+        else {
+            tracing::warn!("Not in a group call in session {session_id}");
+            return;
+        };
+        tracing::info!("Leaving group call");
+        // The client is deleted when ringrtc reports the call ended.
+        if let Err(e) = call_state.manager.leave(client_id) {
+            tracing::error!("Could not leave group call: {e:#}");
+        }
+        if let Err(e) = call_state.manager.disconnect(client_id) {
+            tracing::error!("Could not disconnect from group call: {e:#}");
+        }
+    }
+}
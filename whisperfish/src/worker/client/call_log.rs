@@ -0,0 +1,236 @@
+use super::*;
+use libsignal_service::proto::sync_message::{CallEvent, CallLogEvent, call_event, call_log_event};
+use whisperfish_store::orm::{CallType, EventType};
+
+/// Remove all calls from the call log, here and on our other devices.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClearCallLog;
+
+/// Mark all calls in the call log as seen, here and on our other devices.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MarkCallLogRead;
+
+impl ClientActor {
+    /// The session a call event or call log event refers to.
+    ///
+    /// One-to-one calls are identified by the peer's ACI, group calls by the group id.
+    fn call_conversation(&self, conversation_id: &[u8]) -> Option<orm::Session> {
+        let storage = self.storage.as_ref().expect("storage");
+        if let Ok(aci) = Uuid::from_slice(conversation_id) {
+            let recipient = storage.fetch_recipient(&ServiceId::Aci(aci.into()))?;
+            storage.fetch_session_by_recipient_id(recipient.id)
+        } else {
+            storage.fetch_session_by_group_v2_id(&hex::encode(conversation_id))
+        }
+    }
+
+    /// A call that was placed, answered, declined or deleted on another device.
+    pub(super) fn handle_sync_call_event(&mut self, event: &CallEvent) {
+        let storage = self.storage.clone().expect("storage");
+        let (Some(call_id), Some(timestamp)) = (event.id, event.timestamp) else {
+            tracing::warn!("Call event without id or timestamp");
+            return;
+        };
+        let r#type = match event.r#type() {
+            call_event::Type::AudioCall => CallType::Audio,
+            call_event::Type::VideoCall => CallType::Video,
+            call_event::Type::GroupCall | call_event::Type::AdHocCall => {
+                tracing::warn!("Group call events are not supported yet");
+                return;
+            }
+            call_event::Type::UnknownType => {
+                tracing::warn!("Call event of unknown type");
+                return;
+            }
+        };
+        let is_outgoing = match event.direction() {
+            call_event::Direction::Incoming => false,
+            call_event::Direction::Outgoing => true,
+            call_event::Direction::UnknownDirection => {
+                tracing::warn!("Call event without direction");
+                return;
+            }
+        };
+        let Some(aci) = event
+            .conversation_id
+            .as_deref()
+            .and_then(|id| Uuid::from_slice(id).ok())
+        else {
+            tracing::warn!("One-to-one call event without peer ACI");
+            return;
+        };
+        let recipient = storage.fetch_or_insert_recipient_by_address(&ServiceId::Aci(aci.into()));
+        let timestamp = millis_to_naive_chrono(timestamp);
+
+        let event = match event.event() {
+            call_event::Event::Accepted => EventType::Accepted,
+            call_event::Event::NotAccepted => EventType::NotAccepted,
+            call_event::Event::Delete => {
+                match storage
+                    .fetch_session_by_recipient_id(recipient.id)
+                    .and_then(|session| storage.fetch_call(call_id, session.id))
+                {
+                    Some(call) => storage.delete_call(&call),
+                    None => tracing::debug!("Deleted call is not known"),
+                }
+                return;
+            }
+            call_event::Event::Observed | call_event::Event::UnknownAction => {
+                tracing::warn!("Unsupported call event {:?}", event.event());
+                return;
+            }
+        };
+
+        storage.merge_one_to_one_call(
+            call_id,
+            timestamp,
+            recipient.id,
+            r#type,
+            is_outgoing,
+            event,
+            false,
+        );
+    }
+
+    /// The call log was cleared or read on another device.
+    pub(super) fn handle_sync_call_log_event(&mut self, event: &CallLogEvent) {
+        let storage = self.storage.clone().expect("storage");
+        let Some(timestamp) = event.timestamp.map(millis_to_naive_chrono) else {
+            tracing::warn!("Call log event without timestamp");
+            return;
+        };
+        let session_id = match event.r#type() {
+            call_log_event::Type::Clear | call_log_event::Type::MarkedAsRead => None,
+            call_log_event::Type::MarkedAsReadInConversation
+            | call_log_event::Type::ClearInConversation => {
+                match event
+                    .conversation_id
+                    .as_deref()
+                    .and_then(|id| self.call_conversation(id))
+                {
+                    Some(session) => Some(session.id),
+                    None => {
+                        tracing::warn!("Call log event for unknown conversation");
+                        return;
+                    }
+                }
+            }
+        };
+
+        let count = match event.r#type() {
+            call_log_event::Type::Clear | call_log_event::Type::ClearInConversation => {
+                storage.clear_call_log(timestamp, session_id)
+            }
+            call_log_event::Type::MarkedAsRead
+            | call_log_event::Type::MarkedAsReadInConversation => {
+                storage.mark_call_log_read(timestamp, session_id)
+            }
+        };
+        tracing::debug!("Call log event {:?} affected {count} calls", event.r#type());
+    }
+
+    /// Record the outcome of a one-to-one call, and let our other devices know.
+    #[cfg(feature = "calling")]
+    pub(super) fn sync_call_event(
+        &mut self,
+        ctx: &mut <Self as Actor>::Context,
+        call_id: u64,
+        event: EventType,
+    ) {
+        let storage = self.storage.as_ref().expect("storage");
+        let Some(call) = storage.fetch_call_by_call_id(call_id) else {
+            tracing::warn!("Call {call_id} is not in the database");
+            return;
+        };
+        if call.event == event {
+            return;
+        }
+        storage.update_call_event(&call, event);
+
+        let aci = storage
+            .fetch_session_by_id(call.session_id)
+            .filter(|s| s.is_dm())
+            .and_then(|s| s.unwrap_dm().uuid);
+        let Some(aci) = aci else {
+            tracing::warn!("Call peer has no ACI, not syncing the call event");
+            return;
+        };
+
+        let r#type = match call.is_video() {
+            true => call_event::Type::VideoCall,
+            false => call_event::Type::AudioCall,
+        };
+        let direction = match call.is_outbound {
+            true => call_event::Direction::Outgoing,
+            false => call_event::Direction::Incoming,
+        };
+        let event = match event {
+            EventType::Accepted => call_event::Event::Accepted,
+            _ => call_event::Event::NotAccepted,
+        };
+        let sync = SyncMessage {
+            call_event: Some(CallEvent {
+                conversation_id: Some(aci.as_bytes().to_vec()),
+                id: Some(call_id),
+                timestamp: Some(naive_chrono_to_millis(call.timestamp)),
+                r#type: Some(r#type as i32),
+                direction: Some(direction as i32),
+                event: Some(event as i32),
+            }),
+            ..SyncMessage::with_padding(&mut rand::rng())
+        };
+        ctx.notify(DeliverSyncMessage(sync));
+    }
+
+    fn sync_call_log_event(
+        &mut self,
+        ctx: &mut <Self as Actor>::Context,
+        r#type: call_log_event::Type,
+        timestamp: u64,
+    ) {
+        let sync = SyncMessage {
+            call_log_event: Some(CallLogEvent {
+                r#type: Some(r#type as i32),
+                timestamp: Some(timestamp),
+                ..Default::default()
+            }),
+            ..SyncMessage::with_padding(&mut rand::rng())
+        };
+        ctx.notify(DeliverSyncMessage(sync));
+    }
+}
+
+impl Handler<ClearCallLog> for ClientActor {
+    type Result = ();
+
+    fn handle(&mut self, _: ClearCallLog, ctx: &mut Self::Context) -> Self::Result {
+        let now = Utc::now();
+        let storage = self.storage.as_ref().expect("storage");
+        let count = storage.clear_call_log(now.naive_utc(), None);
+        tracing::info!("Cleared {count} calls from the call log");
+        self.sync_call_log_event(
+            ctx,
+            call_log_event::Type::Clear,
+            now.timestamp_millis() as u64,
+        );
+    }
+}
+
+impl Handler<MarkCallLogRead> for ClientActor {
+    type Result = ();
+
+    fn handle(&mut self, _: MarkCallLogRead, ctx: &mut Self::Context) -> Self::Result {
+        let now = Utc::now();
+        let storage = self.storage.as_ref().expect("storage");
+        if storage.mark_call_log_read(now.naive_utc(), None) == 0 {
+            return;
+        }
+        self.sync_call_log_event(
+            ctx,
+            call_log_event::Type::MarkedAsRead,
+            now.timestamp_millis() as u64,
+        );
+    }
+}
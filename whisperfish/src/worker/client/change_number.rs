@@ -0,0 +1,97 @@
+use super::*;
+use libsignal_service::proto::sync_message::PniChangeNumber;
+use libsignal_service::protocol::{IdentityKeyPair, KyberPreKeyRecord, SignedPreKeyRecord};
+
+/// Our primary device changed our phone number, and with it our PNI identity.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ChangeNumber {
+    pub change: PniChangeNumber,
+    /// Our new PNI, from the envelope that carried the change.
+    pub pni: Pni,
+}
+
+struct NewPniIdentity {
+    identity_key_pair: IdentityKeyPair,
+    signed_pre_key: SignedPreKeyRecord,
+    last_resort_kyber_pre_key: KyberPreKeyRecord,
+    registration_id: u32,
+    e164: PhoneNumber,
+}
+
+impl TryFrom<PniChangeNumber> for NewPniIdentity {
+    type Error = anyhow::Error;
+
+    fn try_from(change: PniChangeNumber) -> Result<Self, Self::Error> {
+        Ok(Self {
+            identity_key_pair: IdentityKeyPair::try_from(
+                change
+                    .identity_key_pair
+                    .as_deref()
+                    .context("no identity key pair")?,
+            )?,
+            signed_pre_key: SignedPreKeyRecord::deserialize(
+                change
+                    .signed_pre_key
+                    .as_deref()
+                    .context("no signed prekey")?,
+            )?,
+            last_resort_kyber_pre_key: KyberPreKeyRecord::deserialize(
+                change
+                    .last_resort_kyber_pre_key
+                    .as_deref()
+                    .context("no last resort Kyber prekey")?,
+            )?,
+            registration_id: change.registration_id.context("no registration id")?,
+            e164: PhoneNumber::from_str(change.new_e164.as_deref().context("no phone number")?)?,
+        })
+    }
+}
+
+impl Handler<ChangeNumber> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        ChangeNumber { change, pni }: ChangeNumber,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let storage = self.storage.clone().expect("storage");
+        let config = self.config.clone();
+        let aci = self.self_aci;
+
+        Box::pin(
+            async move {
+                let new = NewPniIdentity::try_from(change)?;
+
+                storage
+                    .pni_storage()
+                    .replace_identity(
+                        new.identity_key_pair,
+                        new.registration_id,
+                        &new.signed_pre_key,
+                        &new.last_resort_kyber_pre_key,
+                    )
+                    .await?;
+
+                config.set_tel(new.e164.clone());
+                config.set_pni(pni.into());
+                config.write_to_file()?;
+
+                storage.invalidate_self_recipient();
+                storage.merge_and_fetch_self_recipient(Some(new.e164), aci, Some(pni));
+
+                Ok(pni)
+            }
+            .instrument(tracing::info_span!("change number"))
+            .into_actor(self)
+            .map(|res: anyhow::Result<Pni>, act, _ctx| match res {
+                Ok(pni) => {
+                    tracing::info!("Changed our phone number and PNI");
+                    act.self_pni = Some(pni);
+                }
+                Err(e) => tracing::error!("Could not change our phone number: {e:#}"),
+            }),
+        )
+    }
+}
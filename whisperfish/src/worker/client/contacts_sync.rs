@@ -0,0 +1,60 @@
+use super::attachment::download_attachment_contents;
+use super::*;
+use whisperfish_store::contacts_sync::parse_contacts_stream;
+use whisperfish_store::store::attachment_download::partial_contacts_path;
+
+/// Download the contacts that our primary device sent, and merge them into our recipients.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ImportContacts {
+    pub blob: AttachmentPointer,
+}
+
+impl Handler<ImportContacts> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        ImportContacts { blob }: ImportContacts,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let service = self.unauthenticated_service();
+        let storage = self.storage.clone().unwrap();
+        let avatar_dir = PathBuf::from(self.settings.get_string("avatar_dir"));
+        let partial = partial_contacts_path(Path::new(&self.settings.get_string("attachment_dir")));
+
+        Box::pin(
+            async move {
+                // A partial download of an earlier sync is of another blob.
+                tokio::fs::remove_file(&partial).await.ok();
+                let data = download_attachment_contents(&service, &blob, &partial).await?;
+                let contacts = parse_contacts_stream(&data)?;
+                tracing::info!("Importing {} synced contacts", contacts.len());
+
+                for contact in contacts {
+                    let Some(recipient) = storage.merge_synced_contact(&contact.details) else {
+                        continue;
+                    };
+                    // A profile avatar, once fetched, takes precedence.
+                    if let (Some(avatar), Some(uuid), None) = (
+                        contact.avatar,
+                        recipient.uuid,
+                        &recipient.signal_profile_avatar,
+                    ) {
+                        storage
+                            .save_file(avatar_dir.join(uuid.to_string()), &avatar)
+                            .await?;
+                    }
+                }
+                Ok(())
+            }
+            .instrument(tracing::info_span!("import contacts"))
+            .into_actor(self)
+            .map(|res: anyhow::Result<()>, _act, _ctx| {
+                if let Err(e) = res {
+                    tracing::error!("Could not import synced contacts: {e:#}");
+                }
+            }),
+        )
+    }
+}
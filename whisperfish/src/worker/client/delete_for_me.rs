@@ -0,0 +1,278 @@
+use super::*;
+use crate::store::delete_for_me::MessageAddress;
+use libsignal_service::proto::sync_message::DeleteForMe;
+use libsignal_service::proto::sync_message::delete_for_me::addressable_message::Author;
+use libsignal_service::proto::sync_message::delete_for_me::conversation_identifier::Identifier;
+use libsignal_service::proto::sync_message::delete_for_me::{
+    AddressableMessage, ConversationDelete, ConversationIdentifier, LocalOnlyConversationDelete,
+    MessageDeletes,
+};
+
+/// Delete a session, and let our other devices know.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct DeleteSession(pub i32);
+
+impl ClientActor {
+    fn conversation_identifier(session: &orm::Session) -> Option<ConversationIdentifier> {
+        let identifier = match &session.r#type {
+            SessionType::DirectMessage(recipient) => match (&recipient.uuid, &recipient.e164) {
+                (Some(uuid), _) => Identifier::ThreadServiceId(uuid.to_string()),
+                (None, Some(e164)) => Identifier::ThreadE164(e164.to_string()),
+                (None, None) => return None,
+            },
+            SessionType::GroupV2(group) => Identifier::ThreadGroupId(hex::decode(&group.id).ok()?),
+            SessionType::GroupV1(_) => return None,
+        };
+        Some(ConversationIdentifier {
+            identifier: Some(identifier),
+        })
+    }
+
+    fn addressable_message(&self, message: &orm::Message) -> Option<AddressableMessage> {
+        let storage = self.storage.as_ref().expect("storage");
+        let author = if message.is_outbound {
+            storage.fetch_self_recipient()?
+        } else {
+            storage.fetch_recipient_by_id(message.sender_recipient_id?)?
+        };
+        let author = match (author.uuid, author.e164) {
+            (Some(uuid), _) => Author::AuthorServiceId(uuid.to_string()),
+            (None, Some(e164)) => Author::AuthorE164(e164.to_string()),
+            (None, None) => return None,
+        };
+        Some(AddressableMessage {
+            author: Some(author),
+            sent_timestamp: Some(naive_chrono_to_millis(message.server_timestamp)),
+        })
+    }
+
+    fn session_for_conversation(
+        &self,
+        conversation: Option<&ConversationIdentifier>,
+    ) -> Option<orm::Session> {
+        let storage = self.storage.as_ref().expect("storage");
+        let session = match conversation.and_then(|c| c.identifier.as_ref()) {
+            Some(Identifier::ThreadServiceId(service_id)) => {
+                let service_id = ServiceId::parse_from_service_id_string(service_id)?;
+                let recipient = storage.fetch_recipient(&service_id)?;
+                storage.fetch_session_by_recipient_id(recipient.id)
+            }
+            Some(Identifier::ThreadServiceIdBinary(service_id)) => {
+                let service_id = ServiceId::parse_from_service_id_binary(service_id)?;
+                let recipient = storage.fetch_recipient(&service_id)?;
+                storage.fetch_session_by_recipient_id(recipient.id)
+            }
+            Some(Identifier::ThreadGroupId(group_id)) => {
+                storage.fetch_session_by_group_v2_id(&hex::encode(group_id))
+            }
+            Some(Identifier::ThreadE164(e164)) => {
+                let e164 = PhoneNumber::from_str(e164).ok()?;
+                storage.fetch_session_by_phonenumber(&e164)
+            }
+            None => None,
+        };
+        if session.is_none() {
+            tracing::warn!("Conversation to delete for me not found");
+        }
+        session
+    }
+
+    fn message_address(&self, message: &AddressableMessage) -> Option<MessageAddress> {
+        let storage = self.storage.as_ref().expect("storage");
+        let author = match message.author.as_ref()? {
+            Author::AuthorServiceId(service_id) => storage.fetch_or_insert_recipient_by_address(
+                &ServiceId::parse_from_service_id_string(service_id)?,
+            ),
+            Author::AuthorServiceIdBinary(service_id) => storage
+                .fetch_or_insert_recipient_by_address(&ServiceId::parse_from_service_id_binary(
+                    service_id,
+                )?),
+            Author::AuthorE164(e164) => {
+                storage.fetch_recipient_by_e164(&PhoneNumber::from_str(e164).ok()?)?
+            }
+        };
+        Some(MessageAddress {
+            author_id: author.id,
+            sent_timestamp: millis_to_naive_chrono(message.sent_timestamp?),
+        })
+    }
+
+    /// Resolve messages that our other devices refer to, within `session`.
+    fn addressed_messages(
+        &self,
+        session: &orm::Session,
+        messages: &[AddressableMessage],
+    ) -> Vec<orm::Message> {
+        let storage = self.storage.as_ref().expect("storage");
+        messages
+            .iter()
+            .filter_map(|message| self.message_address(message))
+            .filter_map(|address| storage.fetch_message_by_address(Some(session.id), address))
+            .collect()
+    }
+
+    pub(super) fn handle_delete_for_me(&mut self, delete_for_me: DeleteForMe) {
+        for deletes in &delete_for_me.message_deletes {
+            let Some(session) = self.session_for_conversation(deletes.conversation.as_ref()) else {
+                continue;
+            };
+            let messages = self.addressed_messages(&session, &deletes.messages);
+            if messages.len() < deletes.messages.len() {
+                tracing::warn!(
+                    "{} message(s) to delete for me not found",
+                    deletes.messages.len() - messages.len()
+                );
+            }
+            let storage = self.storage.as_mut().expect("storage");
+            for message in messages {
+                storage.delete_message(message.id);
+            }
+        }
+
+        for delete in &delete_for_me.conversation_deletes {
+            let Some(session) = self.session_for_conversation(delete.conversation.as_ref()) else {
+                continue;
+            };
+            let addresses = |messages: &[AddressableMessage]| -> Vec<MessageAddress> {
+                messages
+                    .iter()
+                    .filter_map(|message| self.message_address(message))
+                    .collect()
+            };
+            let most_recent = addresses(&delete.most_recent_messages);
+            let most_recent_non_expiring = addresses(&delete.most_recent_non_expiring_messages);
+            self.storage
+                .as_mut()
+                .expect("storage")
+                .delete_conversation_for_me(
+                    session.id,
+                    &most_recent,
+                    &most_recent_non_expiring,
+                    delete.is_full_delete(),
+                );
+        }
+
+        for delete in &delete_for_me.local_only_conversation_deletes {
+            let Some(session) = self.session_for_conversation(delete.conversation.as_ref()) else {
+                continue;
+            };
+            self.storage
+                .as_ref()
+                .expect("storage")
+                .delete_session(session.id);
+        }
+
+        for delete in &delete_for_me.attachment_deletes {
+            let Some(session) = self.session_for_conversation(delete.conversation.as_ref()) else {
+                continue;
+            };
+            let Some(message) = delete
+                .target_message
+                .as_ref()
+                .and_then(|message| self.addressed_messages(&session, &[message.clone()]).pop())
+            else {
+                tracing::warn!("Message of attachment to delete for me not found");
+                continue;
+            };
+            self.storage
+                .as_mut()
+                .expect("storage")
+                .delete_attachment_for_me(
+                    message.id,
+                    delete.client_uuid.as_deref(),
+                    delete.fallback_digest.as_deref(),
+                );
+        }
+    }
+
+    /// Let our other devices know that we deleted `message` locally.
+    pub(super) fn sync_message_delete(
+        &self,
+        ctx: &mut <Self as Actor>::Context,
+        session: &orm::Session,
+        message: &orm::Message,
+    ) {
+        let (Some(conversation), Some(message)) = (
+            Self::conversation_identifier(session),
+            self.addressable_message(message),
+        ) else {
+            tracing::warn!("Cannot address deleted message, not syncing the delete");
+            return;
+        };
+        let sync = SyncMessage {
+            delete_for_me: Some(DeleteForMe {
+                message_deletes: vec![MessageDeletes {
+                    conversation: Some(conversation),
+                    messages: vec![message],
+                }],
+                ..Default::default()
+            }),
+            ..SyncMessage::with_padding(&mut rand::rng())
+        };
+        ctx.notify(DeliverSyncMessage(sync));
+    }
+}
+
+impl Handler<DeleteSession> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        DeleteSession(session_id): DeleteSession,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let _span = tracing::info_span!("DeleteSession", session_id).entered();
+        let storage = self.storage.clone().expect("storage");
+        let Some(session) = storage.fetch_session_by_id(session_id) else {
+            tracing::warn!("Tried to delete non-existing session");
+            return;
+        };
+
+        let addressable = |messages: Vec<orm::Message>| -> Vec<AddressableMessage> {
+            messages
+                .iter()
+                .filter_map(|message| self.addressable_message(message))
+                .collect()
+        };
+        let conversation_delete =
+            Self::conversation_identifier(&session).map(|conversation| ConversationDelete {
+                conversation: Some(conversation),
+                most_recent_messages: addressable(
+                    storage.fetch_most_recent_messages(session_id, false),
+                ),
+                most_recent_non_expiring_messages: addressable(
+                    storage.fetch_most_recent_messages(session_id, true),
+                ),
+                is_full_delete: Some(true),
+            });
+
+        storage.delete_session(session_id);
+
+        // A conversation without addressable messages, e.g. one with only local updates,
+        // can only be deleted as a whole.
+        let delete_for_me = match conversation_delete {
+            Some(conversation_delete) if !conversation_delete.most_recent_messages.is_empty() => {
+                DeleteForMe {
+                    conversation_deletes: vec![conversation_delete],
+                    ..Default::default()
+                }
+            }
+            Some(conversation_delete) => DeleteForMe {
+                local_only_conversation_deletes: vec![LocalOnlyConversationDelete {
+                    conversation: conversation_delete.conversation,
+                }],
+                ..Default::default()
+            },
+            None => {
+                tracing::debug!("Nothing to address the deleted session by, not syncing");
+                return;
+            }
+        };
+        let sync = SyncMessage {
+            delete_for_me: Some(delete_for_me),
+            ..SyncMessage::with_padding(&mut rand::rng())
+        };
+        ctx.notify(DeliverSyncMessage(sync));
+    }
+}
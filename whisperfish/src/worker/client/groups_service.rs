@@ -0,0 +1,839 @@
+use super::*;
+use base64::prelude::*;
+use libsignal_service::configuration::Endpoint;
+use libsignal_service::groups_v2::{CredentialsCache, GroupsManager};
+use libsignal_service::proto::{
+    AvatarUploadAttributes, Group, GroupChange, GroupChangeResponse, GroupJoinInfo, group_change,
+};
+use libsignal_service::push_service::{HttpAuthOverride, ReqwestExt};
+use qmeta_async::with_executor;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Method, StatusCode};
+use tracing_futures::Instrument;
+use whisperfish_store::groups::{
+    GroupInviteLink, GroupsServiceApi, NewGroupV2, RevisionConflict, fetch_group_invite_preview,
+};
+use zkgroup::api::groups::GroupSecretParams;
+use zkgroup::profiles::{
+    ExpiringProfileKeyCredentialResponse, ProfileKeyCredentialRequest, ProfileKeyVersion,
+};
+
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+const MULTIPART_BOUNDARY: &str = "whisperfish-group-avatar";
+
+/// The part of a profile that carries the requested credential.
+#[derive(serde::Deserialize)]
+struct CredentialProfile {
+    /// Base64 encoded [ExpiringProfileKeyCredentialResponse].
+    credential: Option<String>,
+}
+
+/// The group server over HTTP.
+///
+/// Requests to the group server are authorized with a credential of the group itself,
+/// which the [GroupsManager] obtains from the chat service.
+struct HttpGroupsService<C: CredentialsCache> {
+    service: PushService,
+    manager: GroupsManager<C>,
+}
+
+impl<C: CredentialsCache> HttpGroupsService<C> {
+    async fn auth(&mut self, secret: &GroupSecretParams) -> anyhow::Result<HttpAuthOverride> {
+        let auth = self
+            .manager
+            .get_authorization_for_today(&mut rand::rng(), *secret)
+            .await?;
+        Ok(HttpAuthOverride::Identified(auth))
+    }
+
+    /// Submit a change to a group, returning the change signed by the server.
+    async fn patch_group(
+        &mut self,
+        secret: &GroupSecretParams,
+        path: &str,
+        actions: group_change::Actions,
+    ) -> anyhow::Result<GroupChange> {
+        let auth = self.auth(secret).await?;
+        let response = self
+            .service
+            .request(Method::PATCH, Endpoint::storage(path), auth)?
+            .header(CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)
+            .body(actions.encode_to_vec())
+            .send()
+            .await?;
+        if response.status() == StatusCode::CONFLICT {
+            return Err(RevisionConflict.into());
+        }
+        let body = response.service_error_for_status().await?.bytes().await?;
+        GroupChangeResponse::decode(body)?
+            .group_change
+            .context("no group change in response")
+    }
+
+    /// A token that proves our membership of the group to the calling server.
+    #[cfg(feature = "calling")]
+    async fn fetch_group_call_token(
+        &mut self,
+        secret: &GroupSecretParams,
+    ) -> anyhow::Result<Vec<u8>> {
+        let auth = self.auth(secret).await?;
+        let body = self
+            .service
+            .request(Method::GET, Endpoint::storage("/v2/groups/token"), auth)?
+            .send()
+            .await?
+            .service_error_for_status()
+            .await?
+            .bytes()
+            .await?;
+        Ok(
+            libsignal_service::proto::GroupExternalCredential::decode(body)?
+                .token
+                .into_bytes(),
+        )
+    }
+
+    /// Build a `multipart/form-data` body for an upload to the CDN.
+    fn avatar_form(form: &AvatarUploadAttributes, avatar: Vec<u8>) -> Vec<u8> {
+        let fields = [
+            ("key", &form.key),
+            ("x-amz-credential", &form.credential),
+            ("acl", &form.acl),
+            ("x-amz-algorithm", &form.algorithm),
+            ("x-amz-date", &form.date),
+            ("policy", &form.policy),
+            ("x-amz-signature", &form.signature),
+        ];
+        let mut body = Vec::with_capacity(avatar.len() + 1024);
+        for (name, value) in fields {
+            body.extend_from_slice(
+                format!(
+                    "--{MULTIPART_BOUNDARY}\r\n\
+                     Content-Disposition: form-data; name=\"{name}\"\r\n\r\n\
+                     {value}\r\n"
+                )
+                .as_bytes(),
+            );
+        }
+        body.extend_from_slice(
+            format!(
+                "--{MULTIPART_BOUNDARY}\r\n\
+                 Content-Disposition: form-data; name=\"file\"; filename=\"file\"\r\n\
+                 Content-Type: application/octet-stream\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend(avatar);
+        body.extend_from_slice(format!("\r\n--{MULTIPART_BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: CredentialsCache + Send> GroupsServiceApi for HttpGroupsService<C> {
+    async fn fetch_profile_key_credential(
+        &mut self,
+        aci: Aci,
+        version: ProfileKeyVersion,
+        request: ProfileKeyCredentialRequest,
+    ) -> anyhow::Result<Option<ExpiringProfileKeyCredentialResponse>> {
+        let version = zkgroup::serialize(&version);
+        let version = std::str::from_utf8(&version)?;
+        let request = hex::encode(zkgroup::serialize(&request));
+        let path = format!(
+            "/v1/profile/{}/{version}/{request}?credentialType=expiringProfileKey",
+            aci.service_id_string()
+        );
+        let response = self
+            .service
+            .request(
+                Method::GET,
+                Endpoint::service(&path),
+                HttpAuthOverride::NoOverride,
+            )?
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let profile: CredentialProfile = response.service_error_for_status().await?.json().await?;
+        profile
+            .credential
+            .map(|credential| {
+                zkgroup::deserialize(&BASE64_STANDARD.decode(credential)?)
+                    .map_err(|_| anyhow!("malformed profile key credential response"))
+            })
+            .transpose()
+    }
+
+    async fn upload_avatar(
+        &mut self,
+        secret: &GroupSecretParams,
+        encrypted_avatar: Vec<u8>,
+    ) -> anyhow::Result<String> {
+        let auth = self.auth(secret).await?;
+        let body = self
+            .service
+            .request(
+                Method::GET,
+                Endpoint::storage("/v2/groups/avatar/form"),
+                auth,
+            )?
+            .send()
+            .await?
+            .service_error_for_status()
+            .await?
+            .bytes()
+            .await?;
+        let form = AvatarUploadAttributes::decode(body)?;
+
+        self.service
+            .request(
+                Method::POST,
+                Endpoint::cdn(0, "/"),
+                HttpAuthOverride::Unidentified,
+            )?
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={MULTIPART_BOUNDARY}"),
+            )
+            .body(Self::avatar_form(&form, encrypted_avatar))
+            .send()
+            .await?
+            .service_error_for_status()
+            .await?;
+        Ok(form.key)
+    }
+
+    async fn create_group(
+        &mut self,
+        secret: &GroupSecretParams,
+        group: Group,
+    ) -> anyhow::Result<()> {
+        let auth = self.auth(secret).await?;
+        self.service
+            .request(Method::PUT, Endpoint::storage("/v2/groups/"), auth)?
+            .header(CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)
+            .body(group.encode_to_vec())
+            .send()
+            .await?
+            .service_error_for_status()
+            .await?;
+        Ok(())
+    }
+
+    async fn modify_group(
+        &mut self,
+        secret: &GroupSecretParams,
+        actions: group_change::Actions,
+    ) -> anyhow::Result<GroupChange> {
+        self.patch_group(secret, "/v2/groups/", actions).await
+    }
+
+    async fn fetch_group_join_info(
+        &mut self,
+        secret: &GroupSecretParams,
+        password: &[u8],
+    ) -> anyhow::Result<GroupJoinInfo> {
+        let auth = self.auth(secret).await?;
+        let path = format!(
+            "/v2/groups/join/{}",
+            BASE64_URL_SAFE_NO_PAD.encode(password)
+        );
+        let body = self
+            .service
+            .request(Method::GET, Endpoint::storage(&path), auth)?
+            .send()
+            .await?
+            .service_error_for_status()
+            .await?
+            .bytes()
+            .await?;
+        Ok(GroupJoinInfo::decode(body)?)
+    }
+
+    async fn join_group(
+        &mut self,
+        secret: &GroupSecretParams,
+        password: &[u8],
+        actions: group_change::Actions,
+    ) -> anyhow::Result<GroupChange> {
+        let path = format!(
+            "/v2/groups/?inviteLinkPassword={}",
+            BASE64_URL_SAFE_NO_PAD.encode(password)
+        );
+        self.patch_group(secret, &path, actions).await
+    }
+}
+
+impl ClientActor {
+    /// Fetch the group call membership proof of the group with `master_key`.
+    #[cfg(feature = "calling")]
+    pub(super) fn fetch_group_call_token(
+        &self,
+        master_key: zkgroup::GroupMasterKey,
+    ) -> impl Future<Output = anyhow::Result<Vec<u8>>> + use<> {
+        let storage = self.storage.clone().unwrap();
+        let service = self.authenticated_service();
+        let zk_params =
+            ServiceConfiguration::from(self.signal_server()).zkgroup_server_public_params;
+        let service_ids = self.service_ids().expect("whoami");
+        let u_ws = self.unidentified_websocket();
+
+        async move {
+            let u_ws = u_ws.await?;
+            let mut credential_cache = storage.credential_cache_mut().await;
+            let manager = GroupsManager::new(
+                service_ids,
+                service.clone(),
+                u_ws,
+                &mut *credential_cache,
+                zk_params,
+            );
+            let mut api = HttpGroupsService { service, manager };
+            api.fetch_group_call_token(&GroupSecretParams::derive_from_master_key(master_key))
+                .await
+        }
+    }
+}
+
+impl ClientWorker {
+    /// Create a group with the comma-separated phone numbers in `members`.
+    #[with_executor]
+    #[tracing::instrument(skip(self))]
+    #[allow(non_snake_case)]
+    pub fn createGroupV2(
+        &self,
+        title: String,
+        description: String,
+        avatar_path: String,
+        expire_timer: u32,
+        members: String,
+    ) {
+        let members = members
+            .split(',')
+            .filter(|number| !number.is_empty())
+            .filter_map(|number| match phonenumber::parse(None, number) {
+                Ok(number) => Some(number),
+                Err(e) => {
+                    tracing::warn!("Skipping group member {}: {}", number, e);
+                    None
+                }
+            })
+            .collect();
+
+        actix::spawn(
+            self.actor
+                .as_ref()
+                .unwrap()
+                .send(CreateGroupV2 {
+                    title,
+                    description: Some(description).filter(|d| !d.is_empty()),
+                    avatar_path: Some(avatar_path).filter(|p| !p.is_empty()),
+                    expire_timer: Some(expire_timer).filter(|&t| t > 0),
+                    members,
+                })
+                .map(Result::unwrap),
+        );
+    }
+
+    /// Show what the group behind an invite link is, before joining it.
+    #[with_executor]
+    #[tracing::instrument(skip(self, url))]
+    #[allow(non_snake_case)]
+    pub fn previewGroupInviteLink(&self, url: String) {
+        actix::spawn(
+            self.actor
+                .as_ref()
+                .unwrap()
+                .send(PreviewGroupInviteLink { url })
+                .map(Result::unwrap),
+        );
+    }
+
+    #[with_executor]
+    #[tracing::instrument(skip(self, url))]
+    #[allow(non_snake_case)]
+    pub fn joinGroupV2(&self, url: String) {
+        actix::spawn(
+            self.actor
+                .as_ref()
+                .unwrap()
+                .send(JoinGroupV2 { url })
+                .map(Result::unwrap),
+        );
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Create a new group with the given recipients, and announce it to them.
+pub struct CreateGroupV2 {
+    pub title: String,
+    pub description: Option<String>,
+    pub avatar_path: Option<String>,
+    /// Disappearing messages timer, in seconds.
+    pub expire_timer: Option<u32>,
+    pub members: Vec<PhoneNumber>,
+}
+
+impl Handler<CreateGroupV2> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, create: CreateGroupV2, _ctx: &mut Self::Context) -> Self::Result {
+        let storage = self.storage.clone().unwrap();
+        let service = self.authenticated_service();
+        let zk_params =
+            ServiceConfiguration::from(self.signal_server()).zkgroup_server_public_params;
+        let service_ids = self.service_ids().expect("whoami");
+        let u_ws = self.unidentified_websocket();
+
+        Box::pin(
+            async move {
+                let members = create
+                    .members
+                    .iter()
+                    .map(|number| {
+                        storage
+                            .fetch_recipient_by_e164(number)
+                            .with_context(|| format!("no recipient with number {number}"))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let avatar = match &create.avatar_path {
+                    Some(path) => Some(
+                        tokio::fs::read(path)
+                            .await
+                            .with_context(|| format!("reading group avatar {path}"))?,
+                    ),
+                    None => None,
+                };
+
+                let u_ws = u_ws.await?;
+                let mut credential_cache = storage.credential_cache_mut().await;
+                let manager = GroupsManager::new(
+                    service_ids,
+                    service.clone(),
+                    u_ws,
+                    &mut *credential_cache,
+                    zk_params,
+                );
+                let mut api = HttpGroupsService { service, manager };
+                storage
+                    .create_group_v2(
+                        &mut api,
+                        &zk_params,
+                        NewGroupV2 {
+                            title: create.title,
+                            description: create.description,
+                            avatar,
+                            expire_timer: create.expire_timer,
+                            members,
+                        },
+                    )
+                    .await
+            }
+            .instrument(tracing::info_span!("create group v2"))
+            .into_actor(self)
+            .map(|result, act, ctx| match result {
+                Ok(created) => {
+                    let session = created.session;
+                    tracing::info!("Created group v2 in session {}", session.id);
+
+                    // Members learn about the group through its master key.
+                    let timestamp = Utc::now().timestamp_millis() as u64;
+                    act.transient_timestamps.insert(timestamp);
+                    ctx.notify(DeliverMessage {
+                        content: DataMessage {
+                            group_v2: Some(GroupContextV2 {
+                                master_key: Some(created.master_key.to_vec()),
+                                revision: Some(0),
+                                group_change: None,
+                            }),
+                            profile_key: act
+                                .storage
+                                .as_ref()
+                                .unwrap()
+                                .fetch_self_recipient_profile_key(),
+                            timestamp: Some(timestamp),
+                            ..Default::default()
+                        },
+                        timestamp,
+                        online: false,
+                        for_story: false,
+                        destination: session.r#type.clone().into(),
+                    });
+                    ctx.notify(RequestGroupV2InfoBySessionId(session.id));
+                    act.inner.pinned().borrow().groupV2Created(session.id);
+                }
+                Err(e) => {
+                    tracing::error!("Creating group v2 failed: {e:#}");
+                    act.inner.pinned().borrow().groupV2CreationFailed();
+                }
+            }),
+        )
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Change a group on the server, and send the change to its members.
+pub struct ModifyGroupV2 {
+    pub group_id: String,
+    pub action: GroupV2Action,
+}
+
+impl Handler<ModifyGroupV2> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        ModifyGroupV2 { group_id, action }: ModifyGroupV2,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let storage = self.storage.clone().unwrap();
+        let Some(session) = storage.fetch_session_by_group_v2_id(&group_id) else {
+            tracing::error!("No such group: '{}'", group_id);
+            return Box::pin(async {}.into_actor(self));
+        };
+        let group_v2 = session.unwrap_group_v2().clone();
+        // Removed members and denied requests are not in the session, but need to learn about
+        // the change.
+        let removed = match &action {
+            GroupV2Action::RemoveMember(recipient)
+            | GroupV2Action::BanMember(recipient)
+            | GroupV2Action::DenyRequest(recipient) => recipient.to_service_address(),
+            _ => None,
+        };
+        let new_avatar = matches!(action, GroupV2Action::Avatar(Some(_)));
+        // Pending members only know the group from their invitation.
+        let joined = matches!(action, GroupV2Action::AcceptInvitation);
+
+        let service = self.authenticated_service();
+        let zk_params =
+            ServiceConfiguration::from(self.signal_server()).zkgroup_server_public_params;
+        let service_ids = self.service_ids().expect("whoami");
+        let u_ws = self.unidentified_websocket();
+        let client = ctx.address();
+
+        Box::pin(
+            async move {
+                let u_ws = u_ws.await?;
+                let mut group_v2 = group_v2;
+                let mut refetched = false;
+                let (context, service_messages) = loop {
+                    let mut credential_cache = storage.credential_cache_mut().await;
+                    let manager = GroupsManager::new(
+                        service_ids,
+                        service.clone(),
+                        u_ws.clone(),
+                        &mut *credential_cache,
+                        zk_params,
+                    );
+                    let mut api = HttpGroupsService {
+                        service: service.clone(),
+                        manager,
+                    };
+                    match storage
+                        .modify_group_v2(&mut api, &zk_params, &group_v2, action.clone())
+                        .await
+                    {
+                        Ok(context) => {
+                            let service_messages = match api
+                                .manager
+                                .decrypt_group_context(context.clone())
+                            {
+                                Ok(Some(changes)) => changes
+                                    .changes
+                                    .iter()
+                                    .filter_map(group_change_to_service_message_json)
+                                    .map(|message| GroupChangeServiceMessage {
+                                        message,
+                                        editor: changes.editor,
+                                        group_id: group_v2.id.clone(),
+                                    })
+                                    .collect(),
+                                Ok(None) => Vec::new(),
+                                Err(e) => {
+                                    tracing::warn!("Could not decrypt our own group change: {}", e);
+                                    Vec::new()
+                                }
+                            };
+                            break (context, service_messages);
+                        }
+                        // Someone else changed the group before us: catch up, and try once more
+                        // against the current revision.
+                        Err(e) if !refetched && e.downcast_ref::<RevisionConflict>().is_some() => {
+                            tracing::info!("Group changed on the server, refetching it");
+                        }
+                        Err(e) => return Err(e),
+                    }
+                    drop(api);
+                    // Refreshing the group needs the credential cache too.
+                    drop(credential_cache);
+
+                    let mut master_key = [0u8; zkgroup::GROUP_MASTER_KEY_LEN];
+                    master_key.clone_from_slice(&hex::decode(&group_v2.master_key)?);
+                    let group = crate::store::GroupV2 {
+                        secret: GroupSecretParams::derive_from_master_key(GroupMasterKey::new(
+                            master_key,
+                        )),
+                        revision: group_v2.revision as _,
+                    };
+                    client.send(RequestGroupV2Info(group, master_key)).await?;
+                    group_v2 = storage
+                        .fetch_session_by_group_v2_id(&group_v2.id)
+                        .context("group disappeared while refetching it")?
+                        .unwrap_group_v2()
+                        .clone();
+                    refetched = true;
+                };
+                Ok::<_, anyhow::Error>((context, service_messages))
+            }
+            .instrument(tracing::info_span!("modify group v2", %group_id))
+            .into_actor(self)
+            .map(move |result, act, ctx| match result {
+                Ok((context, service_messages)) => {
+                    let timestamp = Utc::now().timestamp_millis() as u64;
+                    act.transient_timestamps.insert(timestamp);
+                    let content = DataMessage {
+                        group_v2: Some(context),
+                        profile_key: act
+                            .storage
+                            .as_ref()
+                            .unwrap()
+                            .fetch_self_recipient_profile_key(),
+                        timestamp: Some(timestamp),
+                        ..Default::default()
+                    };
+                    if let Some(removed) = removed {
+                        ctx.notify(DeliverMessage {
+                            content: content.clone(),
+                            timestamp,
+                            online: false,
+                            for_story: false,
+                            destination: DeliveryRecipient::ServiceId(removed),
+                        });
+                    }
+                    ctx.notify(DeliverMessage {
+                        content,
+                        timestamp,
+                        online: false,
+                        for_story: false,
+                        destination: session.r#type.clone().into(),
+                    });
+                    for message in service_messages {
+                        ctx.notify(message);
+                    }
+                    if new_avatar {
+                        ctx.notify(RefreshGroupAvatar(group_id));
+                    }
+                    if joined {
+                        ctx.notify(RequestGroupV2InfoBySessionId(session.id));
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Changing group {} failed: {e:#}", group_id);
+                    // We may have been working from an outdated revision.
+                    ctx.notify(RequestGroupV2InfoBySessionId(session.id));
+                }
+            }),
+        )
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Set the avatar of a group to the image at `avatar_path`, or remove it.
+pub struct SetGroupV2Avatar {
+    pub group_id: String,
+    pub avatar_path: Option<String>,
+}
+
+impl Handler<SetGroupV2Avatar> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        SetGroupV2Avatar {
+            group_id,
+            avatar_path,
+        }: SetGroupV2Avatar,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        Box::pin(
+            async move {
+                let avatar = match &avatar_path {
+                    Some(path) => Some(
+                        tokio::fs::read(path)
+                            .await
+                            .with_context(|| format!("reading group avatar {path}"))?,
+                    ),
+                    None => None,
+                };
+                Ok::<_, anyhow::Error>(avatar)
+            }
+            .into_actor(self)
+            .map(move |result, _act, ctx| match result {
+                Ok(avatar) => ctx.notify(ModifyGroupV2 {
+                    group_id,
+                    action: GroupV2Action::Avatar(avatar),
+                }),
+                Err(e) => tracing::error!("Could not set the avatar of group {group_id}: {e:#}"),
+            }),
+        )
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Fetch what an invite link reveals about its group.
+pub struct PreviewGroupInviteLink {
+    pub url: String,
+}
+
+impl Handler<PreviewGroupInviteLink> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        PreviewGroupInviteLink { url }: PreviewGroupInviteLink,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let storage = self.storage.clone().unwrap();
+        let service = self.authenticated_service();
+        let zk_params =
+            ServiceConfiguration::from(self.signal_server()).zkgroup_server_public_params;
+        let service_ids = self.service_ids().expect("whoami");
+        let u_ws = self.unidentified_websocket();
+
+        Box::pin(
+            async move {
+                let link = GroupInviteLink::parse(&url)?;
+                let u_ws = u_ws.await?;
+                let mut credential_cache = storage.credential_cache_mut().await;
+                let manager = GroupsManager::new(
+                    service_ids,
+                    service.clone(),
+                    u_ws,
+                    &mut *credential_cache,
+                    zk_params,
+                );
+                let mut api = HttpGroupsService { service, manager };
+                fetch_group_invite_preview(&mut api, &link).await
+            }
+            .instrument(tracing::info_span!("preview group invite link"))
+            .into_actor(self)
+            .map(|result, act, _ctx| match result {
+                Ok(preview) => {
+                    act.inner.pinned().borrow().groupInvitePreviewed(
+                        preview.title.into(),
+                        preview.description.unwrap_or_default().into(),
+                        preview.member_count,
+                        preview.requires_approval,
+                        preview.pending_approval,
+                    );
+                }
+                Err(e) => {
+                    tracing::error!("Previewing group invite link failed: {e:#}");
+                    act.inner.pinned().borrow().groupInvitePreviewFailed();
+                }
+            }),
+        )
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Join a group through its invite link, or ask to join it.
+pub struct JoinGroupV2 {
+    pub url: String,
+}
+
+impl Handler<JoinGroupV2> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        JoinGroupV2 { url }: JoinGroupV2,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let storage = self.storage.clone().unwrap();
+        let service = self.authenticated_service();
+        let zk_params =
+            ServiceConfiguration::from(self.signal_server()).zkgroup_server_public_params;
+        let service_ids = self.service_ids().expect("whoami");
+        let u_ws = self.unidentified_websocket();
+        let client = ctx.address();
+
+        Box::pin(
+            async move {
+                let link = GroupInviteLink::parse(&url)?;
+                let joined = {
+                    let u_ws = u_ws.await?;
+                    let mut credential_cache = storage.credential_cache_mut().await;
+                    let manager = GroupsManager::new(
+                        service_ids,
+                        service.clone(),
+                        u_ws,
+                        &mut *credential_cache,
+                        zk_params,
+                    );
+                    let mut api = HttpGroupsService { service, manager };
+                    storage.join_group_v2(&mut api, &zk_params, &link).await?
+                };
+
+                // As a member, we can fetch the whole group, and learn whom to announce us to.
+                if let Some(context) = &joined.context {
+                    let group = crate::store::GroupV2 {
+                        secret: GroupSecretParams::derive_from_master_key(GroupMasterKey::new(
+                            link.master_key,
+                        )),
+                        revision: context.revision(),
+                    };
+                    client
+                        .send(RequestGroupV2Info(group, link.master_key))
+                        .await?;
+                }
+                Ok::<_, anyhow::Error>(joined)
+            }
+            .instrument(tracing::info_span!("join group v2"))
+            .into_actor(self)
+            .map(|result, act, ctx| match result {
+                Ok(joined) => {
+                    let storage = act.storage.as_ref().unwrap();
+                    let session = storage
+                        .fetch_session_by_id(joined.session.id)
+                        .unwrap_or(joined.session);
+                    tracing::info!("Joined group v2 in session {}", session.id);
+
+                    let pending_approval = joined.context.is_none();
+                    if let Some(context) = joined.context {
+                        let timestamp = Utc::now().timestamp_millis() as u64;
+                        act.transient_timestamps.insert(timestamp);
+                        ctx.notify(DeliverMessage {
+                            content: DataMessage {
+                                group_v2: Some(context),
+                                profile_key: storage.fetch_self_recipient_profile_key(),
+                                timestamp: Some(timestamp),
+                                ..Default::default()
+                            },
+                            timestamp,
+                            online: false,
+                            for_story: false,
+                            destination: session.r#type.clone().into(),
+                        });
+                    }
+                    act.inner
+                        .pinned()
+                        .borrow()
+                        .groupV2Joined(session.id, pending_approval);
+                }
+                Err(e) => {
+                    tracing::error!("Joining group v2 failed: {e:#}");
+                    act.inner.pinned().borrow().groupV2JoinFailed();
+                }
+            }),
+        )
+    }
+}
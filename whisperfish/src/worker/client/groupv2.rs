@@ -1,13 +1,13 @@
 use super::*;
 use crate::store::{GroupV2, TrustLevel, observer::PrimaryKey};
 use actix::prelude::*;
+use base64::prelude::*;
 use diesel::prelude::*;
 use libsignal_service::{
     ServiceIdExt,
     groups_v2::{self, *},
 };
 use qmeta_async::with_executor;
-use tokio::io::AsyncWriteExt;
 use whisperfish_store::NewMessage;
 
 impl ClientWorker {
@@ -398,7 +398,7 @@ impl Handler<RequestGroupV2Info> for ClientActor {
 /// Queue a force-refresh of a group avatar by group hex id
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct RefreshGroupAvatar(String);
+pub struct RefreshGroupAvatar(pub String);
 
 impl Handler<RefreshGroupAvatar> for ClientActor {
     type Result = ();
@@ -492,16 +492,9 @@ impl Handler<GroupAvatarFetched> for ClientActor {
             async move {
                 let settings = crate::config::SettingsBridge::default();
                 let avatar_dir = settings.get_string("avatar_dir");
-                let avatar_dir = Path::new(&avatar_dir);
+                let out_path = Path::new(&avatar_dir).join(&group_id);
 
-                if !avatar_dir.exists() {
-                    std::fs::create_dir(avatar_dir)?;
-                }
-
-                let out_path = avatar_dir.join(&group_id);
-
-                let mut f = tokio::fs::File::create(out_path).await?;
-                f.write_all(&bytes).await?;
+                storage.save_file(out_path, &bytes).await?;
 
                 use whisperfish_store::schema;
                 storage.observe_update(schema::group_v2s::table, group_id.clone());
@@ -560,7 +553,7 @@ fn role_to_string(role: &Role) -> String {
     }
 }
 
-fn group_change_to_service_message_json(group_change: &GroupChange) -> Option<String> {
+pub(super) fn group_change_to_service_message_json(group_change: &GroupChange) -> Option<String> {
     let mut change: Option<String> = None;
     let mut value: Option<String> = None;
     let mut target_aci: Option<String> = None;
@@ -870,9 +863,14 @@ impl Handler<GroupV2Update> for ClientActor {
                                 db_triggers.push(GroupV2Trigger::Revision);
                             }
                             GroupChange::InviteLinkPassword(password) => {
-                                tracing::debug!("Invite link password: {:?}", password);
-                                storage.update_group_v2_invite_link_password(&group_v2, &password);
-                                // TODO: Reftect in UI
+                                tracing::debug!("Invite link password changed");
+                                match BASE64_STANDARD.decode(&password) {
+                                    Ok(password) => storage
+                                        .update_group_v2_invite_link_password(&group_v2, &password),
+                                    Err(e) => {
+                                        tracing::warn!("Malformed invite link password: {}", e)
+                                    }
+                                }
                                 db_triggers.push(GroupV2Trigger::Revision);
                             }
                             GroupChange::MemberAccess(access) => {
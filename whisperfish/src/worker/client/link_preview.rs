@@ -0,0 +1,467 @@
+use super::*;
+use libsignal_service::content::AttachmentPointer;
+use libsignal_service::proto::Preview;
+use regex::Regex;
+use reqwest::header::CONTENT_TYPE;
+use std::sync::LazyLock;
+use url::Url;
+
+/// Pages and images larger than this are cut off, respectively ignored.
+const MAX_DOWNLOAD_SIZE: usize = 2 * 1024 * 1024;
+const MAX_TITLE_LENGTH: usize = 200;
+const MAX_DESCRIPTION_LENGTH: usize = 500;
+
+static META_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<meta\s[^>]*>").expect("valid regex"));
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)([a-z:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).expect("valid regex")
+});
+static TITLE_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").expect("valid regex"));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkPreviewImage {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// The contents of a link preview, as scraped from the linked page.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FetchedLinkPreview {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<LinkPreviewImage>,
+}
+
+/// Retrieves the preview data for a link.
+///
+/// This is a trait, such that preview generation can be tested without network access.
+#[async_trait::async_trait]
+pub trait LinkPreviewFetcher: Send + Sync {
+    /// Fetch the preview for `url`, or `None` if the page has nothing to show.
+    async fn fetch(&self, url: &Url) -> anyhow::Result<Option<FetchedLinkPreview>>;
+}
+
+/// Signal's content proxy, which fetches link previews on behalf of its clients.
+const CONTENT_PROXY: &str = "http://contentproxy.signal.org:443";
+const MAX_REDIRECTS: usize = 5;
+
+/// Fetches link previews through Signal's content proxy, like the other Signal clients do.
+///
+/// The linked website only sees the proxy, and not the address of the user. The proxy resolves
+/// the host names, such that links into the local network cannot be previewed.
+pub struct HttpLinkPreviewFetcher {
+    client: reqwest::Client,
+}
+
+impl Default for HttpLinkPreviewFetcher {
+    fn default() -> Self {
+        let client = reqwest::Client::builder()
+            .proxy(reqwest::Proxy::all(CONTENT_PROXY).expect("content proxy url"))
+            .timeout(Duration::from_secs(15))
+            .redirect(reqwest::redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if is_previewable(attempt.url()) {
+                    attempt.follow()
+                } else {
+                    attempt.stop()
+                }
+            }))
+            .build()
+            .expect("reqwest client");
+        Self { client }
+    }
+}
+
+impl HttpLinkPreviewFetcher {
+    /// GET `url`, returning its content type and at most `MAX_DOWNLOAD_SIZE + 1` bytes of its body.
+    async fn get(&self, url: &Url) -> anyhow::Result<(String, Vec<u8>)> {
+        let mut response = self
+            .client
+            .get(url.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() > MAX_DOWNLOAD_SIZE {
+                body.truncate(MAX_DOWNLOAD_SIZE + 1);
+                break;
+            }
+        }
+        Ok((content_type, body))
+    }
+}
+
+#[async_trait::async_trait]
+impl LinkPreviewFetcher for HttpLinkPreviewFetcher {
+    async fn fetch(&self, url: &Url) -> anyhow::Result<Option<FetchedLinkPreview>> {
+        let (content_type, body) = self.get(url).await?;
+        if !content_type.starts_with("text/html") {
+            tracing::debug!("Not generating a preview for content type {content_type}");
+            return Ok(None);
+        }
+
+        let page = parse_html(url, &String::from_utf8_lossy(&body));
+        if page.title.is_none() && page.image_url.is_none() {
+            return Ok(None);
+        }
+
+        let image = match page.image_url {
+            Some(image_url) => match self.get(&image_url).await {
+                Ok((content_type, data))
+                    if content_type.starts_with("image/") && data.len() <= MAX_DOWNLOAD_SIZE =>
+                {
+                    Some(LinkPreviewImage { content_type, data })
+                }
+                Ok((content_type, _)) => {
+                    tracing::debug!("Ignoring preview image of type {content_type}");
+                    None
+                }
+                Err(e) => {
+                    tracing::warn!("Could not fetch preview image: {e:#}");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        Ok(Some(FetchedLinkPreview {
+            title: page.title,
+            description: page.description,
+            image,
+        }))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ParsedPage {
+    title: Option<String>,
+    description: Option<String>,
+    image_url: Option<Url>,
+}
+
+/// Extract the OpenGraph title, description and image from a page,
+/// falling back to the `<title>` and `description` meta tag.
+fn parse_html(base: &Url, html: &str) -> ParsedPage {
+    let mut meta = HashMap::new();
+    for tag in META_TAG.find_iter(html) {
+        let mut key = None;
+        let mut content = None;
+        for attr in ATTRIBUTE.captures_iter(tag.as_str()) {
+            let value = attr.get(2).or(attr.get(3)).map(|v| v.as_str());
+            match attr[1].to_ascii_lowercase().as_str() {
+                "property" | "name" => key = value.map(str::to_ascii_lowercase),
+                "content" => content = value,
+                _ => {}
+            }
+        }
+        if let (Some(key), Some(content)) = (key, content) {
+            meta.entry(key).or_insert_with(|| decode_entities(content));
+        }
+    }
+
+    let mut take = |keys: &[&str], max_len: usize| {
+        keys.iter()
+            .find_map(|k| meta.remove(*k))
+            .map(|v| truncate(&v, max_len))
+            .filter(|v| !v.is_empty())
+    };
+    let title = take(&["og:title"], MAX_TITLE_LENGTH).or_else(|| {
+        TITLE_TAG
+            .captures(html)
+            .map(|c| truncate(&decode_entities(&c[1]), MAX_TITLE_LENGTH))
+            .filter(|v| !v.is_empty())
+    });
+    let description = take(&["og:description", "description"], MAX_DESCRIPTION_LENGTH);
+    let image_url = take(&["og:image", "og:image:url"], usize::MAX)
+        .and_then(|image| base.join(&image).ok())
+        .filter(is_previewable);
+
+    ParsedPage {
+        title,
+        description,
+        image_url,
+    }
+}
+
+fn decode_entities(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+fn truncate(s: &str, max_len: usize) -> String {
+    let s = s.split_whitespace().join(" ");
+    match s.char_indices().nth(max_len) {
+        Some((idx, _)) => format!("{}…", &s[..idx]),
+        None => s,
+    }
+}
+
+/// Whether Signal allows generating a preview for this link.
+///
+/// Only https links to named hosts are allowed.
+fn is_previewable(url: &Url) -> bool {
+    url.scheme() == "https"
+        && url.username().is_empty()
+        && url.password().is_none()
+        && matches!(url.host(), Some(url::Host::Domain(domain)) if domain.contains('.'))
+}
+
+/// The first link in `text` for which a preview can be generated.
+pub fn first_previewable_url(text: &str) -> Option<Url> {
+    let mut finder = linkify::LinkFinder::new();
+    finder.kinds(&[linkify::LinkKind::Url]);
+    finder
+        .links(text)
+        .filter_map(|link| Url::parse(link.as_str()).ok())
+        .find(is_previewable)
+}
+
+/// Generate the preview for the first previewable link in `text`.
+pub async fn link_preview_for_text(
+    fetcher: &dyn LinkPreviewFetcher,
+    text: &str,
+) -> anyhow::Result<Option<(Url, FetchedLinkPreview)>> {
+    let Some(url) = first_previewable_url(text) else {
+        return Ok(None);
+    };
+    Ok(fetcher.fetch(&url).await?.map(|preview| (url, preview)))
+}
+
+/// The link to generate a preview for when sending `text`, if any.
+///
+/// Messages with attachments or stickers don't get a preview.
+pub(super) fn link_preview_url(msg: &orm::Message, has_attachments: bool) -> Option<Url> {
+    if msg.flags != 0 || has_attachments {
+        return None;
+    }
+    first_previewable_url(msg.text.as_deref()?)
+}
+
+/// Generate the link preview of an outgoing message that is not sent yet, and save it.
+///
+/// This happens before the message is sent, such that a slow website doesn't hold up sending.
+/// The thumbnail is stored with an attachment pointer that is not uploaded yet.
+pub(super) async fn generate_link_preview(
+    storage: &mut Storage,
+    fetcher: &dyn LinkPreviewFetcher,
+    message_id: i32,
+    url: &Url,
+    attachment_dir: &Path,
+) -> anyhow::Result<()> {
+    let Some(fetched) = fetcher.fetch(url).await? else {
+        return Ok(());
+    };
+
+    let preview = Preview {
+        url: Some(url.to_string()),
+        title: fetched.title,
+        description: fetched.description,
+        date: None,
+        image: fetched.image.as_ref().map(|image| AttachmentPointer {
+            content_type: Some(image.content_type.clone()),
+            size: Some(image.data.len() as u32),
+            ..Default::default()
+        }),
+    };
+
+    let thumbnail_id = storage.register_link_preview(message_id, &preview);
+    if let (Some(thumbnail_id), Some(image)) = (thumbnail_id, fetched.image) {
+        let ext = mime_guess::get_mime_extensions_str(&image.content_type)
+            .and_then(|x| x.first())
+            .copied()
+            .unwrap_or("bin");
+        storage
+            .save_attachment(thumbnail_id, attachment_dir, ext, &image.data)
+            .await?;
+    }
+    Ok(())
+}
+
+/// The `Preview` to send along with an outgoing message, from its stored link preview.
+///
+/// The thumbnail is uploaded the first time the message is sent, and reused when sending is retried.
+pub(super) async fn link_preview_for_message(
+    sender: &mut MessageSender<AciOrPniStorage>,
+    storage: &mut Storage,
+    msg: &orm::AugmentedMessage,
+) -> anyhow::Result<Option<Preview>> {
+    let Some((preview, thumbnail)) = storage
+        .fetch_link_previews_for_message(msg.id)
+        .into_iter()
+        .next()
+    else {
+        return Ok(None);
+    };
+
+    let image = match thumbnail {
+        Some(thumbnail) => {
+            let ptr = thumbnail
+                .pointer
+                .as_deref()
+                .map(AttachmentPointer::decode)
+                .transpose()?
+                .unwrap_or_default();
+            if ptr.attachment_identifier.is_some() {
+                Some(ptr)
+            } else {
+                let path = thumbnail
+                    .absolute_attachment_path()
+                    .context("link preview thumbnail without file")?;
+                let data = storage
+                    .read_attachment(&*path)
+                    .await
+                    .context("reading link preview thumbnail")?;
+                let spec = AttachmentSpec {
+                    content_type: thumbnail.content_type.clone(),
+                    length: data.len(),
+                    file_name: None,
+                    preview: None,
+                    voice_note: None,
+                    borderless: None,
+                    width: None,
+                    height: None,
+                    caption: None,
+                    blur_hash: None,
+                };
+                let ptr = sender
+                    .upload_attachment(spec, data, &mut rand::rng())
+                    .await
+                    .context("uploading link preview thumbnail")?;
+                storage.store_attachment_pointer(thumbnail.id, &ptr);
+                Some(ptr)
+            }
+        }
+        None => None,
+    };
+
+    Ok(Some(Preview {
+        url: Some(preview.url),
+        title: preview.title,
+        description: preview.description,
+        date: preview.date.map(naive_chrono_to_millis),
+        image,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeFetcher(Option<FetchedLinkPreview>);
+
+    #[async_trait::async_trait]
+    impl LinkPreviewFetcher for FakeFetcher {
+        async fn fetch(&self, url: &Url) -> anyhow::Result<Option<FetchedLinkPreview>> {
+            assert_eq!(url.as_str(), "https://example.org/article?id=1");
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn previewable_urls() {
+        assert_eq!(
+            first_previewable_url("see http://example.org and https://example.org/a")
+                .map(String::from),
+            Some("https://example.org/a".into())
+        );
+        assert_eq!(first_previewable_url("https://127.0.0.1/x"), None);
+        assert_eq!(first_previewable_url("https://localhost/x"), None);
+        assert_eq!(first_previewable_url("https://user:pw@example.org/x"), None);
+        assert_eq!(first_previewable_url("no links here"), None);
+    }
+
+    #[test]
+    fn parse_open_graph() {
+        let base = Url::parse("https://example.org/article").unwrap();
+        let html = r#"<html><head>
+            <title>Fallback</title>
+            <meta property="og:title" content="Cats &amp; dogs">
+            <meta content='All about   pets' property='og:description' />
+            <meta property="og:image" content="/img/cover.jpg">
+        </head></html>"#;
+        assert_eq!(
+            parse_html(&base, html),
+            ParsedPage {
+                title: Some("Cats & dogs".into()),
+                description: Some("All about pets".into()),
+                image_url: Some(Url::parse("https://example.org/img/cover.jpg").unwrap()),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_fallbacks() {
+        let base = Url::parse("https://example.org/").unwrap();
+        let html = r#"<TITLE>Just a title</TITLE>
+            <meta name="description" content="A description">
+            <meta property="og:image" content="http://insecure.example.org/a.png">"#;
+        assert_eq!(
+            parse_html(&base, html),
+            ParsedPage {
+                title: Some("Just a title".into()),
+                description: Some("A description".into()),
+                image_url: None,
+            }
+        );
+    }
+
+    #[test]
+    fn truncate_long_titles() {
+        let title = "a".repeat(MAX_TITLE_LENGTH + 10);
+        let truncated = truncate(&title, MAX_TITLE_LENGTH);
+        assert_eq!(truncated.chars().count(), MAX_TITLE_LENGTH + 1);
+        assert!(truncated.ends_with('…'));
+    }
+
+    #[actix_rt::test]
+    async fn preview_for_text() {
+        let preview = FetchedLinkPreview {
+            title: Some("Article".into()),
+            description: None,
+            image: Some(LinkPreviewImage {
+                content_type: "image/png".into(),
+                data: vec![1, 2, 3],
+            }),
+        };
+        let fetcher = FakeFetcher(Some(preview.clone()));
+
+        let (url, fetched) =
+            link_preview_for_text(&fetcher, "Read https://example.org/article?id=1 now")
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(url.as_str(), "https://example.org/article?id=1");
+        assert_eq!(fetched, preview);
+
+        assert!(
+            link_preview_for_text(&fetcher, "nothing to see")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let empty = FakeFetcher(None);
+        assert!(
+            link_preview_for_text(&empty, "https://example.org/article?id=1")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -1,5 +1,7 @@
 /// Migration to ensure the primary device Whisperfish has master key and storage service key
 mod account_entropy_pool;
+/// Migration to encrypt attachments that were saved before the storage encrypted them
+mod encrypt_attachments;
 /// Migrations related to groupv2
 mod groupv2;
 /// Migration to remove R@ reactions and dump them in the correct table.
@@ -12,6 +14,7 @@ mod pni;
 mod whoami;
 
 use self::account_entropy_pool::*;
+use self::encrypt_attachments::*;
 use self::groupv2::*;
 use self::parse_reactions::*;
 use self::pni::*;
@@ -142,6 +145,7 @@ impl ClientActor {
         ctx.notify(ParseOldReaction);
         ctx.notify(InitializePni);
         ctx.notify(CheckAccountEntropyPool);
+        ctx.notify(EncryptAttachments);
     }
 }
 
@@ -0,0 +1,37 @@
+use super::*;
+use actix::prelude::*;
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EncryptAttachments;
+
+impl Handler<EncryptAttachments> for ClientActor {
+    type Result = ();
+    fn handle(&mut self, _: EncryptAttachments, ctx: &mut Self::Context) -> Self::Result {
+        let storage = self.storage.clone().expect("storage initialized");
+        let attachment_dir = self.settings.get_string("attachment_dir");
+        let avatar_dir = self.settings.get_string("avatar_dir");
+
+        // Decrypted copies from a previous run are not needed anymore.
+        Storage::clear_plaintext_attachments();
+
+        if !storage.is_encrypted() {
+            return;
+        }
+
+        ctx.spawn(
+            tokio::task::spawn_blocking(move || {
+                Ok::<_, anyhow::Error>(
+                    storage.encrypt_files_in_dir(attachment_dir)?
+                        + storage.encrypt_files_in_dir(avatar_dir)?,
+                )
+            })
+            .into_actor(self)
+            .map(|res, _act, _ctx| match res.expect("threadpool") {
+                Ok(0) => tracing::trace!("All attachments and avatars are encrypted"),
+                Ok(count) => tracing::info!("Encrypted {} attachment and avatar files", count),
+                Err(e) => tracing::error!("Could not encrypt attachments and avatars: {:#}", e),
+            }),
+        );
+    }
+}
@@ -178,6 +178,11 @@ impl AttachmentResizeTask {
                     ) {
                         Ok(ResizeResult::NoAction) => {}
                         Ok(ResizeResult::Resized(path)) => {
+                            // The resized copy lives in the attachment directory, so it is
+                            // encrypted like the other attachments.
+                            if let Err(e) = storage.encrypt_file_in_place(&path) {
+                                tracing::warn!("Could not encrypt resized attachment: {e:#}");
+                            }
                             let resized_path = path
                                 .to_str()
                                 .expect("valid storage path after resizing image")
@@ -0,0 +1,255 @@
+use super::*;
+use base64::prelude::*;
+use libsignal_service::proto::{Content as ContentProto, Envelope, envelope};
+use libsignal_service::protocol::{CiphertextMessageType, DecryptionErrorMessage, Timestamp};
+use libsignal_service::sender::{OutgoingPushMessage, OutgoingPushMessages};
+
+/// Ask the sender of a message we could not decrypt to send it again.
+///
+/// Our side of the broken session is archived first, such that the retry receipt starts a fresh one.
+/// The retry receipt is only sent to the device that sent the message.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(super) struct RequestResend {
+    pub identity: ServiceIdKind,
+    pub sender: ProtocolAddress,
+    pub message: DecryptionErrorMessage,
+}
+
+/// A recipient could not decrypt one of our messages.
+///
+/// The message is resent to the device that could not decrypt it only.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(super) struct RetryReceipt {
+    pub sender: ServiceId,
+    pub sender_device: DeviceId,
+    pub message: DecryptionErrorMessage,
+}
+
+/// Build the retry receipt for an envelope that could not be decrypted.
+///
+/// Sealed sender envelopes hide the inner message type, so they are reported as sender key
+/// messages, without a ratchet key.
+pub(super) fn retry_receipt(
+    envelope: &Envelope,
+    sender_device: DeviceId,
+) -> Option<DecryptionErrorMessage> {
+    let (original_type, original_bytes) = match envelope.r#type() {
+        envelope::Type::Ciphertext => (CiphertextMessageType::Whisper, envelope.content()),
+        envelope::Type::PrekeyBundle => (CiphertextMessageType::PreKey, envelope.content()),
+        _ => (CiphertextMessageType::SenderKey, &[][..]),
+    };
+    DecryptionErrorMessage::for_original(
+        original_bytes,
+        original_type,
+        Timestamp::from_epoch_millis(envelope.timestamp()),
+        sender_device,
+    )
+    .inspect_err(|e| tracing::warn!("Could not create a retry receipt: {e}"))
+    .ok()
+}
+
+/// The send log entry for outgoing content, if it is worth resending on request.
+pub(super) fn send_log_content(content: &ContentBody) -> Option<Vec<u8>> {
+    match content {
+        ContentBody::DataMessage(_) | ContentBody::EditMessage(_) => {
+            Some(content.clone().into_proto().encode_to_vec())
+        }
+        _ => None,
+    }
+}
+
+/// Remember which recipients received a message, such that it can be resent to them.
+pub(super) fn log_sent_message(
+    storage: &Storage,
+    timestamp: u64,
+    content: &[u8],
+    results: &[SendMessageResult],
+) {
+    let recipient_ids: Vec<i32> = results
+        .iter()
+        .filter_map(|result| result.as_ref().ok())
+        .filter_map(|sent| storage.fetch_recipient(&sent.recipient))
+        .map(|recipient| recipient.id)
+        .collect();
+    storage.insert_send_log(millis_to_naive_chrono(timestamp), content, &recipient_ids);
+}
+
+fn resendable_content(content: &[u8]) -> Option<ContentBody> {
+    let content = ContentProto::decode(content)
+        .inspect_err(|e| tracing::warn!("Invalid send log entry: {e}"))
+        .ok()?;
+    if let Some(message) = content.data_message {
+        Some(ContentBody::DataMessage(message))
+    } else {
+        content.edit_message.map(ContentBody::EditMessage)
+    }
+}
+
+impl Handler<RequestResend> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        RequestResend {
+            identity,
+            sender,
+            message,
+        }: RequestResend,
+        ctx: &mut Self::Context,
+    ) {
+        let storage = self.storage.clone().expect("storage");
+        let websocket = self.identified_websocket();
+        let span = tracing::info_span!("request resend", %sender);
+
+        ctx.spawn(
+            async move {
+                let destination = ServiceId::parse_from_service_id_string(sender.name())
+                    .context("invalid protocol address")?;
+                let mut protocol_storage = storage.aci_or_pni(identity);
+                // The registration id is that of the broken session, if we still have it.
+                let mut registration_id = 0;
+                if let Some(mut session) = protocol_storage.load_session(&sender).await? {
+                    registration_id = session.remote_registration_id().unwrap_or(0);
+                    session.archive_current_state()?;
+                    protocol_storage.store_session(&sender, &session).await?;
+                }
+
+                // Without a working session, the retry receipt is sent unencrypted.
+                let content = PlaintextContent::from(message);
+                let message = OutgoingPushMessage {
+                    r#type: envelope::Type::PlaintextContent as u32,
+                    destination_device_id: sender.device_id().into(),
+                    destination_registration_id: registration_id,
+                    content: BASE64_STANDARD.encode(content.serialized()),
+                };
+                websocket
+                    .await?
+                    .send_messages(OutgoingPushMessages {
+                        destination,
+                        timestamp: Utc::now().timestamp_millis() as u64,
+                        messages: vec![message],
+                        online: false,
+                    })
+                    .await?;
+                Ok(())
+            }
+            .instrument(span)
+            .into_actor(self)
+            .map(|res: anyhow::Result<()>, _act, _ctx| match res {
+                Ok(()) => tracing::info!("Sent retry receipt"),
+                Err(e) => tracing::error!("Could not send retry receipt: {e:#}"),
+            }),
+        );
+    }
+}
+
+impl Handler<RetryReceipt> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        RetryReceipt {
+            sender,
+            sender_device,
+            message,
+        }: RetryReceipt,
+        ctx: &mut Self::Context,
+    ) {
+        let device_id = self.config.get_device_id();
+        if u32::from(message.device_id()) != u32::from(device_id) {
+            tracing::debug!("Retry receipt for another one of our devices");
+            return;
+        }
+
+        let storage = self.storage.clone().expect("storage");
+        let Some(recipient) = storage.fetch_recipient(&sender) else {
+            tracing::warn!("Retry receipt from an unknown recipient");
+            return;
+        };
+        let Ok(address) = sender.to_protocol_address(sender_device) else {
+            tracing::warn!("Retry receipt from an invalid device");
+            return;
+        };
+        let timestamp = message.timestamp().epoch_millis();
+
+        // Without a ratchet key, the message was encrypted with our sender key.
+        if message.ratchet_key().is_none() {
+            let own_address = self
+                .self_aci
+                .expect("aci when registered")
+                .to_protocol_address(device_id)
+                .expect("valid device id");
+            storage.delete_sender_keys(&own_address);
+        }
+
+        let Some(content) = storage
+            .fetch_send_log(millis_to_naive_chrono(timestamp), recipient.id)
+            .and_then(|content| resendable_content(&content))
+        else {
+            tracing::info!("Message {timestamp} is not in the send log, resetting the session");
+            ctx.notify(ResetSession::Device(address));
+            return;
+        };
+
+        let mut cipher = self.cipher(ServiceIdKind::Aci);
+        let websocket = self.identified_websocket();
+        ctx.spawn(
+            async move {
+                let mut protocol_storage = storage.aci_storage();
+                // The recipient's side of the session is broken if it refers to our current ratchet.
+                if let Some(ratchet_key) = message.ratchet_key() {
+                    if let Some(mut session) = protocol_storage.load_session(&address).await? {
+                        if session.current_ratchet_key_matches(ratchet_key)? {
+                            session.archive_current_state()?;
+                            protocol_storage.store_session(&address, &session).await?;
+                        }
+                    }
+                }
+
+                let content = content.into_proto().encode_to_vec();
+                let mut websocket = websocket.await?;
+                let message = match cipher
+                    .encrypt(&address, None, &content, &mut rand::rng())
+                    .await
+                {
+                    Err(ServiceError::SignalProtocolError(
+                        SignalProtocolError::SessionNotFound(_),
+                    )) => {
+                        // Start a new session with that device only.
+                        let bundle = websocket.get_pre_key(&sender, address.device_id()).await?;
+                        process_prekey_bundle(
+                            &address,
+                            &mut protocol_storage.clone(),
+                            &mut protocol_storage,
+                            &bundle,
+                            std::time::SystemTime::now(),
+                            &mut rand::rng(),
+                        )
+                        .await?;
+                        cipher
+                            .encrypt(&address, None, &content, &mut rand::rng())
+                            .await?
+                    }
+                    message => message?,
+                };
+                websocket
+                    .send_messages(OutgoingPushMessages {
+                        destination: sender,
+                        timestamp,
+                        messages: vec![message],
+                        online: false,
+                    })
+                    .await?;
+                Ok(())
+            }
+            .instrument(tracing::info_span!("resend message", timestamp))
+            .into_actor(self)
+            .map(|res: anyhow::Result<()>, _act, _ctx| match res {
+                Ok(()) => tracing::info!("Resent a message after a retry receipt"),
+                Err(e) => tracing::error!("Could not resend message: {e:#}"),
+            }),
+        );
+    }
+}
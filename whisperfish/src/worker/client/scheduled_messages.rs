@@ -0,0 +1,104 @@
+use super::*;
+use crate::worker::scheduled_messages::DueScheduledMessages;
+use actix::prelude::*;
+
+/// Move a scheduled message to another send time.
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct RescheduleMessage {
+    pub message_id: i32,
+    pub send_time: NaiveDateTime,
+}
+
+/// Replace the text of a message that was not sent yet.
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct EditScheduledMessage {
+    pub message_id: i32,
+    pub text: String,
+}
+
+/// Remove a message before it is sent.
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct CancelScheduledMessage {
+    pub message_id: i32,
+}
+
+impl ClientActor {
+    /// Let the scheduled messages stream know that the schedule changed.
+    fn wake_scheduled_messages(&self) {
+        if let Some(h) = self.scheduled_messages_notification_handle.as_ref() {
+            h.send(()).expect("send scheduled messages notification");
+        }
+    }
+}
+
+impl Handler<RescheduleMessage> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        RescheduleMessage {
+            message_id,
+            send_time,
+        }: RescheduleMessage,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if self
+            .storage
+            .as_ref()
+            .unwrap()
+            .reschedule_message(message_id, send_time)
+        {
+            self.wake_scheduled_messages();
+        }
+    }
+}
+
+impl Handler<EditScheduledMessage> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        EditScheduledMessage { message_id, text }: EditScheduledMessage,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        self.storage
+            .as_ref()
+            .unwrap()
+            .update_scheduled_message_text(message_id, &text);
+    }
+}
+
+impl Handler<CancelScheduledMessage> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        CancelScheduledMessage { message_id }: CancelScheduledMessage,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if self
+            .storage
+            .as_mut()
+            .unwrap()
+            .cancel_scheduled_message(message_id)
+        {
+            self.wake_scheduled_messages();
+        }
+    }
+}
+
+impl StreamHandler<DueScheduledMessages> for ClientActor {
+    fn handle(&mut self, _: DueScheduledMessages, ctx: &mut Self::Context) {
+        let storage = self.storage.as_ref().unwrap();
+        for message_id in storage.fetch_due_scheduled_message_ids() {
+            // Only the first dispatch sends; the stream may fire again before this one is done.
+            if storage.dispatch_scheduled_message(message_id) {
+                tracing::info!(message_id, "Sending scheduled message");
+                ctx.notify(SendMessage(message_id));
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use super::*;
+use libsignal_service::proto::sync_message::StickerPackOperation;
+use libsignal_service::proto::sync_message::sticker_pack_operation::Type as StickerPackOperationType;
+use std::collections::HashMap;
+
+/// Download, decrypt and install a sticker pack.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct InstallStickerPack {
+    pub pack_id: Vec<u8>,
+    pub pack_key: Vec<u8>,
+}
+
+/// Remove an installed sticker pack.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UninstallStickerPack {
+    pub pack_id: Vec<u8>,
+}
+
+impl ClientActor {
+    pub(super) fn handle_sticker_pack_operations(
+        &mut self,
+        ctx: &mut <Self as Actor>::Context,
+        operations: Vec<StickerPackOperation>,
+    ) {
+        for operation in operations {
+            let Some(pack_id) = operation.pack_id else {
+                tracing::warn!("Sticker pack operation without pack id");
+                continue;
+            };
+            match operation.r#type() {
+                StickerPackOperationType::Install => match operation.pack_key {
+                    Some(pack_key) => ctx.notify(InstallStickerPack { pack_id, pack_key }),
+                    None => tracing::warn!("Sticker pack install without pack key"),
+                },
+                StickerPackOperationType::Remove => ctx.notify(UninstallStickerPack { pack_id }),
+            }
+        }
+    }
+}
+
+impl Handler<InstallStickerPack> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        InstallStickerPack { pack_id, pack_key }: InstallStickerPack,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let pack_id_hex = hex::encode(&pack_id);
+        let _span = tracing::info_span!("InstallStickerPack", pack_id = %pack_id_hex).entered();
+
+        let mut receiver = MessageReceiver::new(self.unauthenticated_service());
+        let storage = self.storage.clone().unwrap();
+        let dest = PathBuf::from(self.settings.get_string("attachment_dir"))
+            .join("stickers")
+            .join(&pack_id_hex);
+
+        Box::pin(
+            async move {
+                let manifest = receiver
+                    .retrieve_sticker_pack_manifest(&pack_id, &pack_key)
+                    .await?;
+                tracing::info!(
+                    "Installing sticker pack \"{}\" by \"{}\" ({} stickers)",
+                    manifest.title(),
+                    manifest.author(),
+                    manifest.stickers.len()
+                );
+
+                tokio::fs::create_dir_all(&dest)
+                    .await
+                    .with_context(|| format!("creating {}", dest.display()))?;
+
+                let mut files = HashMap::new();
+                for sticker in manifest.stickers.iter().chain(manifest.cover.as_ref()) {
+                    if files.contains_key(&sticker.id()) {
+                        continue;
+                    }
+                    let data = receiver
+                        .retrieve_sticker(&pack_id, &pack_key, sticker.id())
+                        .await?;
+                    let ext = sticker
+                        .content_type
+                        .as_deref()
+                        .and_then(mime_guess::get_mime_extensions_str)
+                        .and_then(|x| x.first())
+                        .copied()
+                        .unwrap_or("webp");
+                    let path = dest.join(format!("{}.{}", sticker.id(), ext));
+                    storage.save_file(&path, &data).await?;
+                    files.insert(sticker.id(), path);
+                }
+
+                storage.install_sticker_pack(&pack_id_hex, &pack_key, &manifest, &files)
+            }
+            .instrument(tracing::Span::current())
+            .into_actor(self)
+            .map(|res: anyhow::Result<()>, _act, _ctx| {
+                if let Err(e) = res {
+                    tracing::error!("Could not install sticker pack: {e:#}");
+                }
+            }),
+        )
+    }
+}
+
+impl Handler<UninstallStickerPack> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        UninstallStickerPack { pack_id }: UninstallStickerPack,
+        _ctx: &mut Self::Context,
+    ) {
+        let pack_id = hex::encode(pack_id);
+        let removed = self
+            .storage
+            .as_ref()
+            .unwrap()
+            .uninstall_sticker_pack(&pack_id);
+        tracing::info!(%pack_id, "Uninstalled sticker pack ({removed} stickers)");
+
+        let dir = PathBuf::from(self.settings.get_string("attachment_dir"))
+            .join("stickers")
+            .join(&pack_id);
+        if let Err(e) = std::fs::remove_dir(&dir) {
+            tracing::debug!("Could not remove {}: {e}", dir.display());
+        }
+    }
+}
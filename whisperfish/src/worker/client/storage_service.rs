@@ -0,0 +1,192 @@
+use super::*;
+use libsignal_service::configuration::Endpoint;
+use libsignal_service::push_service::{HttpAuth, HttpAuthOverride, ReqwestExt};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Method, StatusCode};
+use tracing_futures::Instrument;
+use whisperfish_store::storage_service::{
+    ReadOperation, StorageItem, StorageItems, StorageManifest, StorageServiceApi, WriteOperation,
+    WriteOutcome,
+};
+
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// Fetch the Storage Service manifest, merge it into the database, and upload local changes.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SyncStorageService;
+
+/// Synchronisations with the Storage Service are never run concurrently:
+/// a request that comes in while one is running is handled once it finishes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) enum StorageSyncState {
+    #[default]
+    Idle,
+    Running,
+    /// Another synchronisation was requested while this one was running.
+    RunningWithFollowUp,
+}
+
+#[derive(serde::Deserialize)]
+struct StorageAuth {
+    username: String,
+    password: String,
+}
+
+/// The Storage Service over HTTP.
+///
+/// The Storage Service does not accept the account credentials;
+/// it requires short-lived credentials that are requested from the chat service first.
+struct HttpStorageService {
+    service: PushService,
+    auth: Option<HttpAuth>,
+}
+
+impl HttpStorageService {
+    fn new(service: PushService) -> Self {
+        Self {
+            service,
+            auth: None,
+        }
+    }
+
+    async fn auth(&mut self) -> anyhow::Result<HttpAuthOverride> {
+        if self.auth.is_none() {
+            let auth: StorageAuth = self
+                .service
+                .request(
+                    Method::GET,
+                    Endpoint::service("/v1/storage/auth"),
+                    HttpAuthOverride::NoOverride,
+                )?
+                .send()
+                .await?
+                .service_error_for_status()
+                .await?
+                .json()
+                .await?;
+            self.auth = Some(HttpAuth {
+                username: auth.username,
+                password: auth.password,
+            });
+        }
+        Ok(HttpAuthOverride::Identified(self.auth.clone().unwrap()))
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageServiceApi for HttpStorageService {
+    async fn read_manifest(
+        &mut self,
+        known_version: Option<u64>,
+    ) -> anyhow::Result<Option<StorageManifest>> {
+        let auth = self.auth().await?;
+        let path = match known_version {
+            Some(version) => format!("/v1/storage/manifest/version/{version}"),
+            None => "/v1/storage/manifest".into(),
+        };
+        let response = self
+            .service
+            .request(Method::GET, Endpoint::storage(&path), auth)?
+            .send()
+            .await?;
+
+        match response.status() {
+            // 204: the known version is the latest one
+            StatusCode::NO_CONTENT | StatusCode::NOT_FOUND => Ok(None),
+            _ => {
+                let body = response.service_error_for_status().await?.bytes().await?;
+                Ok(Some(StorageManifest::decode(body)?))
+            }
+        }
+    }
+
+    async fn read_records(&mut self, keys: Vec<Vec<u8>>) -> anyhow::Result<Vec<StorageItem>> {
+        let auth = self.auth().await?;
+        let body = self
+            .service
+            .request(Method::PUT, Endpoint::storage("/v1/storage/read"), auth)?
+            .header(CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)
+            .body(ReadOperation { read_key: keys }.encode_to_vec())
+            .send()
+            .await?
+            .service_error_for_status()
+            .await?
+            .bytes()
+            .await?;
+        Ok(StorageItems::decode(body)?.items)
+    }
+
+    async fn write(&mut self, operation: WriteOperation) -> anyhow::Result<WriteOutcome> {
+        let auth = self.auth().await?;
+        let response = self
+            .service
+            .request(Method::PUT, Endpoint::storage("/v1/storage"), auth)?
+            .header(CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)
+            .body(operation.encode_to_vec())
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::CONFLICT {
+            let body = response.bytes().await?;
+            return Ok(WriteOutcome::Conflict(StorageManifest::decode(body)?));
+        }
+        response.service_error_for_status().await?;
+        Ok(WriteOutcome::Written)
+    }
+}
+
+impl Handler<SyncStorageService> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, _: SyncStorageService, _ctx: &mut Self::Context) -> Self::Result {
+        if self.storage_sync_state != StorageSyncState::Idle {
+            tracing::debug!("Storage service synchronisation already running, queueing another");
+            self.storage_sync_state = StorageSyncState::RunningWithFollowUp;
+            return Box::pin(async {}.into_actor(self));
+        }
+        let storage = self.storage.clone().unwrap();
+        let Some(storage_key) = storage.fetch_storage_service_key() else {
+            tracing::warn!("No storage service key yet, not syncing the storage service");
+            return Box::pin(async {}.into_actor(self));
+        };
+        let service = self.authenticated_service();
+        self.storage_sync_state = StorageSyncState::Running;
+
+        Box::pin(
+            async move {
+                let mut api = HttpStorageService::new(service);
+                storage.sync_storage_service(&mut api, &storage_key).await
+            }
+            .instrument(tracing::info_span!("sync storage service"))
+            .into_actor(self)
+            .map(|result, act, ctx| {
+                if act.storage_sync_state == StorageSyncState::RunningWithFollowUp {
+                    ctx.notify(SyncStorageService);
+                }
+                act.storage_sync_state = StorageSyncState::Idle;
+                match result {
+                    Ok(outcome) => {
+                        tracing::info!(
+                            "Synchronised with storage manifest version {}; wrote {} records",
+                            outcome.manifest_version,
+                            outcome.records_written,
+                        );
+                        for session_id in outcome.new_group_v2_sessions {
+                            ctx.notify(RequestGroupV2InfoBySessionId(session_id));
+                        }
+                        if let Some(account) = outcome.account_settings {
+                            act.settings.set_enable_read_receipts(account.read_receipts);
+                            act.settings
+                                .set_enable_typing_indicators(account.typing_indicators);
+                            act.settings.set_enable_link_previews(account.link_previews);
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Storage service synchronisation failed: {e:#}");
+                    }
+                }
+            }),
+        )
+    }
+}
@@ -0,0 +1,369 @@
+use super::*;
+use crate::store::GroupV2;
+use crate::store::stories::{MY_STORY_DISTRIBUTION_ID, STORY_LIFETIME};
+use libsignal_service::proto::story_message::Attachment as StoryAttachment;
+use libsignal_service::proto::sync_message::sent::StoryMessageRecipient;
+use libsignal_service::proto::{GroupContextV2, StoryMessage, TextAttachment};
+
+/// Send a text story to the sessions of a distribution list.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendStory {
+    pub distribution_id: Uuid,
+    pub text: String,
+}
+
+fn group_v2_from_context(group: &GroupContextV2) -> Option<GroupV2> {
+    let master_key = group.master_key.as_deref()?;
+    let master_key: [u8; zkgroup::GROUP_MASTER_KEY_LEN] = master_key.try_into().ok()?;
+    Some(GroupV2 {
+        secret: GroupSecretParams::derive_from_master_key(GroupMasterKey::new(master_key)),
+        revision: group.revision(),
+    })
+}
+
+fn story_context_author(context: &StoryContext) -> Option<Uuid> {
+    context
+        .author_aci_binary
+        .as_deref()
+        .and_then(|aci| Uuid::from_slice(aci).ok())
+        .or_else(|| {
+            context
+                .author_aci
+                .as_deref()
+                .and_then(|aci| Uuid::parse_str(aci).ok())
+        })
+}
+
+impl ClientActor {
+    /// Insert a story, filling in the story specific parts of `new_message`.
+    ///
+    /// Stories expire through the ordinary disappearing messages mechanism.
+    fn insert_story(
+        &mut self,
+        ctx: &mut <Self as Actor>::Context,
+        new_message: crate::store::NewMessage,
+        story: &StoryMessage,
+    ) -> orm::Message {
+        let mut storage = self.storage.clone().expect("storage");
+
+        let text_attachment = match &story.attachment {
+            Some(StoryAttachment::TextAttachment(text)) => Some(text),
+            _ => None,
+        };
+        let message = storage.create_message(&crate::store::NewMessage {
+            text: text_attachment
+                .and_then(|text| text.text.clone())
+                .unwrap_or_default(),
+            story_type: StoryType::from_params(story.allows_replies(), text_attachment.is_some()),
+            body_ranges: crate::store::body_ranges::serialize(&story.body_ranges),
+            expires_in: Some(STORY_LIFETIME),
+            expiry_started: Some(new_message.timestamp),
+            ..new_message
+        });
+
+        if let Some(StoryAttachment::FileAttachment(attachment)) = &story.attachment {
+            // Stories are gone after a day, there is no point in waiting for the user.
+            let attachment_id = storage.register_attachment(message.id, attachment.clone());
+            ctx.notify(FetchAttachment { attachment_id });
+        }
+
+        if let Some(h) = self.message_expiry_notification_handle.as_ref() {
+            h.send(()).expect("send message expiry notification");
+        }
+
+        message
+    }
+
+    /// The session a story belongs to.
+    ///
+    /// Group stories live in the group; other stories in the session with their author.
+    /// Stories to groups we are not part of are dropped.
+    fn story_session(&self, story: &StoryMessage, author_id: i32) -> Option<orm::Session> {
+        let storage = self.storage.as_ref().expect("storage");
+        match &story.group {
+            Some(group) => {
+                let Some(group) = group_v2_from_context(group) else {
+                    tracing::error!("Group story without valid master key");
+                    return None;
+                };
+                if !storage.group_v2_exists(&group) {
+                    tracing::warn!("Story for an unknown group, dropping it.");
+                    return None;
+                }
+                Some(storage.fetch_or_insert_session_by_group_v2(&group))
+            }
+            None => Some(storage.fetch_or_insert_session_by_recipient_id(author_id)),
+        }
+    }
+
+    /// Find the story a reply refers to.
+    pub(super) fn fetch_story_for_context(&self, context: &StoryContext) -> Option<orm::Message> {
+        let storage = self.storage.as_ref().expect("storage");
+        let (Some(author), Some(sent_timestamp)) =
+            (story_context_author(context), context.sent_timestamp)
+        else {
+            tracing::warn!("Story reply without author or timestamp");
+            return None;
+        };
+        let author = storage.fetch_or_insert_recipient_by_address(&Aci::from(author).into());
+        let story =
+            storage.fetch_story_by_timestamp(author.id, millis_to_naive_chrono(sent_timestamp));
+        if story.is_none() {
+            tracing::warn!("Reply to a story we do not have (anymore)");
+        }
+        story
+    }
+
+    pub(super) fn handle_story_message(
+        &mut self,
+        ctx: &mut <Self as Actor>::Context,
+        metadata: &Metadata,
+        story: StoryMessage,
+    ) {
+        let storage = self.storage.clone().expect("storage");
+        let Ok(author) = Aci::try_from(metadata.sender) else {
+            tracing::warn!("Story sent by a PNI, dropping it.");
+            return;
+        };
+        let author =
+            storage.merge_and_fetch_recipient(None, Some(author), None, TrustLevel::Certain);
+        if author.is_blocked {
+            tracing::debug!("Story from a blocked recipient, dropping it.");
+            return;
+        }
+
+        if let Some(key) = story.profile_key.as_deref() {
+            let (recipient, was_updated) =
+                storage.update_profile_key(None, Some(metadata.sender), key, TrustLevel::Certain);
+            if was_updated {
+                ctx.notify(RefreshProfile::ByRecipientId(recipient.id));
+            }
+        }
+
+        let timestamp = millis_to_naive_chrono(metadata.client_timestamp.timestamp_millis() as u64);
+        if storage
+            .fetch_story_by_timestamp(author.id, timestamp)
+            .is_some()
+        {
+            tracing::debug!("Story already received, dropping the duplicate.");
+            return;
+        }
+        let Some(session) = self.story_session(&story, author.id) else {
+            return;
+        };
+
+        let message = self.insert_story(
+            ctx,
+            crate::store::NewMessage {
+                session_id: session.id,
+                source_addr: Some(metadata.sender),
+                server_guid: metadata.server_guid,
+                timestamp,
+                is_unidentified: metadata.unidentified_sender,
+                ..crate::store::NewMessage::new_incoming()
+            },
+            &story,
+        );
+        tracing::debug!(story_id = message.id, "Story saved");
+    }
+
+    /// Save a story that was sent by one of our other devices.
+    pub(super) fn handle_sync_sent_story(
+        &mut self,
+        ctx: &mut <Self as Actor>::Context,
+        sent: &Sent,
+        story: &StoryMessage,
+    ) {
+        let storage = self.storage.clone().expect("storage");
+        let Some(timestamp) = sent.timestamp.map(millis_to_naive_chrono) else {
+            tracing::error!("Synced story without timestamp");
+            return;
+        };
+        let self_recipient = storage.fetch_self_recipient().expect("self recipient");
+
+        let message = match storage.fetch_story_by_timestamp(self_recipient.id, timestamp) {
+            // An update of the recipients of a story we already know about.
+            Some(message) => message,
+            None => {
+                let Some(session) = self.story_session(story, self_recipient.id) else {
+                    return;
+                };
+                self.insert_story(
+                    ctx,
+                    crate::store::NewMessage {
+                        session_id: session.id,
+                        source_addr: storage.fetch_self_service_address_aci(),
+                        timestamp,
+                        sent: true,
+                        ..crate::store::NewMessage::new_outgoing()
+                    },
+                    story,
+                )
+            }
+        };
+
+        for recipient in &sent.story_message_recipients {
+            let Some(address) = recipient
+                .destination_service_id
+                .as_deref()
+                .and_then(ServiceId::parse_from_service_id_string)
+            else {
+                tracing::warn!("Story recipient without valid service id");
+                continue;
+            };
+            let recipient_id = storage.fetch_or_insert_recipient_by_address(&address).id;
+            let session = storage.fetch_or_insert_session_by_recipient_id(recipient_id);
+
+            for distribution_id in &recipient.distribution_list_ids {
+                let Ok(distribution_id) = Uuid::parse_str(distribution_id) else {
+                    tracing::warn!("Unparsable distribution list id: {distribution_id}");
+                    continue;
+                };
+                let list = storage.fetch_or_insert_distribution_list(distribution_id);
+                // Learn the members of lists that were not synced (yet).
+                if list.is_unknown {
+                    storage.add_distribution_list_member(distribution_id, session.id);
+                }
+                storage.insert_story_sends(
+                    message.id,
+                    distribution_id,
+                    recipient.is_allowed_to_reply(),
+                    &[session.id],
+                    timestamp,
+                );
+            }
+        }
+    }
+}
+
+impl Handler<SendStory> for ClientActor {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        SendStory {
+            distribution_id,
+            text,
+        }: SendStory,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let _span = tracing::info_span!("SendStory", %distribution_id).entered();
+        let storage = self.storage.clone().unwrap();
+
+        let list = if distribution_id == MY_STORY_DISTRIBUTION_ID {
+            storage.fetch_or_insert_my_story()
+        } else if let Some(list) = storage.fetch_distribution_list(distribution_id) {
+            list
+        } else {
+            tracing::error!("Unknown distribution list, not sending story");
+            return Box::pin(async {}.into_actor(self));
+        };
+        let recipients = storage.fetch_story_recipients(&list);
+
+        // Round to milliseconds, such that replies can find the story back.
+        let timestamp = millis_to_naive_chrono(Utc::now().timestamp_millis() as u64);
+        let story = StoryMessage {
+            profile_key: storage.fetch_self_recipient_profile_key(),
+            attachment: Some(StoryAttachment::TextAttachment(TextAttachment {
+                text: Some(text),
+                ..Default::default()
+            })),
+            allows_replies: Some(list.allows_replies),
+            ..Default::default()
+        };
+
+        let self_recipient = storage.fetch_self_recipient().expect("self recipient");
+        let self_session = storage.fetch_or_insert_session_by_recipient_id(self_recipient.id);
+        let message = self.insert_story(
+            ctx,
+            crate::store::NewMessage {
+                session_id: self_session.id,
+                source_addr: storage.fetch_self_service_address_aci(),
+                timestamp,
+                ..crate::store::NewMessage::new_outgoing()
+            },
+            &story,
+        );
+        tracing::info!(
+            story_id = message.id,
+            "Sending story to {} recipients",
+            recipients.len()
+        );
+
+        let addr = ctx.address();
+        let story_id = message.id;
+        Box::pin(
+            async move {
+                let mut delivered = Vec::new();
+                let mut story_recipients = Vec::new();
+                let mut unidentified = true;
+                for session in recipients {
+                    let recipient = session.unwrap_dm().clone();
+                    let results = addr
+                        .send(DeliverMessage {
+                            content: story.clone(),
+                            timestamp: naive_chrono_to_millis(timestamp),
+                            online: false,
+                            for_story: true,
+                            destination: session.r#type.into(),
+                        })
+                        .await?;
+                    let results = match results {
+                        Ok(results) => results,
+                        Err(e) => {
+                            tracing::warn!("Could not deliver story to {}: {e:#}", recipient.id);
+                            continue;
+                        }
+                    };
+                    if let Some(e) = results.iter().find_map(|res| res.as_ref().err()) {
+                        tracing::warn!("Could not deliver story to {}: {e}", recipient.id);
+                        continue;
+                    }
+                    unidentified &= results
+                        .iter()
+                        .all(|res| res.as_ref().is_ok_and(|sent| sent.unidentified));
+
+                    delivered.push(session.id);
+                    story_recipients.push(StoryMessageRecipient {
+                        destination_service_id: recipient.uuid.map(|uuid| uuid.to_string()),
+                        distribution_list_ids: vec![distribution_id.to_string()],
+                        is_allowed_to_reply: Some(list.allows_replies),
+                        ..Default::default()
+                    });
+                }
+
+                storage.insert_story_sends(
+                    story_id,
+                    distribution_id,
+                    list.allows_replies,
+                    &delivered,
+                    timestamp,
+                );
+                storage.dequeue_message(story_id, Utc::now().naive_utc(), unidentified);
+
+                // Stories are not transcribed to our other devices by the sender.
+                let sync = SyncMessage {
+                    content: Some(SyncMessageContent::Sent(Sent {
+                        timestamp: Some(naive_chrono_to_millis(timestamp)),
+                        story_message: Some(story),
+                        story_message_recipients: story_recipients,
+                        is_recipient_update: Some(false),
+                        ..Default::default()
+                    })),
+                    ..SyncMessage::with_padding(&mut rand::rng())
+                };
+                addr.send(DeliverSyncMessage(sync)).await??;
+
+                Ok(())
+            }
+            .instrument(tracing::debug_span!("sending story", story_id))
+            .into_actor(self)
+            .map(|res: anyhow::Result<()>, _act, _ctx| {
+                if let Err(e) = res {
+                    tracing::error!("Could not send story: {e:#}");
+                }
+            }),
+        )
+    }
+}
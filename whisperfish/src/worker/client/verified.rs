@@ -0,0 +1,105 @@
+use super::*;
+use libsignal_service::proto::{Verified, verified};
+use libsignal_service::protocol::IdentityKey;
+use whisperfish_store::orm::VerifiedStatus;
+
+/// Mark the safety number of a recipient as verified or not, and let our other devices know.
+///
+/// Unmarking a recipient whose key changed since its verification accepts the new key.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MarkIdentityVerified {
+    pub recipient_id: i32,
+    pub verified: bool,
+}
+
+impl ClientActor {
+    /// A safety number that was (un)verified on another device.
+    pub(super) fn handle_sync_verified(&mut self, verified: &Verified) {
+        let storage = self.storage.as_ref().expect("storage");
+        let aci = verified
+            .destination_aci_binary
+            .as_deref()
+            .and_then(|aci| Uuid::from_slice(aci).ok())
+            .or_else(|| {
+                verified
+                    .destination_aci
+                    .as_deref()
+                    .and_then(|aci| Uuid::parse_str(aci).ok())
+            })
+            .map(Aci::from);
+        let Some(aci) = aci else {
+            tracing::warn!("Verified sync message without destination");
+            return;
+        };
+        let key = match verified.identity_key.as_deref().map(IdentityKey::decode) {
+            Some(Ok(key)) => key,
+            Some(Err(e)) => {
+                tracing::warn!("Verified sync message with invalid identity key: {e}");
+                return;
+            }
+            None => {
+                tracing::warn!("Verified sync message without identity key");
+                return;
+            }
+        };
+
+        // Only applies when we know the same key; otherwise, the other device is out of date.
+        storage.set_identity_verified_status(&aci.into(), &key, verified.state().into());
+    }
+}
+
+impl Handler<MarkIdentityVerified> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        MarkIdentityVerified {
+            recipient_id,
+            verified,
+        }: MarkIdentityVerified,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let _span = tracing::info_span!("MarkIdentityVerified", recipient_id, verified).entered();
+        let storage = self.storage.as_ref().expect("storage");
+        let Some(uuid) = storage
+            .fetch_recipient_by_id(recipient_id)
+            .and_then(|r| r.uuid)
+        else {
+            tracing::warn!("Recipient without ACI, cannot verify");
+            return;
+        };
+        let aci = ServiceId::Aci(uuid.into());
+        let Some(record) = storage.fetch_identity_record(&aci) else {
+            tracing::warn!("No identity key known for recipient");
+            return;
+        };
+
+        let status = match (verified, record.verified_status()) {
+            (false, VerifiedStatus::Unverified) => {
+                // The key that needs confirmation may not be in the database yet,
+                // so there is nothing to sync.
+                storage.accept_identity_key_change(&aci);
+                return;
+            }
+            (false, _) => VerifiedStatus::Default,
+            (true, _) => VerifiedStatus::Verified,
+        };
+        let key = record.identity_key();
+        if !storage.set_identity_verified_status(&aci, &key, status) {
+            return;
+        }
+
+        let sync = SyncMessage {
+            verified: Some(Verified {
+                destination_aci: Some(uuid.to_string()),
+                destination_aci_binary: Some(uuid.as_bytes().to_vec()),
+                identity_key: Some(key.serialize().to_vec()),
+                state: Some(verified::State::from(status) as i32),
+                ..Default::default()
+            }),
+            ..SyncMessage::with_padding(&mut rand::rng())
+        };
+        ctx.notify(DeliverSyncMessage(sync));
+    }
+}
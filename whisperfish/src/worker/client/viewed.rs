@@ -0,0 +1,157 @@
+use super::*;
+use crate::store::delete_for_me::MessageAddress;
+use libsignal_service::proto::sync_message::{ViewOnceOpen, Viewed};
+
+/// Mark a voice note or view-once message as viewed, and let the sender and our other devices know.
+///
+/// The attachments of view-once messages are deleted afterwards.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MarkMessageViewed(pub i32);
+
+fn parse_sender_aci(binary: Option<&[u8]>, string: Option<&str>) -> Option<Aci> {
+    binary
+        .and_then(|aci| Uuid::from_slice(aci).ok())
+        .or_else(|| string.and_then(|aci| Uuid::parse_str(aci).ok()))
+        .map(Aci::from)
+}
+
+impl ClientActor {
+    /// Find a message that our other devices refer to by its sender and timestamp.
+    fn fetch_synced_message(
+        &self,
+        sender: Option<Aci>,
+        timestamp: Option<u64>,
+    ) -> Option<orm::Message> {
+        let storage = self.storage.as_ref().expect("storage");
+        let (Some(sender), Some(timestamp)) = (sender, timestamp) else {
+            tracing::warn!("Synced message without sender or timestamp");
+            return None;
+        };
+        let author = storage.fetch_or_insert_recipient_by_address(&sender.into());
+        // Viewed messages come without their conversation.
+        let message = storage.fetch_message_by_address(
+            None,
+            MessageAddress {
+                author_id: author.id,
+                sent_timestamp: millis_to_naive_chrono(timestamp),
+            },
+        );
+        if message.is_none() {
+            tracing::warn!("Synced message not found");
+        }
+        message
+    }
+
+    /// Voice notes and view-once media that were viewed on another device.
+    pub(super) fn handle_sync_viewed(&mut self, viewed: &[Viewed]) {
+        for viewed in viewed {
+            let sender = parse_sender_aci(
+                viewed.sender_aci_binary.as_deref(),
+                viewed.sender_aci.as_deref(),
+            );
+            let Some(message) = self.fetch_synced_message(sender, viewed.timestamp) else {
+                continue;
+            };
+            self.storage
+                .as_mut()
+                .expect("storage")
+                .mark_message_viewed(message.id, Utc::now().naive_utc());
+        }
+    }
+
+    /// View-once media that was opened on another device, and should not be shown here anymore.
+    pub(super) fn handle_view_once_open(&mut self, opened: &ViewOnceOpen) {
+        let sender = parse_sender_aci(
+            opened.sender_aci_binary.as_deref(),
+            opened.sender_aci.as_deref(),
+        );
+        let Some(message) = self.fetch_synced_message(sender, opened.timestamp) else {
+            return;
+        };
+        if !message.is_view_once {
+            tracing::warn!("View-once open for a message that is not view-once");
+            return;
+        }
+        self.storage
+            .as_mut()
+            .expect("storage")
+            .mark_message_viewed(message.id, Utc::now().naive_utc());
+    }
+}
+
+impl Handler<MarkMessageViewed> for ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        MarkMessageViewed(id): MarkMessageViewed,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let _span = tracing::info_span!("MarkMessageViewed", message_id = id).entered();
+        let mut storage = self.storage.clone().unwrap();
+
+        let Some(message) = storage.fetch_augmented_message(id) else {
+            tracing::warn!("Tried to mark non-existing message viewed");
+            return;
+        };
+        if message.is_outbound {
+            return;
+        }
+        if !message.is_view_once && !message.is_voice_note {
+            tracing::warn!("Only voice notes and view-once media can be viewed");
+            return;
+        }
+        if storage
+            .mark_message_viewed(id, Utc::now().naive_utc())
+            .is_none()
+        {
+            tracing::debug!("Message was viewed before");
+            return;
+        }
+
+        let Some(sender) = message
+            .sender_recipient_id
+            .and_then(|id| storage.fetch_recipient_by_id(id))
+        else {
+            tracing::warn!("Viewed message without sender");
+            return;
+        };
+        let Some(sender_aci) = sender.uuid else {
+            tracing::warn!("Viewed message sender without ACI, not sending receipts");
+            return;
+        };
+        let timestamp = naive_chrono_to_millis(message.server_timestamp);
+
+        let sync = SyncMessage {
+            viewed: vec![Viewed {
+                sender_aci: Some(sender_aci.to_string()),
+                sender_aci_binary: Some(sender_aci.as_bytes().to_vec()),
+                timestamp: Some(timestamp),
+            }],
+            content: message.is_view_once.then(|| {
+                SyncMessageContent::ViewOnceOpen(ViewOnceOpen {
+                    sender_aci: Some(sender_aci.to_string()),
+                    sender_aci_binary: Some(sender_aci.as_bytes().to_vec()),
+                    timestamp: Some(timestamp),
+                })
+            }),
+            ..SyncMessage::with_padding(&mut rand::rng())
+        };
+        ctx.notify(DeliverSyncMessage(sync));
+
+        // Like Signal, viewed receipts go along with read receipts.
+        if self.settings.get_enable_read_receipts() {
+            ctx.notify(DeliverMessage {
+                content: ReceiptMessage {
+                    r#type: Some(ReceiptType::Viewed as _),
+                    timestamp: vec![timestamp],
+                },
+                timestamp: Utc::now().timestamp_millis() as u64,
+                destination: SessionType::DirectMessage(sender).into(),
+                online: false,
+                for_story: false,
+            });
+        }
+    }
+}
@@ -1,30 +1,167 @@
+mod command;
+mod speech_note;
+
+pub use command::CommandTranscriber;
+pub use speech_note::SpeechNoteTranscriber;
+
 use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
 
 use actix::prelude::*;
-use dbus::message::MatchRule;
-use futures::prelude::*;
+use anyhow::Context;
 
 impl super::ClientWorker {
     #[allow(non_snake_case)]
     #[qmeta_async::with_executor]
     pub(super) fn transcribeVoiceNote(&self, message_id: i32) {
-        let transcribe_voice_note = TranscribeVoiceNote { message_id };
+        let transcribe_voice_note = TranscribeVoiceNote {
+            message_id,
+            language: None,
+        };
         let actor = self.actor.clone().unwrap();
         actix::spawn(async move { actor.send(transcribe_voice_note).await.unwrap() });
     }
+
+    #[allow(non_snake_case)]
+    #[qmeta_async::with_executor]
+    pub(super) fn cancelVoiceNoteTranscription(&self, message_id: i32) {
+        let actor = self.actor.clone().unwrap();
+        actix::spawn(async move {
+            actor
+                .send(CancelVoiceNoteTranscription { message_id })
+                .await
+                .unwrap()
+        });
+    }
+}
+
+/// The result of transcribing a voice note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcription {
+    pub text: String,
+    /// The spoken language, as detected by the backend or as it was asked to transcribe.
+    pub language: Option<String>,
+}
+
+/// A speech-to-text engine that transcribes voice notes.
+///
+/// Dropping the future returned by [Transcriber::transcribe] cancels the transcription.
+#[async_trait::async_trait]
+pub trait Transcriber: Send + Sync {
+    /// Transcribe the audio file at `path`.
+    ///
+    /// `language` is the spoken language as an ISO 639-1 code, or `None` to detect it.
+    /// Backends that produce intermediate results pass them to `partial`.
+    async fn transcribe(
+        &self,
+        path: &Path,
+        language: Option<&str>,
+        partial: &mut (dyn FnMut(&str) + Send),
+    ) -> anyhow::Result<Transcription>;
+}
+
+/// The configured transcription backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptionBackend {
+    /// The `org.mkiol.Speech` D-Bus service of Speech Note.
+    SpeechNote,
+    /// A local command line tool, such as whisper.cpp or Vosk.
+    Command,
+}
+
+impl TranscriptionBackend {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            TranscriptionBackend::SpeechNote => "speech-note",
+            TranscriptionBackend::Command => "command",
+        }
+    }
 }
 
+impl From<&str> for TranscriptionBackend {
+    fn from(value: &str) -> Self {
+        match value {
+            "speech-note" => TranscriptionBackend::SpeechNote,
+            "command" => TranscriptionBackend::Command,
+            x => {
+                tracing::warn!("Unknown TranscriptionBackend value {x}, returning speech-note");
+                TranscriptionBackend::SpeechNote
+            }
+        }
+    }
+}
+
+/// Maps the language setting onto the language to ask the backend for.
+fn language_from_setting(language: &str) -> Option<String> {
+    match language.trim() {
+        "" | "auto" => None,
+        language => Some(language.to_lowercase()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TranscriptionJob {
+    message_id: i32,
+    attachment_id: i32,
+    language: Option<String>,
+    /// The transcription the attachment had when the job started,
+    /// restored when the job is cancelled or fails.
+    previous: Option<Transcription>,
+}
+
+/// Voice notes waiting to be transcribed, one at a time.
 #[derive(Debug, Default)]
 pub(super) struct VoiceNoteTranscriptionQueue {
-    // attachment-id, task-id
-    current_attachment: Option<(i32, i32)>,
-    queue: VecDeque<i32>,
+    current: Option<(TranscriptionJob, SpawnHandle)>,
+    queue: VecDeque<TranscriptionJob>,
+}
+
+impl VoiceNoteTranscriptionQueue {
+    /// Queues the job, unless its attachment is already being transcribed or waiting.
+    fn push(&mut self, job: TranscriptionJob) -> bool {
+        let attachment_id = job.attachment_id;
+        let current = self.current.as_ref().map(|(job, _)| job);
+        if current
+            .into_iter()
+            .chain(self.queue.iter())
+            .any(|queued| queued.attachment_id == attachment_id)
+        {
+            return false;
+        }
+        self.queue.push_back(job);
+        true
+    }
+
+    /// The next job to start, if none is running.
+    fn next(&mut self) -> Option<TranscriptionJob> {
+        if self.current.is_some() {
+            return None;
+        }
+        self.queue.pop_front()
+    }
+
+    /// Removes the waiting jobs of the message, and returns the running one if it belongs to it.
+    fn cancel(&mut self, message_id: i32) -> Option<(TranscriptionJob, SpawnHandle)> {
+        self.queue.retain(|job| job.message_id != message_id);
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|(job, _)| job.message_id == message_id)
+        {
+            self.current.take()
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(actix::Message)]
 #[rtype(result = "()")]
 pub(super) struct TranscribeVoiceNote {
     pub message_id: i32,
+    /// Overrides the language from the settings.
+    pub language: Option<String>,
 }
 
 impl actix::Handler<TranscribeVoiceNote> for super::ClientActor {
@@ -32,7 +169,10 @@ impl actix::Handler<TranscribeVoiceNote> for super::ClientActor {
 
     fn handle(
         &mut self,
-        TranscribeVoiceNote { message_id }: TranscribeVoiceNote,
+        TranscribeVoiceNote {
+            message_id,
+            language,
+        }: TranscribeVoiceNote,
         ctx: &mut Self::Context,
     ) -> Self::Result {
         let attachments = self
@@ -44,249 +184,354 @@ impl actix::Handler<TranscribeVoiceNote> for super::ClientActor {
             tracing::warn!("No attachments found for message {}", message_id);
             return;
         }
+        let language = language.unwrap_or_else(|| self.settings.get_transcription_language());
         for attachment in attachments {
             if attachment.is_voice_note {
-                self.voice_note_transcription_queue
-                    .queue
-                    .push_back(attachment.id);
+                let queued = self.voice_note_transcription_queue.push(TranscriptionJob {
+                    message_id,
+                    attachment_id: attachment.id,
+                    language: language_from_setting(&language),
+                    previous: None,
+                });
+                if !queued {
+                    tracing::debug!("Attachment {} is already queued", attachment.id);
+                }
             }
         }
         self.try_queue_next_voice_note_transcription(ctx);
     }
 }
 
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub(super) struct CancelVoiceNoteTranscription {
+    pub message_id: i32,
+}
+
+impl actix::Handler<CancelVoiceNoteTranscription> for super::ClientActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        CancelVoiceNoteTranscription { message_id }: CancelVoiceNoteTranscription,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if let Some((job, handle)) = self.voice_note_transcription_queue.cancel(message_id) {
+            tracing::info!(
+                "Cancelling transcription of attachment {}",
+                job.attachment_id
+            );
+            ctx.cancel_future(handle);
+            // Do not leave a partial transcription behind.
+            self.restore_transcription(job.attachment_id, job.previous);
+            self.try_queue_next_voice_note_transcription(ctx);
+        }
+    }
+}
+
 impl super::ClientActor {
+    fn transcriber(&self) -> Arc<dyn Transcriber> {
+        match TranscriptionBackend::from(self.settings.get_transcription_backend().as_str()) {
+            TranscriptionBackend::SpeechNote => Arc::new(SpeechNoteTranscriber),
+            TranscriptionBackend::Command => Arc::new(CommandTranscriber::new(
+                self.settings.get_transcription_command(),
+            )),
+        }
+    }
+
+    /// Puts back the transcription an attachment had before a job started.
+    fn restore_transcription(&self, attachment_id: i32, previous: Option<Transcription>) {
+        let storage = self.storage.as_ref().unwrap();
+        match previous {
+            Some(previous) => storage.update_transcription(
+                attachment_id,
+                &previous.text,
+                previous.language.as_deref(),
+            ),
+            None => storage.clear_transcription(attachment_id),
+        }
+    }
+
     fn try_queue_next_voice_note_transcription(
         &mut self,
         ctx: &mut <Self as actix::Actor>::Context,
     ) {
-        if self
-            .voice_note_transcription_queue
-            .current_attachment
-            .is_some()
-        {
+        let Some(mut job) = self.voice_note_transcription_queue.next() else {
             return;
-        }
-
-        if let Some(attachment_id) = self.voice_note_transcription_queue.queue.pop_front() {
-            let task =
-                TranscriptionTask::start_transcribe(self.storage.clone().unwrap(), attachment_id);
-            let addr = ctx.address();
-            actix::spawn(async move {
-                let task = match task.await {
-                    Ok(t) => t,
-                    Err(e) => {
-                        // XXX: On failure, we should probably retry, if the daemon was already
-                        // busy.
-                        tracing::error!("Failed to start transcription task: {}", e);
-                        // TODO: mark the transcription as failed
-                        return;
-                    }
-                };
-                let task_id = task.task_id;
-
-                addr.send(TranscriptionStarted {
-                    attachment_id,
-                    task_id: task.task_id,
-                })
-                .await
-                .unwrap();
+        };
 
-                let transcription = task.wait_for_transcription().await.unwrap();
-                addr.send(TranscriptionFinished {
-                    task_id,
-                    transcription,
+        let storage = self.storage.clone().unwrap();
+        job.previous = storage
+            .fetch_attachment(job.attachment_id)
+            .and_then(|attachment| {
+                Some(Transcription {
+                    text: attachment.transcription?,
+                    language: attachment.transcription_language,
                 })
-                .await
-                .unwrap();
             });
-        }
+        let previous = job.previous.clone();
+        let transcriber = self.transcriber();
+        let attachment_id = job.attachment_id;
+        let language = job.language.clone();
+        let handle = ctx.spawn(
+            async move {
+                let attachment = storage
+                    .fetch_attachment(attachment_id)
+                    .context("attachment disappeared")?;
+                anyhow::ensure!(attachment.is_voice_note, "not a voice note");
+                let attachment_path = attachment
+                    .absolute_attachment_path()
+                    .context("voice note is not downloaded")?;
+                // The speech engines cannot read encrypted attachments.
+                let path = storage.plaintext_attachment(&*attachment_path)?;
+
+                let partial_storage = storage.clone();
+                let mut partial = move |text: &str| {
+                    partial_storage.update_transcription(attachment_id, text, None)
+                };
+                let transcription =
+                    transcribe_file(&*transcriber, &path, language.as_deref(), &mut partial)
+                        .await?;
+                storage.update_transcription(
+                    attachment_id,
+                    &transcription.text,
+                    transcription.language.as_deref(),
+                );
+                Ok(transcription)
+            }
+            .into_actor(self)
+            .map(move |result: anyhow::Result<Transcription>, act, ctx| {
+                match result {
+                    Ok(transcription) => tracing::info!(
+                        language = ?transcription.language,
+                        "Transcription finished for attachment {}: {}",
+                        attachment_id,
+                        transcription.text
+                    ),
+                    Err(e) => {
+                        tracing::error!(
+                            "Could not transcribe attachment {}: {:#}",
+                            attachment_id,
+                            e
+                        );
+                        act.restore_transcription(attachment_id, previous);
+                    }
+                }
+                act.voice_note_transcription_queue.current = None;
+                act.try_queue_next_voice_note_transcription(ctx);
+            }),
+        );
+        self.voice_note_transcription_queue.current = Some((job, handle));
     }
 }
 
-#[derive(actix::Message)]
-#[rtype(result = "()")]
-struct TranscriptionStarted {
-    attachment_id: i32,
-    task_id: i32,
+/// Runs the transcriber, and cleans up what it returns.
+async fn transcribe_file(
+    transcriber: &dyn Transcriber,
+    path: &Path,
+    language: Option<&str>,
+    partial: &mut (dyn FnMut(&str) + Send),
+) -> anyhow::Result<Transcription> {
+    let mut on_partial = |text: &str| {
+        let text = text.trim();
+        if !text.is_empty() {
+            partial(text);
+        }
+    };
+    let Transcription {
+        text,
+        language: detected,
+    } = transcriber
+        .transcribe(path, language, &mut on_partial)
+        .await?;
+
+    let text = text.trim();
+    anyhow::ensure!(!text.is_empty(), "no speech recognized");
+    Ok(Transcription {
+        text: text.to_string(),
+        language: detected
+            .filter(|language| !language.is_empty() && language != "auto")
+            .or_else(|| language.map(String::from)),
+    })
 }
 
-impl actix::Handler<TranscriptionStarted> for super::ClientActor {
-    type Result = ();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn handle(
-        &mut self,
-        TranscriptionStarted {
-            attachment_id,
-            task_id,
-        }: TranscriptionStarted,
-        _ctx: &mut Self::Context,
-    ) -> Self::Result {
-        self.voice_note_transcription_queue.current_attachment = Some((attachment_id, task_id));
+    /// Replays canned results, or never finishes.
+    struct FakeTranscriber {
+        partials: Vec<&'static str>,
+        result: Option<Transcription>,
     }
-}
 
-#[derive(actix::Message)]
-#[rtype(result = "()")]
-struct TranscriptionFinished {
-    task_id: i32,
-    transcription: String,
-}
+    #[async_trait::async_trait]
+    impl Transcriber for FakeTranscriber {
+        async fn transcribe(
+            &self,
+            path: &Path,
+            _language: Option<&str>,
+            partial: &mut (dyn FnMut(&str) + Send),
+        ) -> anyhow::Result<Transcription> {
+            assert_eq!(path, Path::new("/tmp/voice-note.aac"));
+            for text in &self.partials {
+                partial(text);
+            }
+            match &self.result {
+                Some(result) => Ok(result.clone()),
+                None => futures::future::pending().await,
+            }
+        }
+    }
 
-impl actix::Handler<TranscriptionFinished> for super::ClientActor {
-    type Result = ();
+    fn job(message_id: i32, attachment_id: i32) -> TranscriptionJob {
+        TranscriptionJob {
+            message_id,
+            attachment_id,
+            language: None,
+            previous: None,
+        }
+    }
 
-    fn handle(
-        &mut self,
-        TranscriptionFinished {
-            task_id,
-            transcription,
-        }: TranscriptionFinished,
-        ctx: &mut Self::Context,
-    ) -> Self::Result {
-        if let Some((attachment_id, current_task_id)) =
-            self.voice_note_transcription_queue.current_attachment
-        {
-            tracing::info!(
-                "Transcription finished for attachment {}: {}",
-                attachment_id,
-                transcription
-            );
-            if current_task_id == task_id {
-                self.voice_note_transcription_queue.current_attachment = None;
-                self.try_queue_next_voice_note_transcription(ctx);
-            }
+    #[test]
+    fn backend_setting() {
+        for backend in [
+            TranscriptionBackend::SpeechNote,
+            TranscriptionBackend::Command,
+        ] {
+            assert_eq!(TranscriptionBackend::from(backend.as_str()), backend);
         }
+        assert_eq!(
+            TranscriptionBackend::from("whatever"),
+            TranscriptionBackend::SpeechNote
+        );
+        assert_eq!(language_from_setting("auto"), None);
+        assert_eq!(language_from_setting(""), None);
+        assert_eq!(language_from_setting("NL"), Some("nl".into()));
     }
-}
 
-struct TranscriptionTask {
-    attachment_id: i32,
-    task_id: i32,
-    storage: super::Storage,
-    start_time: std::time::Instant,
-}
+    #[test]
+    fn queue_skips_duplicates() {
+        let mut queue = VoiceNoteTranscriptionQueue::default();
+        assert!(queue.push(job(1, 10)));
+        assert!(queue.push(job(2, 20)));
+        assert!(!queue.push(job(1, 10)));
 
-impl TranscriptionTask {
-    /// Transcribe the voice note
-    async fn start_transcribe(storage: super::Storage, attachment_id: i32) -> anyhow::Result<Self> {
-        let (resource, conn) = dbus_tokio::connection::new_session_sync()?;
+        let first = queue.next().unwrap();
+        assert_eq!(first, job(1, 10));
+        queue.current = Some((first, SpawnHandle::default()));
+        // Only one transcription at a time
+        assert_eq!(queue.next(), None);
+        // Not even when it is transcribing
+        assert!(!queue.push(job(1, 10)));
 
-        let attachment = storage
-            .fetch_attachment(attachment_id)
-            .expect("valid attachment");
-        assert!(attachment.is_voice_note);
+        queue.current = None;
+        assert_eq!(queue.next(), Some(job(2, 20)));
+        assert_eq!(queue.next(), None);
+    }
 
-        actix::spawn(async {
-            let err = resource.await;
-            panic!("Lost connection to D-Bus: {}", err);
-        });
+    #[test]
+    fn queue_cancellation() {
+        let mut queue = VoiceNoteTranscriptionQueue::default();
+        queue.push(job(1, 10));
+        queue.push(job(1, 11));
+        queue.push(job(2, 20));
+        queue.push(job(3, 30));
 
-        let proxy = dbus::nonblock::Proxy::new(
-            "org.mkiol.Speech",
-            "/",
-            std::time::Duration::from_secs(5),
-            conn,
-        );
+        let first = queue.next().unwrap();
+        queue.current = Some((first, SpawnHandle::default()));
 
-        // XXX we should probably first check whether the dbus daemon is busy or not.
-
-        // Prepare the arguments
-        let file_path = attachment
-            .absolute_attachment_path()
-            .expect("valid attachment path");
-        let lang = "auto";
-        let out_lang = "auto";
-        let options: std::collections::HashMap<
-            &str,
-            dbus::arg::Variant<Box<dyn dbus::arg::RefArg>>,
-        > = std::collections::HashMap::new();
-        let path = std::path::Path::new(file_path.as_ref());
-        assert!(path.exists(), "file exists: {:?}", path);
-
-        let (task_id,): (i32,) = proxy
-            .method_call(
-                "org.mkiol.Speech",
-                "SttTranscribeFile",
-                (file_path.as_ref(), lang, out_lang, options),
-            )
-            .await?;
+        // Waiting jobs are dropped, there is nothing to stop
+        assert_eq!(queue.cancel(2), None);
+        // The running job is handed back for cancellation, together with the waiting ones
+        let (cancelled, _handle) = queue.cancel(1).unwrap();
+        assert_eq!(cancelled, job(1, 10));
+        assert!(queue.current.is_none());
+        assert_eq!(queue.next(), Some(job(3, 30)));
+        assert_eq!(queue.next(), None);
+    }
 
-        if task_id < 0 {
-            anyhow::bail!("Failed to start transcription task");
-        }
+    #[actix_rt::test]
+    async fn transcription_is_cleaned_up() {
+        let transcriber = FakeTranscriber {
+            partials: vec!["Hello", "  ", "Hello world "],
+            result: Some(Transcription {
+                text: " Hello world.\n".into(),
+                language: Some("en".into()),
+            }),
+        };
+        let mut partials = Vec::new();
+        let transcription = transcribe_file(
+            &transcriber,
+            Path::new("/tmp/voice-note.aac"),
+            None,
+            &mut |text: &str| partials.push(text.to_string()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(partials, ["Hello", "Hello world"]);
+        assert_eq!(
+            transcription,
+            Transcription {
+                text: "Hello world.".into(),
+                language: Some("en".into()),
+            }
+        );
+    }
 
-        Ok(Self {
-            attachment_id,
-            task_id,
-            storage,
-            start_time: std::time::Instant::now(),
-        })
+    #[actix_rt::test]
+    async fn selected_language_is_kept() {
+        let transcriber = FakeTranscriber {
+            partials: vec![],
+            result: Some(Transcription {
+                text: "Hallo".into(),
+                language: Some("auto".into()),
+            }),
+        };
+        let transcription = transcribe_file(
+            &transcriber,
+            Path::new("/tmp/voice-note.aac"),
+            Some("nl"),
+            &mut |_: &str| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(transcription.language.as_deref(), Some("nl"));
+
+        let silence = FakeTranscriber {
+            partials: vec![],
+            result: Some(Transcription {
+                text: "\n".into(),
+                language: None,
+            }),
+        };
+        assert!(
+            transcribe_file(
+                &silence,
+                Path::new("/tmp/voice-note.aac"),
+                None,
+                &mut |_: &str| {}
+            )
+            .await
+            .is_err()
+        );
     }
 
-    async fn wait_for_transcription(mut self) -> anyhow::Result<String> {
-        let (resource, conn) = dbus_tokio::connection::new_session_sync()?;
-        actix::spawn(async {
-            let err = resource.await;
-            panic!("Lost connection to D-Bus: {}", err);
-        });
-        let proxy = dbus::nonblock::Proxy::new(
-            "org.mkiol.Speech",
-            "/",
-            std::time::Duration::from_secs(5),
-            conn.clone(),
+    #[actix_rt::test]
+    async fn transcription_can_be_cancelled() {
+        let transcriber = FakeTranscriber {
+            partials: vec!["Hello"],
+            result: None,
+        };
+        let mut partials = Vec::new();
+        let transcription = transcribe_file(
+            &transcriber,
+            Path::new("/tmp/voice-note.aac"),
+            None,
+            &mut |text: &str| partials.push(text.to_string()),
         );
-        // Keep-alive timer of 5 seconds
-        let mut interval = tokio::time::interval(std::time::Duration::from_secs(5));
-        // Signal
-        let (signal, mut decoded) = conn
-            .add_match(MatchRule::new_signal("org.mkiol.Speech", "SttTextDecoded"))
-            .await?
-            .stream();
-        let token = signal.token();
-
-        let (intermediate_signal, mut intermediate_decoded) = conn
-            .add_match(MatchRule::new_signal(
-                "org.mkiol.Speech",
-                "SttIntermediateTextDecoded",
-            ))
-            .await?
-            .stream();
-        let intermediate_token = intermediate_signal.token();
-        loop {
-            let interval = interval.tick();
-            let duration = self.start_time.elapsed();
-            futures::select! {
-                _ = interval.fuse() => {
-                    // Call KeepAliveTask(task_id)
-                    tracing::trace!("Sending keep-alive for task {}", self.task_id);
-                    #[allow(clippy::let_unit_value)]
-                    let _: () = proxy.method_call("org.mkiol.Speech", "KeepAliveTask", (self.task_id,)).await?;
-                }
-                signal = decoded.next().fuse() => {
-                    let (message, ()) = signal.unwrap();
-                    let (Some(text), Some(lang), Some(task_id) ): (Option<String>, Option<String>, Option<i32>) = message.get3() else {
-                        panic!("Invalid arguments for signal");
-                    };
-                    tracing::info!(%lang, "Received transcription for task {} after {} seconds: {}", task_id, duration.as_secs(), text);
-                    if task_id == self.task_id {
-                        conn.remove_match(intermediate_token).await?;
-                        conn.remove_match(token).await?;
-                        self.storage.update_transcription(self.attachment_id, &text);
-                        return Ok(text);
-                    }
-                }
-                intermediate_decoded = intermediate_decoded.next().fuse() => {
-                    let (message, ()) = intermediate_decoded.unwrap();
-                    let (Some(text), Some(lang), Some(task_id) ): (Option<String>, Option<String>, Option<i32>) = message.get3() else {
-                        panic!("Invalid arguments for signal");
-                    };
-                    tracing::info!(%lang, "Received partial transcription for task {} after {} seconds: {}", task_id, duration.as_secs(), text);
-                    if task_id == self.task_id {
-                        self.storage.update_transcription(self.attachment_id, &text);
-                    }
-                }
-            }
-        }
+        let timeout = tokio::time::timeout(std::time::Duration::from_millis(50), transcription);
+        assert!(timeout.await.is_err());
+        assert_eq!(partials, ["Hello"]);
     }
 }
@@ -0,0 +1,139 @@
+use super::{Transcriber, Transcription};
+use anyhow::Context;
+use std::path::Path;
+use std::process::Stdio;
+
+/// Transcribes voice notes with a local speech-to-text program, such as whisper.cpp or Vosk.
+///
+/// The command line is split on whitespace, without involving a shell. `{file}` is replaced by
+/// the path of the voice note, and `{language}` by the language code, or `auto`. The transcription
+/// is read from the standard output, e.g. with
+/// `whisper-cli -m ggml-base.bin -nt -l {language} -f {file}`
+/// or `vosk-transcriber -l {language} -i {file}`.
+pub struct CommandTranscriber {
+    command: String,
+}
+
+impl CommandTranscriber {
+    pub fn new(command: String) -> Self {
+        Self { command }
+    }
+
+    fn arguments(&self, path: &Path, language: Option<&str>) -> anyhow::Result<Vec<String>> {
+        let path = path.to_str().context("UTF8-compliant path")?;
+        let language = language.unwrap_or("auto");
+        let arguments: Vec<String> = self
+            .command
+            .split_whitespace()
+            .map(|argument| {
+                argument
+                    .replace("{file}", path)
+                    .replace("{language}", language)
+            })
+            .collect();
+        anyhow::ensure!(!arguments.is_empty(), "no transcription command configured");
+        Ok(arguments)
+    }
+}
+
+/// Finds the language that whisper.cpp reports when it detects it, e.g.
+/// `whisper_full_with_state: auto-detected language: en (p = 0.971372)`.
+fn detected_language(stderr: &str) -> Option<String> {
+    stderr.lines().find_map(|line| {
+        let (_, detected) = line.split_once("auto-detected language:")?;
+        let language = detected.split_whitespace().next()?;
+        Some(language.to_string())
+    })
+}
+
+#[async_trait::async_trait]
+impl Transcriber for CommandTranscriber {
+    async fn transcribe(
+        &self,
+        path: &Path,
+        language: Option<&str>,
+        _partial: &mut (dyn FnMut(&str) + Send),
+    ) -> anyhow::Result<Transcription> {
+        let arguments = self.arguments(path, language)?;
+        tracing::debug!("Running transcription command {:?}", arguments);
+
+        // Cancelling the transcription kills the program.
+        let output = tokio::process::Command::new(&arguments[0])
+            .args(&arguments[1..])
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("could not run {}", arguments[0]))?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} failed with {}: {}",
+                arguments[0],
+                output.status,
+                stderr.trim()
+            );
+        }
+
+        Ok(Transcription {
+            text: String::from_utf8_lossy(&output.stdout).into_owned(),
+            language: detected_language(&stderr),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders() {
+        let transcriber =
+            CommandTranscriber::new("whisper-cli  -nt -l {language} -f {file}".into());
+        assert_eq!(
+            transcriber
+                .arguments(Path::new("/tmp/note.wav"), Some("nl"))
+                .unwrap(),
+            ["whisper-cli", "-nt", "-l", "nl", "-f", "/tmp/note.wav"]
+        );
+        assert_eq!(
+            transcriber
+                .arguments(Path::new("/tmp/note.wav"), None)
+                .unwrap()[3],
+            "auto"
+        );
+        assert!(
+            CommandTranscriber::new(" ".into())
+                .arguments(Path::new("/tmp/note.wav"), None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn whisper_language_detection() {
+        let stderr = "whisper_init_from_file_with_params_no_state: loading model\n\
+                      whisper_full_with_state: auto-detected language: de (p = 0.971372)\n";
+        assert_eq!(detected_language(stderr).as_deref(), Some("de"));
+        assert_eq!(detected_language("vosk says nothing"), None);
+    }
+
+    #[actix_rt::test]
+    async fn runs_the_command() {
+        let transcriber = CommandTranscriber::new("echo Hello from {file} in {language}".into());
+        let transcription = transcriber
+            .transcribe(Path::new("note.wav"), Some("en"), &mut |_: &str| {})
+            .await
+            .unwrap();
+        assert_eq!(transcription.text, "Hello from note.wav in en\n");
+        assert_eq!(transcription.language, None);
+
+        let failing = CommandTranscriber::new("false {file}".into());
+        assert!(
+            failing
+                .transcribe(Path::new("note.wav"), None, &mut |_: &str| {})
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use super::{Transcriber, Transcription};
+use anyhow::Context;
+use dbus::message::MatchRule;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use dbus::nonblock::{Proxy, SyncConnection};
+use futures::future::Either;
+use futures::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// https://github.com/mkiol/dsnote/blob/main/dbus/org.mkiol.Speech.xml
+const SERVICE: &str = "org.mkiol.Speech";
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// The `State` in which the service accepts a new task.
+const STATE_IDLE: i32 = 3;
+/// How long to wait for the service to finish the tasks of other applications.
+const MAX_BUSY_WAIT: Duration = Duration::from_secs(120);
+
+/// Transcribes voice notes with the Speech Note application, over D-Bus.
+pub struct SpeechNoteTranscriber;
+
+#[async_trait::async_trait]
+impl Transcriber for SpeechNoteTranscriber {
+    async fn transcribe(
+        &self,
+        path: &Path,
+        language: Option<&str>,
+        partial: &mut (dyn FnMut(&str) + Send),
+    ) -> anyhow::Result<Transcription> {
+        let (resource, conn) = dbus_tokio::connection::new_session_sync()?;
+        // The connection is driven separately, such that a task can still be cancelled at the
+        // service when the transcription is dropped.
+        let mut driver = actix::spawn(resource);
+        let mut task = SpeechNoteTask {
+            conn,
+            task_id: None,
+            driver: Some(driver.abort_handle()),
+        };
+
+        let work = task.run(path, language, partial);
+        futures::pin_mut!(work);
+        match future::select(work, &mut driver).await {
+            Either::Left((result, _)) => result,
+            Either::Right((lost, _)) => {
+                let e = lost.map_or_else(|e| e.to_string(), |e| e.to_string());
+                Err(anyhow::anyhow!("Lost connection to D-Bus: {}", e))
+            }
+        }
+    }
+}
+
+struct SpeechNoteTask {
+    conn: Arc<SyncConnection>,
+    /// The task at the service, while it is running.
+    task_id: Option<i32>,
+    driver: Option<tokio::task::AbortHandle>,
+}
+
+impl SpeechNoteTask {
+    fn proxy(&self) -> Proxy<'static, Arc<SyncConnection>> {
+        Proxy::new(SERVICE, "/", TIMEOUT, self.conn.clone())
+    }
+
+    /// Waits for the service to finish what it is doing for other applications.
+    async fn wait_until_idle(&self) -> anyhow::Result<()> {
+        let start = Instant::now();
+        loop {
+            let state: i32 = self.proxy().get(SERVICE, "State").await?;
+            if state == STATE_IDLE {
+                return Ok(());
+            }
+            anyhow::ensure!(
+                start.elapsed() < MAX_BUSY_WAIT,
+                "Speech Note stays busy (state {state})"
+            );
+            tracing::debug!("Speech Note is busy (state {state}), waiting");
+            tokio::time::sleep(TIMEOUT).await;
+        }
+    }
+
+    async fn run(
+        &mut self,
+        path: &Path,
+        language: Option<&str>,
+        partial: &mut (dyn FnMut(&str) + Send),
+    ) -> anyhow::Result<Transcription> {
+        self.wait_until_idle().await?;
+
+        // Listen before starting the task, such that no result can be missed.
+        let (signal, mut decoded) = self
+            .conn
+            .add_match(MatchRule::new_signal(SERVICE, "SttTextDecoded"))
+            .await?
+            .stream();
+        let token = signal.token();
+        let (intermediate_signal, mut intermediate_decoded) = self
+            .conn
+            .add_match(MatchRule::new_signal(SERVICE, "SttIntermediateTextDecoded"))
+            .await?
+            .stream();
+        let intermediate_token = intermediate_signal.token();
+
+        let file_path = path.to_str().context("UTF8-compliant path")?;
+        let lang = language.unwrap_or("auto");
+        let options =
+            std::collections::HashMap::<&str, dbus::arg::Variant<Box<dyn dbus::arg::RefArg>>>::new(
+            );
+        // Not awaited in the same statement, as the options may not be sent across threads.
+        let reply = self.proxy().method_call(
+            SERVICE,
+            "SttTranscribeFile",
+            (file_path, lang, lang, options),
+        );
+        let (task_id,): (i32,) = reply.await?;
+        if task_id < 0 {
+            anyhow::bail!("Speech Note refused the transcription task");
+        }
+        self.task_id = Some(task_id);
+
+        let start_time = Instant::now();
+        // Keep-alive timer of 5 seconds
+        let mut interval = tokio::time::interval(TIMEOUT);
+        loop {
+            let tick = interval.tick();
+            let duration = start_time.elapsed();
+            futures::select! {
+                _ = tick.fuse() => {
+                    tracing::trace!("Sending keep-alive for task {}", task_id);
+                    #[allow(clippy::let_unit_value)]
+                    let _: () = self.proxy().method_call(SERVICE, "KeepAliveTask", (task_id,)).await?;
+                }
+                signal = decoded.next().fuse() => {
+                    let (message, ()) = signal.context("D-Bus signal stream ended")?;
+                    let (Some(text), Some(lang), Some(signal_task_id)): (Option<String>, Option<String>, Option<i32>) = message.get3() else {
+                        tracing::warn!("Invalid arguments for SttTextDecoded signal");
+                        continue;
+                    };
+                    if signal_task_id == task_id {
+                        tracing::info!(%lang, "Received transcription for task {} after {} seconds", task_id, duration.as_secs());
+                        self.task_id = None;
+                        self.conn.remove_match(intermediate_token).await?;
+                        self.conn.remove_match(token).await?;
+                        return Ok(Transcription {
+                            text,
+                            language: Some(lang),
+                        });
+                    }
+                }
+                signal = intermediate_decoded.next().fuse() => {
+                    let (message, ()) = signal.context("D-Bus signal stream ended")?;
+                    let (Some(text), Some(lang), Some(signal_task_id)): (Option<String>, Option<String>, Option<i32>) = message.get3() else {
+                        tracing::warn!("Invalid arguments for SttIntermediateTextDecoded signal");
+                        continue;
+                    };
+                    if signal_task_id == task_id {
+                        tracing::debug!(%lang, "Received partial transcription for task {} after {} seconds", task_id, duration.as_secs());
+                        partial(&text);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for SpeechNoteTask {
+    fn drop(&mut self) {
+        let driver = self.driver.take().expect("dropped once");
+        let Some(task_id) = self.task_id.take() else {
+            driver.abort();
+            return;
+        };
+
+        tracing::info!("Cancelling Speech Note task {}", task_id);
+        let proxy = self.proxy();
+        actix::spawn(async move {
+            let cancel = async {
+                let (_result,): (i32,) = proxy.method_call(SERVICE, "Cancel", (task_id,)).await?;
+                Ok::<_, dbus::Error>(())
+            };
+            match tokio::time::timeout(TIMEOUT, cancel).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::warn!("Could not cancel Speech Note task: {}", e),
+                Err(_) => tracing::warn!("Cancelling Speech Note task timed out"),
+            }
+            driver.abort();
+        });
+    }
+}
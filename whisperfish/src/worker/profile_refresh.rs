@@ -16,7 +16,6 @@ use std::{
     sync::Weak,
     time::Duration,
 };
-use tokio::io::AsyncWriteExt;
 use tracing_futures::Instrument;
 use uuid::Uuid;
 use whisperfish_store::{StoreProfile, orm::UnidentifiedAccessMode};
@@ -305,6 +304,7 @@ impl Handler<FetchAvatar> for ProfileUpdater {
     ) -> Self::Result {
         let span = tracing::info_span!("fetch avatar", recipient=%recipient_uuid, avatar=%avatar_attachment_path);
         let ws = self.unidentified_websocket();
+        let storage = self.storage.clone();
         Box::pin(
             async move {
                 // XXX this connection might fail, and I don't believe we retry avatar fetching
@@ -313,11 +313,8 @@ impl Handler<FetchAvatar> for ProfileUpdater {
 
                 let settings = crate::config::SettingsBridge::default();
                 let avatar_dir = settings.get_string("avatar_dir");
-                let avatar_dir = std::path::Path::new(&avatar_dir);
-                if !avatar_dir.exists() {
-                    std::fs::create_dir(avatar_dir)?;
-                }
-                let avatar_path = avatar_dir.join(recipient_uuid.to_string());
+                let avatar_path =
+                    std::path::Path::new(&avatar_dir).join(recipient_uuid.to_string());
 
                 let mut avatar = ws.retrieve_profile_avatar(&avatar_attachment_path).await?;
                 // 10MB is what Signal Android allocates
@@ -328,8 +325,7 @@ impl Handler<FetchAvatar> for ProfileUpdater {
                 let cipher = ProfileCipher::new(profile_key);
                 let avatar_bytes = cipher.decrypt_avatar(&contents)?;
 
-                let mut f = tokio::fs::File::create(avatar_path).await?;
-                f.write_all(&avatar_bytes).await?;
+                storage.save_file(avatar_path, &avatar_bytes).await?;
                 tracing::info!("Profile avatar saved!");
 
                 Ok(())
@@ -0,0 +1,79 @@
+use crate::store::Storage;
+use chrono::Utc;
+use futures::{Future, Stream};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// Wakes up when scheduled messages are due for sending.
+///
+/// The schedule lives in the database, so messages that came due while Whisperfish was not
+/// running are picked up right after a restart.
+pub struct ScheduledMessagesStream {
+    storage: Storage,
+    next_wake: Option<Pin<Box<tokio::time::Sleep>>>,
+    wake_channel: tokio::sync::mpsc::UnboundedReceiver<()>,
+}
+
+pub struct DueScheduledMessages;
+
+impl ScheduledMessagesStream {
+    pub fn new(storage: Storage, wake_channel: tokio::sync::mpsc::UnboundedReceiver<()>) -> Self {
+        Self {
+            storage,
+            next_wake: None,
+            wake_channel,
+        }
+    }
+
+    #[tracing::instrument(skip(self, cx))]
+    fn update_next_wake(&mut self, cx: &mut Context<'_>) {
+        if let Some((message_id, time)) = self.storage.fetch_next_scheduled_message_id() {
+            tracing::info!(
+                "message {} is scheduled for {}; scheduling wake-up.",
+                message_id,
+                time
+            );
+            let delta = time - Utc::now();
+            self.next_wake = Some(Box::pin(tokio::time::sleep(
+                delta.to_std().unwrap_or(Duration::from_secs(1)),
+            )));
+
+            cx.waker().wake_by_ref();
+        } else {
+            self.next_wake = None;
+        }
+    }
+}
+
+impl Stream for ScheduledMessagesStream {
+    type Item = DueScheduledMessages;
+
+    #[tracing::instrument(skip(self, cx))]
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(next_wake) = &mut self.next_wake
+            && Pin::new(next_wake).poll(cx).is_ready()
+        {
+            self.next_wake = None;
+            if !self.storage.fetch_due_scheduled_message_ids().is_empty() {
+                return Poll::Ready(Some(DueScheduledMessages));
+            }
+        }
+
+        let woken = self.wake_channel.poll_recv(cx).is_ready();
+
+        if woken || self.next_wake.is_none() {
+            let _span = if woken {
+                tracing::trace_span!("woken by channel").entered()
+            } else {
+                tracing::trace_span!("no next wake, computing").entered()
+            };
+
+            self.update_next_wake(cx);
+        }
+
+        Poll::Pending
+    }
+}